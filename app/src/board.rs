@@ -1,27 +1,45 @@
 use rtrs::sync::RwLock;
-use rtrs::time::TickProvider;
-
-use alloc::boxed::Box;
 
 pub enum CallbackType {
     Systick(fn()),
     TriggerCrash(fn()),
+    Reset(fn()),
     MicrosecondDelay(fn(u32)),
-    MicrosecondTickProvider(fn(&mut Box<dyn TickProvider<Tick = u32>>)),
+    SampleTimer(fn()),
+    SetSampleRate(fn(u32)),
+    Sleep(fn()),
+    Stop(fn(u32) -> u32),
+    Micros(fn() -> u64),
 }
 
 pub enum Callback<'a> {
     Systick,
     TriggerCrash,
+    // System reset, doesn't return
+    Reset,
     MicrosecondDelay(u32),
-    MicrosecondTickProvider(&'a mut Box<dyn TickProvider<Tick = u32>>),
+    // Called from the sampling timer interrupt
+    SampleTimer,
+    // Sampling timer rate in Hz, 0 stops it
+    SetSampleRate(u32),
+    // Wait for interrupt
+    Sleep,
+    // Enter STOP for at most given ms, time actually spent there is written back
+    Stop(u32, &'a mut u32),
+    // Monotonic microseconds since boot
+    Micros(&'a mut u64),
 }
 
 struct Callbacks {
     systick:                   Option<fn()>,
     crash:                     Option<fn()>,
+    reset:                     Option<fn()>,
     microsecond_delay:         Option<fn(u32)>,
-    microsecond_tick_provider: Option<fn(&mut Box<dyn TickProvider<Tick = u32>>)>
+    sample_timer:              Option<fn()>,
+    set_sample_rate:           Option<fn(u32)>,
+    sleep:                     Option<fn()>,
+    stop:                      Option<fn(u32) -> u32>,
+    micros:                    Option<fn() -> u64>,
 }
 
 impl Callbacks {
@@ -29,8 +47,13 @@ impl Callbacks {
         Self {
             systick:                   None,
             crash:                     None,
+            reset:                     None,
             microsecond_delay:         None,
-            microsecond_tick_provider: None
+            sample_timer:              None,
+            set_sample_rate:           None,
+            sleep:                     None,
+            stop:                      None,
+            micros:                    None,
         }
     }
 }
@@ -50,11 +73,26 @@ impl BoardInterface {
             CallbackType::TriggerCrash(f) => {
                 (*cbs).crash = Some(f);
             }
+            CallbackType::Reset(f) => {
+                (*cbs).reset = Some(f);
+            }
             CallbackType::MicrosecondDelay(f) => {
                 (*cbs).microsecond_delay = Some(f);
             }
-            CallbackType::MicrosecondTickProvider(f) => {
-                (*cbs).microsecond_tick_provider = Some(f);
+            CallbackType::SampleTimer(f) => {
+                (*cbs).sample_timer = Some(f);
+            }
+            CallbackType::SetSampleRate(f) => {
+                (*cbs).set_sample_rate = Some(f);
+            }
+            CallbackType::Sleep(f) => {
+                (*cbs).sleep = Some(f);
+            }
+            CallbackType::Stop(f) => {
+                (*cbs).stop = Some(f);
+            }
+            CallbackType::Micros(f) => {
+                (*cbs).micros = Some(f);
             }
         }
     }
@@ -73,14 +111,39 @@ impl BoardInterface {
                     f();
                 }
             }
+            Callback::Reset => {
+                if let Some(f) = (*cbs).reset {
+                    f();
+                }
+            }
             Callback::MicrosecondDelay(us) => {
                 if let Some(f) = (*cbs).microsecond_delay {
                     f(us);
                 }
             }
-            Callback::MicrosecondTickProvider(provider) => {
-                if let Some(f) = (*cbs).microsecond_tick_provider {
-                    f(provider);
+            Callback::SampleTimer => {
+                if let Some(f) = (*cbs).sample_timer {
+                    f();
+                }
+            }
+            Callback::SetSampleRate(hz) => {
+                if let Some(f) = (*cbs).set_sample_rate {
+                    f(hz);
+                }
+            }
+            Callback::Sleep => {
+                if let Some(f) = (*cbs).sleep {
+                    f();
+                }
+            }
+            Callback::Stop(ms, slept) => {
+                if let Some(f) = (*cbs).stop {
+                    *slept = f(ms);
+                }
+            }
+            Callback::Micros(us) => {
+                if let Some(f) = (*cbs).micros {
+                    *us = f();
                 }
             }
         }
@@ -3,6 +3,8 @@ use rtrs::log::meta::ModuleMetaManager;
 use rtrs::log::console::CONSOLE_OBJECT_NAME;
 use rtrs::shell::script::Runtime;
 use rtrs::object::STORAGE;
+use rtrs::sync::RwLock;
+use rtrs::task::Task;
 
 use rtrs::{
     print,
@@ -18,18 +20,86 @@ use rtrs::{
     trace,
     info,
     error,
-    ignore
+    task_yield
 };
 
-use rtrs_drivers::radio::{Radio, RadioError, RadioIoctl};
+use crate::peripherals::spi::{self, DeviceError, SpiDevice};
+use crate::peripherals::gpio::{self, PinKind};
+use crate::services::pattern_player::{self, PatternPlayer, PATTERN_PLAYER_OBJECT_NAME};
+use crate::services::melody::{self, MelodyPlayer, MELODY_PLAYER_OBJECT_NAME};
+use crate::peripherals::tone::{Tone, ToneInterface, TONE_OBJECT_NAME};
+use crate::services::button::{ButtonEventKind, ButtonService, BUTTON_SERVICE_OBJECT_NAME};
+use crate::services::heart_rate::{self, HeartRate, HEART_RATE_OBJECT_NAME};
+use crate::peripherals::adc::{Adc, AdcError, ADC_OBJECT_NAME};
+use crate::services::battery::{self, Battery, BATTERY_OBJECT_NAME};
+use crate::services::idle::{self, Idle, IdleMode, IDLE_OBJECT_NAME};
+use crate::peripherals::rtc::{self, DateTime, Rtc, RTC_OBJECT_NAME};
+use crate::peripherals::clock::{self, Clock, ClockProfile, CLOCK_OBJECT_NAME};
+use crate::peripherals::flash::{Flash, FLASH_OBJECT_NAME};
+use crate::net::{TransportError, MAX_FRAME_SIZE};
+use crate::net::link::{self, DeliveryStatus, Link, LinkError, LINK_OBJECT_NAME};
+use crate::net::{mesh, ota, ping, radio, remote};
+use crate::net::sniffer::{self, SniffFormat};
+use crate::net::config::{self, Param};
+use crate::net::{aes, airtime, secure};
 
 use core::alloc::Layout;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::board::{BoardInterface, Callback};
 
 logger!("shell");
 
+// Same as RTRS_SHELL_INPUT_SIZE
+pub const MAX_COMMAND_SIZE: usize = 64;
+
+const COMMAND_QUEUE_SIZE: usize = 4;
+
+// Commands scheduled from outside of the shell (e.g. button bindings), executed by `shell_task`
+static COMMAND_QUEUE: RwLock<heapless::Deque<heapless::String<MAX_COMMAND_SIZE>, COMMAND_QUEUE_SIZE>> =
+    RwLock::new(heapless::Deque::new());
+
+pub(crate) fn schedule(command: &str) -> Result<(), ()> {
+    let mut cmd = heapless::String::new();
+    cmd.push_str(command)?;
+
+    let mut queue = COMMAND_QUEUE.lock_mut();
+    (*queue).push_back(cmd).map_err(|_| ())
+}
+
+// Set while a spawned command (buzz, adc stream, bench) owns the console, `shell_task` clears it on
+// any key press
+static CONSOLE_JOB: AtomicBool = AtomicBool::new(false);
+
+fn console_job() -> bool {
+    CONSOLE_JOB.load(Ordering::SeqCst)
+}
+
+// Set before the task is spawned, so a key press right away isn't missed
+fn spawn_console_job<F>(job: F)
+where
+    F: core::future::Future<Output = ()> + 'static,
+{
+    CONSOLE_JOB.store(true, Ordering::SeqCst);
+    rtrs::task::this::spawn(Task::new(job));
+}
+
+fn stop_console_job() {
+    CONSOLE_JOB.store(false, Ordering::SeqCst);
+}
+
+fn parse_hex_u8(arg: &str) -> Option<u8> {
+    u8::from_str_radix(arg.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_u32(arg: &str) -> Option<u32> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None      => arg.parse().ok(),
+    }
+}
+
 fn cmd_panic(_rt: &mut Runtime, args: &[&str]) -> i8 {
     panic!("{}", args.get(0).map_or("Manual panic", |v| v));
 }
@@ -54,6 +124,14 @@ fn cmd_test(_rt: &mut Runtime, args: &[&str]) -> i8 {
         println!("  hexdump");
         println!("  heap");
         println!("  btn");
+        println!("  pulse");
+        println!("  melody");
+        println!("  exti");
+        println!("  hrv");
+        println!("  time");
+        println!("  link");
+        println!("  airtime");
+        println!("  ota");
     }
 
     enum Test {
@@ -70,6 +148,13 @@ fn cmd_test(_rt: &mut Runtime, args: &[&str]) -> i8 {
         Heap,
         Button,
         Pulse,
+        Melody,
+        Exti,
+        Hrv,
+        Time,
+        Link,
+        Airtime,
+        Ota,
     }
 
     let mut tests: u32 = 0;
@@ -78,7 +163,7 @@ fn cmd_test(_rt: &mut Runtime, args: &[&str]) -> i8 {
 
     while let Some(arg) = iter.next() {
         match *arg {
-            "all"               => tests = 0xFFFF,
+            "all"               => tests = 0xFFFF_FFFF,
             "task"              => bit_set!(tests, Test::Task),
             "task-irq"          => bit_set!(tests, Test::TaskIrq),
             "task-nest"         => bit_set!(tests, Test::TaskNest),
@@ -92,6 +177,13 @@ fn cmd_test(_rt: &mut Runtime, args: &[&str]) -> i8 {
             "heap"              => bit_set!(tests, Test::Heap),
             "btn"               => bit_set!(tests, Test::Button),
             "pulse"             => bit_set!(tests, Test::Pulse),
+            "melody"            => bit_set!(tests, Test::Melody),
+            "exti"              => bit_set!(tests, Test::Exti),
+            "hrv"               => bit_set!(tests, Test::Hrv),
+            "time"              => bit_set!(tests, Test::Time),
+            "link"              => bit_set!(tests, Test::Link),
+            "airtime"           => bit_set!(tests, Test::Airtime),
+            "ota"               => bit_set!(tests, Test::Ota),
             "help" => {
                 help();
                 return 0;
@@ -154,6 +246,7 @@ fn cmd_test(_rt: &mut Runtime, args: &[&str]) -> i8 {
         crate::test_box()
     });
 
+    #[cfg(not(test))]
     bit_if!(tests, Test::Heap, {
         trace!("Running Test::Heap");
         crate::test_heap()
@@ -169,6 +262,41 @@ fn cmd_test(_rt: &mut Runtime, args: &[&str]) -> i8 {
         crate::test_pulse_sensor()
     });
 
+    bit_if!(tests, Test::Melody, {
+        trace!("Running Test::Melody");
+        crate::test_melody()
+    });
+
+    bit_if!(tests, Test::Exti, {
+        trace!("Running Test::Exti");
+        crate::test_exti()
+    });
+
+    bit_if!(tests, Test::Hrv, {
+        trace!("Running Test::Hrv");
+        crate::test_hrv()
+    });
+
+    bit_if!(tests, Test::Time, {
+        trace!("Running Test::Time");
+        crate::test_time()
+    });
+
+    bit_if!(tests, Test::Link, {
+        trace!("Running Test::Link");
+        crate::test_link()
+    });
+
+    bit_if!(tests, Test::Airtime, {
+        trace!("Running Test::Airtime");
+        crate::test_airtime()
+    });
+
+    bit_if!(tests, Test::Ota, {
+        trace!("Running Test::Ota");
+        crate::test_ota()
+    });
+
     0
 }
 
@@ -291,14 +419,187 @@ fn cmd_log(_rt: &mut Runtime, args: &[&str]) -> i8 {
 
 fn cmd_time(_rt: &mut Runtime, _args: &[&str]) -> i8 {
     info!("tick: {}", rtrs::time::global_tick());
+    info!("us: {}", crate::time::micros());
+
+    if let Some(time) = rtc::timestamp() {
+        info!("date: {}", time);
+    }
+
+    0
+}
+
+fn cmd_date(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: date [set|alarm] ...");
+        error!(" date set YYYY-MM-DD HH:MM:SS");
+        error!(" date alarm [YYYY-MM-DD HH:MM:SS|in SECONDS|off]");
+    }
+
+    if !crate::object_exists(RTC_OBJECT_NAME) {
+        error!("RTC is not available");
+        return 1;
+    }
+
+    let res = match (args.get(0).map(|v| *v), args.get(1).map(|v| *v), args.get(2).map(|v| *v)) {
+        (None, _, _) => {
+            object_with_mut!(RTC_OBJECT_NAME, Rtc, r, r.now()).map(|time| {
+                println!("{}", time);
+            })
+        }
+        (Some("set"), Some(date), Some(time)) => {
+            DateTime::parse(date, time).and_then(|time| object_with_mut!(RTC_OBJECT_NAME, Rtc, r, r.set(&time)))
+        }
+        (Some("alarm"), None, _) => {
+            match object_with!(RTC_OBJECT_NAME, Rtc, r, r.alarm()) {
+                Some(at) => println!("alarm: {}{}", at, if rtc::alarm_fired() { " (fired)" } else { "" }),
+                None => println!("alarm: off"),
+            }
+            Ok(())
+        }
+        (Some("alarm"), Some("off"), None) => {
+            object_with_mut!(RTC_OBJECT_NAME, Rtc, r, r.set_alarm(None))
+        }
+        (Some("alarm"), Some("in"), Some(secs)) => {
+            match parse_u32(secs) {
+                Some(secs) => object_with_mut!(RTC_OBJECT_NAME, Rtc, r, {
+                    r.now().and_then(|now| now.add_seconds(secs)).and_then(|at| r.set_alarm(Some(at)))
+                }),
+                None => Err(rtc::RtcError::Parse),
+            }
+        }
+        (Some("alarm"), Some(date), Some(time)) => {
+            DateTime::parse(date, time).and_then(|at| object_with_mut!(RTC_OBJECT_NAME, Rtc, r, r.set_alarm(Some(at))))
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    };
+
+    if let Err(err) = res {
+        error!("Error: {:?}", err);
+        return 1;
+    }
+
     0
 }
 
 fn cmd_led(_rt: &mut Runtime, args: &[&str]) -> i8 {
-    match args.get(0).map(|v| *v) {
-        Some("on")  => object_with_mut!("led_green", rtrs::gpio::Output, led, { ignore!(led.set_high()); }),
-        Some("off") => object_with_mut!("led_green", rtrs::gpio::Output, led, { ignore!(led.set_low());  }),
-        _           => error!("Unknown subcommand. Usage: led on|off")
+    const LED_NAME: &str = "led_green";
+
+    let level = match args.get(0).map(|v| *v) {
+        Some("on")  => true,
+        Some("off") => false,
+        _ => {
+            error!("Unknown subcommand. Usage: led on|off");
+            return 1;
+        }
+    };
+
+    if let Err(err) = gpio::write(LED_NAME, level) {
+        error!("{}: {:?}", LED_NAME, err);
+        return 1;
+    }
+
+    0
+}
+
+fn cmd_gpio(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: gpio list|read|set|toggle|watch|play|stop ...");
+        error!(" gpio list              - List all pins");
+        error!(" gpio read PIN          - Read pin level");
+        error!(" gpio set PIN 0|1       - Set output level");
+        error!(" gpio toggle PIN        - Toggle output");
+        error!(" gpio watch PIN         - Print input edges until key press");
+        error!(" gpio play PIN PATTERN  - Play pattern on output in background");
+        error!(" gpio stop PIN          - Stop pattern on output");
+        print!("Patterns: ");
+        for pattern in crate::patterns::PATTERNS.iter() {
+            print!("{} ", pattern.name);
+        }
+        println!();
+    }
+
+    let cmd = args.get(0).map(|v| *v);
+    let pin = args.get(1).map(|v| *v);
+
+    let res = match (cmd, pin) {
+        (Some("list"), _) | (None, _) => {
+            for pin in gpio::pins() {
+                let kind = match pin.kind {
+                    PinKind::Input  => "in ",
+                    PinKind::Output => "out",
+                };
+
+                match gpio::read(pin.name) {
+                    Ok(level) => println!("  {} {} {}", kind, level as u8, pin.name),
+                    Err(_)    => println!("  {} ? {}", kind, pin.name),
+                }
+            }
+            Ok(())
+        }
+        (Some("read"), Some(pin)) => {
+            gpio::read(pin).map(|level| println!("{}", level as u8))
+        }
+        (Some("set"), Some(pin)) => {
+            match args.get(2).map(|v| *v) {
+                Some("1") | Some("on")  => gpio::write(pin, true),
+                Some("0") | Some("off") => gpio::write(pin, false),
+                _ => {
+                    help();
+                    return 1;
+                }
+            }
+        }
+        (Some("toggle"), Some(pin)) => {
+            gpio::toggle(pin).map(|level| println!("{}", level as u8))
+        }
+        (Some("watch"), Some(pin)) => {
+            if gpio::watching() {
+                error!("Already watching a pin");
+                return 1;
+            }
+
+            gpio::start_watch(pin).map(|info| {
+                info!("Watching '{}'. Press any key to stop", info.name);
+                rtrs::task::this::spawn(Task::new(gpio::watch(info)));
+            })
+        }
+        // Pattern player runs the pattern in its own task, these only hand it over
+        (Some("play"), Some(pin)) => {
+            let Some(pattern) = args.get(2).and_then(|v| crate::patterns::find(v)) else {
+                error!("Unknown pattern");
+                help();
+                return 1;
+            };
+
+            let entry = pattern_player::Entry { pattern, priority: pattern_player::DEFAULT_PRIORITY, duration: None };
+
+            if let Err(err) = object_with_mut!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer, player, player.start(pin, entry)) {
+                error!("{}: {:?}", pin, err);
+                return 1;
+            }
+
+            Ok(())
+        }
+        (Some("stop"), Some(pin)) => {
+            if let Err(err) = object_with_mut!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer, player, player.stop(pin)) {
+                error!("{}: {:?}", pin, err);
+                return 1;
+            }
+
+            Ok(())
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    };
+
+    if let Err(err) = res {
+        error!("{}: {:?}", pin.unwrap_or("gpio"), err);
+        return 1;
     }
 
     0
@@ -314,88 +615,321 @@ fn cmd_buzz(_rt: &mut Runtime, args: &[&str]) -> i8 {
 
     info!("Selected delay: {}. Press any key to stop", delay);
 
-    let mut state = false;
+    // Buzzer pin belongs to the PWM timer, delay is half the period
+    object_with_mut!(TONE_OBJECT_NAME, Tone, tone, tone.start(500_000 / delay.max(1)));
 
-    while matches!(object_with_mut!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty, tty, tty.read()), None) {
-        object_with_mut!("buzzer", rtrs::gpio::Output, pin,
-             if state {
-                ignore!(pin.set_high());
-            } else {
-                ignore!(pin.set_low());
+    spawn_console_job(async {
+        while console_job() {
+            idle::sleep(10).await;
+        }
+
+        object_with_mut!(TONE_OBJECT_NAME, Tone, tone, tone.stop());
+    });
+
+    0
+}
+
+fn cmd_bench(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    if args.get(0) != Some(&"delay") {
+        error!("Usage: bench delay [ITERATIONS]");
+        return 1;
+    }
+
+    let iterations = args.get(1).and_then(|v| v.parse::<u32>().ok()).unwrap_or(8).max(1);
+
+    spawn_console_job(bench_delay(iterations));
+
+    0
+}
+
+// Busy waits one delay at a time and yields in between, so other tasks keep running
+async fn bench_delay(iterations: u32) {
+    const DELAYS_US: [u32; 6] = [1, 10, 100, 1_000, 10_000, 100_000];
+
+    // Cost of reading the clock itself, subtracted from every measurement
+    let overhead = (0..iterations)
+        .map(|_| {
+            let start = crate::time::micros();
+            crate::time::micros() - start
+        })
+        .min()
+        .unwrap_or(0);
+
+    println!("clock read overhead: {} us", overhead);
+    println!("{:>10} {:>10} {:>10} {:>10}", "delay_us", "min_us", "max_us", "error");
+
+    for delay in DELAYS_US {
+        let (mut min, mut max) = (u64::MAX, 0);
+
+        for _ in 0..iterations {
+            if !console_job() {
+                info!("Benchmark stopped");
+                return;
             }
+
+            let start = crate::time::micros();
+            BoardInterface::callback(Callback::MicrosecondDelay(delay));
+            let elapsed = (crate::time::micros() - start).saturating_sub(overhead);
+
+            min = min.min(elapsed);
+            max = max.max(elapsed);
+
+            task_yield!();
+        }
+
+        // Error of the best case, interrupts only ever make delays longer
+        let error = min as i64 - delay as i64;
+        let permille = error * 1000 / delay as i64;
+
+        println!(
+            "{:>10} {:>10} {:>10} {:>+7} us ({}{}.{}%)",
+            delay, min, max, error,
+            if permille < 0 { "-" } else { "+" }, permille.abs() / 10, permille.abs() % 10
         );
+    }
 
-        state = !state;
+    stop_console_job();
+}
+
+fn cmd_tone(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    if args.get(0) == Some(&"off") {
+        object_with_mut!(MELODY_PLAYER_OBJECT_NAME, MelodyPlayer, player, player.stop());
+        return 0;
+    }
+
+    let freq = args.get(0).and_then(|v| v.parse::<u32>().ok());
+    let duration = args.get(1).and_then(|v| v.parse::<u32>().ok());
 
-        BoardInterface::callback(Callback::MicrosecondDelay(delay));
+    match (freq, duration) {
+        (Some(freq), Some(duration)) if freq > 0 => {
+            object_with_mut!(MELODY_PLAYER_OBJECT_NAME, MelodyPlayer, player, player.tone(freq, duration));
+        }
+        _ => {
+            error!("Usage: tone FREQ_HZ DURATION_MS | tone off");
+            return 1;
+        }
     }
 
     0
 }
 
-fn cmd_radio(_rt: &mut Runtime, args: &[&str]) -> i8 {
+fn cmd_melody(_rt: &mut Runtime, args: &[&str]) -> i8 {
     fn help() {
-        error!("Usage: radio init|send|recv ...");
-        error!(" radio send BYTES...");
-        error!(" radio recv TIMEOUT_MS");
+        error!("Usage: melody list|play|stop");
+        error!(" melody play NAME|RTTTL - Play built-in or RTTTL melody in background");
+        error!(" melody stop            - Stop playing");
     }
 
     match args.get(0).map(|v| *v) {
-        Some("init") => {
-            object_with_mut!("radio", Radio, radio, {
-                let _ = radio.init();
+        Some("list") | None => {
+            for (name, melody) in melody::MELODIES.iter() {
+                println!("  {}: {}", name, melody);
+            }
+        }
+        Some("play") => {
+            let Some(arg) = args.get(1) else {
+                help();
+                return 1;
+            };
+
+            let text = melody::find(arg).unwrap_or(arg);
+
+            if let Err(err) = object_with_mut!(MELODY_PLAYER_OBJECT_NAME, MelodyPlayer, player, player.play(text)) {
+                error!("Invalid melody: {:?}", err);
+                return 1;
+            }
+        }
+        Some("stop") => {
+            object_with_mut!(MELODY_PLAYER_OBJECT_NAME, MelodyPlayer, player, player.stop());
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    }
+
+    0
+}
 
-                let _ = radio.set_frequency(433000);
-                let _ = radio.set_power(20);
-                let _ = radio.ioctl(RadioIoctl::SetPreambleSize(10));
-                let _ = radio.set_bandwidth(125000);
+fn cmd_button(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: button list|add|log ...");
+        error!(" button list            - List buttons and their state");
+        error!(" button add PIN high|low - Add input as a button, with active level");
+        error!(" button log on|off      - Print events as they happen");
+    }
+
+    match args.get(0).map(|v| *v) {
+        Some("list") | None => {
+            object_with!(BUTTON_SERVICE_OBJECT_NAME, ButtonService, service, {
+                for (pin, pressed) in service.buttons() {
+                    println!("  {}: {}", pin, if pressed { "pressed" } else { "released" });
+                }
             });
         }
-        Some("send") => {
-            let mut buf = [0; 64];
-            let mut size = 0;
+        Some("add") => {
+            let active_high = match args.get(2).map(|v| *v) {
+                Some("high") => true,
+                Some("low")  => false,
+                _ => {
+                    help();
+                    return 1;
+                }
+            };
 
-            for i in 1..args.len() {
-                buf[i - 1] = args.get(i).map_or("0", |v| *v).parse().unwrap_or(0);
-                size += 1;
+            let pin = args.get(1).map_or("", |v| v);
+
+            if let Err(err) = object_with_mut!(BUTTON_SERVICE_OBJECT_NAME, ButtonService, service, service.add(pin, active_high)) {
+                error!("{}: {:?}", pin, err);
+                return 1;
             }
+        }
+        Some("log") => {
+            let verbose = matches!(args.get(1).map(|v| *v), Some("on"));
+            object_with_mut!(BUTTON_SERVICE_OBJECT_NAME, ButtonService, service, service.set_verbose(verbose));
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    }
 
-            loop {
-                object_with_mut!("radio", Radio, radio, {
-                    match radio.send(&buf[0..size]) {
-                        Ok(()) => {
-                            break;
-                        }
-                        Err(RadioError::InProgress) => {}
-                        Err(err) => {
-                            error!("Error: {:?}", err);
-                            break;
-                        }
-                    }
-                });
+    0
+}
+
+fn cmd_bind(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: bind [list] | bind BTN press|release|long|double CMD...");
+    }
+
+    match (args.get(0).map(|v| *v), args.get(1).and_then(|v| ButtonEventKind::from_name(v))) {
+        (Some("list"), _) | (None, _) => {
+            object_with!(BUTTON_SERVICE_OBJECT_NAME, ButtonService, service, {
+                for binding in service.bindings() {
+                    println!("  {} {}: {}", binding.button, binding.kind.name(), binding.command);
+                }
+            });
+        }
+        (Some(button), Some(kind)) if args.len() > 2 => {
+            let mut cmd: heapless::String<MAX_COMMAND_SIZE> = heapless::String::new();
+
+            for (i, arg) in args[2..].iter().enumerate() {
+                if i > 0 && cmd.push(' ').is_err() || cmd.push_str(arg).is_err() {
+                    error!("Command is too long");
+                    return 1;
+                }
+            }
+
+            let cmd = cmd.trim_matches('"');
+
+            if let Err(err) = object_with_mut!(BUTTON_SERVICE_OBJECT_NAME, ButtonService, service, service.bind(button, kind, cmd)) {
+                error!("{}: {:?}", button, err);
+                return 1;
             }
         }
-        Some("recv") => {
-            let ms = args.get(1).map_or("1000", |v| v).parse().unwrap_or(1000);
+        _ => {
+            help();
+            return 1;
+        }
+    }
 
-            loop {
-                object_with_mut!("radio", Radio, radio, {
-                    match radio.recv(rtrs::time::Timeout::new(ms)) {
-                        Ok((bytes, size)) => {
-                            print!("[{}] ", size);
-                            for i in 0..size {
-                                print!("{:x} ", bytes[i]);
-                            }
-                            println!();
-                            break;
-                        }
-                        Err(RadioError::InProgress) => {}
-                        Err(err) => {
-                            error!("Error: {:?}", err);
-                            break;
-                        }
+    0
+}
+
+fn cmd_unbind(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    match (args.get(0), args.get(1).and_then(|v| ButtonEventKind::from_name(v))) {
+        (Some(button), Some(kind)) => {
+            object_with_mut!(BUTTON_SERVICE_OBJECT_NAME, ButtonService, service, service.unbind(button, kind));
+        }
+        _ => {
+            error!("Usage: unbind BTN press|release|long|double");
+            return 1;
+        }
+    }
+
+    0
+}
+
+fn cmd_pulse(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: pulse status|start|stop|bpm|ibi|hrv|window");
+        error!(" pulse start [HZ] - Start sampling (default {} Hz)", heart_rate::DEFAULT_SAMPLE_RATE);
+        error!(" pulse stop       - Stop sampling");
+        error!(" pulse bpm        - Show heart rate and signal quality");
+        error!(" pulse ibi        - Show recent inter-beat intervals");
+        error!(" pulse hrv        - Show heart rate variability metrics");
+        error!(" pulse window [N] - Show or set number of intervals used for HRV");
+    }
+
+    match args.get(0).map(|v| *v) {
+        Some("status") | None => {
+            object_with!(HEART_RATE_OBJECT_NAME, HeartRate, hr, {
+                println!("running: {}, rate: {} Hz, dropped: {}", hr.is_running(), hr.rate(), hr.dropped_samples());
+                println!("signal: {}, quality: {:?}", hr.detector().filtered(), hr.detector().quality());
+            });
+        }
+        Some("start") => {
+            let rate = match args.get(1) {
+                Some(arg) => match arg.parse::<u32>() {
+                    Ok(rate) if (10..=1000).contains(&rate) => rate,
+                    _ => {
+                        error!("Invalid rate (10..=1000 Hz)");
+                        return 1;
                     }
-                });
+                },
+                None => heart_rate::DEFAULT_SAMPLE_RATE,
+            };
+
+            if !heart_rate::has_sensor() {
+                error!("No pulse sensor");
+                return 1;
+            }
+
+            object_with_mut!(HEART_RATE_OBJECT_NAME, HeartRate, hr, hr.start(rate));
+        }
+        Some("stop") => {
+            object_with_mut!(HEART_RATE_OBJECT_NAME, HeartRate, hr, hr.stop());
+        }
+        Some("bpm") => {
+            object_with!(HEART_RATE_OBJECT_NAME, HeartRate, hr, {
+                let detector = hr.detector();
+
+                match detector.bpm() {
+                    Some(bpm) => println!("BPM: {}, IBI: {} ms, quality: {:?}", bpm, detector.last_ibi().unwrap_or(0), detector.quality()),
+                    None      => println!("BPM: -, quality: {:?}", detector.quality()),
+                }
+            });
+        }
+        Some("ibi") => {
+            object_with!(HEART_RATE_OBJECT_NAME, HeartRate, hr, {
+                for ibi in hr.history().ibis() {
+                    print!("{} ", ibi);
+                }
+                println!();
+            });
+        }
+        Some("hrv") => {
+            match object_with!(HEART_RATE_OBJECT_NAME, HeartRate, hr, hr.history().metrics()) {
+                Some(hrv) => {
+                    println!("intervals: {}, mean IBI: {} ms", hrv.count, hrv.mean_ibi);
+                    println!("SDNN: {} ms, RMSSD: {} ms, pNN50: {}.{}%", hrv.sdnn, hrv.rmssd, hrv.pnn50 / 10, hrv.pnn50 % 10);
+                    println!("artifacts: {} missed, {} extra", hrv.missed, hrv.extra);
+                }
+                None => println!("Not enough intervals"),
+            }
+        }
+        Some("window") => {
+            match args.get(1).map(|v| v.parse::<usize>()) {
+                Some(Ok(window)) => {
+                    object_with_mut!(HEART_RATE_OBJECT_NAME, HeartRate, hr, hr.history_mut().set_window(window));
+                }
+                Some(Err(_)) => {
+                    help();
+                    return 1;
+                }
+                None => {
+                    println!("{}", object_with!(HEART_RATE_OBJECT_NAME, HeartRate, hr, hr.history().window()));
+                }
             }
         }
         _ => {
@@ -407,6 +941,1244 @@ fn cmd_radio(_rt: &mut Runtime, args: &[&str]) -> i8 {
     0
 }
 
+fn adc_read(adc: &mut Adc, name: &str) -> Result<(u16, u32), AdcError> {
+    adc.vdda_mv()?;
+    let raw = adc.read_raw(name)?;
+    Ok((raw, adc.to_mv(raw)))
+}
+
+async fn adc_stream(name: heapless::String<MAX_COMMAND_SIZE>, period: u32) {
+    while console_job() {
+        let start = rtrs::time::global_tick();
+
+        let res = object_with_mut!(ADC_OBJECT_NAME, Adc, adc, adc_read(adc, &name)).map(|(raw, mv)| {
+            println!("[{}] {} {}", start, raw, mv);
+        });
+
+        if let Err(err) = res {
+            error!("Error: {:?}", err);
+            break;
+        }
+
+        idle::sleep(period).await;
+    }
+
+    stop_console_job();
+}
+
+fn cmd_adc(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: adc read|scan|stream|oversample ...");
+        error!(" adc read CHANNEL               - Read channel in raw counts and mV");
+        error!(" adc scan                       - Read all channels, VDDA and temperature");
+        error!(" adc stream CHANNEL [PERIOD_MS] - Print readings until a key is pressed");
+        error!(" adc oversample [RATIO]         - Show or set oversampling ratio (1..256)");
+    }
+
+    if !crate::object_exists(ADC_OBJECT_NAME) {
+        error!("ADC is not available");
+        return 1;
+    }
+
+    let res = match (args.get(0).map(|v| *v), args.get(1).map(|v| *v)) {
+        (Some("read"), Some(name)) => {
+            object_with_mut!(ADC_OBJECT_NAME, Adc, adc, adc_read(adc, name)).map(|(raw, mv)| {
+                println!("{}: {} ({} mV)", name, raw, mv);
+            })
+        }
+        (Some("scan"), None) => {
+            object_with_mut!(ADC_OBJECT_NAME, Adc, adc, {
+                for i in 0..adc.channels().len() {
+                    let ch = adc.channels()[i];
+
+                    match adc_read(adc, ch.name) {
+                        Ok((raw, mv)) => println!("{:<12} ch{:<2} {:>4} {:>5} mV", ch.name, ch.channel, raw, mv),
+                        Err(err)      => println!("{:<12} ch{:<2} {:?}", ch.name, ch.channel, err),
+                    }
+                }
+
+                adc.vdda_mv().and_then(|vdda| {
+                    let temp = adc.temperature()?;
+                    let sign = if temp < 0 { "-" } else { "" };
+                    println!("VDDA: {} mV, temperature: {}{}.{} C", vdda, sign, temp.abs() / 10, temp.abs() % 10);
+                    Ok(())
+                })
+            })
+        }
+        (Some("stream"), Some(name)) => {
+            let period = args.get(2).and_then(|v| parse_u32(v)).unwrap_or(100);
+
+            // Checked once up front, so a bad channel is reported by the command itself
+            object_with_mut!(ADC_OBJECT_NAME, Adc, adc, adc_read(adc, name)).and_then(|_| {
+                let mut channel = heapless::String::new();
+                channel.push_str(name).map_err(|_| AdcError::NoChannel)?;
+
+                info!("Streaming '{}' every {} ms. Press any key to stop", name, period);
+                spawn_console_job(adc_stream(channel, period));
+
+                Ok(())
+            })
+        }
+        (Some("oversample"), None) => {
+            println!("{}", object_with!(ADC_OBJECT_NAME, Adc, adc, adc.oversampling()));
+            Ok(())
+        }
+        (Some("oversample"), Some(ratio)) => {
+            match ratio.parse::<u16>() {
+                Ok(ratio) => object_with_mut!(ADC_OBJECT_NAME, Adc, adc, adc.set_oversampling(ratio)),
+                Err(_) => Err(AdcError::InvalidOversampling),
+            }
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    };
+
+    if let Err(err) = res {
+        error!("Error: {:?}", err);
+        return 1;
+    }
+
+    0
+}
+
+fn cmd_clock(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    if !crate::object_exists(CLOCK_OBJECT_NAME) {
+        error!("Clock switching is not available");
+        return 1;
+    }
+
+    match args.get(0).map(|v| *v) {
+        None => {
+            object_with!(CLOCK_OBJECT_NAME, Clock, c, {
+                println!("profile: {}, SYSCLK: {} Hz, switches: {}", c.profile().name(), c.sysclk(), c.switches());
+            });
+
+            print!("listeners:");
+            for (name, _) in clock::listeners() {
+                print!(" {}", name);
+            }
+            println!();
+        }
+        Some("list") => {
+            for profile in ClockProfile::ALL {
+                println!("  {}", profile.name());
+            }
+        }
+        Some(name) => {
+            let Some(profile) = ClockProfile::from_name(name) else {
+                error!("Usage: clock [list|msi|hsi16|pll32]");
+                return 1;
+            };
+
+            match object_with_mut!(CLOCK_OBJECT_NAME, Clock, c, c.set_profile(profile)) {
+                Ok(hz) => info!("SYSCLK: {} Hz", hz),
+                Err(err) => {
+                    error!("Clock switch failed: {:?}", err);
+                    return 1;
+                }
+            }
+        }
+    }
+
+    0
+}
+
+fn cmd_power(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: power [status|measure|reset|thresholds|period|idle] ...");
+        error!(" power measure                 - Measure VDD now");
+        error!(" power reset                   - Reset min/max");
+        error!(" power thresholds LOW BROWNOUT - Set low battery and brownout risk levels in mV");
+        error!(" power period MS               - Set measurement period");
+        error!(" power idle [off|sleep|stop]   - Show idle state or set deepest idle mode");
+    }
+
+    fn mv(value: Option<u32>) -> u32 {
+        value.unwrap_or(0)
+    }
+
+    match args.get(0).map(|v| *v) {
+        Some("status") | None => {
+            object_with!(BATTERY_OBJECT_NAME, Battery, b, {
+                let (low, brownout) = b.thresholds();
+
+                println!("VDD: {} mV (min {} mV, max {} mV), state: {:?}", mv(b.vdd_mv()), mv(b.min_mv()), mv(b.max_mv()), battery::state());
+                println!("low: {} mV, brownout: {} mV, period: {} ms, errors: {}", low, brownout, b.period(), b.errors());
+            });
+        }
+        Some("measure") => {
+            let Some(measurement) = battery::measure() else {
+                error!("ADC is not available");
+                return 1;
+            };
+
+            object_with_mut!(BATTERY_OBJECT_NAME, Battery, b, b.update(measurement));
+
+            match measurement {
+                Ok(vdd) => println!("{} mV", vdd),
+                Err(err) => {
+                    error!("Error: {:?}", err);
+                    return 1;
+                }
+            }
+        }
+        Some("reset") => {
+            object_with_mut!(BATTERY_OBJECT_NAME, Battery, b, b.reset_min_max());
+        }
+        Some("thresholds") => {
+            let (Some(low), Some(brownout)) = (args.get(1).and_then(|v| parse_u32(v)), args.get(2).and_then(|v| parse_u32(v))) else {
+                help();
+                return 1;
+            };
+
+            if object_with_mut!(BATTERY_OBJECT_NAME, Battery, b, b.set_thresholds(low, brownout)).is_err() {
+                error!("Brownout level must be below low battery level");
+                return 1;
+            }
+        }
+        Some("period") => {
+            let Some(period) = args.get(1).and_then(|v| parse_u32(v)) else {
+                help();
+                return 1;
+            };
+
+            object_with_mut!(BATTERY_OBJECT_NAME, Battery, b, b.set_period(period));
+        }
+        Some("idle") => {
+            if let Some(mode) = args.get(1) {
+                let Some(mode) = IdleMode::from_name(mode) else {
+                    help();
+                    return 1;
+                };
+
+                object_with_mut!(IDLE_OBJECT_NAME, Idle, idle, idle.set_mode(mode));
+
+                return 0;
+            }
+
+            object_with!(IDLE_OBJECT_NAME, Idle, idle, {
+                let (sleeps, stops, stopped_ms) = idle.stats();
+
+                println!("mode: {}, allowed: {}", idle.mode().name(), idle.allowed().name());
+                println!("sleeps: {}, stops: {}, time in stop: {} ms", sleeps, stops, stopped_ms);
+            });
+
+            for (name, deepest) in idle::inhibitors() {
+                println!("  inhibitor {}: {}", name, deepest.name());
+            }
+
+            let now = rtrs::time::global_tick();
+
+            for (name, tick) in idle::deadlines() {
+                println!("  deadline {}: in {} ms", name, tick.wrapping_sub(now) as i32);
+            }
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    }
+
+    0
+}
+
+// Radio commands run as background tasks, so the shell stays responsive while they wait
+const LINK_POLL_MS: u32 = 10;
+
+fn report_send_error(err: TransportError) {
+    match err {
+        TransportError::Brownout => error!("Supply voltage is too low to transmit"),
+        TransportError::DutyCycle => error!("Duty cycle budget exhausted, try again later"),
+        TransportError::NotReady => error!("Radio is not initialized, run 'radio init'"),
+        TransportError::TooLarge => error!("Frame too large (max {})", MAX_FRAME_SIZE),
+        TransportError::Radio => error!("TX failed"),
+    }
+}
+
+async fn radio_send_task(frame: heapless::Vec<u8, MAX_FRAME_SIZE>) {
+    match radio::send(&frame).await {
+        Ok(()) => info!("Sent {} bytes", frame.len()),
+        Err(err) => report_send_error(err),
+    }
+}
+
+async fn radio_recv_task(ms: u32) {
+    let mut buf = [0; MAX_FRAME_SIZE];
+
+    match radio::recv(&mut buf, ms).await {
+        Ok((size, info)) => {
+            print!("[{}] ", size);
+            for byte in &buf[..size] {
+                print!("{:x} ", byte);
+            }
+            println!();
+
+            if let Some(info) = info {
+                println!("{}", info);
+            }
+        }
+        Err(err) => error!("Error: {:?}", err),
+    }
+}
+
+async fn link_delivery_task(dst: u8, seq: u8, timeout: u32) {
+    let start = rtrs::time::global_tick();
+
+    // Link always reports a result, unless it was pushed out of the delivery queue
+    while rtrs::time::global_tick().wrapping_sub(start) < timeout {
+        if let Some(delivery) = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.take_delivery(seq)) {
+            match delivery.status {
+                DeliveryStatus::Failed => error!("#{} to {}: {:?}", seq, dst, delivery.status),
+                _ => info!("#{} to {}: {:?}", seq, dst, delivery.status),
+            }
+
+            return;
+        }
+
+        idle::sleep(LINK_POLL_MS).await;
+    }
+
+    error!("#{} to {}: no result", seq, dst);
+}
+
+async fn link_recv_task(ms: u32) {
+    let start = rtrs::time::global_tick();
+
+    while rtrs::time::global_tick().wrapping_sub(start) < ms {
+        if let Some(packet) = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_from()) {
+            print!("[{} -> {} #{}] ", packet.src, packet.dst, packet.seq);
+            for byte in packet.payload() {
+                print!("{:x} ", byte);
+            }
+            println!();
+
+            return;
+        }
+
+        idle::sleep(LINK_POLL_MS).await;
+    }
+
+    error!("Timeout");
+}
+
+async fn mesh_recv_task(ms: u32) {
+    let start = rtrs::time::global_tick();
+
+    while rtrs::time::global_tick().wrapping_sub(start) < ms {
+        if let Some(packet) = mesh::recv() {
+            print!("[{}, {} hops] ", packet.origin, packet.hops);
+            for byte in packet.payload() {
+                print!("{:x} ", byte);
+            }
+            println!();
+
+            return;
+        }
+
+        idle::sleep(LINK_POLL_MS).await;
+    }
+
+    error!("Timeout");
+}
+
+// Only failures in the background task (radio errors, waiting out the duty cycle) are left to the log
+fn radio_spawn_send(data: &[u8]) -> i8 {
+    if let Err(err) = radio::check_send(data.len()) {
+        report_send_error(err);
+        return 1;
+    }
+
+    let Ok(frame) = heapless::Vec::from_slice(data) else {
+        return 1;
+    };
+
+    rtrs::task::this::spawn(Task::new(radio_send_task(frame)));
+
+    0
+}
+
+fn parse_bytes(args: &[&str], buf: &mut [u8]) -> Option<usize> {
+    if args.len() > buf.len() {
+        return None;
+    }
+
+    for (byte, arg) in buf.iter_mut().zip(args) {
+        *byte = arg.parse().ok()?;
+    }
+
+    Some(args.len())
+}
+
+// 32 hex digits
+fn parse_key(arg: &str) -> Option<[u8; aes::KEY_SIZE]> {
+    let mut key = [0; aes::KEY_SIZE];
+
+    if arg.len() != 2 * key.len() || !arg.is_ascii() {
+        return None;
+    }
+
+    for (byte, digits) in key.iter_mut().zip(arg.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+
+    Some(key)
+}
+
+fn cmd_radio(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: radio init|send|recv|telemetry|addr|sendto|recvfrom|link|config|stats|sniff|ping|mesh|key ...");
+        error!(" Transfers run in the background, results are logged");
+        error!(" radio send BYTES...");
+        error!(" radio recv TIMEOUT_MS");
+        error!(" radio telemetry");
+        error!(" radio addr [ADDR]              - Show or set link address");
+        error!(" radio sendto ADDR BYTES...     - Send with ACK and retransmission (255 is broadcast)");
+        error!(" radio recvfrom [TIMEOUT_MS]    - Receive a link frame, ACKs are sent automatically");
+        error!(" radio link [reset|retry MS N]  - Link statistics and retransmission settings");
+        error!(" radio config [PARAM VALUE]     - Show or change modem configuration");
+        error!(" radio config profiles          - List saved profiles ('{}' is loaded at boot)", config::BOOT_PROFILE);
+        error!(" radio config save|load|delete NAME");
+        error!(" radio stats [reset|duty PERMILLE] - Radio counters, airtime and duty cycle budget");
+        error!(" radio sniff [pcap]             - Print every packet until key press, pcap: binary records");
+        error!(" radio ping ADDR [COUNT] [MS]   - Round-trip time, loss and signal in both directions");
+        error!(" radio ping respond [on|off]    - Answer pings from other nodes");
+        error!(" radio mesh [on|off|reset]      - Mesh routing state and statistics");
+        error!(" radio mesh neighbours|routes");
+        error!(" radio mesh beacon MS           - Beacon interval, neighbours expire after 3");
+        error!(" radio mesh send ADDR BYTES...  - Send over multiple hops");
+        error!(" radio mesh recv [TIMEOUT_MS]");
+        error!(" radio key                      - Security state, keys are never shown");
+        error!(" radio key set [ADDR] KEY       - AES-128 key (32 hex digits) for a peer, or the network key");
+        error!(" radio key clear [ADDR]         - Without any key frames go out in clear text");
+        for param in Param::ALL {
+            error!("  {:<8} {}", param.name(), param.range());
+        }
+    }
+
+    let transfer = matches!(args.get(0).map(|v| *v), Some("send" | "recv" | "telemetry" | "sendto" | "recvfrom" | "sniff"))
+        || (args.get(0) == Some(&"mesh") && matches!(args.get(1).map(|v| *v), Some("send" | "recv")));
+
+    if transfer && !radio::ready() {
+        error!("Radio is not initialized, run 'radio init'");
+        return 1;
+    }
+
+    // Sniffer keeps the radio until a key press, transfers would only time out in the background
+    if transfer && args.get(0) != Some(&"sniff") && sniffer::active() {
+        error!("Radio is in use by the sniffer");
+        return 1;
+    }
+
+    match args.get(0).map(|v| *v) {
+        Some("init") => {
+            if let Err(err) = radio::init() {
+                error!("Error: {:?}", err);
+                return 1;
+            }
+        }
+        Some("send") => {
+            let mut buf = [0; MAX_FRAME_SIZE];
+
+            let Some(size) = parse_bytes(&args[1..], &mut buf) else {
+                error!("Invalid bytes (max {})", MAX_FRAME_SIZE);
+                return 1;
+            };
+
+            return radio_spawn_send(&buf[..size]);
+        }
+        Some("addr") => {
+            match args.get(1).map(|v| v.parse::<u8>()) {
+                None => println!("{}", object_with!(LINK_OBJECT_NAME, Link, link, link.addr())),
+                Some(Ok(addr)) => {
+                    match object_with_mut!(LINK_OBJECT_NAME, Link, link, link.set_addr(addr)) {
+                        Ok(()) => {}
+                        Err(LinkError::Secured) => {
+                            error!("Keys are bound to the address, clear them first");
+                            return 1;
+                        }
+                        Err(_) => {
+                            error!("{} is the broadcast address", addr);
+                            return 1;
+                        }
+                    }
+                }
+                Some(Err(_)) => {
+                    help();
+                    return 1;
+                }
+            }
+        }
+        Some("sendto") => {
+            let mut buf = [0; link::MAX_PAYLOAD];
+
+            let dst = args.get(1).and_then(|v| v.parse::<u8>().ok());
+            let size = parse_bytes(args.get(2..).unwrap_or(&[]), &mut buf);
+
+            let (Some(dst), Some(size)) = (dst, size) else {
+                help();
+                return 1;
+            };
+
+            // Link task would only report these as a failed delivery later
+            if let Err(err) = radio::check_send(MAX_FRAME_SIZE) {
+                report_send_error(err);
+                return 1;
+            }
+
+            let sent = object_with_mut!(LINK_OBJECT_NAME, Link, link, {
+                link.send_to(dst, &buf[..size]).map(|seq| (seq, link.retry()))
+            });
+
+            let (seq, (ack_timeout, retries)) = match sent {
+                Ok(sent) => sent,
+                Err(err) => {
+                    error!("Error: {:?}", err);
+                    return 1;
+                }
+            };
+
+            // Twice the sum of linearly growing ACK timeouts, a frame may be queued ahead of this one
+            let attempts = retries as u32 + 1;
+            let timeout = ack_timeout * attempts * (attempts + 1);
+
+            rtrs::task::this::spawn(Task::new(link_delivery_task(dst, seq, timeout)));
+        }
+        Some("recvfrom") => {
+            let ms = args.get(1).and_then(|v| v.parse::<u32>().ok()).unwrap_or(1000);
+
+            rtrs::task::this::spawn(Task::new(link_recv_task(ms)));
+        }
+        Some("link") => {
+            match (args.get(1).map(|v| *v), args.get(2).and_then(|v| v.parse().ok()), args.get(3).and_then(|v| v.parse().ok())) {
+                (None, _, _) => {
+                    object_with!(LINK_OBJECT_NAME, Link, link, {
+                        let (timeout, retries) = link.retry();
+                        println!("addr: {}, ACK timeout: {} ms, retries: {}", link.addr(), timeout, retries);
+                        println!("{:?}", link.stats());
+                    });
+                }
+                (Some("reset"), _, _) => object_with_mut!(LINK_OBJECT_NAME, Link, link, link.reset_stats()),
+                (Some("retry"), Some(timeout), Some(retries)) => {
+                    object_with_mut!(LINK_OBJECT_NAME, Link, link, link.set_retry(timeout, retries));
+                }
+                _ => {
+                    help();
+                    return 1;
+                }
+            }
+        }
+        Some("ping") => {
+            match args.get(1).map(|v| *v) {
+                Some("respond") => match args.get(2).map(|v| *v) {
+                    None => println!("{}", if ping::responder() { "on" } else { "off" }),
+                    Some("on") => ping::set_responder(true),
+                    Some("off") => ping::set_responder(false),
+                    Some(_) => {
+                        help();
+                        return 1;
+                    }
+                },
+                Some(dst) => {
+                    let dst = dst.parse::<u8>().ok();
+                    let count = args.get(2).map_or(Some(ping::DEFAULT_COUNT), |v| v.parse().ok());
+                    let interval = args.get(3).map_or(Some(ping::DEFAULT_INTERVAL_MS), |v| v.parse().ok());
+
+                    let (Some(dst), Some(count), Some(interval)) = (dst, count, interval) else {
+                        help();
+                        return 1;
+                    };
+
+                    if !radio::ready() {
+                        error!("Radio is not initialized, run 'radio init'");
+                        return 1;
+                    }
+
+                    if sniffer::active() {
+                        error!("Radio is in use by the sniffer");
+                        return 1;
+                    }
+
+                    if !ping::start() {
+                        error!("Ping is already running");
+                        return 1;
+                    }
+
+                    rtrs::task::this::spawn(Task::new(ping::run(dst, count, interval)));
+                }
+                None => {
+                    help();
+                    return 1;
+                }
+            }
+        }
+        Some("mesh") => {
+            match args.get(1).map(|v| *v) {
+                None => {
+                    println!(
+                        "{}, beacon interval: {} ms, neighbours: {}, routes: {}",
+                        if mesh::enabled() { "on" } else { "off" }, mesh::beacon_interval(), mesh::neighbours().len(), mesh::routes().len()
+                    );
+                    println!("{:?}", mesh::stats());
+                }
+                Some("on") => mesh::set_enabled(true),
+                Some("off") => mesh::set_enabled(false),
+                Some("reset") => mesh::reset_stats(),
+                Some("neighbours") => {
+                    let now = rtrs::time::global_tick();
+
+                    println!("addr quality cost  heard");
+                    for neighbour in mesh::neighbours() {
+                        print!("{:>4} {:>7} {:>4} {:>5} s", neighbour.addr, neighbour.quality, neighbour.cost(), now.wrapping_sub(neighbour.last_heard) / 1000);
+
+                        match neighbour.info {
+                            Some(info) => println!(", {}", info),
+                            None => println!(),
+                        }
+                    }
+                }
+                Some("routes") => {
+                    let now = rtrs::time::global_tick();
+
+                    println!(" dst  via metric hops   age");
+                    for route in mesh::routes() {
+                        println!(
+                            "{:>4} {:>4} {:>6} {:>4} {:>5} s",
+                            route.dst, route.next_hop, route.metric, route.hops, now.wrapping_sub(route.updated) / 1000
+                        );
+                    }
+                }
+                Some("beacon") => {
+                    let Some(interval) = args.get(2).and_then(|v| parse_u32(v)).filter(|ms| *ms > 0) else {
+                        help();
+                        return 1;
+                    };
+
+                    mesh::set_beacon_interval(interval);
+                }
+                Some("send") => {
+                    let mut buf = [0; mesh::MAX_PAYLOAD];
+
+                    let dst = args.get(2).and_then(|v| v.parse::<u8>().ok());
+                    let size = parse_bytes(args.get(3..).unwrap_or(&[]), &mut buf);
+
+                    let (Some(dst), Some(size)) = (dst, size) else {
+                        help();
+                        return 1;
+                    };
+
+                    if let Err(err) = mesh::send(dst, &buf[..size]) {
+                        error!("Error: {:?}", err);
+                        return 1;
+                    }
+                }
+                Some("recv") => {
+                    let ms = args.get(2).and_then(|v| v.parse::<u32>().ok()).unwrap_or(1000);
+
+                    rtrs::task::this::spawn(Task::new(mesh_recv_task(ms)));
+                }
+                Some(_) => {
+                    help();
+                    return 1;
+                }
+            }
+        }
+        Some("key") => {
+            let peer = |arg: Option<&&str>| match arg {
+                None => Some(link::BROADCAST),
+                Some(addr) => addr.parse::<u8>().ok(),
+            };
+
+            let res = match (args.get(1).map(|v| *v), args.len()) {
+                (None, _) => {
+                    object_with!(LINK_OBJECT_NAME, Link, link, {
+                        let security = link.security();
+                        let (counter, limit) = security.counter();
+                        let stats = link.stats();
+
+                        match security.enabled() {
+                            true => println!("on, frame counter: {}, reserved up to {}", counter, limit),
+                            false => println!("off, frames are sent in clear text"),
+                        }
+
+                        for peer in security.peers() {
+                            match peer {
+                                link::BROADCAST => println!("  network key"),
+                                peer => println!("  key for {}", peer),
+                            }
+                        }
+
+                        println!(
+                            "auth errors: {}, replays: {}, clear text dropped: {}, seal errors: {}",
+                            stats.auth_errors, stats.replays, stats.insecure, stats.seal_errors
+                        );
+                    });
+
+                    Ok(())
+                }
+                (Some("set"), 3 | 4) => {
+                    let addr = if args.len() == 4 { args.get(2) } else { None };
+
+                    let (Some(peer), Some(key)) = (peer(addr), args.last().and_then(|v| parse_key(v))) else {
+                        help();
+                        return 1;
+                    };
+
+                    secure::set_key(peer, &key)
+                }
+                (Some("clear"), 2 | 3) => {
+                    let Some(peer) = peer(args.get(2)) else {
+                        help();
+                        return 1;
+                    };
+
+                    secure::clear_key(peer)
+                }
+                _ => {
+                    help();
+                    return 1;
+                }
+            };
+
+            match res {
+                Ok(()) => {}
+                Err(secure::SecurityError::DefaultAddress) => {
+                    error!("Node address isn't set, see radio addr");
+                    return 1;
+                }
+                Err(secure::SecurityError::AddressChanged) => {
+                    error!("Keys were set for another address, clear them first");
+                    return 1;
+                }
+                Err(err) => {
+                    error!("Error: {:?}", err);
+                    return 1;
+                }
+            }
+        }
+        Some("sniff") => {
+            let format = match args.get(1).map(|v| *v) {
+                None => SniffFormat::Text,
+                Some("pcap") => SniffFormat::Pcap,
+                Some(_) => {
+                    help();
+                    return 1;
+                }
+            };
+
+            if !sniffer::start() {
+                error!("Sniffer is already running");
+                return 1;
+            }
+
+            info!("Sniffing. Press any key to stop");
+
+            rtrs::task::this::spawn(Task::new(sniffer::task(format)));
+        }
+        Some("stats") => {
+            match (args.get(1).map(|v| *v), args.get(2).and_then(|v| parse_u32(v))) {
+                (None, _) => {
+                    let stats = radio::stats();
+                    let (permille, available_us) = radio::duty_cycle();
+                    let config = config::current();
+
+                    println!(
+                        "tx: {}, rx: {}, CRC errors: {}, timeouts: {}, TX errors: {}",
+                        stats.tx, stats.rx, stats.crc_errors, stats.timeouts, stats.tx_errors
+                    );
+                    println!("airtime: {} ms", stats.airtime_us / 1000);
+                    println!(
+                        "duty cycle: {}.{} %, available: {} ms, delayed: {}, refused: {}",
+                        permille / 10, permille % 10, available_us / 1000, stats.duty_cycle_delays, stats.duty_cycle_refusals
+                    );
+                    println!(
+                        "time on air: {} us (empty), {} us ({} bytes)",
+                        airtime::time_on_air_us(&config, 0), airtime::time_on_air_us(&config, MAX_FRAME_SIZE), MAX_FRAME_SIZE
+                    );
+
+                    match stats.last {
+                        Some(info) => println!("last packet: {}", info),
+                        None => println!("last packet: -"),
+                    }
+                }
+                (Some("reset"), _) => radio::reset_stats(),
+                (Some("duty"), Some(permille)) if (1..=airtime::MAX_DUTY_CYCLE_PERMILLE).contains(&permille) => {
+                    radio::set_duty_cycle(permille);
+                }
+                _ => {
+                    help();
+                    return 1;
+                }
+            }
+        }
+        Some("config") => {
+            let name = args.get(2).map(|v| *v);
+
+            let res = match (args.get(1).map(|v| *v), name) {
+                (None, _) => {
+                    println!("{}", config::current());
+                    Ok(())
+                }
+                (Some("profiles"), None) => config::profiles().map(|profiles| {
+                    for (name, config) in profiles {
+                        println!("  {:<8} {}", name, config);
+                    }
+                }),
+                (Some("save"), Some(name)) => config::save_profile(name, &config::current()),
+                (Some("load"), Some(name)) => config::load_profile(name).map(config::set_current),
+                (Some("delete"), Some(name)) => config::delete_profile(name),
+                (Some(param), Some(value)) => match Param::from_name(param) {
+                    Some(param) => {
+                        let mut config = config::current();
+                        config.set(param, value).map(|_| config::set_current(config))
+                    }
+                    None => {
+                        help();
+                        return 1;
+                    }
+                },
+                _ => {
+                    help();
+                    return 1;
+                }
+            };
+
+            if let Err(err) = res {
+                error!("Error: {:?}", err);
+                return 1;
+            }
+        }
+        Some("telemetry") => {
+            let mut buf = [0; crate::telemetry::MAX_ENCODED_SIZE];
+
+            let Some(size) = crate::telemetry::Telemetry::collect().encode(&mut buf) else {
+                error!("Failed to encode telemetry");
+                return 1;
+            };
+
+            return radio_spawn_send(&buf[..size]);
+        }
+        Some("recv") => {
+            let ms = args.get(1).map_or("1000", |v| v).parse().unwrap_or(1000);
+
+            rtrs::task::this::spawn(Task::new(radio_recv_task(ms)));
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    }
+
+    0
+}
+
+fn cmd_spi(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: spi xfer|reg|dump DEV ...");
+        error!(" spi xfer DEV BYTES...  - Full-duplex transfer of hex bytes");
+        error!(" spi reg DEV ADDR [VAL] - Read or write a register");
+        error!(" spi dump DEV ADDR LEN  - Dump a register range");
+    }
+
+    const MAX_XFER_SIZE: usize = 16;
+    const MAX_DUMP_SIZE: usize = 128;
+
+    let Some(name) = args.get(1) else {
+        help();
+        return 1;
+    };
+
+    let dev = match spi::find(name) {
+        Ok(dev) => dev,
+        Err(DeviceError::NotFound) => {
+            error!("No such object: '{}'", name);
+            return 1;
+        }
+        Err(DeviceError::NotSpi) => {
+            error!("'{}' is not an SPI device", name);
+            return 1;
+        }
+    };
+
+    match args.get(0).map(|v| *v) {
+        Some("xfer") => {
+            let mut buf = [0u8; MAX_XFER_SIZE];
+            let mut size = 0;
+
+            for arg in args[2..].iter() {
+                if size == MAX_XFER_SIZE {
+                    error!("Too many bytes (max {})", MAX_XFER_SIZE);
+                    return 1;
+                }
+
+                match parse_hex_u8(arg) {
+                    Some(byte) => buf[size] = byte,
+                    None => {
+                        error!("Invalid byte: '{}'", arg);
+                        return 1;
+                    }
+                }
+
+                size += 1;
+            }
+
+            match object_with_mut!(dev, SpiDevice, spi, spi.transfer(&mut buf[..size])) {
+                Ok(()) => {
+                    for byte in buf[..size].iter() {
+                        print!("{:02x} ", byte);
+                    }
+                    println!();
+                }
+                Err(err) => {
+                    error!("Transfer failed: {:?}", err);
+                    return 1;
+                }
+            }
+        }
+        Some("reg") => {
+            let Some(reg) = args.get(2).and_then(|v| parse_hex_u8(v)) else {
+                help();
+                return 1;
+            };
+
+            let res = match args.get(3) {
+                Some(arg) => {
+                    let Some(value) = parse_hex_u8(arg) else {
+                        error!("Invalid value: '{}'", arg);
+                        return 1;
+                    };
+
+                    object_with_mut!(dev, SpiDevice, spi, spi.write_reg(reg, value))
+                }
+                None => {
+                    object_with_mut!(dev, SpiDevice, spi, spi.read_reg(reg))
+                        .map(|value| println!("0x{:02x}: 0x{:02x}", reg, value))
+                }
+            };
+
+            if let Err(err) = res {
+                error!("Register access failed: {:?}", err);
+                return 1;
+            }
+        }
+        Some("dump") => {
+            let reg = args.get(2).and_then(|v| parse_hex_u8(v));
+            let len = args.get(3).and_then(|v| parse_u32(v)).map(|v| v as usize);
+
+            let (Some(reg), Some(len)) = (reg, len) else {
+                help();
+                return 1;
+            };
+
+            if len == 0 || len > MAX_DUMP_SIZE {
+                error!("Invalid length (1..={})", MAX_DUMP_SIZE);
+                return 1;
+            }
+
+            let mut buf = [0u8; MAX_DUMP_SIZE];
+
+            match object_with_mut!(dev, SpiDevice, spi, spi.read_regs(reg, &mut buf[..len])) {
+                Ok(()) => print!("{}", rtrs::util::Hexdump::from(&buf[..len]).default_color()),
+                Err(err) => {
+                    error!("Register access failed: {:?}", err);
+                    return 1;
+                }
+            }
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    }
+
+    0
+}
+
+fn cmd_pattern(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: pattern status|start|queue|stop ...");
+        error!(" pattern status                      - Show active and queued patterns");
+        error!(" pattern start PIN NAME [PRIO] [MS]  - Play now, unless a higher priority timed one is active");
+        error!(" pattern queue PIN NAME [PRIO] [MS]  - Play after current and queued ones");
+        error!(" pattern stop PIN|all                - Stop and clear queue");
+        print!("Patterns: ");
+        for pattern in crate::patterns::PATTERNS.iter() {
+            print!("{} ", pattern.name);
+        }
+        println!();
+    }
+
+    fn entry(args: &[&str]) -> Option<pattern_player::Entry> {
+        let pattern = crate::patterns::find(args.get(0)?)?;
+
+        let priority = match args.get(1) {
+            Some(arg) => arg.parse().ok()?,
+            None      => pattern_player::DEFAULT_PRIORITY,
+        };
+
+        let duration = match args.get(2) {
+            Some(arg) => Some(arg.parse().ok()?),
+            None      => None,
+        };
+
+        Some(pattern_player::Entry { pattern, priority, duration })
+    }
+
+    let res = match (args.get(0).map(|v| *v), args.get(1).map(|v| *v)) {
+        (Some("status"), _) | (None, _) => {
+            object_with!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer, player, {
+                player.for_each(|output, active, queue| {
+                    match active {
+                        Some(entry) => print!("  {}: {} (prio {})", output, entry.pattern.name, entry.priority),
+                        None        => print!("  {}: idle", output),
+                    }
+
+                    for entry in queue {
+                        print!(", {} (prio {})", entry.pattern.name, entry.priority);
+                    }
+
+                    println!();
+                });
+            });
+            Ok(())
+        }
+        (Some("start"), Some(pin)) | (Some("queue"), Some(pin)) => {
+            let Some(entry) = entry(&args[2..]) else {
+                help();
+                return 1;
+            };
+
+            object_with_mut!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer, player, {
+                if args[0] == "start" {
+                    player.start(pin, entry)
+                } else {
+                    player.queue(pin, entry)
+                }
+            })
+        }
+        (Some("stop"), Some("all")) => {
+            object_with_mut!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer, player, player.stop_all());
+            Ok(())
+        }
+        (Some("stop"), Some(pin)) => {
+            object_with_mut!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer, player, player.stop(pin))
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    };
+
+    if let Err(err) = res {
+        error!("{:?}", err);
+        return 1;
+    }
+
+    0
+}
+
+fn cmd_remote(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: remote NODE CMD...");
+        error!(" Runs CMD on a neighbour and prints its console output");
+        error!(" Both nodes need a key for each other ('radio key set ADDR KEY'), the network key isn't enough");
+        error!(" Output of background tasks the command starts isn't sent back");
+        error!(" Commands that own the console (sniff, ota load, gpio watch, ...) only run locally");
+    }
+
+    let (Some(dst), true) = (args.get(0).and_then(|v| v.parse::<u8>().ok()), args.len() > 1) else {
+        help();
+        return 1;
+    };
+
+    let mut command = heapless::String::<{ remote::MAX_COMMAND }>::new();
+
+    for (i, arg) in args[1..].iter().enumerate() {
+        if (i > 0 && command.push(' ').is_err()) || command.push_str(arg).is_err() {
+            error!("Command too long (max {})", remote::MAX_COMMAND);
+            return 1;
+        }
+    }
+
+    if !remote::runs_remotely(&command) {
+        error!("Command owns the console, it only runs locally");
+        return 1;
+    }
+
+    if !radio::ready() {
+        error!("Radio is not initialized, run 'radio init'");
+        return 1;
+    }
+
+    if sniffer::active() {
+        error!("Radio is in use by the sniffer");
+        return 1;
+    }
+
+    if !object_with!(LINK_OBJECT_NAME, Link, link, link.security().has_peer_key(dst)) {
+        error!("No key for {}, see 'radio key set'", dst);
+        return 1;
+    }
+
+    if !remote::start() {
+        error!("Remote command is already running");
+        return 1;
+    }
+
+    rtrs::task::this::spawn(Task::new(remote::run(dst, command)));
+
+    0
+}
+
+fn cmd_ota(_rt: &mut Runtime, args: &[&str]) -> i8 {
+    fn help() {
+        error!("Usage: ota [status|send|load|activate|clear] ...");
+        error!(" ota status                 - Staged image and transfer progress");
+        error!(" ota send NODE [activate]   - Send the staged image to a neighbour, which installs it if asked to");
+        error!(" ota load                   - Receive an image over the console, see scripts/ota/ota_load.py");
+        error!(" ota activate               - Reset into the staged image, the running one is staged instead");
+        error!(" ota clear                  - Forget the staged image");
+        error!(" Sending resumes where an interrupted transfer stopped, both nodes need a key for each other");
+    }
+
+    if !ota::available() {
+        error!("No staging area for firmware images on this board");
+        return 1;
+    }
+
+    match args.get(0).map(|v| *v) {
+        Some("status") | None => {
+            let (address, size, (erases, writes)) = object_with!(FLASH_OBJECT_NAME, Flash, flash, (flash.address(), flash.size(), flash.stats()));
+            let progress = ota::progress();
+
+            println!("Staging area: {} KB at 0x{:08x}, {} page erases and {} writes since boot", size / 1024, address, erases, writes);
+            println!("State: {:?}", progress.state);
+
+            if let Some(header) = progress.header {
+                print!("Image: v{}, {} bytes, SHA-256 ", header.version, header.size);
+                for byte in header.digest {
+                    print!("{:02x}", byte);
+                }
+                println!();
+
+                println!("Received: {}/{} ({}%)", progress.received, header.size, progress.received as u64 * 100 / header.size as u64);
+            }
+        }
+        Some("send") => {
+            let (Some(dst), activate) = (args.get(1).and_then(|v| v.parse::<u8>().ok()), args.get(2).map(|v| *v)) else {
+                help();
+                return 1;
+            };
+
+            let activate = match activate {
+                None => false,
+                Some("activate") => true,
+                Some(_) => {
+                    help();
+                    return 1;
+                }
+            };
+
+            if ota::progress().state != ota::State::Verified {
+                error!("No verified image staged, see 'ota load'");
+                return 1;
+            }
+
+            if !radio::ready() {
+                error!("Radio is not initialized, run 'radio init'");
+                return 1;
+            }
+
+            if sniffer::active() {
+                error!("Radio is in use by the sniffer");
+                return 1;
+            }
+
+            if !object_with!(LINK_OBJECT_NAME, Link, link, link.security().has_peer_key(dst)) {
+                error!("No key for {}, see 'radio key set'", dst);
+                return 1;
+            }
+
+            if !ota::start_send() {
+                error!("Image is already being sent");
+                return 1;
+            }
+
+            rtrs::task::this::spawn(Task::new(ota::send(dst, activate)));
+        }
+        Some("load") => {
+            if !ota::start_load() {
+                error!("Upload is already running");
+                return 1;
+            }
+
+            println!("Waiting for the image, console is back once it's complete or after 30 s of silence");
+        }
+        Some("activate") => {
+            if let Err(err) = ota::activate() {
+                error!("Error: {:?}", err);
+                return 1;
+            }
+        }
+        Some("clear") => {
+            if let Err(err) = ota::clear() {
+                error!("Error: {:?}", err);
+                return 1;
+            }
+        }
+        _ => {
+            help();
+            return 1;
+        }
+    }
+
+    0
+}
+
+pub async fn shell_task(mut shell: rtrs::shell::Shell) {
+    loop {
+        // Sniffer, pin watch and console jobs own the console, any key stops them
+        if sniffer::active() || gpio::watching() || console_job() {
+            if object_with_mut!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty, tty, tty.read()).is_some() {
+                sniffer::stop();
+                gpio::stop_watch();
+                stop_console_job();
+            }
+
+            task_yield!();
+            continue;
+        }
+
+        // So does an image upload from the host
+        if ota::loading() {
+            ota::load_poll().await;
+
+            task_yield!();
+            continue;
+        }
+
+        shell.cycle();
+
+        // Lock is released before running, so the command itself can schedule more
+        let cmd = COMMAND_QUEUE.lock_mut().pop_front();
+
+        if let Some(cmd) = cmd {
+            trace!("Running scheduled: '{}'", cmd);
+            shell.run(&cmd);
+        }
+
+        // Everything the command prints goes back to the requester too
+        if let Some(cmd) = remote::take_command() {
+            remote::start_capture();
+            shell.run(&cmd);
+            remote::stop_capture();
+        }
+
+        task_yield!();
+    }
+}
+
 pub fn create_shell() -> rtrs::shell::Shell {
     shell!(
         // Builtin commands
@@ -422,8 +2194,24 @@ pub fn create_shell() -> rtrs::shell::Shell {
         command!("mem",     "Memory control",   cmd_mem),
         command!("log",     "Logging control",  cmd_log),
         command!("time",    "Get tick",         cmd_time),
+        command!("date",    "Calendar time",    cmd_date),
         command!("led",     "Control led",      cmd_led),
+        command!("gpio",    "Pin control",      cmd_gpio),
+        command!("pattern", "Pattern player",   cmd_pattern),
         command!("buzz",    "Control buzzer",   cmd_buzz),
+        command!("bench",   "Benchmarks",       cmd_bench),
+        command!("tone",    "Play a tone",      cmd_tone),
+        command!("melody",  "Melody player",    cmd_melody),
+        command!("button",  "Button service",   cmd_button),
+        command!("bind",    "Bind btn command", cmd_bind),
+        command!("unbind",  "Remove binding",   cmd_unbind),
+        command!("pulse",   "Heart rate",       cmd_pulse),
+        command!("adc",     "Analog inputs",    cmd_adc),
+        command!("power",   "Supply voltage",   cmd_power),
+        command!("clock",   "Clock profiles",   cmd_clock),
         command!("radio",   "Radio control",    cmd_radio),
+        command!("spi",     "Raw SPI access",   cmd_spi),
+        command!("remote",  "Remote shell",     cmd_remote),
+        command!("ota",     "Firmware update",  cmd_ota),
     )
 }
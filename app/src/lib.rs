@@ -1,9 +1,15 @@
-#![no_std]
-#![no_main]
+// Unit tests run on the host with std, its allocator and panic handler
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
 mod cmd;
 mod logs;
 mod tests;
+mod patterns;
+mod telemetry;
+pub mod net;
+pub mod time;
+pub mod services;
 pub mod board;
 pub mod peripherals;
 
@@ -13,21 +19,35 @@ pub(crate) use tests::*;
 use crate::cmd::create_shell;
 
 use core::fmt::Write; // For println!
+#[cfg(not(test))]
 use core::sync::atomic::{self, Ordering};
+#[cfg(not(test))]
 use core::panic::PanicInfo;
 
+#[cfg(not(test))]
 use rtrs::log::console::CONSOLE_OBJECT_NAME;
 use rtrs::object::STORAGE;
 use rtrs::task; // For task_yield!
-use rtrs::{heap_allocator, println, colored};
+use rtrs::task::Task;
+use rtrs::task::sched::Scheduler;
+use rtrs::{println, colored};
+#[cfg(not(test))]
+use rtrs::heap_allocator;
 
+#[cfg(not(test))]
 heap_allocator!(global, pub GLOBAL_HEAP, 2048);
 
 const AUTORUN: Option<&str> = option_env!("AUTORUN");
 
+// Optional objects depend on the target (and on whether their driver initialized)
+pub fn object_exists(name: &str) -> bool {
+    STORAGE.lock().keys().any(|key| key == &name)
+}
+
 pub fn main() -> ! {
     board::BoardInterface::register_callback(board::CallbackType::Systick(|| {
         SYSTICK_EVENT.trigger();
+        peripherals::exti::on_tick(rtrs::time::global_tick());
     }));
 
     logs::init_logs();
@@ -50,20 +70,49 @@ pub fn main() -> ! {
         colored!(rtrs::ANSI_TEXT_BOLD, env!("BUILD_COMPILER"))
     );
 
-    let mut shell = create_shell();
+    match services::battery::measure() {
+        Some(Ok(vdd)) => println!("Supply: {}{} mV{}\r\n", rtrs::ANSI_TEXT_BOLD, vdd, rtrs::ANSI_TEXT_RESET),
+        Some(Err(err)) => println!("Supply: {:?}\r\n", err),
+        None => {}
+    }
+
+    net::link::init();
+    net::config::init();
+    net::secure::init();
+    net::ota::init();
+
+    let shell = create_shell();
 
+    // Runs from the shell task, so it can spawn tasks of its own
     if let Some(cmd) = AUTORUN {
         println!("Running autorun: '{}'", cmd);
-        shell.run(cmd);
+
+        if cmd::schedule(cmd).is_err() {
+            println!("Autorun command is too long");
+        }
     }
 
     println!("Type help for list of commands");
 
-    loop {
-        shell.cycle();
-    }
+    // Shell runs as a task, so commands can spawn background tasks
+    let mut sched = Scheduler::new();
+
+    sched.attach(Task::new(cmd::shell_task(shell)));
+
+    services::init(&mut sched);
+
+    sched.attach(Task::new(net::link::task()));
+    sched.attach(Task::new(net::ping::task()));
+    sched.attach(Task::new(net::mesh::task()));
+    sched.attach(Task::new(net::remote::task()));
+    sched.attach(Task::new(net::ota::task()));
+
+    sched.run_to_completion();
+
+    panic!("Shell task exited");
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     // If panic happened during printing, try to allow console to be accessed, disregarding safety.
@@ -97,6 +146,15 @@ fn panic(info: &PanicInfo) -> ! {
         rtrs::ANSI_TEXT_RESET
     );
 
+    if let Some(time) = peripherals::rtc::timestamp_unlocked() {
+        println!(
+            "{}Time:{}     {}",
+            rtrs::ANSI_COLOR_FG_CYAN,
+            rtrs::ANSI_TEXT_RESET,
+            time
+        );
+    }
+
     if let Some(location) = info.location() {
         println!(
             "{}Location:{} {}{}{}",
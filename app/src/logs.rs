@@ -8,4 +8,8 @@ pub(crate) fn init_logs() {
     rtrs::log::register("shell", Severity::Trace, 255);
     rtrs::log::register("radio", Severity::Trace, 2);
     rtrs::log::register("alloc", Severity::Warn, 0);
+    rtrs::log::register("melody", Severity::Info, 0);
+    rtrs::log::register("button", Severity::Info, 0);
+    rtrs::log::register("battery", Severity::Info, 0);
+    rtrs::log::register("idle", Severity::Info, 0);
 }
@@ -0,0 +1,94 @@
+use crate::net::config::RadioConfig;
+
+// Low data rate optimization is mandated above this symbol duration
+const LDRO_SYMBOL_US: u64 = 16_000;
+
+// ETSI EN 300 220 measures duty cycle over one hour
+const DUTY_CYCLE_WINDOW_MS: u64 = 3_600_000;
+
+// g1 sub-band (868.0 - 868.6 MHz) and 433.05 - 434.79 MHz allow 1 %
+pub const DEFAULT_DUTY_CYCLE_PERMILLE: u32 = 10;
+pub const MAX_DUTY_CYCLE_PERMILLE: u32 = 1000;
+
+pub fn symbol_us(config: &RadioConfig) -> u32 {
+    ((1u64 << config.spreading_factor) * 1_000_000 / config.bandwidth_hz as u64) as u32
+}
+
+// SX1276/77/78/79 datasheet 4.1.1.7, explicit header
+pub fn time_on_air_us(config: &RadioConfig, payload: usize) -> u32 {
+    let symbol = symbol_us(config) as u64;
+
+    let sf = config.spreading_factor as i64;
+    let ldro = if symbol > LDRO_SYMBOL_US { 1 } else { 0 };
+    let crc = if config.crc { 1 } else { 0 };
+
+    let bits = 8 * payload as i64 - 4 * sf + 28 + 16 * crc;
+    let per_block = 4 * (sf - 2 * ldro);
+    let blocks = if bits > 0 { (bits + per_block - 1) / per_block } else { 0 };
+
+    let payload_symbols = 8 + blocks as u64 * config.coding_rate as u64;
+
+    // Preamble is followed by 4.25 symbols of sync word and start frame delimiter
+    let preamble_us = (config.preamble as u64 * 4 + 17) * symbol / 4;
+
+    (preamble_us + payload_symbols * symbol) as u32
+}
+
+// Token bucket: airtime refills at the allowed fraction of elapsed time, up to one window's worth
+pub struct DutyCycle {
+    permille:     u32,
+    available_us: u64,
+    last_tick:    u32,
+}
+
+impl DutyCycle {
+    pub const fn new(permille: u32) -> Self {
+        Self { permille, available_us: DUTY_CYCLE_WINDOW_MS * permille as u64, last_tick: 0 }
+    }
+
+    pub fn permille(&self) -> u32 {
+        self.permille
+    }
+
+    // Budget restarts full, the bands don't care about past transmissions at another rate
+    pub fn set_permille(&mut self, permille: u32) {
+        *self = Self::new(permille.clamp(1, MAX_DUTY_CYCLE_PERMILLE));
+    }
+
+    fn capacity_us(&self) -> u64 {
+        DUTY_CYCLE_WINDOW_MS * self.permille as u64
+    }
+
+    fn refill(&mut self, now: u32) {
+        // 1 ms at 1 permille is 1 us of airtime
+        let elapsed_ms = now.wrapping_sub(self.last_tick) as u64;
+
+        self.available_us = (self.available_us + elapsed_ms * self.permille as u64).min(self.capacity_us());
+        self.last_tick = now;
+    }
+
+    pub fn available_us(&mut self, now: u32) -> u64 {
+        self.refill(now);
+        self.available_us
+    }
+
+    // How long to wait before `airtime_us` can be used, `None` if it never fits the budget
+    pub fn wait_ms(&mut self, now: u32, airtime_us: u32) -> Option<u32> {
+        self.refill(now);
+
+        let airtime_us = airtime_us as u64;
+
+        if airtime_us > self.capacity_us() {
+            return None;
+        }
+
+        let missing = airtime_us.saturating_sub(self.available_us);
+
+        Some(missing.div_ceil(self.permille as u64) as u32)
+    }
+
+    pub fn consume(&mut self, now: u32, airtime_us: u32) {
+        self.refill(now);
+        self.available_us = self.available_us.saturating_sub(airtime_us as u64);
+    }
+}
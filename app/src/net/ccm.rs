@@ -0,0 +1,230 @@
+use crate::net::aes::{Aes128, BLOCK_SIZE, KEY_SIZE};
+
+// Counter with CBC-MAC (RFC 3610, NIST SP 800-38C) over AES-128
+pub const MIN_NONCE_SIZE: usize = 7;
+pub const MAX_NONCE_SIZE: usize = 13;
+
+// Associated data up to this size has a two byte length prefix, that's all we ever need
+const MAX_AAD_SIZE: usize = 0xFEFF;
+
+pub struct Ccm {
+    aes:      Aes128,
+    tag_size: usize,
+}
+
+impl Ccm {
+    // Tag is 4..=16 bytes, even
+    pub fn new(key: &[u8; KEY_SIZE], tag_size: usize) -> Self {
+        debug_assert!((4..=16).contains(&tag_size) && tag_size % 2 == 0);
+
+        Self { aes: Aes128::new(key), tag_size }
+    }
+
+    pub fn tag_size(&self) -> usize {
+        self.tag_size
+    }
+
+    // Encrypts `data` in place and writes the tag. Nonce must never repeat with the same key
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], data: &mut [u8], tag: &mut [u8]) {
+        let mac = self.mac(nonce, aad, data);
+
+        self.ctr(nonce, data);
+
+        let s0 = self.keystream(nonce, 0);
+
+        for i in 0..self.tag_size {
+            tag[i] = mac[i] ^ s0[i];
+        }
+    }
+
+    // Decrypts `data` in place if the tag checks out, otherwise it's zeroed and `false` returned
+    pub fn open(&self, nonce: &[u8], aad: &[u8], data: &mut [u8], tag: &[u8]) -> bool {
+        if tag.len() != self.tag_size {
+            return false;
+        }
+
+        self.ctr(nonce, data);
+
+        let mac = self.mac(nonce, aad, data);
+        let s0 = self.keystream(nonce, 0);
+
+        // Constant time, a forger learns nothing from how long the comparison took
+        let diff = (0..self.tag_size).fold(0, |diff, i| diff | (mac[i] ^ s0[i] ^ tag[i]));
+
+        if diff != 0 {
+            data.fill(0);
+            return false;
+        }
+
+        true
+    }
+
+    // Size of the length field, the rest of the 15 bytes after the flags is nonce
+    fn length_size(nonce: &[u8]) -> usize {
+        debug_assert!((MIN_NONCE_SIZE..=MAX_NONCE_SIZE).contains(&nonce.len()));
+
+        15 - nonce.len()
+    }
+
+    // Counter block i: flags, nonce, i (big endian)
+    fn keystream(&self, nonce: &[u8], i: usize) -> [u8; BLOCK_SIZE] {
+        let l = Self::length_size(nonce);
+        let mut block = [0; BLOCK_SIZE];
+
+        block[0] = (l - 1) as u8;
+        block[1..1 + nonce.len()].copy_from_slice(nonce);
+        write_be(&mut block[1 + nonce.len()..], i);
+
+        self.aes.encrypt_block(&mut block);
+        block
+    }
+
+    fn ctr(&self, nonce: &[u8], data: &mut [u8]) {
+        for (i, chunk) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            let stream = self.keystream(nonce, i + 1);
+
+            for (byte, key) in chunk.iter_mut().zip(stream) {
+                *byte ^= key;
+            }
+        }
+    }
+
+    // CBC-MAC over B0, the length-prefixed associated data and the plaintext, each zero padded to a block
+    fn mac(&self, nonce: &[u8], aad: &[u8], data: &[u8]) -> [u8; BLOCK_SIZE] {
+        debug_assert!(aad.len() <= MAX_AAD_SIZE);
+
+        let l = Self::length_size(nonce);
+        let mut x = [0; BLOCK_SIZE];
+
+        x[0] = (if aad.is_empty() { 0 } else { 0x40 }) | (((self.tag_size - 2) / 2) as u8) << 3 | (l - 1) as u8;
+        x[1..1 + nonce.len()].copy_from_slice(nonce);
+        write_be(&mut x[1 + nonce.len()..], data.len());
+
+        self.aes.encrypt_block(&mut x);
+
+        if !aad.is_empty() {
+            // First block carries the length, so the data is shifted by two bytes
+            let first = aad.len().min(BLOCK_SIZE - 2);
+            let mut block = [0; BLOCK_SIZE];
+
+            block[..2].copy_from_slice(&(aad.len() as u16).to_be_bytes());
+            block[2..2 + first].copy_from_slice(&aad[..first]);
+
+            self.absorb(&mut x, &block);
+
+            for chunk in aad[first..].chunks(BLOCK_SIZE) {
+                let mut block = [0; BLOCK_SIZE];
+                block[..chunk.len()].copy_from_slice(chunk);
+
+                self.absorb(&mut x, &block);
+            }
+        }
+
+        for chunk in data.chunks(BLOCK_SIZE) {
+            let mut block = [0; BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+
+            self.absorb(&mut x, &block);
+        }
+
+        x
+    }
+
+    fn absorb(&self, x: &mut [u8; BLOCK_SIZE], block: &[u8; BLOCK_SIZE]) {
+        for (x, byte) in x.iter_mut().zip(block) {
+            *x ^= byte;
+        }
+
+        self.aes.encrypt_block(x);
+    }
+}
+
+fn write_be(out: &mut [u8], mut value: usize) {
+    for byte in out.iter_mut().rev() {
+        *byte = value as u8;
+        value >>= 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Vector {
+        key:      [u8; KEY_SIZE],
+        nonce:    &'static [u8],
+        aad:      &'static [u8],
+        plain:    &'static [u8],
+        // Ciphertext followed by the tag
+        expected: &'static [u8],
+        tag_size: usize,
+    }
+
+    fn check(vector: &Vector) {
+        let ccm = Ccm::new(&vector.key, vector.tag_size);
+        let len = vector.plain.len();
+
+        let mut data = [0; 32];
+        let mut tag = [0; 16];
+
+        data[..len].copy_from_slice(vector.plain);
+        ccm.seal(vector.nonce, vector.aad, &mut data[..len], &mut tag[..vector.tag_size]);
+
+        assert_eq!(data[..len], vector.expected[..len]);
+        assert_eq!(tag[..vector.tag_size], vector.expected[len..]);
+
+        assert!(ccm.open(vector.nonce, vector.aad, &mut data[..len], &tag[..vector.tag_size]));
+        assert_eq!(data[..len], *vector.plain);
+
+        // Any flipped bit has to fail
+        ccm.seal(vector.nonce, vector.aad, &mut data[..len], &mut tag[..vector.tag_size]);
+        tag[0] ^= 1;
+
+        assert!(!ccm.open(vector.nonce, vector.aad, &mut data[..len], &tag[..vector.tag_size]));
+    }
+
+    #[test]
+    fn sp_800_38c_c1() {
+        check(&Vector {
+            key:      core::array::from_fn(|i| 0x40 + i as u8),
+            nonce:    &[0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16],
+            aad:      &[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07],
+            plain:    &[0x20, 0x21, 0x22, 0x23],
+            expected: &[0x71, 0x62, 0x01, 0x5b, 0x4d, 0xac, 0x25, 0x5d],
+            tag_size: 4,
+        });
+    }
+
+    #[test]
+    fn sp_800_38c_c2() {
+        check(&Vector {
+            key:      core::array::from_fn(|i| 0x40 + i as u8),
+            nonce:    &[0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17],
+            aad:      &[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f],
+            plain:    &[0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f],
+            expected: &[
+                0xd2, 0xa1, 0xf0, 0xe0, 0x51, 0xea, 0x5f, 0x62, 0x08, 0x1a, 0x77, 0x92, 0x07, 0x3d, 0x59, 0x3d,
+                0x1f, 0xc6, 0x4f, 0xbf, 0xac, 0xcd,
+            ],
+            tag_size: 6,
+        });
+    }
+
+    #[test]
+    fn rfc_3610_packet_1() {
+        check(&Vector {
+            key:      core::array::from_fn(|i| 0xc0 + i as u8),
+            nonce:    &[0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00, 0xa0, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5],
+            aad:      &[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07],
+            plain:    &[
+                0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17,
+                0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+            ],
+            expected: &[
+                0x58, 0x8c, 0x97, 0x9a, 0x61, 0xc6, 0x63, 0xd2, 0xf0, 0x66, 0xd0, 0xc2, 0xc0, 0xf9, 0x89, 0x80,
+                0x6d, 0x5f, 0x6b, 0x61, 0xda, 0xc3, 0x84, 0x17, 0xe8, 0xd1, 0x2c, 0xfd, 0xf9, 0x26, 0xe0,
+            ],
+            tag_size: 8,
+        });
+    }
+}
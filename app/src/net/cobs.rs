@@ -0,0 +1,64 @@
+// Consistent Overhead Byte Stuffing: output has no zero bytes, so zero can delimit frames
+pub const fn max_encoded_size(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+// Encoded size, `None` if `out` is too small. Delimiter isn't appended
+pub fn encode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    if out.len() < max_encoded_size(data.len()) {
+        return None;
+    }
+
+    let mut code_index = 0;
+    let mut code = 1u8;
+    let mut size = 1;
+
+    for byte in data {
+        if *byte != 0 {
+            out[size] = *byte;
+            size += 1;
+            code += 1;
+        }
+
+        if *byte == 0 || code == 0xFF {
+            out[code_index] = code;
+            code_index = size;
+            code = 1;
+            size += 1;
+        }
+    }
+
+    out[code_index] = code;
+
+    Some(size)
+}
+
+// Decoded size of one frame without its delimiter, `None` if it's malformed or `out` is too small
+pub fn decode(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut i = 0;
+    let mut size = 0;
+
+    while i < data.len() {
+        let code = data[i] as usize;
+
+        if code == 0 || i + code > data.len() || size + code - 1 > out.len() {
+            return None;
+        }
+
+        out[size..size + code - 1].copy_from_slice(&data[i + 1..i + code]);
+        size += code - 1;
+        i += code;
+
+        // Every group but the last and full ones ends where a zero was
+        if code < 0xFF && i < data.len() {
+            if size == out.len() {
+                return None;
+            }
+
+            out[size] = 0;
+            size += 1;
+        }
+    }
+
+    Some(size)
+}
@@ -0,0 +1,427 @@
+use rtrs::sync::RwLock;
+use rtrs::{object_with_mut, logger, info, error};
+use rtrs_drivers::radio::{Radio, RadioIoctl};
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::net::crc::crc16;
+use crate::peripherals::nvm::{self, Nvm, NvmError, NVM_OBJECT_NAME, RADIO_PROFILES_OFFSET, RADIO_PROFILES_SIZE};
+
+logger!("radio");
+
+// SX1278 LoRa bandwidths, everything else is rejected by the modem
+pub const BANDWIDTHS_HZ: [u32; 10] = [7800, 10400, 15600, 20800, 31250, 41700, 62500, 125000, 250000, 500000];
+
+// SX1278 synthesizer range (RF_HF and RF_LF ports together)
+const MIN_FREQUENCY_KHZ: u32 = 137_000;
+const MAX_FREQUENCY_KHZ: u32 = 525_000;
+
+// PA_BOOST output, 20 dBm needs the high power setting
+const MIN_POWER_DBM: i8 = 2;
+const MAX_POWER_DBM: i8 = 20;
+
+// SF6 only works with implicit header, link layer frames have variable length
+const MIN_SPREADING_FACTOR: u8 = 7;
+const MAX_SPREADING_FACTOR: u8 = 12;
+
+// Coding rate 4/5 .. 4/8, stored as the denominator
+const MIN_CODING_RATE: u8 = 5;
+const MAX_CODING_RATE: u8 = 8;
+
+const MIN_PREAMBLE: u16 = 6;
+
+// Loaded by `init` if it exists
+pub const BOOT_PROFILE: &str = "default";
+
+pub const PROFILE_NAME_SIZE: usize = 8;
+
+// Slot: magic, name (zero padded), config, padding, CRC-16 of everything before it
+const PROFILE_SIZE: usize = 32;
+const PROFILE_SLOTS: usize = RADIO_PROFILES_SIZE / PROFILE_SIZE;
+const PROFILE_MAGIC: u8 = 0xA5;
+const CONFIG_OFFSET: usize = 1 + PROFILE_NAME_SIZE;
+const CONFIG_SIZE: usize = 15;
+const CRC_OFFSET: usize = PROFILE_SIZE - 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Param {
+    Frequency,
+    Power,
+    Bandwidth,
+    SpreadingFactor,
+    CodingRate,
+    SyncWord,
+    Crc,
+    Preamble,
+}
+
+impl Param {
+    pub const ALL: [Param; 8] = [
+        Param::Frequency,
+        Param::Power,
+        Param::Bandwidth,
+        Param::SpreadingFactor,
+        Param::CodingRate,
+        Param::SyncWord,
+        Param::Crc,
+        Param::Preamble,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Param::Frequency       => "freq",
+            Param::Power           => "power",
+            Param::Bandwidth       => "bw",
+            Param::SpreadingFactor => "sf",
+            Param::CodingRate      => "cr",
+            Param::SyncWord        => "sync",
+            Param::Crc             => "crc",
+            Param::Preamble        => "preamble",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|param| param.name() == name)
+    }
+
+    // Unit and allowed values for usage messages
+    pub fn range(&self) -> &'static str {
+        match self {
+            Param::Frequency       => "kHz, 137000..525000",
+            Param::Power           => "dBm, 2..20",
+            Param::Bandwidth       => "Hz, 7800|10400|15600|20800|31250|41700|62500|125000|250000|500000",
+            Param::SpreadingFactor => "7..12",
+            Param::CodingRate      => "4/N, 5..8",
+            Param::SyncWord        => "0..0xFF",
+            Param::Crc             => "on|off",
+            Param::Preamble        => "symbols, 6..65535",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConfigError {
+    Parse(Param),
+    Invalid(Param),
+    // Driver rejected the parameter
+    Radio(Param),
+    // Radio is used by another task
+    Busy,
+    // Modem didn't respond to init
+    Init,
+    Name,
+    NoProfile,
+    ProfilesFull,
+    NoNvm,
+    Nvm(NvmError),
+}
+
+impl From<NvmError> for ConfigError {
+    fn from(err: NvmError) -> Self {
+        ConfigError::Nvm(err)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RadioConfig {
+    pub frequency_khz:    u32,
+    pub power_dbm:        i8,
+    pub bandwidth_hz:     u32,
+    pub spreading_factor: u8,
+    pub coding_rate:      u8,
+    pub sync_word:        u8,
+    pub crc:              bool,
+    pub preamble:         u16,
+}
+
+impl RadioConfig {
+    // SX1278 reset defaults, except for frequency, power, CRC and preamble
+    pub const fn new() -> Self {
+        Self {
+            frequency_khz:    433_000,
+            power_dbm:        20,
+            bandwidth_hz:     125_000,
+            spreading_factor: 7,
+            coding_rate:      5,
+            sync_word:        0x12,
+            crc:              true,
+            preamble:         10,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let checks = [
+            (Param::Frequency,       (MIN_FREQUENCY_KHZ..=MAX_FREQUENCY_KHZ).contains(&self.frequency_khz)),
+            (Param::Power,           (MIN_POWER_DBM..=MAX_POWER_DBM).contains(&self.power_dbm)),
+            (Param::Bandwidth,       BANDWIDTHS_HZ.contains(&self.bandwidth_hz)),
+            (Param::SpreadingFactor, (MIN_SPREADING_FACTOR..=MAX_SPREADING_FACTOR).contains(&self.spreading_factor)),
+            (Param::CodingRate,      (MIN_CODING_RATE..=MAX_CODING_RATE).contains(&self.coding_rate)),
+            (Param::Preamble,        self.preamble >= MIN_PREAMBLE),
+        ];
+
+        match checks.iter().find(|(_, valid)| !valid) {
+            Some((param, _)) => Err(ConfigError::Invalid(*param)),
+            None => Ok(()),
+        }
+    }
+
+    // Config is left untouched if the value is invalid
+    pub fn set(&mut self, param: Param, value: &str) -> Result<(), ConfigError> {
+        fn number<T: TryFrom<u32>>(param: Param, value: &str) -> Result<T, ConfigError> {
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None      => value.parse().ok(),
+            };
+
+            let parsed = parsed.ok_or(ConfigError::Parse(param))?;
+            T::try_from(parsed).map_err(|_| ConfigError::Invalid(param))
+        }
+
+        let mut config = *self;
+
+        match param {
+            Param::Frequency       => config.frequency_khz = number(param, value)?,
+            Param::Power           => config.power_dbm = value.parse().map_err(|_| ConfigError::Parse(param))?,
+            Param::Bandwidth       => config.bandwidth_hz = number(param, value)?,
+            Param::SpreadingFactor => config.spreading_factor = number(param, value)?,
+            Param::CodingRate      => config.coding_rate = number(param, value)?,
+            Param::SyncWord        => config.sync_word = number(param, value)?,
+            Param::Preamble        => config.preamble = number(param, value)?,
+            Param::Crc => {
+                config.crc = match value {
+                    "on"  => true,
+                    "off" => false,
+                    _ => return Err(ConfigError::Parse(param)),
+                }
+            }
+        }
+
+        config.validate()?;
+        *self = config;
+
+        Ok(())
+    }
+
+    // Stops at the first setting the driver rejects
+    pub fn apply(&self, radio: &mut Radio) -> Result<(), ConfigError> {
+        radio.set_frequency(self.frequency_khz).map_err(|_| ConfigError::Radio(Param::Frequency))?;
+        radio.set_power(self.power_dbm).map_err(|_| ConfigError::Radio(Param::Power))?;
+        radio.set_bandwidth(self.bandwidth_hz).map_err(|_| ConfigError::Radio(Param::Bandwidth))?;
+
+        let ioctls = [
+            (Param::SpreadingFactor, RadioIoctl::SetSpreadingFactor(self.spreading_factor)),
+            (Param::CodingRate,      RadioIoctl::SetCodingRate(self.coding_rate)),
+            (Param::SyncWord,        RadioIoctl::SetSyncWord(self.sync_word)),
+            (Param::Crc,             RadioIoctl::SetCrc(self.crc)),
+            (Param::Preamble,        RadioIoctl::SetPreambleSize(self.preamble)),
+        ];
+
+        for (param, ioctl) in ioctls {
+            radio.ioctl(ioctl).map_err(|_| ConfigError::Radio(param))?;
+        }
+
+        Ok(())
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0..4].copy_from_slice(&self.frequency_khz.to_le_bytes());
+        buf[4] = self.power_dbm as u8;
+        buf[5..9].copy_from_slice(&self.bandwidth_hz.to_le_bytes());
+        buf[9] = self.spreading_factor;
+        buf[10] = self.coding_rate;
+        buf[11] = self.sync_word;
+        buf[12] = self.crc as u8;
+        buf[13..15].copy_from_slice(&self.preamble.to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let config = Self {
+            frequency_khz:    u32::from_le_bytes(buf[0..4].try_into().ok()?),
+            power_dbm:        buf[4] as i8,
+            bandwidth_hz:     u32::from_le_bytes(buf[5..9].try_into().ok()?),
+            spreading_factor: buf[9],
+            coding_rate:      buf[10],
+            sync_word:        buf[11],
+            crc:              buf[12] != 0,
+            preamble:         u16::from_le_bytes(buf[13..15].try_into().ok()?),
+        };
+
+        // Written by firmware with wider limits
+        config.validate().ok()?;
+
+        Some(config)
+    }
+}
+
+impl fmt::Display for RadioConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "freq {} kHz, power {} dBm, bw {} Hz, sf {}, cr 4/{}, sync 0x{:02x}, crc {}, preamble {}",
+            self.frequency_khz, self.power_dbm, self.bandwidth_hz, self.spreading_factor, self.coding_rate,
+            self.sync_word, if self.crc { "on" } else { "off" }, self.preamble
+        )
+    }
+}
+
+// Configuration the radio runs with, changes are applied before the next radio operation
+static CONFIG: RwLock<RadioConfig> = RwLock::new(RadioConfig::new());
+
+// Written by the shell (set) and the task using the radio (cleared), thumbv6m has no atomic RMW
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+pub fn current() -> RadioConfig {
+    *CONFIG.lock()
+}
+
+pub fn set_current(config: RadioConfig) {
+    *CONFIG.lock_mut() = config;
+    PENDING.store(true, Ordering::SeqCst);
+}
+
+// Configuration to apply, clears pending changes
+pub(crate) fn take_current() -> RadioConfig {
+    PENDING.store(false, Ordering::SeqCst);
+    current()
+}
+
+// Called with the radio acquired
+pub(crate) fn apply_pending() {
+    if !PENDING.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let config = take_current();
+
+    match object_with_mut!(crate::net::radio::RADIO_OBJECT_NAME, Radio, radio, config.apply(radio)) {
+        Ok(()) => info!("Applied {}", config),
+        Err(err) => error!("Failed to apply configuration: {:?}", err),
+    }
+}
+
+pub type ProfileName = heapless::String<PROFILE_NAME_SIZE>;
+
+fn slot_offset(slot: usize) -> usize {
+    RADIO_PROFILES_OFFSET + slot * PROFILE_SIZE
+}
+
+fn read_slot(slot: usize) -> Result<Option<(ProfileName, RadioConfig)>, ConfigError> {
+    let mut buf = [0; PROFILE_SIZE];
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.read(slot_offset(slot), &mut buf))?;
+
+    // Erased, deleted or torn by a reset during the write
+    if buf[0] != PROFILE_MAGIC || crc16(&buf[..CRC_OFFSET]) != u16::from_le_bytes([buf[CRC_OFFSET], buf[CRC_OFFSET + 1]]) {
+        return Ok(None);
+    }
+
+    let name = &buf[1..CONFIG_OFFSET];
+    let len = name.iter().position(|byte| *byte == 0).unwrap_or(PROFILE_NAME_SIZE);
+
+    let Some(name) = core::str::from_utf8(&name[..len]).ok().and_then(|name| ProfileName::try_from(name).ok()) else {
+        return Ok(None);
+    };
+
+    Ok(RadioConfig::decode(&buf[CONFIG_OFFSET..CONFIG_OFFSET + CONFIG_SIZE]).map(|config| (name, config)))
+}
+
+fn find(name: &str) -> Result<Option<(usize, RadioConfig)>, ConfigError> {
+    for slot in 0..PROFILE_SLOTS {
+        if let Some((slot_name, config)) = read_slot(slot)? {
+            if slot_name == name {
+                return Ok(Some((slot, config)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn check_nvm() -> Result<(), ConfigError> {
+    if nvm::available() { Ok(()) } else { Err(ConfigError::NoNvm) }
+}
+
+pub fn profiles() -> Result<heapless::Vec<(ProfileName, RadioConfig), PROFILE_SLOTS>, ConfigError> {
+    check_nvm()?;
+
+    let mut profiles = heapless::Vec::new();
+
+    for slot in 0..PROFILE_SLOTS {
+        if let Some(profile) = read_slot(slot)? {
+            let _ = profiles.push(profile);
+        }
+    }
+
+    Ok(profiles)
+}
+
+// Overwrites a profile with the same name
+pub fn save_profile(name: &str, config: &RadioConfig) -> Result<(), ConfigError> {
+    check_nvm()?;
+
+    let valid_name = !name.is_empty() && name.len() <= PROFILE_NAME_SIZE && name.bytes().all(|b| b.is_ascii_graphic());
+
+    if !valid_name {
+        return Err(ConfigError::Name);
+    }
+
+    config.validate()?;
+
+    let slot = match find(name)? {
+        Some((slot, _)) => slot,
+        None => {
+            let mut free = None;
+
+            for slot in 0..PROFILE_SLOTS {
+                if read_slot(slot)?.is_none() {
+                    free = Some(slot);
+                    break;
+                }
+            }
+
+            free.ok_or(ConfigError::ProfilesFull)?
+        }
+    };
+
+    let mut buf = [0; PROFILE_SIZE];
+
+    buf[0] = PROFILE_MAGIC;
+    buf[1..1 + name.len()].copy_from_slice(name.as_bytes());
+    config.encode(&mut buf[CONFIG_OFFSET..CONFIG_OFFSET + CONFIG_SIZE]);
+
+    let crc = crc16(&buf[..CRC_OFFSET]);
+    buf[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.write(slot_offset(slot), &buf))?;
+
+    Ok(())
+}
+
+pub fn load_profile(name: &str) -> Result<RadioConfig, ConfigError> {
+    check_nvm()?;
+
+    find(name)?.map(|(_, config)| config).ok_or(ConfigError::NoProfile)
+}
+
+pub fn delete_profile(name: &str) -> Result<(), ConfigError> {
+    check_nvm()?;
+
+    let (slot, _) = find(name)?.ok_or(ConfigError::NoProfile)?;
+
+    // Clearing the magic is enough, and it's a single cell write
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.write(slot_offset(slot), &[0]))?;
+
+    Ok(())
+}
+
+// Boot profile becomes the configuration `radio init` applies
+pub(crate) fn init() {
+    match load_profile(BOOT_PROFILE) {
+        Ok(config) => {
+            *CONFIG.lock_mut() = config;
+            info!("Loaded profile '{}'", BOOT_PROFILE);
+        }
+        Err(ConfigError::NoProfile) | Err(ConfigError::NoNvm) => {}
+        Err(err) => error!("Failed to load profile '{}': {:?}", BOOT_PROFILE, err),
+    }
+}
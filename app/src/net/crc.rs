@@ -0,0 +1,8 @@
+// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF), bitwise to keep flash usage low
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
@@ -0,0 +1,757 @@
+use rtrs::{object_insert, object_with, object_with_mut, task_sleep, task_yield, logger, warn};
+
+use crate::net::crc::crc16;
+use crate::net::radio::{self, PacketInfo};
+use crate::net::secure::{self, Security, SecurityError};
+use crate::net::{self, airtime, config, sniffer, Transport, MAX_FRAME_SIZE};
+use crate::services::idle;
+
+logger!("link");
+
+pub const LINK_OBJECT_NAME: &str = "link";
+
+pub const BROADCAST: u8 = 0xFF;
+
+// Flags, destination, source, sequence number
+const HEADER_SIZE: usize = 4;
+const CRC_SIZE: usize = 2;
+
+// Clear text frames, secured ones carry secure::OVERHEAD on top
+pub const MAX_PAYLOAD: usize = MAX_FRAME_SIZE - HEADER_SIZE - CRC_SIZE;
+// Fits with or without keys, services size their frames by it
+pub const MAX_SECURE_PAYLOAD: usize = MAX_PAYLOAD - secure::OVERHEAD;
+
+// Used when NODE_ADDR isn't set at build time, security refuses to work with it
+pub const DEFAULT_ADDR: u8 = 1;
+
+// Upper nibble of the flags byte
+const VERSION: u8 = 1;
+const VERSION_SHIFT: u8 = 4;
+
+// Frame acknowledges a data frame with the same sequence number, sealed like data while keys are set
+const FLAG_ACK: u8 = 1 << 0;
+// Receiver has to acknowledge the frame
+const FLAG_ACK_REQUEST: u8 = 1 << 1;
+// Payload is encrypted and authenticated, see net::secure
+const FLAG_SECURE: u8 = 1 << 2;
+// Payload is for a firmware service, its first byte is the protocol (see net::is_service)
+const FLAG_SERVICE: u8 = 1 << 3;
+
+pub const DEFAULT_ACK_TIMEOUT_MS: u32 = 300;
+pub const DEFAULT_RETRIES: u8 = 3;
+
+const RX_QUEUE_SIZE: usize = 4;
+const SERVICE_QUEUE_SIZE: usize = 4;
+const TX_QUEUE_SIZE: usize = 2;
+const ACK_QUEUE_SIZE: usize = 4;
+const DELIVERY_QUEUE_SIZE: usize = 4;
+const DEDUP_SIZE: usize = 8;
+
+// Received frames nobody took are dropped after this, so a stale frame doesn't answer a later request
+const RX_MAX_AGE_MS: u32 = 10_000;
+
+// Bounds a single poll, so a chatty neighbour can't starve the caller
+const MAX_FRAMES_PER_POLL: usize = 8;
+
+// Link task listens this long between TX opportunities, short so ACKs go out in time
+const RX_WINDOW_MS: u32 = 50;
+// Until `radio init`, or while the sniffer has the radio
+const RADIO_WAIT_MS: u32 = 100;
+
+// Used by `send_reliable` while waiting for room in the queue and for the delivery
+const DELIVERY_POLL_MS: u32 = 10;
+// On top of retransmissions of a frame, covers link task RX windows and the peer's reaction
+const DELIVERY_MARGIN_MS: u32 = 2000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LinkError {
+    InvalidAddress,
+    TooLarge,
+    // Transmit queue is full, previous frames are waiting for ACKs
+    Busy,
+    // Keys are set, but none for this destination
+    NoKey,
+    // Keys are bound to the address, see secure::set_key
+    Secured,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeliveryStatus {
+    Acked { attempts: u8 },
+    // Broadcasts aren't acknowledged, only transmitted
+    Sent,
+    // No ACK after all retries, or transport refused to transmit
+    Failed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Delivery {
+    pub dst:    u8,
+    pub seq:    u8,
+    pub status: DeliveryStatus,
+}
+
+#[derive(Clone)]
+pub struct Packet {
+    pub src:  u8,
+    pub dst:  u8,
+    pub seq:  u8,
+    // Signal quality, if the transport reports it
+    pub info:    Option<PacketInfo>,
+    // Authenticated with a key, see net::secure
+    pub secured: bool,
+    received:    u32,
+    len:         usize,
+    data:        [u8; MAX_PAYLOAD],
+}
+
+impl Packet {
+    fn new(src: u8, dst: u8, seq: u8, payload: &[u8]) -> Self {
+        let mut data = [0; MAX_PAYLOAD];
+        data[..payload.len()].copy_from_slice(payload);
+
+        Self { src, dst, seq, info: None, secured: false, received: 0, len: payload.len(), data }
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LinkStats {
+    pub tx:               u32,
+    pub rx:               u32,
+    pub retries:          u32,
+    pub acked:            u32,
+    pub failed:           u32,
+    pub crc_errors:       u32,
+    pub duplicates:       u32,
+    // Received, but RX queue was full (not acknowledged, so sender retries)
+    pub dropped:          u32,
+    // Nobody took them from the RX queue in time
+    pub expired:          u32,
+    // Service protocol this firmware doesn't have
+    pub unknown:          u32,
+    pub transport_errors: u32,
+    // Secured frames that failed authentication or were replayed, clear text frames while keys are set
+    pub auth_errors:      u32,
+    pub replays:          u32,
+    pub insecure:         u32,
+    // Frames that couldn't be sealed (key removed or frame counters exhausted)
+    pub seal_errors:      u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FrameError {
+    Short,
+    Crc,
+    Version,
+}
+
+struct Header {
+    flags: u8,
+    dst:   u8,
+    src:   u8,
+    seq:   u8,
+}
+
+fn write_header(header: &Header, buf: &mut [u8; MAX_FRAME_SIZE]) {
+    buf[0] = VERSION << VERSION_SHIFT | header.flags;
+    buf[1] = header.dst;
+    buf[2] = header.src;
+    buf[3] = header.seq;
+}
+
+fn append_crc(buf: &mut [u8; MAX_FRAME_SIZE], size: usize) -> usize {
+    let crc = crc16(&buf[..size]);
+    buf[size..size + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+    size + CRC_SIZE
+}
+
+fn encode(header: &Header, payload: &[u8], buf: &mut [u8; MAX_FRAME_SIZE]) -> usize {
+    let size = HEADER_SIZE + payload.len();
+
+    write_header(header, buf);
+    buf[HEADER_SIZE..size].copy_from_slice(payload);
+
+    append_crc(buf, size)
+}
+
+// Header (with FLAG_SECURE) is authenticated along with the payload
+fn encode_secure(security: &mut Security, header: &Header, payload: &[u8], buf: &mut [u8; MAX_FRAME_SIZE]) -> Result<usize, SecurityError> {
+    write_header(header, buf);
+
+    // Frame queued in clear text before keys were set may not fit anymore
+    let (head, body) = buf[..MAX_FRAME_SIZE - CRC_SIZE].split_at_mut(HEADER_SIZE);
+    let size = HEADER_SIZE + security.seal(head, header.src, header.dst, payload, body)?;
+
+    Ok(append_crc(buf, size))
+}
+
+fn decode(frame: &[u8]) -> Result<(Header, &[u8]), FrameError> {
+    if frame.len() < HEADER_SIZE + CRC_SIZE {
+        return Err(FrameError::Short);
+    }
+
+    let (body, crc) = frame.split_at(frame.len() - CRC_SIZE);
+
+    if crc16(body).to_le_bytes() != crc {
+        return Err(FrameError::Crc);
+    }
+
+    if body[0] >> VERSION_SHIFT != VERSION {
+        return Err(FrameError::Version);
+    }
+
+    let header = Header { flags: body[0] & 0xF, dst: body[1], src: body[2], seq: body[3] };
+
+    Ok((header, &body[HEADER_SIZE..]))
+}
+
+// Queues are in arrival order, so the oldest frames are in front
+fn expire<const N: usize>(queue: &mut heapless::Deque<Packet, N>, now: u32) -> u32 {
+    let mut expired = 0;
+
+    while queue.front().is_some_and(|packet| now.wrapping_sub(packet.received) > RX_MAX_AGE_MS) {
+        queue.pop_front();
+        expired += 1;
+    }
+
+    expired
+}
+
+// Kind of the last frame returned by `output`
+#[derive(Copy, Clone)]
+enum InFlight {
+    None,
+    Ack,
+    Data { ack: bool },
+}
+
+// Frame waiting to be (re)transmitted, `attempts` is 0 until it's transmitted for the first time
+struct Outgoing {
+    packet:   Packet,
+    // Receiver is asked to acknowledge, otherwise the frame is sent once
+    ack:      bool,
+    // Sent with FLAG_SERVICE
+    service:  bool,
+    attempts: u8,
+    deadline: u32,
+}
+
+// Stop-and-wait ARQ: only the frame at the front of the TX queue is in flight.
+// Time is passed in by the caller, so the same code runs on the radio and in simulation
+pub struct Link {
+    addr:           u8,
+    seq:            u8,
+    ack_timeout_ms: u32,
+    retries:        u8,
+    tx:             heapless::Deque<Outgoing, TX_QUEUE_SIZE>,
+    acks:           heapless::Deque<(u8, u8), ACK_QUEUE_SIZE>,
+    rx:             heapless::Deque<Packet, RX_QUEUE_SIZE>,
+    // Firmware services have their own queue, unread data frames can't hold them up
+    service:        heapless::Deque<Packet, SERVICE_QUEUE_SIZE>,
+    // Oldest first, a Vec so results can be taken out of the middle in place
+    deliveries:     heapless::Vec<Delivery, DELIVERY_QUEUE_SIZE>,
+    // Last sequence number seen from each source and when, most recently heard last
+    last_seen:      heapless::Vec<(u8, u8, u32), DEDUP_SIZE>,
+    in_flight:      InFlight,
+    security:       Security,
+    stats:          LinkStats,
+}
+
+impl Link {
+    pub fn new(addr: u8) -> Self {
+        Self {
+            addr,
+            seq:            0,
+            ack_timeout_ms: DEFAULT_ACK_TIMEOUT_MS,
+            retries:        DEFAULT_RETRIES,
+            tx:             heapless::Deque::new(),
+            acks:           heapless::Deque::new(),
+            rx:             heapless::Deque::new(),
+            service:        heapless::Deque::new(),
+            deliveries:     heapless::Vec::new(),
+            last_seen:      heapless::Vec::new(),
+            in_flight:      InFlight::None,
+            security:       Security::new(),
+            stats:          LinkStats::default(),
+        }
+    }
+
+    pub fn addr(&self) -> u8 {
+        self.addr
+    }
+
+    // Frame counters are only unique per address, so it can't change under the keys
+    pub fn set_addr(&mut self, addr: u8) -> Result<(), LinkError> {
+        if addr == BROADCAST {
+            return Err(LinkError::InvalidAddress);
+        }
+
+        if self.security.enabled() {
+            return Err(LinkError::Secured);
+        }
+
+        self.addr = addr;
+        Ok(())
+    }
+
+    pub fn retry(&self) -> (u32, u8) {
+        (self.ack_timeout_ms, self.retries)
+    }
+
+    pub fn set_retry(&mut self, ack_timeout_ms: u32, retries: u8) {
+        self.ack_timeout_ms = ack_timeout_ms.max(1);
+        self.retries = retries;
+    }
+
+    pub fn security(&self) -> &Security {
+        &self.security
+    }
+
+    pub fn security_mut(&mut self) -> &mut Security {
+        &mut self.security
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = LinkStats::default();
+    }
+
+    pub fn max_payload(&self) -> usize {
+        if self.security.enabled() { MAX_SECURE_PAYLOAD } else { MAX_PAYLOAD }
+    }
+
+    // Nothing is waiting to be sent or acknowledged
+    pub fn idle(&self) -> bool {
+        self.tx.is_empty() && self.acks.is_empty()
+    }
+
+    // Queues a frame, it's transmitted by `poll` or the link task. Returns sequence number to match with `delivery`
+    pub fn send_to(&mut self, dst: u8, payload: &[u8]) -> Result<u8, LinkError> {
+        self.queue(dst, payload, dst != BROADCAST, false)
+    }
+
+    // `send_to` for firmware services, the first payload byte is their protocol. Flagged in the
+    // header, so the receiver hands it to its services and not to `recv_from`
+    pub fn send_service(&mut self, dst: u8, payload: &[u8]) -> Result<u8, LinkError> {
+        self.queue(dst, payload, dst != BROADCAST, true)
+    }
+
+    // Service frame sent once without ACK and no delivery is reported, for traffic that measures the channel itself
+    pub fn send_datagram(&mut self, dst: u8, payload: &[u8]) -> Result<u8, LinkError> {
+        self.queue(dst, payload, false, true)
+    }
+
+    fn queue(&mut self, dst: u8, payload: &[u8], ack: bool, service: bool) -> Result<u8, LinkError> {
+        if dst == self.addr {
+            return Err(LinkError::InvalidAddress);
+        }
+
+        if payload.len() > self.max_payload() {
+            return Err(LinkError::TooLarge);
+        }
+
+        if self.security.enabled() && !self.security.has_key(dst) {
+            return Err(LinkError::NoKey);
+        }
+
+        let seq = self.seq;
+        let outgoing = Outgoing { packet: Packet::new(self.addr, dst, seq, payload), ack, service, attempts: 0, deadline: 0 };
+
+        self.tx.push_back(outgoing).map_err(|_| LinkError::Busy)?;
+        self.seq = self.seq.wrapping_add(1);
+
+        Ok(seq)
+    }
+
+    // Frames for firmware services (FLAG_SERVICE) are left for `recv_service`
+    pub fn recv_from(&mut self) -> Option<Packet> {
+        self.rx.pop_front()
+    }
+
+    pub fn recv_service(&mut self, proto: u8) -> Option<Packet> {
+        let index = self.service.iter().position(|packet| packet.payload().first() == Some(&proto))?;
+
+        // Rotates the ones in front to the back and back again, the others keep their order
+        for _ in 0..index {
+            if let Some(packet) = self.service.pop_front() {
+                let _ = self.service.push_back(packet);
+            }
+        }
+
+        let packet = self.service.pop_front();
+
+        for _ in 0..self.service.len() - index {
+            if let Some(packet) = self.service.pop_front() {
+                let _ = self.service.push_back(packet);
+            }
+        }
+
+        packet
+    }
+
+    fn expire(&mut self, now: u32) {
+        self.stats.expired += expire(&mut self.rx, now) + expire(&mut self.service, now);
+    }
+
+    pub fn delivery(&mut self) -> Option<Delivery> {
+        if self.deliveries.is_empty() { None } else { Some(self.deliveries.remove(0)) }
+    }
+
+    // Result of a particular frame, leaves the others for their senders
+    pub fn take_delivery(&mut self, seq: u8) -> Option<Delivery> {
+        let index = self.deliveries.iter().position(|delivery| delivery.seq == seq)?;
+
+        Some(self.deliveries.remove(index))
+    }
+
+    // Synchronous driver for transports that can be polled (simulation, shell loops),
+    // async users call `input`, `output` and `transmitted` themselves
+    pub fn poll(&mut self, transport: &mut dyn Transport, now: u32) {
+        let mut buf = [0; MAX_FRAME_SIZE];
+
+        for _ in 0..MAX_FRAMES_PER_POLL {
+            match transport.receive(&mut buf) {
+                Ok(Some(size)) => self.input(&buf[..size], None, now),
+                Ok(None) => break,
+                Err(_) => {
+                    self.stats.transport_errors += 1;
+                    break;
+                }
+            }
+        }
+
+        while let Some(size) = self.output(now, &mut buf) {
+            let sent = transport.transmit(&buf[..size]).is_ok();
+            self.transmitted(sent);
+        }
+    }
+
+    // Next frame to transmit: pending ACKs first, then the data frame in flight if it's due.
+    // Result has to be reported with `transmitted` before the next call
+    pub fn output(&mut self, now: u32, buf: &mut [u8; MAX_FRAME_SIZE]) -> Option<usize> {
+        self.expire(now);
+
+        let secure = self.security.enabled();
+        let secure_flag = if secure { FLAG_SECURE } else { 0 };
+
+        if let Some((dst, seq)) = self.acks.pop_front() {
+            let header = Header { flags: FLAG_ACK | secure_flag, dst, src: self.addr, seq };
+
+            // Empty payload, only the MIC and frame counter, so an ACK can't be forged or replayed
+            let size = if secure {
+                match encode_secure(&mut self.security, &header, &[], buf) {
+                    Ok(size) => size,
+                    Err(_) => {
+                        self.stats.seal_errors += 1;
+                        return None;
+                    }
+                }
+            } else {
+                encode(&header, &[], buf)
+            };
+
+            self.in_flight = InFlight::Ack;
+            return Some(size);
+        }
+
+        let retries = self.retries;
+        let ack_timeout_ms = self.ack_timeout_ms;
+
+        let outgoing = self.tx.front_mut()?;
+
+        if outgoing.attempts > 0 && now.wrapping_sub(outgoing.deadline) > u32::MAX / 2 {
+            return None;
+        }
+
+        if outgoing.attempts > retries {
+            self.finish(DeliveryStatus::Failed);
+            return None;
+        }
+
+        let ack = outgoing.ack;
+        let service_flag = if outgoing.service { FLAG_SERVICE } else { 0 };
+        let flags = if ack { FLAG_ACK_REQUEST } else { 0 } | service_flag | secure_flag;
+        let header = Header { flags, dst: outgoing.packet.dst, src: self.addr, seq: outgoing.packet.seq };
+
+        // Sealed again for every attempt, a retransmission with the old frame counter would look like a replay
+        let size = if secure {
+            match encode_secure(&mut self.security, &header, outgoing.packet.payload(), buf) {
+                Ok(size) => size,
+                Err(_) => {
+                    self.stats.seal_errors += 1;
+                    self.finish(DeliveryStatus::Failed);
+                    return None;
+                }
+            }
+        } else {
+            encode(&header, outgoing.packet.payload(), buf)
+        };
+
+        if outgoing.attempts > 0 {
+            self.stats.retries += 1;
+        }
+
+        // Linear backoff, so two nodes retrying at once drift apart
+        outgoing.attempts += 1;
+        outgoing.deadline = now.wrapping_add(ack_timeout_ms * outgoing.attempts as u32);
+
+        self.in_flight = InFlight::Data { ack };
+
+        Some(size)
+    }
+
+    // Unicast frames that failed to transmit are retried on ACK timeout
+    pub fn transmitted(&mut self, sent: bool) {
+        if sent {
+            self.stats.tx += 1;
+        } else {
+            self.stats.transport_errors += 1;
+        }
+
+        if let InFlight::Data { ack: false } = self.in_flight {
+            self.finish(if sent { DeliveryStatus::Sent } else { DeliveryStatus::Failed });
+        }
+
+        self.in_flight = InFlight::None;
+    }
+
+    fn finish(&mut self, status: DeliveryStatus) {
+        let Some(outgoing) = self.tx.pop_front() else {
+            return;
+        };
+
+        match status {
+            DeliveryStatus::Acked { .. } => self.stats.acked += 1,
+            DeliveryStatus::Failed => self.stats.failed += 1,
+            DeliveryStatus::Sent => {}
+        }
+
+        if !outgoing.ack && outgoing.packet.dst != BROADCAST {
+            return;
+        }
+
+        // Oldest result is lost if nobody reads them
+        if self.deliveries.is_full() {
+            self.deliveries.remove(0);
+        }
+
+        let _ = self.deliveries.push(Delivery { dst: outgoing.packet.dst, seq: outgoing.packet.seq, status });
+    }
+
+    pub fn input(&mut self, frame: &[u8], info: Option<PacketInfo>, now: u32) {
+        let (header, payload) = match decode(frame) {
+            Ok(decoded) => decoded,
+            Err(FrameError::Crc) => {
+                self.stats.crc_errors += 1;
+                return;
+            }
+            // Not our protocol (or another version of it)
+            Err(_) => return,
+        };
+
+        if header.dst != self.addr && header.dst != BROADCAST {
+            return;
+        }
+
+        self.stats.rx += 1;
+
+        let mut plain = [0; MAX_FRAME_SIZE];
+
+        let Some(payload) = self.open(&header, frame, payload, &mut plain) else {
+            return;
+        };
+
+        if header.flags & FLAG_ACK != 0 {
+            let acked = matches!(
+                self.tx.front(),
+                Some(outgoing) if outgoing.attempts > 0 && outgoing.packet.dst == header.src && outgoing.packet.seq == header.seq
+            );
+
+            // Late ACKs of retransmitted frames are ignored
+            if acked && payload.is_empty() {
+                let attempts = self.tx.front().map_or(0, |outgoing| outgoing.attempts);
+                self.finish(DeliveryStatus::Acked { attempts });
+            }
+
+            return;
+        }
+
+        let service = header.flags & FLAG_SERVICE != 0;
+
+        // Nothing would ever take it from the queue. Not acknowledged, the sender learns it wasn't handled
+        if service && !net::is_service(payload) {
+            self.stats.unknown += 1;
+            return;
+        }
+
+        let ack = header.flags & FLAG_ACK_REQUEST != 0 && header.dst != BROADCAST;
+
+        self.expire(now);
+
+        if self.duplicate(header.src, header.seq, now) {
+            // Our ACK got lost, sender retransmitted
+            self.stats.duplicates += 1;
+
+            if ack {
+                let _ = self.acks.push_back((header.src, header.seq));
+            }
+
+            return;
+        }
+
+        let mut packet = Packet::new(header.src, header.dst, header.seq, payload);
+        packet.info = info;
+        packet.secured = header.flags & FLAG_SECURE != 0;
+        packet.received = now;
+
+        let queued = if service { self.service.push_back(packet) } else { self.rx.push_back(packet) };
+
+        if queued.is_err() {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        self.seen(header.src, header.seq, now);
+
+        if ack {
+            let _ = self.acks.push_back((header.src, header.seq));
+        }
+    }
+
+    // Plaintext of a secured payload, clear text is only accepted while no keys are set
+    fn open<'a>(&mut self, header: &Header, frame: &[u8], payload: &'a [u8], plain: &'a mut [u8; MAX_FRAME_SIZE]) -> Option<&'a [u8]> {
+        if header.flags & FLAG_SECURE == 0 {
+            if self.security.enabled() {
+                self.stats.insecure += 1;
+                return None;
+            }
+
+            return Some(payload);
+        }
+
+        let data = &mut plain[..payload.len()];
+        data.copy_from_slice(payload);
+
+        let range = match self.security.open(&frame[..HEADER_SIZE], header.src, data) {
+            Ok(range) => range,
+            Err(SecurityError::Replay) => {
+                self.stats.replays += 1;
+                return None;
+            }
+            Err(_) => {
+                self.stats.auth_errors += 1;
+                return None;
+            }
+        };
+
+        let plain: &'a [u8; MAX_FRAME_SIZE] = plain;
+        Some(&plain[range])
+    }
+
+    // Retransmissions stop after the sender's last ACK timeout. A frame with the same sequence
+    // number after that is new, the sender rebooted and counts from 0 again
+    fn dedup_window(&self) -> u32 {
+        let retries = self.retries as u32;
+        self.ack_timeout_ms * (retries * (retries + 1) / 2 + 1)
+    }
+
+    fn duplicate(&self, src: u8, seq: u8, now: u32) -> bool {
+        let window = self.dedup_window();
+
+        self.last_seen.iter().any(|(s, q, heard)| *s == src && *q == seq && now.wrapping_sub(*heard) <= window)
+    }
+
+    fn seen(&mut self, src: u8, seq: u8, now: u32) {
+        if let Some(index) = self.last_seen.iter().position(|(s, _, _)| *s == src) {
+            self.last_seen.remove(index);
+        } else if self.last_seen.is_full() {
+            self.last_seen.remove(0);
+        }
+
+        let _ = self.last_seen.push((src, seq, now));
+    }
+}
+
+impl rtrs::object::Object for Link {}
+
+// Address comes from NODE_ADDR at build time, so two boards flashed from the same tree can talk
+pub(crate) fn init() {
+    let addr = option_env!("NODE_ADDR")
+        .and_then(|addr| addr.parse().ok())
+        .filter(|addr| *addr != BROADCAST)
+        .unwrap_or(DEFAULT_ADDR);
+
+    object_insert!(LINK_OBJECT_NAME, Link::new(addr));
+}
+
+// Runs the link over the radio in the background: listens, then sends pending ACKs and data
+pub(crate) async fn task() {
+    let mut buf = [0; MAX_FRAME_SIZE];
+
+    loop {
+        if !radio::ready() || sniffer::active() {
+            task_sleep!(RADIO_WAIT_MS);
+            continue;
+        }
+
+        // Expired window is reported as an error, nothing to count
+        if let Ok((size, info)) = radio::recv(&mut buf, RX_WINDOW_MS).await {
+            let now = rtrs::time::global_tick();
+            object_with_mut!(LINK_OBJECT_NAME, Link, link, link.input(&buf[..size], info, now));
+        }
+
+        secure::maintain();
+
+        let now = rtrs::time::global_tick();
+
+        while let Some(size) = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.output(now, &mut buf)) {
+            let sent = radio::send(&buf[..size]).await.is_ok();
+            object_with_mut!(LINK_OBJECT_NAME, Link, link, link.transmitted(sent));
+        }
+
+        // Radio is free here, tasks waiting for it get their turn
+        task_yield!();
+    }
+}
+
+// Worst case for one frame: airtime of the frame and its ACK on every attempt
+pub(crate) fn delivery_timeout() -> u32 {
+    let (ack_timeout, retries) = object_with!(LINK_OBJECT_NAME, Link, link, link.retry());
+    let airtime_ms = airtime::time_on_air_us(&config::current(), MAX_FRAME_SIZE) / 1000;
+
+    (2 * airtime_ms + ack_timeout) * (retries as u32 + 1) + DELIVERY_MARGIN_MS
+}
+
+// Queued unicast with ACK, waits for room in the queue and then for the delivery
+pub(crate) async fn send_reliable(dst: u8, frame: &[u8]) -> bool {
+    let timeout = delivery_timeout();
+    let start = rtrs::time::global_tick();
+
+    let seq = loop {
+        match object_with_mut!(LINK_OBJECT_NAME, Link, link, link.send_service(dst, frame)) {
+            Ok(seq) => break seq,
+            Err(LinkError::Busy) if rtrs::time::global_tick().wrapping_sub(start) < timeout => idle::sleep(DELIVERY_POLL_MS).await,
+            Err(err) => {
+                warn!("Sending to {} failed: {:?}", dst, err);
+                return false;
+            }
+        }
+    };
+
+    while rtrs::time::global_tick().wrapping_sub(start) < timeout {
+        match object_with_mut!(LINK_OBJECT_NAME, Link, link, link.take_delivery(seq)) {
+            Some(delivery) => return matches!(delivery.status, DeliveryStatus::Acked { .. }),
+            None => idle::sleep(DELIVERY_POLL_MS).await,
+        }
+    }
+
+    false
+}
@@ -0,0 +1,647 @@
+use rtrs::sync::RwLock;
+use rtrs::{object_with_mut, task_sleep};
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::net::link::{self, DeliveryStatus, Link, LinkError, Packet, BROADCAST, LINK_OBJECT_NAME};
+use crate::net::radio::{self, PacketInfo};
+use crate::net::{sniffer, PROTO_MESH_BEACON, PROTO_MESH_DATA};
+use crate::services::idle;
+
+// Proto, origin, destination, TTL, hops so far
+const DATA_HEADER_SIZE: usize = 5;
+
+pub const MAX_PAYLOAD: usize = link::MAX_SECURE_PAYLOAD - DATA_HEADER_SIZE;
+
+// Proto and sequence number, then an entry per route: destination, metric, hops, next hop
+const BEACON_HEADER_SIZE: usize = 2;
+const ROUTE_ENTRY_SIZE: usize = 4;
+
+// Whole table fits a single beacon
+pub const MAX_ROUTES: usize = (link::MAX_SECURE_PAYLOAD - BEACON_HEADER_SIZE) / ROUTE_ENTRY_SIZE;
+pub const MAX_NEIGHBOURS: usize = 8;
+
+// Metric of an unreachable destination, also bounds counting to infinity
+pub const INFINITY: u8 = 32;
+pub const DEFAULT_TTL: u8 = 8;
+
+pub const DEFAULT_BEACON_INTERVAL_MS: u32 = 60_000;
+// Neighbours and routes are dropped after this many intervals without hearing about them
+const EXPIRY_INTERVALS: u32 = 3;
+// Spreads beacons of nodes that booted together, different for each address and beacon
+const JITTER_STEP_MS: u32 = 211;
+
+// Quality of a new neighbour, every beacon moves it a quarter of the way towards 255 (or 0 if missed)
+const INITIAL_QUALITY: u8 = 192;
+const QUALITY_STEP: u16 = 64;
+// Sequence gaps beyond this are a reboot rather than lost beacons
+const MAX_MISSED: u8 = 8;
+
+const RX_QUEUE_SIZE: usize = 2;
+const PENDING_SIZE: usize = 4;
+
+const POLL_MS: u32 = 10;
+// Until `radio init`, or while the sniffer has the radio
+const RADIO_WAIT_MS: u32 = 100;
+
+// Idle deadline of the next beacon
+const BEACON_DEADLINE_NAME: &str = "mesh_beacon";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MeshError {
+    InvalidAddress,
+    TooLarge,
+    NoRoute,
+    Link(LinkError),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Neighbour {
+    pub addr:       u8,
+    // Beacon reception rate, 255 is every beacon heard
+    pub quality:    u8,
+    // Signal of the last beacon, if the transport reports it
+    pub info:       Option<PacketInfo>,
+    pub last_heard: u32,
+    last_seq:       u8,
+}
+
+impl Neighbour {
+    // Link cost: 1 for a clean link, up to 8 for a weak and lossy one
+    pub fn cost(&self) -> u8 {
+        // Quarter dB, SF7 demodulates down to -7.5 dB
+        let snr_penalty = match self.info {
+            Some(info) if info.snr_x4 < -20 => 3,
+            Some(info) if info.snr_x4 < 0   => 2,
+            Some(info) if info.snr_x4 < 20  => 1,
+            _                               => 0,
+        };
+
+        1 + snr_penalty + (255 - self.quality) / 64
+    }
+
+    fn heard(&mut self, seq: u8, info: Option<PacketInfo>, now: u32) {
+        let missed = seq.wrapping_sub(self.last_seq).wrapping_sub(1).min(MAX_MISSED);
+
+        let mut quality = self.quality as u16;
+
+        for _ in 0..missed {
+            quality = quality * 3 / 4;
+        }
+
+        self.quality = (quality * 3 / 4 + QUALITY_STEP).min(255) as u8;
+        self.info = info;
+        self.last_heard = now;
+        self.last_seq = seq;
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Route {
+    pub dst:      u8,
+    pub next_hop: u8,
+    // Sum of link costs along the path
+    pub metric:   u8,
+    pub hops:     u8,
+    pub updated:  u32,
+}
+
+#[derive(Clone)]
+pub struct MeshPacket {
+    pub origin: u8,
+    pub hops:   u8,
+    len:        usize,
+    data:       [u8; MAX_PAYLOAD],
+}
+
+impl MeshPacket {
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MeshStats {
+    pub beacons_tx:    u32,
+    pub beacons_rx:    u32,
+    pub sent:          u32,
+    pub received:      u32,
+    pub forwarded:     u32,
+    pub no_route:      u32,
+    pub ttl_expired:   u32,
+    // RX queue or link TX queue was full
+    pub dropped:       u32,
+    // Next hop didn't acknowledge, its routes were dropped
+    pub link_failures: u32,
+}
+
+impl MeshStats {
+    const fn new() -> Self {
+        Self {
+            beacons_tx:    0,
+            beacons_rx:    0,
+            sent:          0,
+            received:      0,
+            forwarded:     0,
+            no_route:      0,
+            ttl_expired:   0,
+            dropped:       0,
+            link_failures: 0,
+        }
+    }
+}
+
+// Distance-vector routing on top of the link layer: neighbours broadcast their route tables in beacons,
+// data is forwarded hop by hop with link layer ACKs. Like `Link`, time is passed in by the caller,
+// so the same code runs on the radio and in simulation
+pub struct Mesh {
+    beacon_interval_ms: u32,
+    beacon_seq:         u8,
+    next_beacon:        Option<u32>,
+    neighbours:         heapless::Vec<Neighbour, MAX_NEIGHBOURS>,
+    routes:             heapless::Vec<Route, MAX_ROUTES>,
+    rx:                 heapless::Deque<MeshPacket, RX_QUEUE_SIZE>,
+    // Link sequence number and next hop of frames waiting for their delivery result
+    pending:            heapless::Vec<(u8, u8), PENDING_SIZE>,
+    stats:              MeshStats,
+}
+
+impl Mesh {
+    pub const fn new() -> Self {
+        Self {
+            beacon_interval_ms: DEFAULT_BEACON_INTERVAL_MS,
+            beacon_seq:         0,
+            next_beacon:        None,
+            neighbours:         heapless::Vec::new(),
+            routes:             heapless::Vec::new(),
+            rx:                 heapless::Deque::new(),
+            pending:            heapless::Vec::new(),
+            stats:              MeshStats::new(),
+        }
+    }
+
+    pub fn beacon_interval(&self) -> u32 {
+        self.beacon_interval_ms
+    }
+
+    pub fn next_beacon(&self) -> Option<u32> {
+        self.next_beacon
+    }
+
+    // Next beacon goes out within the new interval
+    pub fn set_beacon_interval(&mut self, interval_ms: u32) {
+        self.beacon_interval_ms = interval_ms.max(1);
+        self.next_beacon = None;
+    }
+
+    pub fn neighbours(&self) -> &[Neighbour] {
+        &self.neighbours
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    pub fn route(&self, dst: u8) -> Option<&Route> {
+        self.routes.iter().find(|route| route.dst == dst)
+    }
+
+    pub fn stats(&self) -> MeshStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = MeshStats::new();
+    }
+
+    // Queues a packet with the link towards the next hop, delivery to `dst` isn't confirmed end to end
+    pub fn send(&mut self, link: &mut Link, dst: u8, payload: &[u8]) -> Result<(), MeshError> {
+        if dst == BROADCAST || dst == link.addr() {
+            return Err(MeshError::InvalidAddress);
+        }
+
+        if payload.len() > MAX_PAYLOAD {
+            return Err(MeshError::TooLarge);
+        }
+
+        let mut frame = [0; link::MAX_SECURE_PAYLOAD];
+        let size = DATA_HEADER_SIZE + payload.len();
+
+        frame[..DATA_HEADER_SIZE].copy_from_slice(&[PROTO_MESH_DATA, link.addr(), dst, DEFAULT_TTL, 0]);
+        frame[DATA_HEADER_SIZE..size].copy_from_slice(payload);
+
+        self.forward(link, dst, &frame[..size])?;
+        self.stats.sent += 1;
+
+        Ok(())
+    }
+
+    pub fn recv(&mut self) -> Option<MeshPacket> {
+        self.rx.pop_front()
+    }
+
+    // Handles beacons and data the link received, checks deliveries, expires stale entries and beacons when due
+    pub fn poll(&mut self, link: &mut Link, now: u32) {
+        while let Some(packet) = link.recv_service(PROTO_MESH_BEACON) {
+            self.beacon_received(link.addr(), &packet, now);
+        }
+
+        while let Some(packet) = link.recv_service(PROTO_MESH_DATA) {
+            self.data_received(link, &packet);
+        }
+
+        self.check_deliveries(link);
+        self.expire(now);
+
+        match self.next_beacon {
+            None => self.next_beacon = Some(now.wrapping_add(self.jitter(link.addr()))),
+            Some(due) if now.wrapping_sub(due) < u32::MAX / 2 => self.send_beacon(link, now),
+            Some(_) => {}
+        }
+    }
+
+    // Within a quarter of the interval
+    fn jitter(&self, addr: u8) -> u32 {
+        let span = (self.beacon_interval_ms / 4).max(1);
+
+        (addr as u32 * JITTER_STEP_MS + self.beacon_seq as u32 * JITTER_STEP_MS / 2) % span
+    }
+
+    fn send_beacon(&mut self, link: &mut Link, now: u32) {
+        let mut beacon = [0; link::MAX_SECURE_PAYLOAD];
+        let mut size = BEACON_HEADER_SIZE;
+
+        beacon[0] = PROTO_MESH_BEACON;
+        beacon[1] = self.beacon_seq;
+
+        for route in &self.routes {
+            beacon[size..size + ROUTE_ENTRY_SIZE].copy_from_slice(&[route.dst, route.metric, route.hops, route.next_hop]);
+            size += ROUTE_ENTRY_SIZE;
+        }
+
+        // Link queue is full, tried again on the next poll
+        let Ok(seq) = link.send_service(BROADCAST, &beacon[..size]) else {
+            return;
+        };
+
+        self.track(seq, BROADCAST);
+        self.stats.beacons_tx += 1;
+        self.beacon_seq = self.beacon_seq.wrapping_add(1);
+
+        let interval = self.beacon_interval_ms;
+        self.next_beacon = Some(now.wrapping_add(interval - interval / 8 + self.jitter(link.addr())));
+    }
+
+    fn beacon_received(&mut self, addr: u8, packet: &Packet, now: u32) {
+        let payload = packet.payload();
+
+        if payload.len() < BEACON_HEADER_SIZE {
+            return;
+        }
+
+        self.stats.beacons_rx += 1;
+
+        let src = packet.src;
+        let seq = payload[1];
+
+        match self.neighbours.iter_mut().find(|neighbour| neighbour.addr == src) {
+            Some(neighbour) => neighbour.heard(seq, packet.info, now),
+            None => {
+                let neighbour = Neighbour { addr: src, quality: INITIAL_QUALITY, info: packet.info, last_heard: now, last_seq: seq };
+
+                // Full table, the newcomer has to wait until someone expires
+                if self.neighbours.push(neighbour).is_err() {
+                    return;
+                }
+            }
+        }
+
+        let cost = self.neighbours.iter().find(|neighbour| neighbour.addr == src).map_or(INFINITY, |neighbour| neighbour.cost());
+
+        self.update_route(src, src, cost, 1, now);
+
+        for entry in payload[BEACON_HEADER_SIZE..].chunks_exact(ROUTE_ENTRY_SIZE) {
+            let (dst, metric, hops, next_hop) = (entry[0], entry[1], entry[2], entry[3]);
+
+            // Split horizon: routes through us would only come back as loops
+            if dst == addr || dst == src || next_hop == addr {
+                continue;
+            }
+
+            self.update_route(dst, src, metric.saturating_add(cost).min(INFINITY), hops.saturating_add(1), now);
+        }
+
+        // Beacon carries the whole table, whatever it no longer has is gone
+        self.routes.retain(|route| route.next_hop != src || route.updated == now);
+    }
+
+    // Route through the current next hop always follows it, even if it gets worse; other ones only win if better
+    fn update_route(&mut self, dst: u8, next_hop: u8, metric: u8, hops: u8, now: u32) {
+        let route = Route { dst, next_hop, metric, hops, updated: now };
+
+        match self.routes.iter().position(|route| route.dst == dst) {
+            Some(index) if self.routes[index].next_hop == next_hop || metric < self.routes[index].metric => {
+                if metric >= INFINITY {
+                    self.routes.swap_remove(index);
+                } else {
+                    self.routes[index] = route;
+                }
+            }
+            Some(_) => {}
+            // Full table, destination stays unreachable until another route expires
+            None if metric < INFINITY => {
+                let _ = self.routes.push(route);
+            }
+            None => {}
+        }
+    }
+
+    fn data_received(&mut self, link: &mut Link, packet: &Packet) {
+        let payload = packet.payload();
+
+        // Longer ones come from a node without keys, and couldn't be forwarded once keys are set
+        if payload.len() < DATA_HEADER_SIZE || payload.len() > link::MAX_SECURE_PAYLOAD {
+            return;
+        }
+
+        let (origin, dst, ttl, hops) = (payload[1], payload[2], payload[3], payload[4].saturating_add(1));
+
+        if dst == link.addr() {
+            let mut data = [0; MAX_PAYLOAD];
+            let len = payload.len() - DATA_HEADER_SIZE;
+            data[..len].copy_from_slice(&payload[DATA_HEADER_SIZE..]);
+
+            match self.rx.push_back(MeshPacket { origin, hops, len, data }) {
+                Ok(_) => self.stats.received += 1,
+                Err(_) => self.stats.dropped += 1,
+            }
+
+            return;
+        }
+
+        // Looped back to where it came from
+        if origin == link.addr() || ttl <= 1 {
+            self.stats.ttl_expired += 1;
+            return;
+        }
+
+        let mut frame = [0; link::MAX_SECURE_PAYLOAD];
+        frame[..payload.len()].copy_from_slice(payload);
+        frame[3] = ttl - 1;
+        frame[4] = hops;
+
+        match self.forward(link, dst, &frame[..payload.len()]) {
+            Ok(_) => self.stats.forwarded += 1,
+            Err(MeshError::NoRoute) => self.stats.no_route += 1,
+            Err(_) => self.stats.dropped += 1,
+        }
+    }
+
+    fn forward(&mut self, link: &mut Link, dst: u8, frame: &[u8]) -> Result<(), MeshError> {
+        let next_hop = self.route(dst).ok_or(MeshError::NoRoute)?.next_hop;
+        let seq = link.send_service(next_hop, frame).map_err(MeshError::Link)?;
+
+        self.track(seq, next_hop);
+
+        Ok(())
+    }
+
+    // Oldest result is left to the link if nobody reads it
+    fn track(&mut self, seq: u8, next_hop: u8) {
+        if self.pending.is_full() {
+            self.pending.remove(0);
+        }
+
+        let _ = self.pending.push((seq, next_hop));
+    }
+
+    fn check_deliveries(&mut self, link: &mut Link) {
+        let mut index = 0;
+
+        while index < self.pending.len() {
+            let (seq, next_hop) = self.pending[index];
+
+            match link.take_delivery(seq) {
+                Some(delivery) => {
+                    self.pending.remove(index);
+
+                    // Retries are exhausted, the neighbour is out of range or gone
+                    if delivery.status == DeliveryStatus::Failed && next_hop != BROADCAST {
+                        self.stats.link_failures += 1;
+                        self.neighbour_lost(next_hop);
+                    }
+                }
+                None => index += 1,
+            }
+        }
+    }
+
+    fn expire(&mut self, now: u32) {
+        let timeout = self.beacon_interval_ms.saturating_mul(EXPIRY_INTERVALS);
+
+        while let Some(addr) = self.neighbours.iter().find(|neighbour| now.wrapping_sub(neighbour.last_heard) > timeout).map(|neighbour| neighbour.addr) {
+            self.neighbour_lost(addr);
+        }
+
+        self.routes.retain(|route| now.wrapping_sub(route.updated) <= timeout);
+    }
+
+    // Heard again with the next beacon, if it's still around
+    fn neighbour_lost(&mut self, addr: u8) {
+        self.neighbours.retain(|neighbour| neighbour.addr != addr);
+        self.routes.retain(|route| route.next_hop != addr);
+    }
+}
+
+static MESH: RwLock<Mesh> = RwLock::new(Mesh::new());
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+// Disabled node neither beacons nor forwards, its neighbours route around it after a while
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn neighbours() -> heapless::Vec<Neighbour, MAX_NEIGHBOURS> {
+    heapless::Vec::from_slice(MESH.lock().neighbours()).unwrap_or_default()
+}
+
+pub fn routes() -> heapless::Vec<Route, MAX_ROUTES> {
+    heapless::Vec::from_slice(MESH.lock().routes()).unwrap_or_default()
+}
+
+pub fn stats() -> MeshStats {
+    MESH.lock().stats()
+}
+
+pub fn reset_stats() {
+    MESH.lock_mut().reset_stats();
+}
+
+pub fn beacon_interval() -> u32 {
+    MESH.lock().beacon_interval()
+}
+
+pub fn set_beacon_interval(interval_ms: u32) {
+    MESH.lock_mut().set_beacon_interval(interval_ms);
+}
+
+pub fn send(dst: u8, payload: &[u8]) -> Result<(), MeshError> {
+    object_with_mut!(LINK_OBJECT_NAME, Link, link, MESH.lock_mut().send(link, dst, payload))
+}
+
+pub fn recv() -> Option<MeshPacket> {
+    MESH.lock_mut().recv()
+}
+
+// Runs the mesh on top of the link task
+pub(crate) async fn task() {
+    loop {
+        if !radio::ready() || sniffer::active() {
+            task_sleep!(RADIO_WAIT_MS);
+            continue;
+        }
+
+        let now = rtrs::time::global_tick();
+
+        object_with_mut!(LINK_OBJECT_NAME, Link, link, {
+            if enabled() {
+                MESH.lock_mut().poll(link, now);
+            } else {
+                // Keeps mesh traffic from filling up the link RX queue
+                while link.recv_service(PROTO_MESH_BEACON).or_else(|| link.recv_service(PROTO_MESH_DATA)).is_some() {}
+            }
+        });
+
+        // Frames come in through the link task, so polling may sleep through STOP, beacons may not
+        match MESH.lock().next_beacon() {
+            Some(due) if enabled() => idle::wake_at(BEACON_DEADLINE_NAME, due),
+            _ => idle::cancel_wake(BEACON_DEADLINE_NAME),
+        }
+
+        task_sleep!(POLL_MS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use crate::net::sim::{SimMedium, SimPort};
+
+    const STEP_MS: u32 = 10;
+    const MAX_STEPS: u32 = 3000;
+    const BEACON_INTERVAL_MS: u32 = 1000;
+
+    type Node = (Link, Mesh);
+
+    // Steps every node until `done` holds
+    fn run(nodes: &mut [Node], medium: &RefCell<SimMedium>, now: &mut u32, mut done: impl FnMut(&mut [Node]) -> bool) -> bool {
+        for _ in 0..MAX_STEPS {
+            *now += STEP_MS;
+
+            for (port, (link, mesh)) in nodes.iter_mut().enumerate() {
+                link.poll(&mut SimPort::new(medium, port), *now);
+                mesh.poll(link, *now);
+            }
+
+            if done(nodes) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn route(nodes: &[Node], from: usize, dst: u8) -> Option<(u8, u8)> {
+        nodes[from].1.route(dst).map(|route| (route.next_hop, route.hops))
+    }
+
+    // Line: 1 - 2 - 3, the ends are out of each other's range
+    fn line() -> ([Node; 3], RefCell<SimMedium>) {
+        let medium = RefCell::new(SimMedium::new());
+        medium.borrow_mut().isolate();
+        medium.borrow_mut().set_link(0, 1, true);
+        medium.borrow_mut().set_link(1, 2, true);
+
+        let mut nodes = [(Link::new(1), Mesh::new()), (Link::new(2), Mesh::new()), (Link::new(3), Mesh::new())];
+
+        for (_, mesh) in nodes.iter_mut() {
+            mesh.set_beacon_interval(BEACON_INTERVAL_MS);
+        }
+
+        (nodes, medium)
+    }
+
+    fn converge(nodes: &mut [Node], medium: &RefCell<SimMedium>, now: &mut u32) {
+        assert!(run(nodes, medium, now, |nodes| route(nodes, 0, 3) == Some((2, 2)) && route(nodes, 2, 1) == Some((2, 2))));
+    }
+
+    #[test]
+    fn forwards_over_two_hops() {
+        let (mut nodes, medium) = line();
+        let mut now = 0;
+
+        converge(&mut nodes, &medium, &mut now);
+
+        let (link, mesh) = &mut nodes[0];
+        mesh.send(link, 3, b"hop").unwrap();
+
+        let mut received = None;
+
+        run(&mut nodes, &medium, &mut now, |nodes| {
+            received = nodes[2].1.recv();
+            received.is_some()
+        });
+
+        let packet = received.unwrap();
+        assert_eq!((packet.origin, packet.hops, packet.payload()), (1, 2, &b"hop"[..]));
+        assert_eq!(nodes[1].1.stats().forwarded, 1);
+    }
+
+    #[test]
+    fn takes_shortcut() {
+        let (mut nodes, medium) = line();
+        let mut now = 0;
+
+        converge(&mut nodes, &medium, &mut now);
+
+        // Ends move into range of each other, the direct route costs less
+        medium.borrow_mut().set_link(0, 2, true);
+
+        assert!(run(&mut nodes, &medium, &mut now, |nodes| route(nodes, 0, 3) == Some((3, 1))));
+    }
+
+    #[test]
+    fn routes_of_lost_node_expire() {
+        let (mut nodes, medium) = line();
+        let mut now = 0;
+
+        converge(&mut nodes, &medium, &mut now);
+
+        medium.borrow_mut().set_link(1, 2, false);
+
+        assert!(run(&mut nodes, &medium, &mut now, |nodes| route(nodes, 0, 3).is_none() && route(nodes, 1, 3).is_none()));
+
+        let (link, mesh) = &mut nodes[0];
+        assert_eq!(mesh.send(link, 3, b"gone"), Err(MeshError::NoRoute));
+    }
+
+    #[test]
+    fn ttl_stops_loops() {
+        let (mut nodes, medium) = line();
+        let mut now = 0;
+
+        converge(&mut nodes, &medium, &mut now);
+
+        // Relay gets a frame for node 3 with the TTL used up
+        nodes[0].0.send_service(2, &[PROTO_MESH_DATA, 1, 3, 1, 0, 0xAA]).unwrap();
+
+        assert!(run(&mut nodes, &medium, &mut now, |nodes| nodes[1].1.stats().ttl_expired == 1));
+        assert_eq!(nodes[1].1.stats().forwarded, 0);
+    }
+}
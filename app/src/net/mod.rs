@@ -0,0 +1,57 @@
+pub mod aes;
+pub mod airtime;
+pub mod ccm;
+pub mod cobs;
+pub mod config;
+pub mod crc;
+pub mod link;
+pub mod mesh;
+pub mod ota;
+pub mod ping;
+pub mod radio;
+pub mod remote;
+pub mod secure;
+pub mod sha256;
+pub mod sim;
+pub mod sniffer;
+
+// Largest frame the SX1278 FIFO setup handles, header and CRC included
+pub const MAX_FRAME_SIZE: usize = 64;
+
+// First payload byte of link frames flagged for the firmware's own services, frames without the
+// flag go to `radio recvfrom` whatever they start with
+pub const SERVICE_PROTO_MIN: u8 = 0xF0;
+pub const PROTO_PING_REQUEST: u8 = 0xF0;
+pub const PROTO_PING_REPLY: u8 = 0xF1;
+pub const PROTO_MESH_BEACON: u8 = 0xF2;
+pub const PROTO_MESH_DATA: u8 = 0xF3;
+pub const PROTO_REMOTE_REQUEST: u8 = 0xF4;
+pub const PROTO_REMOTE_OUTPUT: u8 = 0xF5;
+pub const PROTO_OTA_REQUEST: u8 = 0xF6;
+pub const PROTO_OTA_STATUS: u8 = 0xF7;
+// Reserved for services to come, frames with them are dropped
+pub const SERVICE_PROTO_MAX: u8 = PROTO_OTA_STATUS;
+
+// Payload of a service frame, see link::send_service
+pub fn is_service(payload: &[u8]) -> bool {
+    payload.first().is_some_and(|proto| (SERVICE_PROTO_MIN..=SERVICE_PROTO_MAX).contains(proto))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransportError {
+    // Supply is too low to transmit, see battery::brownout_risk
+    Brownout,
+    // Transmission would exceed the duty cycle budget for too long
+    DutyCycle,
+    // Radio wasn't initialized with `radio init`
+    NotReady,
+    Radio,
+    TooLarge,
+}
+
+// Raw frame exchange for `Link::poll`, both calls must not block for long
+pub trait Transport {
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), TransportError>;
+    // Frame size, `None` if nothing was received
+    fn receive(&mut self, buf: &mut [u8; MAX_FRAME_SIZE]) -> Result<Option<usize>, TransportError>;
+}
@@ -0,0 +1,1150 @@
+use rtrs::log::console::CONSOLE_OBJECT_NAME;
+use rtrs::sync::RwLock;
+use rtrs::{object_with, object_with_mut, task_sleep, task_yield, logger, info, warn, error};
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::board::{BoardInterface, Callback};
+use crate::net::crc::crc16;
+use crate::net::link::{self, Link, LINK_OBJECT_NAME};
+use crate::net::sha256::{Sha256, DIGEST_SIZE};
+use crate::net::{cobs, PROTO_OTA_REQUEST, PROTO_OTA_STATUS};
+use crate::peripherals::flash::{self, Flash, FlashError, FLASH_OBJECT_NAME, WRITE_SIZE};
+use crate::peripherals::nvm::{self, Nvm, NvmError, NVM_OBJECT_NAME, OTA_STATE_OFFSET, OTA_STATE_SIZE};
+use crate::services::idle;
+
+logger!("ota");
+
+// Firmware updates. An image goes into the staging area in flash chunk by chunk, sent by another
+// node or by scripts/ota/ota_load.py over the console. Every chunk is answered with the offset the
+// receiver wants next, that's also how an interrupted transfer resumes.
+//
+// Header and progress are kept in the NVM region at OTA_STATE_OFFSET, state Verified marks an image
+// whose SHA-256 was checked. Activating stores state Activating, has the flash boot the staging
+// area (Flash::boot_staged, the other bank on the L073) and resets. The new firmware finds state
+// Activating and stores Idle, the staging area holds the previous firmware from then on.
+
+// Image header (little endian): magic, version, image size, SHA-256 of the image, CRC16 of all that
+pub const HEADER_SIZE: usize = 46;
+const HEADER_MAGIC: u32 = 0x5746_5452; // "RTFW"
+const HEADER_CRC_OFFSET: usize = HEADER_SIZE - 2;
+
+// Requests: proto, type, then
+//  BEGIN   image header
+//  DATA    offset (LE), image bytes
+//  FINISH  flags
+const MSG_BEGIN: u8 = 1;
+const MSG_DATA: u8 = 2;
+const MSG_FINISH: u8 = 3;
+
+const DATA_HEADER_SIZE: usize = 6;
+// Whole words, only the last chunk of an image may be shorter
+pub const CHUNK_SIZE: usize = (link::MAX_SECURE_PAYLOAD - DATA_HEADER_SIZE) / WRITE_SIZE * WRITE_SIZE;
+
+const _: () = assert!(2 + HEADER_SIZE <= link::MAX_SECURE_PAYLOAD);
+
+// Receiver installs the image right after checking it
+const FINISH_ACTIVATE: u8 = 1 << 0;
+
+// Answer to every request: proto, status, offset the receiver wants next (LE)
+const STATUS_SIZE: usize = 6;
+
+// NVM region: image header, then state, reserved, saved offset (LE) and CRC16 of those
+const HEADER_RECORD: usize = 0;
+const PROGRESS_RECORD: usize = 48;
+const PROGRESS_RECORD_SIZE: usize = 8;
+
+const _: () = assert!(HEADER_RECORD + HEADER_SIZE <= PROGRESS_RECORD);
+const _: () = assert!(PROGRESS_RECORD + PROGRESS_RECORD_SIZE <= OTA_STATE_SIZE);
+
+// Received offset is saved this often. A multiple of the flash page size, after a reset the
+// transfer continues at a page boundary and that page is erased again
+const PROGRESS_INTERVAL: u32 = 1024;
+
+// Sender other than the one that started a transfer is turned away for this long
+const SESSION_TIMEOUT_MS: u32 = 60_000;
+
+// Flash is read back in blocks this big, with a yield in between
+const VERIFY_BLOCK_SIZE: usize = 256;
+// On top of the delivery timeout, covers hashing the whole staging area on the receiver
+const VERIFY_TIMEOUT_MS: u32 = 30_000;
+
+// Sender gives up after this many requests in a row without progress
+const MAX_ATTEMPTS: u32 = 5;
+
+const POLL_MS: u32 = 10;
+// Lets the last answer go out before the reset
+const RESET_DELAY_MS: u32 = 500;
+
+// Console upload: zero-delimited COBS frames with a request and its CRC16 (LE), answered the same way
+const LOAD_FRAME_SIZE: usize = link::MAX_SECURE_PAYLOAD + 2;
+const LOAD_ENCODED_SIZE: usize = cobs::max_encoded_size(LOAD_FRAME_SIZE);
+// Console has no RX FIFO, once a frame started it's read without yielding until the line is idle
+const LOAD_BYTE_GAP_US: u64 = 1000;
+const LOAD_TIMEOUT_MS: u32 = 30_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ImageHeader {
+    pub version: u32,
+    pub size:    u32,
+    pub digest:  [u8; DIGEST_SIZE],
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+impl ImageHeader {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != HEADER_SIZE || read_u32(bytes, 0) != HEADER_MAGIC {
+            return None;
+        }
+
+        let crc = u16::from_le_bytes([bytes[HEADER_CRC_OFFSET], bytes[HEADER_CRC_OFFSET + 1]]);
+
+        if crc16(&bytes[..HEADER_CRC_OFFSET]) != crc {
+            return None;
+        }
+
+        let mut digest = [0; DIGEST_SIZE];
+        digest.copy_from_slice(&bytes[12..HEADER_CRC_OFFSET]);
+
+        Some(Self { version: read_u32(bytes, 4), size: read_u32(bytes, 8), digest })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+
+        bytes[0..4].copy_from_slice(&HEADER_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.version.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.size.to_le_bytes());
+        bytes[12..HEADER_CRC_OFFSET].copy_from_slice(&self.digest);
+
+        let crc = crc16(&bytes[..HEADER_CRC_OFFSET]);
+        bytes[HEADER_CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+
+        bytes
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+    // Send from the offset on
+    Receiving,
+    Verified,
+    // Receiver resets into the staged image
+    Activating,
+    // Image was thrown away, the transfer has to start over
+    HashMismatch,
+    // DATA or FINISH without BEGIN, for example after the receiver was reset
+    NoSession,
+    // Another sender's transfer is in progress, or the receiver is sending an image itself
+    Busy,
+    // Bad header, image doesn't fit or chunk isn't word aligned
+    Invalid,
+    // Sender has no key of its own with the receiver
+    Denied,
+    // Flash or NVM error, the offset says where to retry
+    Failed,
+    // Receiver has no staging area
+    Unsupported,
+}
+
+impl Status {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Status::Receiving),
+            1 => Some(Status::Verified),
+            2 => Some(Status::Activating),
+            3 => Some(Status::HashMismatch),
+            4 => Some(Status::NoSession),
+            5 => Some(Status::Busy),
+            6 => Some(Status::Invalid),
+            7 => Some(Status::Denied),
+            8 => Some(Status::Failed),
+            9 => Some(Status::Unsupported),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum State {
+    Idle,
+    Receiving,
+    Verified,
+    // Handed over, the staged image boots on the next reset
+    Activating,
+}
+
+impl State {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(State::Idle),
+            1 => Some(State::Receiving),
+            2 => Some(State::Verified),
+            3 => Some(State::Activating),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OtaError {
+    Unsupported,
+    NotVerified,
+    Nvm(NvmError),
+}
+
+impl From<NvmError> for OtaError {
+    fn from(err: NvmError) -> Self {
+        OtaError::Nvm(err)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Source {
+    Console,
+    Node(u8),
+}
+
+struct Receiver {
+    state:  State,
+    header: Option<ImageHeader>,
+    // Who sent BEGIN. Forgotten on reset, the sender begins again and continues at the saved offset
+    source: Option<Source>,
+    last:   u32,
+    // Bytes of the image written so far
+    next:   u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Progress {
+    pub state:    State,
+    pub header:   Option<ImageHeader>,
+    pub received: u32,
+}
+
+struct Loader {
+    frame:    heapless::Vec<u8, LOAD_ENCODED_SIZE>,
+    overflow: bool,
+    last:     u32,
+}
+
+static RECEIVER: RwLock<Receiver> = RwLock::new(Receiver { state: State::Idle, header: None, source: None, last: 0, next: 0 });
+static LOADER: RwLock<Loader> = RwLock::new(Loader { frame: heapless::Vec::new(), overflow: false, last: 0 });
+
+// Console and radio requests take turns, hashing the image yields in between
+static HANDLING: AtomicBool = AtomicBool::new(false);
+static SENDING: AtomicBool = AtomicBool::new(false);
+static LOADING: AtomicBool = AtomicBool::new(false);
+static RESET_PENDING: AtomicBool = AtomicBool::new(false);
+
+pub fn available() -> bool {
+    nvm::available() && flash::available()
+}
+
+fn store_header(header: Option<&ImageHeader>) -> Result<(), NvmError> {
+    let bytes = header.map_or([0; HEADER_SIZE], |header| header.to_bytes());
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.write(OTA_STATE_OFFSET + HEADER_RECORD, &bytes))
+}
+
+fn store_progress(state: State, offset: u32) -> Result<(), NvmError> {
+    let mut buf = [0; PROGRESS_RECORD_SIZE];
+
+    buf[0] = state as u8;
+    buf[2..6].copy_from_slice(&offset.to_le_bytes());
+
+    let crc = crc16(&buf[..6]);
+    buf[6..8].copy_from_slice(&crc.to_le_bytes());
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.write(OTA_STATE_OFFSET + PROGRESS_RECORD, &buf))
+}
+
+fn load() -> Result<Option<(State, ImageHeader, u32)>, NvmError> {
+    let mut header = [0; HEADER_SIZE];
+    let mut progress = [0; PROGRESS_RECORD_SIZE];
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.read(OTA_STATE_OFFSET + HEADER_RECORD, &mut header))?;
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.read(OTA_STATE_OFFSET + PROGRESS_RECORD, &mut progress))?;
+
+    let crc = u16::from_le_bytes([progress[6], progress[7]]);
+
+    // Never written, or torn by a reset during the write
+    let (Some(header), Some(state), true) = (ImageHeader::parse(&header), State::from_byte(progress[0]), crc16(&progress[..6]) == crc) else {
+        return Ok(None);
+    };
+
+    Ok(Some((state, header, read_u32(&progress, 2).min(header.size))))
+}
+
+// Picks up a transfer or a staged image from before the reset
+pub(crate) fn init() {
+    if !available() {
+        return;
+    }
+
+    debug_assert!(PROGRESS_INTERVAL as usize % object_with!(FLASH_OBJECT_NAME, Flash, flash, flash.page_size()) == 0);
+
+    let (state, header, next) = match load() {
+        Ok(Some(loaded)) => loaded,
+        Ok(None) => return,
+        Err(err) => {
+            error!("Loading state failed: {:?}", err);
+            return;
+        }
+    };
+
+    match state {
+        State::Idle => return,
+        State::Receiving => info!("Image v{} received up to {}/{}, continues when the sender begins again", header.version, next, header.size),
+        State::Verified => info!("Image v{} is staged, 'ota activate' installs it", header.version),
+        State::Activating => {
+            // Running it now, the staging area has the previous firmware
+            info!("Image v{} is installed", header.version);
+
+            if let Err(err) = store_progress(State::Idle, 0) {
+                error!("Saving state failed: {:?}", err);
+            }
+
+            return;
+        }
+    }
+
+    *RECEIVER.lock_mut() = Receiver { state, header: Some(header), source: None, last: 0, next };
+}
+
+pub fn progress() -> Progress {
+    let receiver = RECEIVER.lock();
+
+    Progress {
+        state:    receiver.state,
+        header:   receiver.header.filter(|_| receiver.state != State::Idle),
+        received: receiver.next,
+    }
+}
+
+fn begin(source: Source, bytes: &[u8]) -> (Status, u32) {
+    let Some(header) = ImageHeader::parse(bytes) else {
+        return (Status::Invalid, 0);
+    };
+
+    if header.size == 0 || header.size as usize > object_with!(FLASH_OBJECT_NAME, Flash, flash, flash.size()) {
+        warn!("Image of {} bytes doesn't fit", header.size);
+        return (Status::Invalid, 0);
+    }
+
+    let now = rtrs::time::global_tick();
+
+    {
+        let mut receiver = RECEIVER.lock_mut();
+
+        let other = receiver.source.is_some_and(|current| current != source);
+
+        if receiver.state == State::Receiving && other && now.wrapping_sub(receiver.last) < SESSION_TIMEOUT_MS {
+            return (Status::Busy, 0);
+        }
+
+        receiver.source = Some(source);
+        receiver.last = now;
+
+        if receiver.header == Some(header) {
+            match receiver.state {
+                State::Receiving => {
+                    info!("Resuming image v{} at {}/{}", header.version, receiver.next, header.size);
+                    return (Status::Receiving, receiver.next);
+                }
+                State::Verified => return (Status::Verified, header.size),
+                State::Activating => return (Status::Activating, header.size),
+                State::Idle => {}
+            }
+        }
+
+        // Reset into the staged image is about to happen
+        if receiver.state == State::Activating {
+            return (Status::Busy, 0);
+        }
+
+        // Whatever was staged is gone from here on
+        receiver.state = State::Idle;
+    }
+
+    if let Err(err) = store_header(Some(&header)).and_then(|_| store_progress(State::Receiving, 0)) {
+        error!("Saving header failed: {:?}", err);
+        return (Status::Failed, 0);
+    }
+
+    info!("Receiving image v{} ({} bytes) from {:?}", header.version, header.size, source);
+
+    let mut receiver = RECEIVER.lock_mut();
+
+    receiver.state = State::Receiving;
+    receiver.header = Some(header);
+    receiver.next = 0;
+
+    (Status::Receiving, 0)
+}
+
+// Image is written in order, so a page is erased when the first chunk reaches into it
+fn write_chunk(offset: usize, chunk: &[u8]) -> Result<(), FlashError> {
+    let mut words = [0; CHUNK_SIZE];
+    let len = chunk.len().next_multiple_of(WRITE_SIZE);
+
+    words[..chunk.len()].copy_from_slice(chunk);
+
+    object_with_mut!(FLASH_OBJECT_NAME, Flash, flash, {
+        let page_size = flash.page_size();
+
+        (offset.next_multiple_of(page_size)..offset + len)
+            .step_by(page_size)
+            .try_for_each(|page| flash.erase_page(page))
+            .and_then(|_| flash.write(offset, &words[..len]))
+    })
+}
+
+fn data(source: Source, request: &[u8]) -> (Status, u32) {
+    if request.len() < DATA_HEADER_SIZE {
+        return (Status::Invalid, 0);
+    }
+
+    let offset = read_u32(request, 2);
+    let chunk = &request[DATA_HEADER_SIZE..];
+
+    let (size, next) = {
+        let mut receiver = RECEIVER.lock_mut();
+
+        let Some(header) = receiver.header.filter(|_| receiver.state == State::Receiving && receiver.source == Some(source)) else {
+            return (Status::NoSession, 0);
+        };
+
+        receiver.last = rtrs::time::global_tick();
+        (header.size, receiver.next)
+    };
+
+    // Repeated or ahead of what was received, the sender continues where the answer says
+    if offset != next {
+        return (Status::Receiving, next);
+    }
+
+    let end = offset + chunk.len() as u32;
+
+    if chunk.is_empty() || chunk.len() > CHUNK_SIZE || end > size || (chunk.len() % WRITE_SIZE != 0 && end != size) {
+        return (Status::Invalid, next);
+    }
+
+    if let Err(err) = write_chunk(offset as usize, chunk) {
+        // From the start of the page, it's erased again before the retry is written
+        let page_size = object_with!(FLASH_OBJECT_NAME, Flash, flash, flash.page_size()) as u32;
+        let restart = offset - offset % page_size;
+
+        warn!("Writing at {} failed: {:?}", offset, err);
+        RECEIVER.lock_mut().next = restart;
+
+        return (Status::Failed, restart);
+    }
+
+    if end / PROGRESS_INTERVAL != offset / PROGRESS_INTERVAL || end == size {
+        let saved = if end == size { end } else { end - end % PROGRESS_INTERVAL };
+
+        // Resuming after a reset goes back further, nothing else is lost
+        if let Err(err) = store_progress(State::Receiving, saved) {
+            warn!("Saving progress failed: {:?}", err);
+        }
+    }
+
+    RECEIVER.lock_mut().next = end;
+
+    (Status::Receiving, end)
+}
+
+async fn verify(header: &ImageHeader) -> Result<bool, FlashError> {
+    let mut sha = Sha256::new();
+    let mut buf = [0; VERIFY_BLOCK_SIZE];
+    let size = header.size as usize;
+
+    for offset in (0..size).step_by(VERIFY_BLOCK_SIZE) {
+        let len = VERIFY_BLOCK_SIZE.min(size - offset);
+
+        object_with_mut!(FLASH_OBJECT_NAME, Flash, flash, flash.read(offset, &mut buf[..len]))?;
+        sha.update(&buf[..len]);
+
+        task_yield!();
+    }
+
+    Ok(sha.finish() == header.digest)
+}
+
+// Staged image takes over after the reset, see `install`
+fn hand_over(header: &ImageHeader) -> Result<(), NvmError> {
+    store_progress(State::Activating, header.size)?;
+
+    RECEIVER.lock_mut().state = State::Activating;
+    RESET_PENDING.store(true, Ordering::SeqCst);
+
+    info!("Installing image v{} after reset", header.version);
+
+    Ok(())
+}
+
+async fn finish(source: Source, request: &[u8]) -> (Status, u32) {
+    let activate = request.get(2).is_some_and(|flags| flags & FINISH_ACTIVATE != 0);
+
+    let (state, header, next) = {
+        let receiver = RECEIVER.lock();
+
+        let Some(header) = receiver.header.filter(|_| receiver.state != State::Idle && receiver.source == Some(source)) else {
+            return (Status::NoSession, 0);
+        };
+
+        (receiver.state, header, receiver.next)
+    };
+
+    if state == State::Activating {
+        return (Status::Activating, header.size);
+    }
+
+    if state == State::Receiving {
+        if next < header.size {
+            return (Status::Receiving, next);
+        }
+
+        match verify(&header).await {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Image v{} doesn't match its hash, discarded", header.version);
+
+                let _ = store_progress(State::Receiving, 0);
+                RECEIVER.lock_mut().next = 0;
+
+                return (Status::HashMismatch, 0);
+            }
+            Err(err) => {
+                warn!("Reading image failed: {:?}", err);
+                return (Status::Failed, next);
+            }
+        }
+
+        if let Err(err) = store_progress(State::Verified, header.size) {
+            error!("Saving state failed: {:?}", err);
+            return (Status::Failed, next);
+        }
+
+        RECEIVER.lock_mut().state = State::Verified;
+        info!("Image v{} verified", header.version);
+    }
+
+    if activate {
+        if let Err(err) = hand_over(&header) {
+            error!("Saving state failed: {:?}", err);
+            return (Status::Failed, header.size);
+        }
+
+        return (Status::Activating, header.size);
+    }
+
+    (Status::Verified, header.size)
+}
+
+async fn handle(source: Source, request: &[u8]) -> (Status, u32) {
+    if !available() {
+        return (Status::Unsupported, 0);
+    }
+
+    // Staging area is read by the sender meanwhile
+    if HANDLING.load(Ordering::SeqCst) || SENDING.load(Ordering::SeqCst) {
+        return (Status::Busy, 0);
+    }
+
+    HANDLING.store(true, Ordering::SeqCst);
+
+    let answer = match request.get(1) {
+        Some(&MSG_BEGIN) => begin(source, &request[2..]),
+        Some(&MSG_DATA) => data(source, request),
+        Some(&MSG_FINISH) => finish(source, request).await,
+        _ => (Status::Invalid, 0),
+    };
+
+    HANDLING.store(false, Ordering::SeqCst);
+
+    answer
+}
+
+fn status_frame(status: Status, next: u32) -> [u8; STATUS_SIZE] {
+    let mut frame = [0; STATUS_SIZE];
+
+    frame[0] = PROTO_OTA_STATUS;
+    frame[1] = status as u8;
+    frame[2..].copy_from_slice(&next.to_le_bytes());
+
+    frame
+}
+
+fn parse_status(payload: &[u8]) -> Option<(Status, u32)> {
+    if payload.len() < STATUS_SIZE || payload[0] != PROTO_OTA_STATUS {
+        return None;
+    }
+
+    Some((Status::from_byte(payload[1])?, read_u32(payload, 2)))
+}
+
+// Installs the staged image, the reset happens from the task
+pub fn activate() -> Result<(), OtaError> {
+    if !available() {
+        return Err(OtaError::Unsupported);
+    }
+
+    let Some(header) = ({
+        let receiver = RECEIVER.lock();
+        receiver.header.filter(|_| receiver.state == State::Verified)
+    }) else {
+        return Err(OtaError::NotVerified);
+    };
+
+    hand_over(&header).map_err(OtaError::Nvm)
+}
+
+// Staging area is left as it is, only the state is wiped
+pub fn clear() -> Result<(), OtaError> {
+    if !available() {
+        return Err(OtaError::Unsupported);
+    }
+
+    store_progress(State::Idle, 0)?;
+    store_header(None)?;
+
+    *RECEIVER.lock_mut() = Receiver { state: State::Idle, header: None, source: None, last: 0, next: 0 };
+
+    Ok(())
+}
+
+// Serves requests from other nodes, resets into the staged image once it was handed over
+pub(crate) async fn task() {
+    loop {
+        let request = object_with_mut!(LINK_OBJECT_NAME, Link, link, {
+            link.recv_service(PROTO_OTA_REQUEST).map(|request| {
+                let authorized = request.secured && link.security().has_peer_key(request.src);
+                (request, authorized)
+            })
+        });
+
+        // Like the remote shell: only a key shared with the sender alone allows replacing the firmware
+        if let Some((request, authorized)) = request {
+            let (status, next) = if authorized {
+                handle(Source::Node(request.src), request.payload()).await
+            } else {
+                warn!("Update from {} denied", request.src);
+                (Status::Denied, 0)
+            };
+
+            let _ = link::send_reliable(request.src, &status_frame(status, next)).await;
+        }
+
+        if !SENDING.load(Ordering::SeqCst) {
+            let _ = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_service(PROTO_OTA_STATUS));
+        }
+
+        if RESET_PENDING.load(Ordering::SeqCst) {
+            task_sleep!(RESET_DELAY_MS);
+            install();
+        }
+
+        // Requests come in through the link task, so this may sleep through STOP
+        task_sleep!(POLL_MS);
+    }
+}
+
+// Boots the handed over image, or goes back to Verified if the flash can't
+fn install() {
+    RESET_PENDING.store(false, Ordering::SeqCst);
+
+    match object_with_mut!(FLASH_OBJECT_NAME, Flash, flash, flash.boot_staged()) {
+        // Some flashes reset by themselves to get there
+        Ok(()) => BoardInterface::callback(Callback::Reset),
+        Err(err) => {
+            error!("Booting the staged image failed: {:?}", err);
+
+            let size = {
+                let mut receiver = RECEIVER.lock_mut();
+                receiver.state = State::Verified;
+                receiver.header.map_or(0, |header| header.size)
+            };
+
+            if let Err(err) = store_progress(State::Verified, size) {
+                error!("Saving state failed: {:?}", err);
+            }
+        }
+    }
+}
+
+// Console upload, takes the console over until the image is complete or the host goes quiet.
+// Needs no key, whoever has the console has the shell anyway
+pub fn start_load() -> bool {
+    if LOADING.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    *LOADER.lock_mut() = Loader { frame: heapless::Vec::new(), overflow: false, last: rtrs::time::global_tick() };
+
+    LOADING.store(true, Ordering::SeqCst);
+    true
+}
+
+pub fn loading() -> bool {
+    LOADING.load(Ordering::SeqCst)
+}
+
+fn write_console_frame(status: Status, next: u32) {
+    let mut frame = [0; STATUS_SIZE + 2];
+    let mut encoded = [0; cobs::max_encoded_size(STATUS_SIZE + 2)];
+
+    frame[..STATUS_SIZE].copy_from_slice(&status_frame(status, next));
+    let crc = crc16(&frame[..STATUS_SIZE]);
+    frame[STATUS_SIZE..].copy_from_slice(&crc.to_le_bytes());
+
+    let Some(size) = cobs::encode(&frame, &mut encoded) else {
+        return;
+    };
+
+    // Leading delimiter separates the frame from whatever was printed before it
+    object_with_mut!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty, tty, {
+        tty.write(0);
+
+        for byte in &encoded[..size] {
+            tty.write(*byte);
+        }
+
+        tty.write(0);
+    });
+}
+
+// Request of a console frame, `None` for log lines echoed back, line noise and the like
+fn decode_console_frame(encoded: &[u8], out: &mut [u8; LOAD_FRAME_SIZE]) -> Option<usize> {
+    let size = cobs::decode(encoded, out)?;
+
+    if size < 4 || out[0] != PROTO_OTA_REQUEST {
+        return None;
+    }
+
+    let crc = u16::from_le_bytes([out[size - 2], out[size - 1]]);
+
+    (crc16(&out[..size - 2]) == crc).then_some(size - 2)
+}
+
+async fn load_frame(encoded: &[u8]) {
+    let mut request = [0; LOAD_FRAME_SIZE];
+
+    let Some(size) = decode_console_frame(encoded, &mut request) else {
+        return;
+    };
+
+    LOADER.lock_mut().last = rtrs::time::global_tick();
+
+    let (status, next) = handle(Source::Console, &request[..size]).await;
+
+    write_console_frame(status, next);
+
+    if request[1] == MSG_FINISH && status != Status::Receiving && status != Status::Failed {
+        LOADING.store(false, Ordering::SeqCst);
+    }
+}
+
+// Called by the shell task instead of the shell while loading
+pub(crate) async fn load_poll() {
+    let mut idle_since = crate::time::micros();
+
+    loop {
+        let byte = object_with_mut!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty, tty, tty.read());
+
+        let Some(byte) = byte else {
+            if LOADER.lock().frame.is_empty() || crate::time::micros() - idle_since > LOAD_BYTE_GAP_US {
+                break;
+            }
+
+            continue;
+        };
+
+        idle_since = crate::time::micros();
+
+        if byte != 0 {
+            let mut loader = LOADER.lock_mut();
+
+            if loader.frame.push(byte).is_err() {
+                loader.overflow = true;
+            }
+
+            continue;
+        }
+
+        let frame = {
+            let mut loader = LOADER.lock_mut();
+            let frame = (!loader.overflow).then(|| loader.frame.clone());
+
+            loader.frame.clear();
+            loader.overflow = false;
+
+            frame
+        };
+
+        if let Some(frame) = frame.filter(|frame| !frame.is_empty()) {
+            load_frame(&frame).await;
+        }
+    }
+
+    if LOADING.load(Ordering::SeqCst) && rtrs::time::global_tick().wrapping_sub(LOADER.lock().last) > LOAD_TIMEOUT_MS {
+        warn!("Upload timed out");
+        LOADING.store(false, Ordering::SeqCst);
+    }
+}
+
+// Started with `start_send`, like ping only one at a time
+pub fn start_send() -> bool {
+    if SENDING.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    SENDING.store(true, Ordering::SeqCst);
+    true
+}
+
+async fn wait_status(dst: u8, timeout: u32) -> Option<(Status, u32)> {
+    let start = rtrs::time::global_tick();
+
+    while rtrs::time::global_tick().wrapping_sub(start) < timeout {
+        let reply = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_service(PROTO_OTA_STATUS));
+
+        match reply {
+            // Late answers to earlier requests are harmless, the receiver always says where it is
+            Some(reply) if reply.src == dst => {
+                if let Some(status) = parse_status(reply.payload()) {
+                    return Some(status);
+                }
+            }
+            Some(_) => {}
+            None => idle::sleep(POLL_MS).await,
+        }
+    }
+
+    None
+}
+
+// Next request for what the receiver said last, `None` once the transfer is over
+fn next_request(header: &ImageHeader, activate: bool, answer: (Status, u32), finished: bool, out: &mut [u8; link::MAX_SECURE_PAYLOAD]) -> Option<usize> {
+    out[0] = PROTO_OTA_REQUEST;
+
+    match answer {
+        (Status::NoSession, _) => {
+            out[1] = MSG_BEGIN;
+            out[2..2 + HEADER_SIZE].copy_from_slice(&header.to_bytes());
+
+            Some(2 + HEADER_SIZE)
+        }
+        (Status::Receiving | Status::Failed, next) if next < header.size => {
+            let len = (CHUNK_SIZE as u32).min(header.size - next) as usize;
+
+            out[1] = MSG_DATA;
+            out[2..DATA_HEADER_SIZE].copy_from_slice(&next.to_le_bytes());
+
+            object_with_mut!(FLASH_OBJECT_NAME, Flash, flash, flash.read(next as usize, &mut out[DATA_HEADER_SIZE..DATA_HEADER_SIZE + len])).ok()?;
+
+            Some(DATA_HEADER_SIZE + len)
+        }
+        (Status::Receiving | Status::Failed, _) => {
+            out[1] = MSG_FINISH;
+            out[2] = if activate { FINISH_ACTIVATE } else { 0 };
+
+            Some(3)
+        }
+        // Receiver had the image already, it may still have to be installed
+        (Status::Verified, _) if activate && !finished => {
+            out[1] = MSG_FINISH;
+            out[2] = FINISH_ACTIVATE;
+
+            Some(3)
+        }
+        _ => None,
+    }
+}
+
+// Sends the staged image to `dst`, which checks it and installs it if asked to
+pub async fn send(dst: u8, activate: bool) {
+    let header = {
+        let receiver = RECEIVER.lock();
+        receiver.header.filter(|_| receiver.state == State::Verified)
+    };
+
+    let Some(header) = header else {
+        error!("No verified image staged");
+        SENDING.store(false, Ordering::SeqCst);
+        return;
+    };
+
+    let timeout = link::delivery_timeout();
+    let start = rtrs::time::global_tick();
+
+    let mut request = [0; link::MAX_SECURE_PAYLOAD];
+    let mut answer = (Status::NoSession, 0);
+    let mut finished = false;
+    let mut attempts = 0;
+    let mut reported = 0;
+
+    info!("Sending image v{} ({} bytes) to {}", header.version, header.size, dst);
+
+    while let Some(size) = next_request(&header, activate, answer, finished, &mut request) {
+        let is_finish = request[1] == MSG_FINISH;
+        let wait = if is_finish { timeout + VERIFY_TIMEOUT_MS } else { timeout };
+
+        let reply = if link::send_reliable(dst, &request[..size]).await {
+            wait_status(dst, wait).await
+        } else {
+            None
+        };
+
+        let Some(reply) = reply else {
+            attempts += 1;
+
+            if attempts >= MAX_ATTEMPTS {
+                error!("No answer from {}, 'ota send' again to resume", dst);
+                break;
+            }
+
+            continue;
+        };
+
+        finished |= is_finish;
+
+        if reply.1 > answer.1 {
+            attempts = 0;
+        } else {
+            attempts += 1;
+        }
+
+        answer = reply;
+
+        let percent = (answer.1 as u64 * 100 / header.size as u64) as u32;
+
+        if answer.0 == Status::Receiving && percent >= reported + 10 {
+            reported = percent - percent % 10;
+            info!("{}%", reported);
+        }
+
+        if attempts >= MAX_ATTEMPTS {
+            error!("{} keeps failing at {}: {:?}", dst, answer.1, answer.0);
+            break;
+        }
+    }
+
+    let secs = rtrs::time::global_tick().wrapping_sub(start) / 1000;
+
+    match answer.0 {
+        Status::Verified => info!("Image verified on {} after {} s", dst, secs),
+        Status::Activating => info!("{} resets to install the image, took {} s", dst, secs),
+        Status::HashMismatch => error!("Image got corrupted on the way, {} discarded it", dst),
+        Status::Busy => error!("{} is receiving another image", dst),
+        Status::Invalid => error!("{} can't take the image", dst),
+        Status::Denied => error!("Access denied, {} needs a key for this node, see 'radio key set'", dst),
+        Status::Unsupported => error!("{} has no staging area", dst),
+        _ => {}
+    }
+
+    SENDING.store(false, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::task::{Context, Poll, Waker};
+    use rtrs::object_insert;
+    use crate::net::sha256;
+    use crate::peripherals::flash::FlashInterface;
+    use crate::peripherals::nvm::NvmInterface;
+
+    const PAGE_SIZE: usize = 128;
+    const AREA_SIZE: usize = 2048;
+    const NVM_SIZE: usize = OTA_STATE_OFFSET + OTA_STATE_SIZE;
+
+    // Like program flash, a word can't be written again before its page is erased
+    struct MemFlash {
+        data:    [u8; AREA_SIZE],
+        written: [bool; AREA_SIZE / WRITE_SIZE],
+    }
+
+    impl FlashInterface for MemFlash {
+        fn address(&self) -> u32 {
+            0
+        }
+
+        fn size(&self) -> usize {
+            AREA_SIZE
+        }
+
+        fn page_size(&self) -> usize {
+            PAGE_SIZE
+        }
+
+        fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), FlashError> {
+            buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+            Ok(())
+        }
+
+        fn erase_page(&mut self, offset: usize) -> Result<(), FlashError> {
+            self.data[offset..offset + PAGE_SIZE].fill(0);
+            self.written[offset / WRITE_SIZE..(offset + PAGE_SIZE) / WRITE_SIZE].fill(false);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+            let words = offset / WRITE_SIZE..(offset + data.len()) / WRITE_SIZE;
+
+            if self.written[words.clone()].contains(&true) {
+                return Err(FlashError::Write);
+            }
+
+            self.written[words].fill(true);
+            self.data[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    struct MemNvm([u8; NVM_SIZE]);
+
+    impl NvmInterface for MemNvm {
+        fn size(&self) -> usize {
+            NVM_SIZE
+        }
+
+        fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), NvmError> {
+            buf.copy_from_slice(&self.0[offset..offset + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NvmError> {
+            self.0[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    // Hashing the image yields, nothing else wakes it
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    fn request(msg: u8, body: &[u8]) -> (Status, u32) {
+        let mut frame = [0; LOAD_FRAME_SIZE];
+
+        frame[..2].copy_from_slice(&[PROTO_OTA_REQUEST, msg]);
+        frame[2..2 + body.len()].copy_from_slice(body);
+
+        block_on(handle(Source::Node(2), &frame[..2 + body.len()]))
+    }
+
+    fn chunk(image: &[u8], offset: u32) -> (Status, u32) {
+        let mut body = [0; 4 + CHUNK_SIZE];
+        let (start, end) = (offset as usize, (offset as usize + CHUNK_SIZE).min(image.len()));
+
+        body[..4].copy_from_slice(&offset.to_le_bytes());
+        body[4..4 + end - start].copy_from_slice(&image[start..end]);
+
+        request(MSG_DATA, &body[..4 + end - start])
+    }
+
+    #[test]
+    fn receives_resumes_and_discards() {
+        object_insert!(FLASH_OBJECT_NAME, Flash::new(MemFlash { data: [0; AREA_SIZE], written: [false; AREA_SIZE / WRITE_SIZE] }));
+        object_insert!(NVM_OBJECT_NAME, Nvm::new(MemNvm([0; NVM_SIZE])));
+
+        // Last chunk is short and not whole words
+        let image: [u8; 1502] = core::array::from_fn(|i| (i * 7 + i / 256) as u8);
+        let header = ImageHeader { version: 2, size: image.len() as u32, digest: sha256::digest(&image) };
+        let size = header.size;
+
+        assert_eq!(request(MSG_DATA, &[0; 8]), (Status::NoSession, 0));
+        assert_eq!(request(MSG_BEGIN, &header.to_bytes()), (Status::Receiving, 0));
+
+        // Every chunk is answered with the offset wanted next, a repeated one too
+        let mut offset = 0;
+
+        while offset < 1100 {
+            let next = offset + CHUNK_SIZE as u32;
+
+            assert_eq!(chunk(&image, offset), (Status::Receiving, next));
+            assert_eq!(chunk(&image, offset), (Status::Receiving, next));
+
+            offset = next;
+        }
+
+        assert_eq!(chunk(&image, offset + CHUNK_SIZE as u32), (Status::Receiving, offset));
+
+        // Reset: progress was saved at the last 1 KB boundary, the words after it are written already
+        *RECEIVER.lock_mut() = Receiver { state: State::Idle, header: None, source: None, last: 0, next: 0 };
+        init();
+
+        assert_eq!(request(MSG_BEGIN, &header.to_bytes()), (Status::Receiving, PROGRESS_INTERVAL));
+
+        // Fails to write unless the page at the boundary is erased again
+        let mut offset = PROGRESS_INTERVAL;
+
+        while offset < size {
+            let (status, next) = chunk(&image, offset);
+
+            assert_eq!(status, Status::Receiving);
+            offset = next;
+        }
+
+        assert_eq!(offset, size);
+        assert_eq!(request(MSG_FINISH, &[]), (Status::Verified, size));
+        assert_eq!(progress().state, State::Verified);
+
+        let mut staged = [0; 1502];
+        object_with_mut!(FLASH_OBJECT_NAME, Flash, flash, flash.read(0, &mut staged)).unwrap();
+        assert_eq!(staged, image);
+
+        // Handed over and marked, but this flash can't boot it, so it stays staged
+        assert_eq!(request(MSG_FINISH, &[FINISH_ACTIVATE]), (Status::Activating, size));
+        assert_eq!(load().unwrap().map(|(state, ..)| state), Some(State::Activating));
+
+        install();
+
+        assert_eq!(load().unwrap().map(|(state, ..)| state), Some(State::Verified));
+        assert_eq!(progress().state, State::Verified);
+
+        // Booted into it, the new firmware finds the mark
+        activate().unwrap();
+
+        *RECEIVER.lock_mut() = Receiver { state: State::Idle, header: None, source: None, last: 0, next: 0 };
+        init();
+
+        assert_eq!(progress().state, State::Idle);
+        assert_eq!(load().unwrap().map(|(state, ..)| state), Some(State::Idle));
+
+        // Corrupted on the way: thrown away, the sender starts over
+        let mut corrupted = image;
+        corrupted[700] ^= 0x01;
+
+        let header = ImageHeader { version: 3, ..header };
+        assert_eq!(request(MSG_BEGIN, &header.to_bytes()), (Status::Receiving, 0));
+
+        let mut offset = 0;
+
+        while offset < size {
+            offset = chunk(&corrupted, offset).1;
+        }
+
+        assert_eq!(request(MSG_FINISH, &[]), (Status::HashMismatch, 0));
+        assert_eq!((progress().state, progress().received), (State::Receiving, 0));
+        assert_eq!(chunk(&image, CHUNK_SIZE as u32), (Status::Receiving, 0));
+
+        // Nothing of it is resumed after a reset either
+        *RECEIVER.lock_mut() = Receiver { state: State::Idle, header: None, source: None, last: 0, next: 0 };
+        init();
+
+        assert_eq!(progress().received, 0);
+    }
+}
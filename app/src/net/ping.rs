@@ -0,0 +1,248 @@
+use rtrs::{object_with_mut, print, println, task_sleep, logger, warn, error};
+
+use core::fmt::Write; // For println!
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::net::link::{Link, Packet, LINK_OBJECT_NAME};
+use crate::net::{airtime, config, PROTO_PING_REPLY, PROTO_PING_REQUEST, MAX_FRAME_SIZE};
+use crate::services::idle;
+
+logger!("ping");
+
+pub const DEFAULT_COUNT: u32 = 4;
+pub const DEFAULT_INTERVAL_MS: u32 = 1000;
+
+const POLL_MS: u32 = 10;
+
+// On top of the airtime of a request and its reply, covers link task RX windows on both ends
+const REPLY_MARGIN_MS: u32 = 500;
+
+// Request: proto, id, sender tick (LE)
+const REQUEST_SIZE: usize = 6;
+// Reply: request echoed, then how the responder heard it: RSSI (LE), SNR in quarter dB, valid flag
+const REPLY_SIZE: usize = REQUEST_SIZE + 4;
+
+static RESPONDER: AtomicBool = AtomicBool::new(false);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub fn responder() -> bool {
+    RESPONDER.load(Ordering::SeqCst)
+}
+
+pub fn set_responder(enabled: bool) {
+    RESPONDER.store(enabled, Ordering::SeqCst);
+}
+
+// Only one ping at a time, replies aren't told apart by session
+pub fn start() -> bool {
+    if RUNNING.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    RUNNING.store(true, Ordering::SeqCst);
+    true
+}
+
+// Quarter dB steps
+struct Snr(i32);
+
+impl fmt::Display for Snr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+
+        write!(f, "{}{}.{:02}", sign, abs / 4, abs % 4 * 25)
+    }
+}
+
+#[derive(Default)]
+struct Range {
+    min:   i32,
+    max:   i32,
+    sum:   i32,
+    count: i32,
+}
+
+impl Range {
+    fn add(&mut self, value: i32) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+
+        if self.count == 0 || value > self.max {
+            self.max = value;
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn avg(&self) -> i32 {
+        if self.count == 0 { 0 } else { self.sum / self.count }
+    }
+}
+
+#[derive(Default)]
+struct Signal {
+    rssi: Range,
+    snr:  Range,
+}
+
+impl Signal {
+    fn add(&mut self, rssi: i16, snr_x4: i8) {
+        self.rssi.add(rssi as i32);
+        self.snr.add(snr_x4 as i32);
+    }
+
+    fn print(&self, side: &str) {
+        if self.rssi.count == 0 {
+            return;
+        }
+
+        println!(
+            "{} RSSI min/avg/max = {}/{}/{} dBm, SNR min/avg/max = {}/{}/{} dB",
+            side, self.rssi.min, self.rssi.avg(), self.rssi.max,
+            Snr(self.snr.min), Snr(self.snr.avg()), Snr(self.snr.max)
+        );
+    }
+}
+
+fn respond(request: &Packet) {
+    let payload = request.payload();
+
+    if payload.len() < REQUEST_SIZE {
+        return;
+    }
+
+    let mut reply = [0; REPLY_SIZE];
+
+    reply[..REQUEST_SIZE].copy_from_slice(&payload[..REQUEST_SIZE]);
+    reply[0] = PROTO_PING_REPLY;
+
+    if let Some(info) = request.info {
+        reply[6..8].copy_from_slice(&info.rssi_dbm.to_le_bytes());
+        reply[8] = info.snr_x4 as u8;
+        reply[9] = 1;
+    }
+
+    if let Err(err) = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.send_datagram(request.src, &reply)) {
+        warn!("Reply to {} dropped: {:?}", request.src, err);
+    }
+}
+
+// Answers requests if the responder is enabled, drops replies nobody waits for
+pub(crate) async fn task() {
+    loop {
+        let request = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_service(PROTO_PING_REQUEST));
+
+        if let Some(request) = request {
+            if responder() {
+                respond(&request);
+            }
+        }
+
+        if !RUNNING.load(Ordering::SeqCst) {
+            let _ = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_service(PROTO_PING_REPLY));
+        }
+
+        // Requests come in through the link task, so this may sleep through STOP
+        task_sleep!(POLL_MS);
+    }
+}
+
+// Waits for the reply to request `id`, stale replies of earlier requests are dropped
+async fn wait_reply(dst: u8, id: u8, timeout: u32) -> Option<Packet> {
+    let start = rtrs::time::global_tick();
+
+    while rtrs::time::global_tick().wrapping_sub(start) < timeout {
+        let reply = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_service(PROTO_PING_REPLY));
+
+        match reply {
+            Some(reply) if reply.src == dst && reply.payload().len() >= REPLY_SIZE && reply.payload()[1] == id => {
+                return Some(reply);
+            }
+            Some(_) => {}
+            None => idle::sleep(POLL_MS).await,
+        }
+    }
+
+    None
+}
+
+// Started with `start`, prints a line per reply and a summary like Unix ping
+pub async fn run(dst: u8, count: u32, interval_ms: u32) {
+    let airtime_ms = airtime::time_on_air_us(&config::current(), MAX_FRAME_SIZE) / 1000;
+    let timeout = 2 * airtime_ms + REPLY_MARGIN_MS;
+
+    let mut sent = 0;
+    let mut rtt = Range::default();
+    let mut local = Signal::default();
+    let mut remote = Signal::default();
+
+    println!("PING {}: {} bytes, timeout {} ms", dst, REQUEST_SIZE, timeout);
+
+    for i in 0..count {
+        let id = i as u8;
+        let start = rtrs::time::global_tick();
+
+        let mut request = [0; REQUEST_SIZE];
+        request[0] = PROTO_PING_REQUEST;
+        request[1] = id;
+        request[2..6].copy_from_slice(&start.to_le_bytes());
+
+        match object_with_mut!(LINK_OBJECT_NAME, Link, link, link.send_datagram(dst, &request)) {
+            Ok(_) => sent += 1,
+            Err(err) => error!("seq={}: {:?}", i, err),
+        }
+
+        match wait_reply(dst, id, timeout).await {
+            Some(reply) => {
+                let payload = reply.payload();
+                let sent_at = u32::from_le_bytes([payload[2], payload[3], payload[4], payload[5]]);
+                let time = rtrs::time::global_tick().wrapping_sub(sent_at);
+
+                rtt.add(time as i32);
+
+                print!("{} bytes from {}: seq={} time={} ms", payload.len(), dst, i, time);
+
+                if let Some(info) = reply.info {
+                    local.add(info.rssi_dbm, info.snr_x4);
+                    print!(", local {} dBm/{} dB", info.rssi_dbm, Snr(info.snr_x4 as i32));
+                }
+
+                if payload[9] != 0 {
+                    let rssi = i16::from_le_bytes([payload[6], payload[7]]);
+                    let snr = payload[8] as i8;
+
+                    remote.add(rssi, snr);
+                    print!(", remote {} dBm/{} dB", rssi, Snr(snr as i32));
+                }
+
+                println!();
+            }
+            None => println!("Request timeout for seq={}", i),
+        }
+
+        let elapsed = rtrs::time::global_tick().wrapping_sub(start);
+
+        if i + 1 < count && elapsed < interval_ms {
+            idle::sleep(interval_ms - elapsed).await;
+        }
+    }
+
+    let received = rtt.count as u32;
+    let loss = if sent == 0 { 100 } else { sent.saturating_sub(received) * 100 / sent };
+
+    println!("--- {} ping statistics ---", dst);
+    println!("{} packets transmitted, {} received, {}% packet loss", sent, received, loss);
+
+    if received > 0 {
+        println!("rtt min/avg/max = {}/{}/{} ms", rtt.min, rtt.avg(), rtt.max);
+    }
+
+    local.print("local ");
+    remote.print("remote");
+
+    RUNNING.store(false, Ordering::SeqCst);
+}
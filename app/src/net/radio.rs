@@ -0,0 +1,366 @@
+use rtrs::sync::RwLock;
+use rtrs::{object_with_mut, task_yield, logger, info, error};
+use rtrs_drivers::radio::{Radio, RadioError};
+
+extern crate alloc;
+use alloc::boxed::Box;
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::net::airtime::{self, DutyCycle, DEFAULT_DUTY_CYCLE_PERMILLE};
+use crate::net::config::{self, ConfigError};
+use crate::net::{TransportError, MAX_FRAME_SIZE};
+use crate::peripherals::exti::{self, ExtiError, RADIO_DIO0_NAME};
+use crate::services::{battery, idle};
+
+logger!("radio");
+
+pub const RADIO_OBJECT_NAME: &str = "radio";
+
+// Signal quality of received packets, see SignalInterface
+pub const RADIO_SIGNAL_OBJECT_NAME: &str = "radio_signal";
+
+// TxDone comes this long after the time on air at most (mode switches, PA ramp)
+const TX_DONE_MARGIN_MS: u32 = 100;
+
+// Longer waits for duty cycle budget fail the transmission, the caller may retry later
+const MAX_DUTY_CYCLE_WAIT_MS: u32 = 10_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PacketInfo {
+    pub rssi_dbm: i16,
+    // Quarter dB steps
+    pub snr_x4:   i8,
+    // Carrier frequency error
+    pub fei_hz:   i32,
+}
+
+impl fmt::Display for PacketInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let snr = self.snr_x4.unsigned_abs();
+        let sign = if self.snr_x4 < 0 { "-" } else { "" };
+
+        write!(
+            f, "RSSI {} dBm, SNR {}{}.{:02} dB, FEI {} Hz",
+            self.rssi_dbm, sign, snr / 4, snr % 4 * 25, self.fei_hz
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SignalError {
+    Bus,
+}
+
+// Modem specific readout of the last received packet, implemented by the target next to the
+// driver until rtrs-drivers' Radio provides it. Only valid right after a packet was received
+pub trait SignalInterface {
+    // Corrected for the noise floor, as the datasheet specifies for the modem
+    fn rssi_dbm(&mut self) -> Result<i16, SignalError>;
+    fn snr_x4(&mut self) -> Result<i8, SignalError>;
+    // Carrier frequency error, its scale depends on the bandwidth
+    fn fei_hz(&mut self, bandwidth_hz: u32) -> Result<i32, SignalError>;
+    // Whether the last reception failed its payload CRC, as opposed to the window expiring
+    fn crc_error(&mut self) -> Result<bool, SignalError>;
+}
+
+pub struct RadioSignal {
+    ifc: Box<dyn SignalInterface + Send + Sync + 'static>,
+}
+
+impl RadioSignal {
+    pub fn new(ifc: impl SignalInterface + Send + Sync + 'static) -> Self {
+        Self { ifc: Box::new(ifc) }
+    }
+
+    pub fn packet_info(&mut self, bandwidth_hz: u32) -> Result<PacketInfo, SignalError> {
+        Ok(PacketInfo {
+            rssi_dbm: self.ifc.rssi_dbm()?,
+            snr_x4:   self.ifc.snr_x4()?,
+            fei_hz:   self.ifc.fei_hz(bandwidth_hz)?,
+        })
+    }
+
+    pub fn crc_error(&mut self) -> Result<bool, SignalError> {
+        self.ifc.crc_error()
+    }
+}
+
+impl rtrs::object::Object for RadioSignal {}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RadioStats {
+    pub tx:                  u32,
+    pub rx:                  u32,
+    pub crc_errors:          u32,
+    pub timeouts:            u32,
+    pub tx_errors:           u32,
+    pub duty_cycle_delays:   u32,
+    pub duty_cycle_refusals: u32,
+    pub airtime_us:          u64,
+    pub last:                Option<PacketInfo>,
+}
+
+impl RadioStats {
+    const fn new() -> Self {
+        Self {
+            tx:                  0,
+            rx:                  0,
+            crc_errors:          0,
+            timeouts:            0,
+            tx_errors:           0,
+            duty_cycle_delays:   0,
+            duty_cycle_refusals: 0,
+            airtime_us:          0,
+            last:                None,
+        }
+    }
+}
+
+static STATS: RwLock<RadioStats> = RwLock::new(RadioStats::new());
+static DUTY_CYCLE: RwLock<DutyCycle> = RwLock::new(DutyCycle::new(DEFAULT_DUTY_CYCLE_PERMILLE));
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+// Driver keeps state of the operation in progress, so only one task may use it at a time.
+// Tasks are cooperative, load and store can't be interleaved with another task
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+struct RadioGuard;
+
+impl Drop for RadioGuard {
+    fn drop(&mut self) {
+        BUSY.store(false, Ordering::SeqCst);
+    }
+}
+
+fn try_acquire() -> Option<RadioGuard> {
+    if BUSY.load(Ordering::SeqCst) {
+        return None;
+    }
+
+    BUSY.store(true, Ordering::SeqCst);
+    Some(RadioGuard)
+}
+
+// Configuration changes are applied here, between operations
+async fn acquire() -> RadioGuard {
+    loop {
+        if let Some(guard) = try_acquire() {
+            if ready() {
+                config::apply_pending();
+            }
+
+            return guard;
+        }
+
+        task_yield!();
+    }
+}
+
+// Sleeps until DIO0 signals TxDone/RxDone, targets without the line poll on every scheduler pass.
+// DIO0 stays high until the driver clears the IRQ, so a signal before the wait isn't missed.
+// Timeout only covers the driver's own deadlines (RX window), MCU can STOP until then
+async fn wait_dio0(timeout_ms: u32) {
+    if let Err(ExtiError::NoLine) = exti::wait_for_level(RADIO_DIO0_NAME, true, Some(timeout_ms)).await {
+        task_yield!();
+    }
+}
+
+pub fn ready() -> bool {
+    READY.load(Ordering::SeqCst)
+}
+
+// Synchronous, so a script can use the radio on the next line
+pub fn init() -> Result<(), ConfigError> {
+    let _guard = try_acquire().ok_or(ConfigError::Busy)?;
+
+    let config = config::take_current();
+
+    object_with_mut!(RADIO_OBJECT_NAME, Radio, radio, {
+        radio.init().map_err(|_| ConfigError::Init)?;
+        config.apply(radio)
+    })?;
+
+    READY.store(true, Ordering::SeqCst);
+
+    info!("Initialized, {}", config);
+
+    Ok(())
+}
+
+// Conditions `send` would fail on right now, so a command can report them before it goes to the background
+pub fn check_send(size: usize) -> Result<(), TransportError> {
+    if size > MAX_FRAME_SIZE {
+        return Err(TransportError::TooLarge);
+    }
+
+    if !ready() {
+        return Err(TransportError::NotReady);
+    }
+
+    if battery::brownout_risk() {
+        return Err(TransportError::Brownout);
+    }
+
+    let airtime = airtime::time_on_air_us(&config::current(), size);
+
+    match DUTY_CYCLE.lock_mut().wait_ms(rtrs::time::global_tick(), airtime) {
+        Some(ms) if ms <= MAX_DUTY_CYCLE_WAIT_MS => Ok(()),
+        _ => Err(TransportError::DutyCycle),
+    }
+}
+
+// Waits for duty cycle budget without holding the radio, then transmits.
+// Radio object is released between polls, so other tasks and the shell keep running during TX
+pub async fn send(frame: &[u8]) -> Result<(), TransportError> {
+    if frame.len() > MAX_FRAME_SIZE {
+        return Err(TransportError::TooLarge);
+    }
+
+    let airtime = airtime::time_on_air_us(&config::current(), frame.len());
+    let mut delayed = false;
+
+    loop {
+        let wait = DUTY_CYCLE.lock_mut().wait_ms(rtrs::time::global_tick(), airtime);
+
+        match wait {
+            Some(0) => break,
+            Some(ms) if ms <= MAX_DUTY_CYCLE_WAIT_MS => {
+                if !delayed {
+                    STATS.lock_mut().duty_cycle_delays += 1;
+                    delayed = true;
+                }
+
+                idle::sleep(ms).await;
+            }
+            _ => {
+                STATS.lock_mut().duty_cycle_refusals += 1;
+                return Err(TransportError::DutyCycle);
+            }
+        }
+    }
+
+    let _guard = acquire().await;
+
+    // TX current may pull VDD below brownout reset level
+    if battery::brownout_risk() {
+        return Err(TransportError::Brownout);
+    }
+
+    let tx_timeout = airtime / 1000 + TX_DONE_MARGIN_MS;
+
+    loop {
+        let result = object_with_mut!(RADIO_OBJECT_NAME, Radio, radio, radio.send(frame));
+
+        match result {
+            Ok(()) => {
+                DUTY_CYCLE.lock_mut().consume(rtrs::time::global_tick(), airtime);
+
+                let mut stats = STATS.lock_mut();
+                stats.tx += 1;
+                stats.airtime_us += airtime as u64;
+
+                return Ok(());
+            }
+            Err(RadioError::InProgress) => wait_dio0(tx_timeout).await,
+            Err(err) => {
+                error!("TX error: {:?}", err);
+                STATS.lock_mut().tx_errors += 1;
+                return Err(TransportError::Radio);
+            }
+        }
+    }
+}
+
+// Frame size and its signal quality (if the modem registers can be read).
+// RX window expiring is reported as an error by the driver
+pub async fn recv(buf: &mut [u8; MAX_FRAME_SIZE], timeout_ms: u32) -> Result<(usize, Option<PacketInfo>), TransportError> {
+    let _guard = acquire().await;
+
+    let start = rtrs::time::global_tick();
+
+    loop {
+        let done = object_with_mut!(RADIO_OBJECT_NAME, Radio, radio, {
+            match radio.recv(rtrs::time::Timeout::new(timeout_ms)) {
+                Ok((bytes, size)) => {
+                    let size = size.min(MAX_FRAME_SIZE);
+                    buf[..size].copy_from_slice(&bytes[..size]);
+                    Some(Ok(size))
+                }
+                Err(RadioError::InProgress) => None,
+                // Driver reports an expired RX window as an error too, so it isn't logged
+                Err(_) => Some(Err(TransportError::Radio)),
+            }
+        });
+
+        match done {
+            Some(Ok(size)) => {
+                let info = packet_info();
+
+                let mut stats = STATS.lock_mut();
+                stats.rx += 1;
+                stats.last = info;
+
+                return Ok((size, info));
+            }
+            Some(Err(err)) => {
+                let crc_error = crc_error();
+                let mut stats = STATS.lock_mut();
+
+                // Driver returns the same error for both, the modem's IRQ flags tell them apart
+                if crc_error {
+                    stats.crc_errors += 1;
+                } else {
+                    stats.timeouts += 1;
+                }
+
+                return Err(err);
+            }
+            None => {
+                let elapsed = rtrs::time::global_tick().wrapping_sub(start);
+                wait_dio0(timeout_ms.saturating_sub(elapsed)).await;
+            }
+        }
+    }
+}
+
+// Whether the last reception failed its CRC, radio has to be acquired
+fn crc_error() -> bool {
+    if !crate::object_exists(RADIO_SIGNAL_OBJECT_NAME) {
+        return false;
+    }
+
+    object_with_mut!(RADIO_SIGNAL_OBJECT_NAME, RadioSignal, signal, signal.crc_error()).unwrap_or(false)
+}
+
+// Signal quality of the last received packet, radio has to be acquired
+fn packet_info() -> Option<PacketInfo> {
+    if !crate::object_exists(RADIO_SIGNAL_OBJECT_NAME) {
+        return None;
+    }
+
+    let bandwidth_hz = config::current().bandwidth_hz;
+
+    object_with_mut!(RADIO_SIGNAL_OBJECT_NAME, RadioSignal, signal, signal.packet_info(bandwidth_hz)).ok()
+}
+
+pub fn stats() -> RadioStats {
+    *STATS.lock()
+}
+
+pub fn reset_stats() {
+    *STATS.lock_mut() = RadioStats::new();
+}
+
+pub fn duty_cycle() -> (u32, u64) {
+    let mut duty = DUTY_CYCLE.lock_mut();
+    let now = rtrs::time::global_tick();
+
+    (duty.permille(), duty.available_us(now))
+}
+
+pub fn set_duty_cycle(permille: u32) {
+    DUTY_CYCLE.lock_mut().set_permille(permille);
+}
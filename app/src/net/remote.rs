@@ -0,0 +1,370 @@
+use rtrs::sync::RwLock;
+use rtrs::tty::TtyBackend;
+use rtrs::{object_with_mut, print, println, task_sleep, logger, info, warn, error};
+
+use core::fmt::Write; // For println!
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::cmd::MAX_COMMAND_SIZE;
+use crate::net::link::{self, Link, Packet, LINK_OBJECT_NAME};
+use crate::net::{PROTO_REMOTE_OUTPUT, PROTO_REMOTE_REQUEST};
+use crate::services::idle;
+
+logger!("remote");
+
+// Request: proto, session, command
+const REQUEST_HEADER_SIZE: usize = 2;
+
+pub const MAX_COMMAND: usize = link::MAX_SECURE_PAYLOAD - REQUEST_HEADER_SIZE;
+
+// Output: proto, session, fragment index, flags, then console output
+const OUTPUT_HEADER_SIZE: usize = 4;
+const FRAGMENT_SIZE: usize = link::MAX_SECURE_PAYLOAD - OUTPUT_HEADER_SIZE;
+
+const FLAG_LAST: u8 = 1 << 0;
+// Requester has no key of its own with this node
+const FLAG_DENIED: u8 = 1 << 1;
+// Another remote command is still running
+const FLAG_BUSY: u8 = 1 << 2;
+// Output didn't fit CAPTURE_SIZE
+const FLAG_TRUNCATED: u8 = 1 << 3;
+// Command only runs from the console, see LOCAL_ONLY
+const FLAG_LOCAL_ONLY: u8 = 1 << 4;
+
+// Commands (with a subcommand, if given) that own the console or run until a key is pressed on it.
+// Run remotely they'd hold the shell until someone at the serial port stops them
+const LOCAL_ONLY: [(&str, Option<&str>); 6] = [
+    ("buzz", None),
+    ("test", None),
+    ("adc", Some("stream")),
+    ("gpio", Some("watch")),
+    ("radio", Some("sniff")),
+    ("ota", Some("load")),
+];
+
+// Everything a command prints beyond this is dropped
+const CAPTURE_SIZE: usize = 512;
+
+const POLL_MS: u32 = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    // Waiting for the shell task
+    Queued,
+    Running,
+    Sending,
+}
+
+struct Session {
+    state:   State,
+    src:     u8,
+    id:      u8,
+    command: heapless::String<MAX_COMMAND_SIZE>,
+}
+
+struct Output {
+    data:      heapless::Vec<u8, CAPTURE_SIZE>,
+    truncated: bool,
+}
+
+static SESSION: RwLock<Session> = RwLock::new(Session { state: State::Idle, src: 0, id: 0, command: heapless::String::new() });
+static OUTPUT: RwLock<Output> = RwLock::new(Output { data: heapless::Vec::new(), truncated: false });
+
+// Checked for every console byte, the lock is only taken while a command runs
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+// Requester side, like ping only one at a time
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU8 = AtomicU8::new(0);
+
+// Console backend wrapper: output of remote commands goes to the serial port and to the requester
+pub struct CaptureBackend<B: TtyBackend> {
+    inner: B,
+}
+
+impl<B: TtyBackend> CaptureBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self { inner }
+    }
+}
+
+impl<B: TtyBackend> TtyBackend for CaptureBackend<B> {
+    fn read(&mut self) -> Option<u8> {
+        self.inner.read()
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.inner.write(byte);
+
+        if CAPTURING.load(Ordering::SeqCst) {
+            let mut output = OUTPUT.lock_mut();
+
+            if output.data.push(byte).is_err() {
+                output.truncated = true;
+            }
+        }
+    }
+}
+
+// Every command of a script is checked, the shell runs them all
+pub fn runs_remotely(command: &str) -> bool {
+    command.split([';', '\n']).all(|command| {
+        let mut words = command.split_whitespace();
+        let (name, sub) = (words.next(), words.next());
+
+        !LOCAL_ONLY.iter().any(|(n, s)| name == Some(*n) && (s.is_none() || sub == *s))
+    })
+}
+
+// Picked up by the shell task, which runs it between `start_capture` and `stop_capture`
+pub(crate) fn take_command() -> Option<heapless::String<MAX_COMMAND_SIZE>> {
+    let mut session = SESSION.lock_mut();
+
+    if session.state != State::Queued {
+        return None;
+    }
+
+    session.state = State::Running;
+    Some(session.command.clone())
+}
+
+pub(crate) fn start_capture() {
+    let mut output = OUTPUT.lock_mut();
+
+    output.data.clear();
+    output.truncated = false;
+
+    CAPTURING.store(true, Ordering::SeqCst);
+}
+
+// Output goes out from the remote task
+pub(crate) fn stop_capture() {
+    CAPTURING.store(false, Ordering::SeqCst);
+    SESSION.lock_mut().state = State::Sending;
+}
+
+async fn reply(dst: u8, id: u8, flags: u8) {
+    let _ = link::send_reliable(dst, &[PROTO_REMOTE_OUTPUT, id, 0, FLAG_LAST | flags]).await;
+}
+
+// Requests are only run if the link authenticated them with a key shared with the requester alone,
+// holders of the network key can't run commands. Replays were already dropped by the link
+async fn handle_request(request: Packet, authorized: bool) {
+    let payload = request.payload();
+
+    if payload.len() < REQUEST_HEADER_SIZE {
+        return;
+    }
+
+    let id = payload[1];
+
+    if !authorized {
+        warn!("Command from {} denied", request.src);
+        reply(request.src, id, FLAG_DENIED).await;
+        return;
+    }
+
+    let Some(command) = core::str::from_utf8(&payload[REQUEST_HEADER_SIZE..]).ok().and_then(|cmd| heapless::String::try_from(cmd).ok()) else {
+        return;
+    };
+
+    if !runs_remotely(&command) {
+        warn!("Command from {} only runs locally: '{}'", request.src, command);
+        reply(request.src, id, FLAG_LOCAL_ONLY).await;
+        return;
+    }
+
+    {
+        let mut session = SESSION.lock_mut();
+
+        if session.state == State::Idle {
+            info!("Command from {}: '{}'", request.src, command);
+            *session = Session { state: State::Queued, src: request.src, id, command };
+            return;
+        }
+    }
+
+    reply(request.src, id, FLAG_BUSY).await;
+}
+
+// Sends captured output in fragments, each one acknowledged before the next
+async fn send_output() {
+    let (dst, id) = {
+        let session = SESSION.lock();
+        (session.src, session.id)
+    };
+
+    let (size, truncated) = {
+        let output = OUTPUT.lock();
+        (output.data.len(), output.truncated)
+    };
+
+    let fragments = size.div_ceil(FRAGMENT_SIZE).max(1);
+
+    for index in 0..fragments {
+        let mut frame = [0; link::MAX_SECURE_PAYLOAD];
+        let start = index * FRAGMENT_SIZE;
+        let end = (start + FRAGMENT_SIZE).min(size);
+
+        let mut flags = 0;
+
+        if index + 1 == fragments {
+            flags |= FLAG_LAST;
+
+            if truncated {
+                flags |= FLAG_TRUNCATED;
+            }
+        }
+
+        frame[..OUTPUT_HEADER_SIZE].copy_from_slice(&[PROTO_REMOTE_OUTPUT, id, index as u8, flags]);
+        frame[OUTPUT_HEADER_SIZE..OUTPUT_HEADER_SIZE + end - start].copy_from_slice(&OUTPUT.lock().data[start..end]);
+
+        if !link::send_reliable(dst, &frame[..OUTPUT_HEADER_SIZE + end - start]).await {
+            warn!("Output to {} lost at fragment {}", dst, index);
+            break;
+        }
+    }
+
+    SESSION.lock_mut().state = State::Idle;
+}
+
+// Serves requests from other nodes and drops output nobody waits for
+pub(crate) async fn task() {
+    loop {
+        let request = object_with_mut!(LINK_OBJECT_NAME, Link, link, {
+            link.recv_service(PROTO_REMOTE_REQUEST).map(|request| {
+                let authorized = request.secured && link.security().has_peer_key(request.src);
+                (request, authorized)
+            })
+        });
+
+        if let Some((request, authorized)) = request {
+            handle_request(request, authorized).await;
+        }
+
+        if SESSION.lock().state == State::Sending {
+            send_output().await;
+        }
+
+        if !RUNNING.load(Ordering::SeqCst) {
+            let _ = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_service(PROTO_REMOTE_OUTPUT));
+        }
+
+        // Requests come in through the link task, so this may sleep through STOP
+        task_sleep!(POLL_MS);
+    }
+}
+
+// Only one command at a time, output isn't told apart by requester
+pub fn start() -> bool {
+    if RUNNING.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    RUNNING.store(true, Ordering::SeqCst);
+    true
+}
+
+fn print_output(data: &[u8]) {
+    match core::str::from_utf8(data) {
+        Ok(text) => print!("{}", text),
+        // Fragment boundary split a character, or the command printed binary data
+        Err(_) => {
+            for byte in data {
+                print!("{}", *byte as char);
+            }
+        }
+    }
+}
+
+// Started with `start`, prints the output of `command` on `dst` as it arrives
+pub async fn run(dst: u8, command: heapless::String<MAX_COMMAND>) {
+    let id = NEXT_ID.load(Ordering::SeqCst);
+    NEXT_ID.store(id.wrapping_add(1), Ordering::SeqCst);
+
+    let mut request = [0; link::MAX_SECURE_PAYLOAD];
+    let size = REQUEST_HEADER_SIZE + command.len();
+
+    request[..REQUEST_HEADER_SIZE].copy_from_slice(&[PROTO_REMOTE_REQUEST, id]);
+    request[REQUEST_HEADER_SIZE..size].copy_from_slice(command.as_bytes());
+
+    if !link::send_reliable(dst, &request[..size]).await {
+        error!("Request to {} not delivered", dst);
+        RUNNING.store(false, Ordering::SeqCst);
+        return;
+    }
+
+    let timeout = link::delivery_timeout();
+    let mut last = rtrs::time::global_tick();
+    let mut expected = 0u8;
+
+    loop {
+        if rtrs::time::global_tick().wrapping_sub(last) > timeout {
+            error!("Timeout");
+            break;
+        }
+
+        let output = object_with_mut!(LINK_OBJECT_NAME, Link, link, link.recv_service(PROTO_REMOTE_OUTPUT));
+
+        let Some(output) = output else {
+            idle::sleep(POLL_MS).await;
+            continue;
+        };
+
+        let payload = output.payload();
+
+        // Stale output of an earlier request, or somebody else's
+        if output.src != dst || payload.len() < OUTPUT_HEADER_SIZE || payload[1] != id {
+            continue;
+        }
+
+        last = rtrs::time::global_tick();
+
+        let (index, flags) = (payload[2], payload[3]);
+
+        if index != expected {
+            println!();
+            warn!("Output fragments {}..{} missing", expected, index);
+        }
+
+        expected = index.wrapping_add(1);
+
+        print_output(&payload[OUTPUT_HEADER_SIZE..]);
+
+        if flags & FLAG_LAST == 0 {
+            continue;
+        }
+
+        if flags & FLAG_DENIED != 0 {
+            error!("Access denied, {} needs a key for this node, see 'radio key set'", dst);
+        } else if flags & FLAG_BUSY != 0 {
+            error!("{} is running another remote command", dst);
+        } else if flags & FLAG_LOCAL_ONLY != 0 {
+            error!("Command only runs from the console of {}", dst);
+        } else if flags & FLAG_TRUNCATED != 0 {
+            warn!("Output truncated to {} bytes", CAPTURE_SIZE);
+        }
+
+        break;
+    }
+
+    RUNNING.store(false, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console_commands_only_run_locally() {
+        assert!(runs_remotely("radio stats"));
+        assert!(runs_remotely("ota status"));
+        assert!(runs_remotely("gpio set PA5 1"));
+
+        assert!(!runs_remotely("radio sniff pcap"));
+        assert!(!runs_remotely("  ota   load"));
+        assert!(!runs_remotely("buzz 500"));
+        assert!(!runs_remotely("radio stats; gpio watch PA0"));
+    }
+}
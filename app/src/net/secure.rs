@@ -0,0 +1,718 @@
+use rtrs::{object_with, object_with_mut, logger, info, error};
+
+use crate::net::aes::KEY_SIZE;
+use crate::net::ccm::Ccm;
+use crate::net::crc::crc16;
+use crate::net::link::{Link, BROADCAST, DEFAULT_ADDR, LINK_OBJECT_NAME};
+use crate::peripherals::nvm::{self, Nvm, NvmError, NVM_OBJECT_NAME, RADIO_KEYS_OFFSET, RADIO_KEYS_SIZE, RADIO_REPLAY_OFFSET, RADIO_REPLAY_SIZE};
+
+logger!("secure");
+
+// Secured frames carry the sender's frame counter (LE) in front of the ciphertext and a MIC after it
+const COUNTER_SIZE: usize = 4;
+const TAG_SIZE: usize = 4;
+
+pub const OVERHEAD: usize = COUNTER_SIZE + TAG_SIZE;
+
+// Sender address, frame counter (BE), zero padding. Unique as long as addresses are,
+// nodes sharing the network key must not share an address, so keys are bound to the address
+const NONCE_SIZE: usize = 13;
+
+// Peer keys, the network key (stored for BROADCAST) covers everyone else
+pub const MAX_KEYS: usize = 4;
+// Senders whose last frame counter is remembered
+const REPLAY_SIZE: usize = 8;
+
+// Frame counters are reserved in NVM this many at a time, a reboot skips what's left of the block
+const COUNTER_BLOCK: u32 = 1024;
+// Floor saved for each sender, ahead of its accepted counter by up to this much. Saved again once it's
+// half used, the link takes one frame per pass, so it can't catch up in between
+const REPLAY_GAP: u32 = 32;
+
+// NVM region: two counter records written alternately (limit LE, address the counters are used
+// with, CRC16), then key slots (magic, peer, key, CRC16 of all that)
+const COUNTER_RECORD_SIZE: usize = 8;
+const COUNTER_RECORDS: usize = 2;
+const KEY_SLOT_SIZE: usize = 24;
+const KEYS_OFFSET: usize = COUNTER_RECORD_SIZE * COUNTER_RECORDS;
+const KEY_SLOT_MAGIC: u8 = 0x5C;
+const KEY_SLOTS: usize = (RADIO_KEYS_SIZE - KEYS_OFFSET) / KEY_SLOT_SIZE;
+
+const _: () = assert!(KEY_SLOTS >= MAX_KEYS);
+
+// Replay region: a slot per sender of the window (magic, sender, floor LE, CRC16)
+const REPLAY_SLOT_SIZE: usize = 8;
+const REPLAY_SLOT_MAGIC: u8 = 0x5D;
+const REPLAY_SLOTS: usize = RADIO_REPLAY_SIZE / REPLAY_SLOT_SIZE;
+
+const _: () = assert!(REPLAY_SLOTS >= REPLAY_SIZE);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SecurityError {
+    // Neither a key for the peer nor a network key
+    NoKey,
+    // Frame counter ran into the end of the reserved block or wrapped
+    Counter,
+    Short,
+    // MIC didn't check out: wrong key, or the frame was tampered with
+    Auth,
+    // Frame counter isn't above the last one from this sender
+    Replay,
+    KeysFull,
+    // NODE_ADDR wasn't set at build time, other nodes may use the same address
+    DefaultAddress,
+    // Keys are in use with another address, they have to be cleared first
+    AddressChanged,
+    NoNvm,
+    Nvm(NvmError),
+}
+
+impl From<NvmError> for SecurityError {
+    fn from(err: NvmError) -> Self {
+        SecurityError::Nvm(err)
+    }
+}
+
+// Keys, frame counter and replay window of the link. Without keys frames go out in clear text
+pub struct Security {
+    keys:    heapless::Vec<(u8, [u8; KEY_SIZE]), MAX_KEYS>,
+    // Next frame counter, frames can be sealed up to `limit`
+    counter: u32,
+    limit:   u32,
+    // Highest frame counter accepted from each sender and the floor saved for it, most recently heard last
+    replay:  heapless::Vec<(u8, u32, u32), REPLAY_SIZE>,
+}
+
+impl Security {
+    pub const fn new() -> Self {
+        Self { keys: heapless::Vec::new(), counter: 0, limit: 0, replay: heapless::Vec::new() }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    // Peers with their own key, BROADCAST stands for the network key
+    pub fn peers(&self) -> impl Iterator<Item = u8> + '_ {
+        self.keys.iter().map(|(peer, _)| *peer)
+    }
+
+    pub fn set_key(&mut self, peer: u8, key: &[u8; KEY_SIZE]) -> Result<(), SecurityError> {
+        self.remove_key(peer);
+        self.keys.push((peer, *key)).map_err(|_| SecurityError::KeysFull)
+    }
+
+    pub fn remove_key(&mut self, peer: u8) {
+        self.keys.retain(|(p, _)| *p != peer);
+    }
+
+    pub fn counter(&self) -> (u32, u32) {
+        (self.counter, self.limit)
+    }
+
+    pub fn set_counter(&mut self, counter: u32, limit: u32) {
+        self.counter = counter;
+        self.limit = limit;
+    }
+
+    pub fn set_limit(&mut self, limit: u32) {
+        self.limit = limit;
+    }
+
+    // Floor saved before the reset, the sender's frames up to it are replays
+    pub fn restore_replay(&mut self, src: u8, saved: u32) {
+        self.replay.retain(|(s, ..)| *s != src);
+
+        if self.replay.is_full() {
+            self.replay.remove(0);
+        }
+
+        let _ = self.replay.push((src, saved, saved));
+    }
+
+    pub fn replay_senders(&self) -> impl Iterator<Item = u8> + '_ {
+        self.replay.iter().map(|(src, ..)| *src)
+    }
+
+    // Sender whose accepted counter is getting close to its saved floor, with the next floor to save
+    pub fn replay_due(&self) -> Option<(u8, u32)> {
+        self.replay
+            .iter()
+            .find(|(_, counter, saved)| counter.saturating_add(REPLAY_GAP / 2) > *saved)
+            .map(|(src, counter, _)| (*src, counter.saturating_add(REPLAY_GAP)))
+    }
+
+    pub fn replay_saved(&mut self, src: u8, saved: u32) {
+        if let Some(entry) = self.replay.iter_mut().find(|(s, ..)| *s == src) {
+            entry.2 = entry.2.max(saved);
+        }
+    }
+
+    // Key shared with this peer alone, not the network key
+    pub fn has_peer_key(&self, peer: u8) -> bool {
+        peer != BROADCAST && self.keys.iter().any(|(p, _)| *p == peer)
+    }
+
+    // Peer's own key, otherwise the network key
+    pub fn has_key(&self, peer: u8) -> bool {
+        self.key(peer).is_some()
+    }
+
+    fn key(&self, peer: u8) -> Option<&[u8; KEY_SIZE]> {
+        let find = |peer| self.keys.iter().find(|(p, _)| *p == peer).map(|(_, key)| key);
+
+        find(peer).or_else(|| find(BROADCAST))
+    }
+
+    fn nonce(src: u8, counter: u32) -> [u8; NONCE_SIZE] {
+        let mut nonce = [0; NONCE_SIZE];
+
+        nonce[0] = src;
+        nonce[1..5].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    // Writes counter, ciphertext and MIC of `payload` to `out`, the frame header is authenticated too.
+    // Every call takes a new counter, retransmissions included, so receivers never see one twice
+    pub fn seal(&mut self, header: &[u8], src: u8, peer: u8, payload: &[u8], out: &mut [u8]) -> Result<usize, SecurityError> {
+        let key = self.key(peer).ok_or(SecurityError::NoKey)?;
+
+        if self.counter >= self.limit {
+            return Err(SecurityError::Counter);
+        }
+
+        let counter = self.counter;
+        let size = COUNTER_SIZE + payload.len() + TAG_SIZE;
+
+        if out.len() < size {
+            return Err(SecurityError::Short);
+        }
+
+        out[..COUNTER_SIZE].copy_from_slice(&counter.to_le_bytes());
+
+        let (data, tag) = out[COUNTER_SIZE..size].split_at_mut(payload.len());
+        data.copy_from_slice(payload);
+
+        Ccm::new(key, TAG_SIZE).seal(&Self::nonce(src, counter), header, data, tag);
+
+        self.counter += 1;
+
+        Ok(size)
+    }
+
+    // Decrypts in place, returns the range of the plaintext in `data`
+    pub fn open(&mut self, header: &[u8], src: u8, data: &mut [u8]) -> Result<core::ops::Range<usize>, SecurityError> {
+        if data.len() < OVERHEAD {
+            return Err(SecurityError::Short);
+        }
+
+        let key = self.key(src).ok_or(SecurityError::NoKey)?;
+
+        let counter = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let end = data.len() - TAG_SIZE;
+        let (body, tag) = data[COUNTER_SIZE..].split_at_mut(end - COUNTER_SIZE);
+
+        if !Ccm::new(key, TAG_SIZE).open(&Self::nonce(src, counter), header, body, tag) {
+            return Err(SecurityError::Auth);
+        }
+
+        // Checked after the MIC, forged frames must not move the window
+        let index = self.replay.iter().position(|(s, ..)| *s == src);
+
+        let saved = match index {
+            Some(index) if counter <= self.replay[index].1 => return Err(SecurityError::Replay),
+            Some(index) => self.replay.remove(index).2,
+            None => {
+                // Forgotten sender is accepted from any counter again
+                if self.replay.is_full() {
+                    self.replay.remove(0);
+                }
+
+                0
+            }
+        };
+
+        let _ = self.replay.push((src, counter, saved));
+
+        Ok(COUNTER_SIZE..end)
+    }
+}
+
+// Limit and the address it was reserved for
+fn read_limit(record: usize) -> Result<Option<(u32, u8)>, SecurityError> {
+    let mut buf = [0; COUNTER_RECORD_SIZE];
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.read(RADIO_KEYS_OFFSET + record * COUNTER_RECORD_SIZE, &mut buf))?;
+
+    let crc = u16::from_le_bytes([buf[5], buf[6]]);
+
+    if crc16(&buf[..5]) != crc {
+        return Ok(None);
+    }
+
+    Ok(Some((u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]), buf[4])))
+}
+
+// Higher of the two records, a write cut short by a reset leaves the other one intact
+fn load_limit() -> Result<Option<(u32, u8)>, SecurityError> {
+    let mut limit = None;
+
+    for record in 0..COUNTER_RECORDS {
+        limit = limit.max(read_limit(record)?);
+    }
+
+    Ok(limit)
+}
+
+fn store_limit(limit: u32, addr: u8) -> Result<(), SecurityError> {
+    let mut buf = [0; COUNTER_RECORD_SIZE];
+
+    buf[..4].copy_from_slice(&limit.to_le_bytes());
+    buf[4] = addr;
+    let crc = crc16(&buf[..5]);
+    buf[5..7].copy_from_slice(&crc.to_le_bytes());
+
+    // Overwrites the older record
+    let oldest = (0..COUNTER_RECORDS).min_by_key(|record| read_limit(*record).ok().flatten()).unwrap_or(0);
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.write(RADIO_KEYS_OFFSET + oldest * COUNTER_RECORD_SIZE, &buf))?;
+
+    Ok(())
+}
+
+fn slot_offset(slot: usize) -> usize {
+    RADIO_KEYS_OFFSET + KEYS_OFFSET + slot * KEY_SLOT_SIZE
+}
+
+fn read_slot(slot: usize) -> Result<Option<(u8, [u8; KEY_SIZE])>, SecurityError> {
+    let mut buf = [0; KEY_SLOT_SIZE];
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.read(slot_offset(slot), &mut buf))?;
+
+    let end = 2 + KEY_SIZE;
+    let crc = u16::from_le_bytes([buf[end], buf[end + 1]]);
+
+    if buf[0] != KEY_SLOT_MAGIC || crc16(&buf[..end]) != crc {
+        return Ok(None);
+    }
+
+    let mut key = [0; KEY_SIZE];
+    key.copy_from_slice(&buf[2..end]);
+
+    Ok(Some((buf[1], key)))
+}
+
+fn write_slot(slot: usize, entry: Option<(u8, &[u8; KEY_SIZE])>) -> Result<(), SecurityError> {
+    let mut buf = [0; KEY_SLOT_SIZE];
+
+    if let Some((peer, key)) = entry {
+        let end = 2 + KEY_SIZE;
+
+        buf[0] = KEY_SLOT_MAGIC;
+        buf[1] = peer;
+        buf[2..end].copy_from_slice(key);
+
+        let crc = crc16(&buf[..end]);
+        buf[end..end + 2].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    // Cleared slot is wiped entirely, the key must not stay readable
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.write(slot_offset(slot), &buf))?;
+
+    Ok(())
+}
+
+fn read_replay(slot: usize) -> Result<Option<(u8, u32)>, SecurityError> {
+    let mut buf = [0; REPLAY_SLOT_SIZE];
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.read(RADIO_REPLAY_OFFSET + slot * REPLAY_SLOT_SIZE, &mut buf))?;
+
+    let crc = u16::from_le_bytes([buf[6], buf[7]]);
+
+    if buf[0] != REPLAY_SLOT_MAGIC || crc16(&buf[..6]) != crc {
+        return Ok(None);
+    }
+
+    Ok(Some((buf[1], u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]))))
+}
+
+// Sender's own slot, else a free one or one of a sender that left the window
+fn store_replay(src: u8, saved: u32, window: &[u8]) -> Result<(), SecurityError> {
+    let mut free = None;
+
+    for slot in 0..REPLAY_SLOTS {
+        match read_replay(slot)? {
+            Some((s, _)) if s == src => {
+                free = Some(slot);
+                break;
+            }
+            Some((s, _)) if window.contains(&s) => {}
+            _ if free.is_none() => free = Some(slot),
+            _ => {}
+        }
+    }
+
+    // Always found, the window has no more senders than there are slots
+    let slot = free.unwrap_or(0);
+
+    let mut buf = [0; REPLAY_SLOT_SIZE];
+
+    buf[0] = REPLAY_SLOT_MAGIC;
+    buf[1] = src;
+    buf[2..6].copy_from_slice(&saved.to_le_bytes());
+    let crc = crc16(&buf[..6]);
+    buf[6..8].copy_from_slice(&crc.to_le_bytes());
+
+    object_with_mut!(NVM_OBJECT_NAME, Nvm, nvm, nvm.write(RADIO_REPLAY_OFFSET + slot * REPLAY_SLOT_SIZE, &buf))?;
+
+    Ok(())
+}
+
+// Stored for the link and saved, BROADCAST sets the network key. Frame counters are bound to the
+// node address, the first key binds them to the current one
+pub fn set_key(peer: u8, key: &[u8; KEY_SIZE]) -> Result<(), SecurityError> {
+    if !nvm::available() {
+        return Err(SecurityError::NoNvm);
+    }
+
+    let (addr, enabled) = object_with!(LINK_OBJECT_NAME, Link, link, (link.addr(), link.security().enabled()));
+
+    if addr == DEFAULT_ADDR {
+        return Err(SecurityError::DefaultAddress);
+    }
+
+    let bound = load_limit()?;
+
+    if enabled && bound.is_some_and(|(_, a)| a != addr) {
+        return Err(SecurityError::AddressChanged);
+    }
+
+    let mut free = None;
+
+    for slot in 0..KEY_SLOTS {
+        match read_slot(slot)? {
+            Some((p, _)) if p == peer => {
+                free = Some(slot);
+                break;
+            }
+            None if free.is_none() => free = Some(slot),
+            _ => {}
+        }
+    }
+
+    let slot = free.ok_or(SecurityError::KeysFull)?;
+
+    // Counters continue above the last reservation whatever address it was for
+    if !enabled {
+        let start = bound.map_or(0, |(limit, _)| limit);
+        let limit = start.checked_add(COUNTER_BLOCK).ok_or(SecurityError::Counter)?;
+
+        store_limit(limit, addr)?;
+        object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().set_counter(start, limit));
+    }
+
+    write_slot(slot, Some((peer, key)))?;
+    object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().set_key(peer, key))
+}
+
+pub fn clear_key(peer: u8) -> Result<(), SecurityError> {
+    if !nvm::available() {
+        return Err(SecurityError::NoNvm);
+    }
+
+    object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().remove_key(peer));
+
+    for slot in 0..KEY_SLOTS {
+        if matches!(read_slot(slot)?, Some((p, _)) if p == peer) {
+            write_slot(slot, None)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Next block of frame counters, before the current one runs out
+fn reserve_counters() {
+    let (addr, (counter, limit)) = object_with!(LINK_OBJECT_NAME, Link, link, (link.addr(), link.security().counter()));
+
+    if counter.saturating_add(COUNTER_BLOCK / 2) < limit {
+        return;
+    }
+
+    let Some(next) = limit.max(counter).checked_add(COUNTER_BLOCK) else {
+        return;
+    };
+
+    match store_limit(next, addr) {
+        Ok(()) => object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().set_limit(next)),
+        Err(err) => error!("Frame counter reservation failed: {:?}", err),
+    }
+}
+
+// Replay floors, before the senders get there
+fn save_replay_floors() {
+    for _ in 0..REPLAY_SIZE {
+        let (due, window) = object_with!(LINK_OBJECT_NAME, Link, link, {
+            let security = link.security();
+            (security.replay_due(), security.replay_senders().collect::<heapless::Vec<u8, REPLAY_SIZE>>())
+        });
+
+        let Some((src, saved)) = due else {
+            return;
+        };
+
+        if let Err(err) = store_replay(src, saved, &window) {
+            error!("Saving the replay floor failed: {:?}", err);
+            return;
+        }
+
+        object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().replay_saved(src, saved));
+    }
+}
+
+pub(crate) fn maintain() {
+    if !nvm::available() {
+        return;
+    }
+
+    reserve_counters();
+    save_replay_floors();
+}
+
+fn load() -> Result<usize, SecurityError> {
+    let mut keys = 0;
+
+    for slot in 0..KEY_SLOTS {
+        if let Some((peer, key)) = read_slot(slot)? {
+            object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().set_key(peer, &key))?;
+            keys += 1;
+        }
+    }
+
+    for slot in 0..REPLAY_SLOTS {
+        if let Some((src, saved)) = read_replay(slot)? {
+            object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().restore_replay(src, saved));
+        }
+    }
+
+    let addr = object_with!(LINK_OBJECT_NAME, Link, link, link.addr());
+
+    // Counters already used with these keys are unknown, or were used by another address.
+    // Either way a nonce could repeat, so nothing is sealed until the keys are cleared
+    let start = match load_limit()? {
+        _ if keys > 0 && addr == DEFAULT_ADDR => Err("address isn't set (NODE_ADDR)"),
+        Some((_, bound)) if keys > 0 && bound != addr => Err("keys were set for another address"),
+        Some((limit, _)) => Ok(limit),
+        None if keys == 0 => Ok(0),
+        None => Err("frame counter lost"),
+    };
+
+    let start = match start {
+        Ok(start) => start,
+        Err(reason) => {
+            error!("Secured frames can't be sent, {}", reason);
+            object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().set_counter(u32::MAX, u32::MAX));
+            return Ok(keys);
+        }
+    };
+
+    let limit = start.checked_add(COUNTER_BLOCK).ok_or(SecurityError::Counter)?;
+
+    store_limit(limit, addr)?;
+    object_with_mut!(LINK_OBJECT_NAME, Link, link, link.security_mut().set_counter(start, limit));
+
+    Ok(keys)
+}
+
+// Loads keys and continues the frame counter above everything reserved before the reset.
+// Without NVM there are no keys, frames go out in clear text
+pub(crate) fn init() {
+    if !nvm::available() {
+        return;
+    }
+
+    match load() {
+        Ok(0) => {}
+        Ok(keys) => info!("{} keys loaded", keys),
+        Err(err) => error!("Loading keys failed: {:?}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use crate::net::link::{DeliveryStatus, LinkError, MAX_PAYLOAD, MAX_SECURE_PAYLOAD};
+    use crate::net::sim::{SimMedium, SimPort};
+    use crate::net::MAX_FRAME_SIZE;
+
+    const KEY: [u8; KEY_SIZE] = [0x2b; KEY_SIZE];
+
+    fn secured(addr: u8, key: &[u8; KEY_SIZE]) -> Link {
+        let mut link = Link::new(addr);
+
+        link.security_mut().set_key(BROADCAST, key).unwrap();
+        link.security_mut().set_counter(0, 1000);
+        link
+    }
+
+    // Frame of a's first unicast to 2
+    fn first_frame(a: &mut Link, payload: &[u8]) -> ([u8; MAX_FRAME_SIZE], usize) {
+        let mut buf = [0; MAX_FRAME_SIZE];
+
+        a.send_to(2, payload).unwrap();
+        let size = a.output(0, &mut buf).unwrap();
+        a.transmitted(true);
+
+        (buf, size)
+    }
+
+    // ACK of `frame` from a node at address 2 holding `key`, if any
+    fn ack(frame: &[u8], key: Option<&[u8; KEY_SIZE]>) -> ([u8; MAX_FRAME_SIZE], usize) {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let mut b = key.map_or_else(|| Link::new(2), |key| secured(2, key));
+
+        b.input(frame, None, 0);
+        let size = b.output(0, &mut buf).unwrap();
+
+        (buf, size)
+    }
+
+    #[test]
+    fn frames_are_opaque_and_checked() {
+        let (mut a, mut b) = (secured(1, &KEY), secured(2, &KEY));
+        let (frame, size) = first_frame(&mut a, b"secret");
+
+        assert!(!frame[..size].windows(6).any(|window| window == b"secret"));
+
+        b.input(&frame[..size], None, 0);
+        assert_eq!(b.recv_from().map(|packet| packet.payload() == b"secret"), Some(true));
+
+        // Same frame again: MIC is fine, frame counter isn't new
+        b.input(&frame[..size], None, 0);
+
+        // Ciphertext changed, CRC fixed up so only the MIC can catch it
+        let mut tampered = frame;
+        tampered[size / 2] ^= 0x01;
+        let crc = crc16(&tampered[..size - 2]);
+        tampered[size - 2..size].copy_from_slice(&crc.to_le_bytes());
+        b.input(&tampered[..size], None, 0);
+
+        // Sender with another key, and one without any
+        for mut sender in [secured(3, &[0x3c; KEY_SIZE]), Link::new(4)] {
+            let (frame, size) = first_frame(&mut sender, b"hi");
+            b.input(&frame[..size], None, 0);
+        }
+
+        let stats = b.stats();
+
+        assert!(b.recv_from().is_none());
+        assert_eq!((stats.replays, stats.auth_errors, stats.insecure), (1, 2, 1));
+    }
+
+    #[test]
+    fn acks_are_authenticated() {
+        let (mut a, mut b) = (secured(1, &KEY), secured(2, &KEY));
+
+        // Same sequence number from nodes without the key
+        let other_key = [0x3c; KEY_SIZE];
+        let (clear_frame, size) = first_frame(&mut Link::new(1), b"data");
+        let (clear, clear_size) = ack(&clear_frame[..size], None);
+        let (other_frame, size) = first_frame(&mut secured(1, &other_key), b"data");
+        let (forged, forged_size) = ack(&other_frame[..size], Some(&other_key));
+
+        a.input(&clear[..clear_size], None, 0);
+        a.input(&forged[..forged_size], None, 0);
+        assert!(a.delivery().is_none());
+
+        let (frame, size) = first_frame(&mut a, b"data");
+        b.input(&frame[..size], None, 0);
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let ack_size = b.output(0, &mut buf).unwrap();
+
+        a.input(&buf[..ack_size], None, 0);
+        assert!(matches!(a.delivery().map(|delivery| delivery.status), Some(DeliveryStatus::Acked { .. })));
+
+        a.input(&buf[..ack_size], None, 0);
+
+        let stats = a.stats();
+        assert_eq!((stats.insecure, stats.auth_errors, stats.replays), (1, 1, 1));
+    }
+
+    #[test]
+    fn lossy_link_seals_every_attempt() {
+        let medium = RefCell::new(SimMedium::new());
+        let (mut a, mut b) = (secured(1, &KEY), secured(2, &KEY));
+        let mut now = 0;
+
+        medium.borrow_mut().set_faults(3, 0);
+
+        for i in 0..5u8 {
+            a.send_to(2, &[i; 8]).unwrap();
+
+            let mut status = None;
+
+            for _ in 0..1000 {
+                now += 10;
+
+                a.poll(&mut SimPort::new(&medium, 0), now);
+                b.poll(&mut SimPort::new(&medium, 1), now);
+
+                if let Some(delivery) = a.delivery() {
+                    status = Some(delivery.status);
+                    break;
+                }
+            }
+
+            assert!(matches!(status, Some(DeliveryStatus::Acked { .. })));
+            assert_eq!(b.recv_from().map(|packet| packet.payload() == [i; 8]), Some(true));
+        }
+
+        assert!(a.stats().retries > 0);
+        assert_eq!(a.stats().auth_errors + b.stats().auth_errors, 0);
+    }
+
+    #[test]
+    fn saved_floor_outlives_reboot() {
+        let mut b = secured(2, &KEY);
+        b.security_mut().restore_replay(1, 100);
+
+        let mut a = secured(1, &KEY);
+        a.security_mut().set_counter(100, 1000);
+        let (frame, size) = first_frame(&mut a, b"old");
+
+        b.input(&frame[..size], None, 0);
+        assert!(b.recv_from().is_none());
+        assert_eq!(b.stats().replays, 1);
+
+        let mut a = secured(1, &KEY);
+        a.security_mut().set_counter(101, 1000);
+        let (frame, size) = first_frame(&mut a, b"new");
+
+        b.input(&frame[..size], None, 0);
+        assert!(b.recv_from().is_some());
+
+        // Saved ahead of the sender, then not again until it gets close
+        assert_eq!(b.security().replay_due(), Some((1, 101 + REPLAY_GAP)));
+        b.security_mut().replay_saved(1, 101 + REPLAY_GAP);
+        assert_eq!(b.security().replay_due(), None);
+    }
+
+    #[test]
+    fn payload_limit_follows_keys() {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let mut a = Link::new(1);
+
+        a.send_to(2, &[0; MAX_PAYLOAD]).unwrap();
+
+        a.security_mut().set_key(BROADCAST, &KEY).unwrap();
+        a.security_mut().set_counter(0, 1000);
+
+        assert_eq!(a.send_to(2, &[0; MAX_SECURE_PAYLOAD + 1]), Err(LinkError::TooLarge));
+
+        // Queued in clear text, too large to seal now
+        assert!(a.output(0, &mut buf).is_none());
+        assert_eq!(a.stats().seal_errors, 1);
+        assert!(matches!(a.delivery().map(|delivery| delivery.status), Some(DeliveryStatus::Failed)));
+    }
+}
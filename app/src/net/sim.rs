@@ -0,0 +1,284 @@
+use core::cell::RefCell;
+
+use crate::net::{Transport, TransportError, MAX_FRAME_SIZE};
+
+// Every port is polled each step, a few frames are enough. Keeps the medium (PORTS * QUEUE_SIZE
+// frames of up to 64 bytes, about 1 KB) small enough for the stack of on-target tests
+const QUEUE_SIZE: usize = 4;
+
+pub const PORTS: usize = 4;
+
+type Frame = heapless::Vec<u8, MAX_FRAME_SIZE>;
+
+// Lossy in-memory broadcast medium, for testing the link and mesh layers without radios.
+// Which ports hear each other is configurable, faults are deterministic, so a test run is reproducible
+pub struct SimMedium {
+    queues:  [heapless::Deque<Frame, QUEUE_SIZE>; PORTS],
+    // In range of each other, symmetric
+    links:   [[bool; PORTS]; PORTS],
+    // Every Nth transmitted frame is dropped/corrupted (0 disables)
+    drop:    u32,
+    corrupt: u32,
+    // Nothing gets through while set
+    down:    bool,
+    frames:  u32,
+}
+
+impl SimMedium {
+    pub fn new() -> Self {
+        Self {
+            queues:  [const { heapless::Deque::new() }; PORTS],
+            links:   [[true; PORTS]; PORTS],
+            drop:    0,
+            corrupt: 0,
+            down:    false,
+            frames:  0,
+        }
+    }
+
+    pub fn set_faults(&mut self, drop_every: u32, corrupt_every: u32) {
+        self.drop = drop_every;
+        self.corrupt = corrupt_every;
+    }
+
+    // Every port hears every other one after `new`
+    pub fn set_link(&mut self, a: usize, b: usize, up: bool) {
+        self.links[a][b] = up;
+        self.links[b][a] = up;
+    }
+
+    pub fn isolate(&mut self) {
+        self.links = [[false; PORTS]; PORTS];
+    }
+
+    pub fn set_down(&mut self, down: bool) {
+        self.down = down;
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    fn transmit(&mut self, from: usize, frame: &[u8]) -> Result<(), TransportError> {
+        let mut frame = Frame::from_slice(frame).map_err(|_| TransportError::TooLarge)?;
+
+        self.frames += 1;
+
+        let nth = |every: u32| every != 0 && self.frames % every == 0;
+
+        if self.down || nth(self.drop) {
+            return Ok(());
+        }
+
+        if nth(self.corrupt) {
+            let middle = frame.len() / 2;
+            frame[middle] ^= 0x55;
+        }
+
+        for port in 0..PORTS {
+            // Full receiver queue looks the same as a lost frame
+            if port != from && self.links[from][port] {
+                let _ = self.queues[port].push_back(frame.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self, port: usize, buf: &mut [u8; MAX_FRAME_SIZE]) -> Option<usize> {
+        let frame = self.queues[port].pop_front()?;
+        buf[..frame.len()].copy_from_slice(&frame);
+
+        Some(frame.len())
+    }
+}
+
+pub struct SimPort<'a> {
+    medium: &'a RefCell<SimMedium>,
+    port:   usize,
+}
+
+impl<'a> SimPort<'a> {
+    pub fn new(medium: &'a RefCell<SimMedium>, port: usize) -> Self {
+        Self { medium, port }
+    }
+}
+
+impl Transport for SimPort<'_> {
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), TransportError> {
+        self.medium.borrow_mut().transmit(self.port, frame)
+    }
+
+    fn receive(&mut self, buf: &mut [u8; MAX_FRAME_SIZE]) -> Result<Option<usize>, TransportError> {
+        Ok(self.medium.borrow_mut().receive(self.port, buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::link::{DeliveryStatus, Link, DEFAULT_ACK_TIMEOUT_MS};
+    use crate::net::PROTO_PING_REQUEST;
+
+    const STEP_MS: u32 = 10;
+    const MAX_STEPS: u32 = 1000;
+
+    // Polls both ends until A has a delivery result
+    fn run(a: &mut Link, b: &mut Link, medium: &RefCell<SimMedium>, now: &mut u32) -> Option<DeliveryStatus> {
+        for _ in 0..MAX_STEPS {
+            *now += STEP_MS;
+
+            a.poll(&mut SimPort::new(medium, 0), *now);
+            b.poll(&mut SimPort::new(medium, 1), *now);
+
+            if let Some(delivery) = a.delivery() {
+                return Some(delivery.status);
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn out_of_range_ports_hear_nothing() {
+        let medium = RefCell::new(SimMedium::new());
+        medium.borrow_mut().isolate();
+        medium.borrow_mut().set_link(0, 1, true);
+
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let _ = SimPort::new(&medium, 0).transmit(b"frame");
+
+        assert_eq!(SimPort::new(&medium, 1).receive(&mut buf), Ok(Some(5)));
+        assert_eq!(SimPort::new(&medium, 2).receive(&mut buf), Ok(None));
+        assert_eq!(SimPort::new(&medium, 0).receive(&mut buf), Ok(None));
+    }
+
+    #[test]
+    fn lossy_link_delivers_once_in_order() {
+        let medium = RefCell::new(SimMedium::new());
+        let (mut a, mut b) = (Link::new(1), Link::new(2));
+        let mut now = 0;
+
+        medium.borrow_mut().set_faults(5, 7);
+
+        for i in 0..10 {
+            a.send_to(2, &[i, i, i]).unwrap();
+
+            assert!(matches!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Acked { .. })));
+            assert_eq!(b.recv_from().map(|packet| packet.payload()[0]), Some(i));
+        }
+
+        for _ in 0..10 {
+            now += STEP_MS;
+            b.poll(&mut SimPort::new(&medium, 1), now);
+        }
+
+        assert!(b.recv_from().is_none());
+        assert!(a.stats().retries > 0);
+        assert!(b.stats().duplicates > 0 && b.stats().crc_errors > 0);
+    }
+
+    #[test]
+    fn rebooted_sender_is_heard() {
+        let medium = RefCell::new(SimMedium::new());
+        let (mut a, mut b) = (Link::new(1), Link::new(2));
+        let mut now = 0;
+
+        a.send_to(2, b"before").unwrap();
+        assert!(matches!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Acked { .. })));
+        assert!(b.recv_from().is_some());
+
+        // Counts from sequence number 0 again
+        let mut a = Link::new(1);
+        now += 10 * DEFAULT_ACK_TIMEOUT_MS;
+
+        a.send_to(2, b"after").unwrap();
+        assert!(matches!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Acked { .. })));
+        assert_eq!(b.recv_from().map(|packet| packet.payload() == b"after"), Some(true));
+        assert_eq!(b.stats().duplicates, 0);
+    }
+
+    #[test]
+    fn unread_data_doesnt_block_services() {
+        let medium = RefCell::new(SimMedium::new());
+        let (mut a, mut b) = (Link::new(1), Link::new(2));
+        let mut now = 0;
+
+        for i in 0..4 {
+            a.send_to(2, &[i]).unwrap();
+            assert!(matches!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Acked { .. })));
+        }
+
+        // Data queue is full, the service queue isn't
+        a.send_to(2, &[4]).unwrap();
+        assert_eq!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Failed));
+
+        a.send_service(2, &[PROTO_PING_REQUEST, 1]).unwrap();
+        assert!(matches!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Acked { .. })));
+
+        assert!(b.recv_service(PROTO_PING_REQUEST).is_some());
+        assert_eq!(b.recv_from().map(|packet| packet.payload()[0]), Some(0));
+    }
+
+    #[test]
+    fn unread_frames_expire() {
+        let medium = RefCell::new(SimMedium::new());
+        let (mut a, mut b) = (Link::new(1), Link::new(2));
+        let mut now = 0;
+
+        a.send_service(2, &[PROTO_PING_REQUEST, 1]).unwrap();
+        assert!(matches!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Acked { .. })));
+
+        for _ in 0..1100 {
+            now += STEP_MS;
+            b.poll(&mut SimPort::new(&medium, 1), now);
+        }
+
+        assert!(b.recv_service(PROTO_PING_REQUEST).is_none());
+        assert_eq!(b.stats().expired, 1);
+    }
+
+    #[test]
+    fn deliveries_are_taken_in_place() {
+        let medium = RefCell::new(SimMedium::new());
+        let (mut a, mut b) = (Link::new(1), Link::new(2));
+        let mut now = 0;
+        let mut seqs = [0; 3];
+
+        // TX queue holds two, the results pile up unread
+        for (i, seq) in seqs.iter_mut().enumerate() {
+            *seq = a.send_to(2, &[i as u8]).unwrap();
+
+            for _ in 0..MAX_STEPS {
+                now += STEP_MS;
+
+                a.poll(&mut SimPort::new(&medium, 0), now);
+                b.poll(&mut SimPort::new(&medium, 1), now);
+            }
+        }
+
+        assert!(a.take_delivery(seqs[1]).is_some());
+        assert_eq!(a.delivery().map(|delivery| delivery.seq), Some(seqs[0]));
+        assert_eq!(a.delivery().map(|delivery| delivery.seq), Some(seqs[2]));
+        assert!(a.delivery().is_none());
+    }
+
+    #[test]
+    fn service_frames_are_flagged() {
+        let medium = RefCell::new(SimMedium::new());
+        let (mut a, mut b) = (Link::new(1), Link::new(2));
+        let mut now = 0;
+
+        // Data may start with anything, only the header flag makes a service frame
+        a.send_to(2, &[PROTO_PING_REQUEST]).unwrap();
+        assert!(matches!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Acked { .. })));
+        assert!(b.recv_service(PROTO_PING_REQUEST).is_none());
+        assert_eq!(b.recv_from().map(|packet| packet.payload()[0]), Some(PROTO_PING_REQUEST));
+
+        // Unknown protocol is neither queued nor acknowledged
+        a.send_service(2, &[0xF9]).unwrap();
+        assert_eq!(run(&mut a, &mut b, &medium, &mut now), Some(DeliveryStatus::Failed));
+        assert!(b.recv_service(0xF9).is_none());
+        assert!(b.stats().unknown > 0);
+    }
+}
@@ -0,0 +1,157 @@
+use rtrs::log::console::CONSOLE_OBJECT_NAME;
+use rtrs::{object_with_mut, print, println, logger, info};
+
+use core::fmt::Write; // For println!
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::net::config::{self, RadioConfig};
+use crate::net::crc::crc16;
+use crate::net::radio::{self, PacketInfo};
+use crate::net::{cobs, MAX_FRAME_SIZE};
+use crate::peripherals::rtc;
+
+logger!("sniffer");
+
+// Listening is restarted with this window, stopping takes at most this long
+const RX_WINDOW_MS: u32 = 500;
+
+// pcap record header: seconds, microseconds, captured and original length (little endian)
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+// LoRaTap v0 header (LINKTYPE_LORATAP), fields are big endian
+const LORATAP_VERSION: u8 = 0;
+const LORATAP_HEADER_SIZE: usize = 15;
+// RSSI is stored as dBm + 139
+const LORATAP_RSSI_OFFSET: i16 = 139;
+
+// Record is followed by CRC-16 of itself, the host drops everything that doesn't check out
+// (log lines printed between records end up in the same zero-delimited chunk)
+const RECORD_SIZE: usize = PCAP_RECORD_HEADER_SIZE + LORATAP_HEADER_SIZE + MAX_FRAME_SIZE + 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SniffFormat {
+    // One line per packet
+    Text,
+    // Zero-delimited COBS frames with pcap records, see scripts/pcap/sniff2pcap.py
+    Pcap,
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+// Set before the task is spawned, so a key press right away isn't missed
+pub fn start() -> bool {
+    if active() {
+        return false;
+    }
+
+    ACTIVE.store(true, Ordering::SeqCst);
+    true
+}
+
+pub fn stop() {
+    ACTIVE.store(false, Ordering::SeqCst);
+}
+
+// Seconds are wall-clock time if the RTC is set, otherwise time since boot
+fn timestamp() -> (u32, u32) {
+    let us = crate::time::micros();
+    let secs = rtc::timestamp().map_or((us / 1_000_000) as u32, |now| now.to_unix());
+
+    (secs, (us % 1_000_000) as u32)
+}
+
+fn loratap_header(config: &RadioConfig, info: Option<PacketInfo>, out: &mut [u8]) {
+    let rssi = info.map_or(0, |info| (info.rssi_dbm + LORATAP_RSSI_OFFSET).clamp(0, 0xFF) as u8);
+    let snr = info.map_or(0, |info| info.snr_x4 as u8);
+
+    out[0] = LORATAP_VERSION;
+    out[1] = 0;
+    out[2..4].copy_from_slice(&(LORATAP_HEADER_SIZE as u16).to_be_bytes());
+    out[4..8].copy_from_slice(&(config.frequency_khz * 1000).to_be_bytes());
+    // 125 kHz steps, narrower bandwidths show up as 0
+    out[8] = (config.bandwidth_hz / 125_000) as u8;
+    out[9] = config.spreading_factor;
+    // Packet, max and current RSSI, only the first one is known
+    out[10] = rssi;
+    out[11] = 0;
+    out[12] = 0;
+    out[13] = snr;
+    out[14] = config.sync_word;
+}
+
+fn write_pcap(frame: &[u8], info: Option<PacketInfo>) {
+    let mut record = [0; RECORD_SIZE];
+    let mut encoded = [0; cobs::max_encoded_size(RECORD_SIZE)];
+
+    let (secs, usecs) = timestamp();
+    let len = (LORATAP_HEADER_SIZE + frame.len()) as u32;
+
+    record[0..4].copy_from_slice(&secs.to_le_bytes());
+    record[4..8].copy_from_slice(&usecs.to_le_bytes());
+    record[8..12].copy_from_slice(&len.to_le_bytes());
+    record[12..16].copy_from_slice(&len.to_le_bytes());
+
+    let data = PCAP_RECORD_HEADER_SIZE + LORATAP_HEADER_SIZE;
+
+    loratap_header(&config::current(), info, &mut record[PCAP_RECORD_HEADER_SIZE..data]);
+    record[data..data + frame.len()].copy_from_slice(frame);
+
+    let size = data + frame.len();
+    let crc = crc16(&record[..size]);
+    record[size..size + 2].copy_from_slice(&crc.to_le_bytes());
+
+    let Some(encoded_size) = cobs::encode(&record[..size + 2], &mut encoded) else {
+        return;
+    };
+
+    // Leading delimiter separates the record from whatever was printed before it
+    object_with_mut!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty, tty, {
+        tty.write(0);
+
+        for byte in &encoded[..encoded_size] {
+            tty.write(*byte);
+        }
+
+        tty.write(0);
+    });
+}
+
+fn write_text(frame: &[u8], info: Option<PacketInfo>) {
+    print!("[{}] len {}", rtrs::time::global_tick(), frame.len());
+
+    if let Some(info) = info {
+        print!(", {}", info);
+    }
+
+    print!(":");
+    for byte in frame {
+        print!(" {:02x}", byte);
+    }
+    println!();
+}
+
+// Runs from `start` until `stop`, link task leaves the radio alone meanwhile
+pub async fn task(format: SniffFormat) {
+    let mut buf = [0; MAX_FRAME_SIZE];
+    let mut packets = 0u32;
+
+    while active() {
+        // Expired window is reported as an error
+        let Ok((size, info)) = radio::recv(&mut buf, RX_WINDOW_MS).await else {
+            continue;
+        };
+
+        packets += 1;
+
+        match format {
+            SniffFormat::Text => write_text(&buf[..size], info),
+            SniffFormat::Pcap => write_pcap(&buf[..size], info),
+        }
+    }
+
+    info!("Stopped, {} packets", packets);
+}
@@ -0,0 +1,68 @@
+use rtrs::gpio::{Command, Action, Pattern};
+use rtrs::time::{TickProvider, GlobalTickProvider};
+use rtrs::gpio_pattern;
+
+use alloc::boxed::Box;
+
+use crate::time::MicrosTickProvider;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TickBase {
+    Millis,
+    Micros,
+}
+
+pub struct NamedPattern {
+    pub name:    &'static str,
+    pub pattern: &'static Pattern,
+    pub tick:    TickBase,
+}
+
+// Timings are in microseconds (TickBase::Micros), for a buzzer on a plain output pin
+pub static BEEP: Pattern = gpio_pattern!(
+    Action::Command(Command::On(20)),
+    Action::Command(Command::Off(20)),
+    Action::Repeat(Command::Goto(0), 500),
+    Action::Command(Command::On(250)),
+    Action::Command(Command::Off(250)),
+    Action::Repeat(Command::Goto(3), 500),
+    Action::Command(Command::Goto(0)),
+);
+
+pub static BLINK: Pattern = gpio_pattern!(
+    Action::Command(Command::On(500)),
+    Action::Command(Command::Off(500)),
+    Action::Command(Command::Goto(0)),
+);
+
+pub static HEARTBEAT: Pattern = gpio_pattern!(
+    Action::Command(Command::On(50)),
+    Action::Command(Command::Off(150)),
+    Action::Command(Command::On(50)),
+    Action::Command(Command::Off(750)),
+    Action::Command(Command::Goto(0)),
+);
+
+pub static ERROR: Pattern = gpio_pattern!(
+    Action::Command(Command::On(100)),
+    Action::Command(Command::Off(100)),
+    Action::Command(Command::Goto(0)),
+);
+
+pub static PATTERNS: [NamedPattern; 4] = [
+    NamedPattern { name: "beep",      pattern: &BEEP,      tick: TickBase::Micros },
+    NamedPattern { name: "blink",     pattern: &BLINK,     tick: TickBase::Millis },
+    NamedPattern { name: "heartbeat", pattern: &HEARTBEAT, tick: TickBase::Millis },
+    NamedPattern { name: "error",     pattern: &ERROR,     tick: TickBase::Millis },
+];
+
+pub fn find(name: &str) -> Option<&'static NamedPattern> {
+    PATTERNS.iter().find(|pattern| pattern.name == name)
+}
+
+pub fn tick_provider(base: TickBase) -> Box<dyn TickProvider<Tick = u32>> {
+    match base {
+        TickBase::Millis => Box::new(GlobalTickProvider {}),
+        TickBase::Micros => Box::new(MicrosTickProvider {}),
+    }
+}
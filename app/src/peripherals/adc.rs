@@ -0,0 +1,152 @@
+extern crate alloc;
+use alloc::boxed::Box;
+
+pub const ADC_OBJECT_NAME: &str = "adc";
+
+// Internal channels, registered by the target
+pub const VREFINT_CHANNEL_NAME: &str = "vrefint";
+pub const TEMPERATURE_CHANNEL_NAME: &str = "temp";
+
+pub const ADC_MAX: u32 = 4095;
+
+pub const MAX_CHANNELS: usize = 8;
+pub const MAX_OVERSAMPLING: u16 = 256;
+
+// Factory calibration values are measured at VDDA = 3.0V, temperature at 30 and 130 degrees
+const CALIBRATION_VDDA_MV: u32 = 3000;
+const TS_CAL1_TEMP: i32 = 30;
+const TS_CAL2_TEMP: i32 = 130;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AdcError {
+    // Driver failed to initialize
+    NoAdc,
+    NoChannel,
+    Full,
+    Timeout,
+    InvalidOversampling,
+    Calibration,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AdcCalibration {
+    pub vrefint: u16,
+    pub ts_cal1: u16,
+    pub ts_cal2: u16,
+}
+
+pub trait AdcInterface {
+    // Result is always 12-bit, oversampled value is shifted back by hardware
+    fn read(&mut self, channel: u8) -> Result<u16, AdcError>;
+    // Power of two in 1..=256, 1 disables oversampling
+    fn set_oversampling(&mut self, ratio: u16);
+    fn calibration(&self) -> AdcCalibration;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct AdcChannel {
+    pub name:    &'static str,
+    pub channel: u8,
+}
+
+pub struct Adc {
+    ifc:          Box<dyn AdcInterface + Send + Sync + 'static>,
+    channels:     heapless::Vec<AdcChannel, MAX_CHANNELS>,
+    oversampling: u16,
+    vdda_mv:      u32,
+}
+
+impl Adc {
+    pub fn new(ifc: impl AdcInterface + Send + Sync + 'static) -> Self {
+        Self {
+            ifc:          Box::new(ifc),
+            channels:     heapless::Vec::new(),
+            oversampling: 1,
+            vdda_mv:      CALIBRATION_VDDA_MV,
+        }
+    }
+
+    pub fn add_channel(&mut self, name: &'static str, channel: u8) -> Result<(), AdcError> {
+        self.channels.push(AdcChannel { name, channel }).map_err(|_| AdcError::Full)
+    }
+
+    pub fn channels(&self) -> &[AdcChannel] {
+        &self.channels
+    }
+
+    pub fn channel(&self, name: &str) -> Option<u8> {
+        self.channels.iter().find(|ch| ch.name == name).map(|ch| ch.channel)
+    }
+
+    pub fn set_oversampling(&mut self, ratio: u16) -> Result<(), AdcError> {
+        if !ratio.is_power_of_two() || ratio > MAX_OVERSAMPLING {
+            return Err(AdcError::InvalidOversampling);
+        }
+
+        self.ifc.set_oversampling(ratio);
+        self.oversampling = ratio;
+
+        Ok(())
+    }
+
+    pub fn oversampling(&self) -> u16 {
+        self.oversampling
+    }
+
+    pub fn calibration(&self) -> AdcCalibration {
+        self.ifc.calibration()
+    }
+
+    pub fn read_raw(&mut self, name: &str) -> Result<u16, AdcError> {
+        let channel = self.channel(name).ok_or(AdcError::NoChannel)?;
+        self.ifc.read(channel)
+    }
+
+    // Measures actual VDDA using internal reference, result is cached for `to_mv`
+    pub fn vdda_mv(&mut self) -> Result<u32, AdcError> {
+        let vrefint = self.ifc.calibration().vrefint as u32;
+        let raw = self.read_raw(VREFINT_CHANNEL_NAME)? as u32;
+
+        if vrefint == 0 || raw == 0 {
+            return Err(AdcError::Calibration);
+        }
+
+        self.vdda_mv = CALIBRATION_VDDA_MV * vrefint / raw;
+
+        Ok(self.vdda_mv)
+    }
+
+    // Uses VDDA from the last `vdda_mv` call
+    pub fn to_mv(&self, raw: u16) -> u32 {
+        raw as u32 * self.vdda_mv / ADC_MAX
+    }
+
+    pub fn read_mv(&mut self, name: &str) -> Result<u32, AdcError> {
+        self.vdda_mv()?;
+
+        let raw = self.read_raw(name)?;
+
+        Ok(self.to_mv(raw))
+    }
+
+    // In tenths of a degree Celsius
+    pub fn temperature(&mut self) -> Result<i32, AdcError> {
+        let cal = self.ifc.calibration();
+
+        if cal.ts_cal2 <= cal.ts_cal1 {
+            return Err(AdcError::Calibration);
+        }
+
+        let vdda = self.vdda_mv()?;
+        let raw = self.read_raw(TEMPERATURE_CHANNEL_NAME)? as i32;
+
+        // Scale the reading to what it would be at calibration VDDA
+        let raw = raw * vdda as i32 / CALIBRATION_VDDA_MV as i32;
+
+        let span = (TS_CAL2_TEMP - TS_CAL1_TEMP) * 10;
+
+        Ok(TS_CAL1_TEMP * 10 + (raw - cal.ts_cal1 as i32) * span / (cal.ts_cal2 as i32 - cal.ts_cal1 as i32))
+    }
+}
+
+impl rtrs::object::Object for Adc {}
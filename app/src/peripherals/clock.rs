@@ -0,0 +1,125 @@
+extern crate alloc;
+use alloc::boxed::Box;
+
+use rtrs::sync::RwLock;
+use rtrs::task::Event;
+
+pub const CLOCK_OBJECT_NAME: &str = "clock";
+
+const MAX_LISTENERS: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClockError {
+    // Oscillator or PLL didn't become ready, previous clock is kept
+    Timeout,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClockProfile {
+    // ~2.1 MHz, lowest consumption, radio and UART still work
+    Msi,
+    // 16 MHz, default after boot
+    Hsi16,
+    // 32 MHz from HSI16, for radio bursts and heavy processing
+    Pll32,
+}
+
+impl ClockProfile {
+    pub const ALL: [ClockProfile; 3] = [ClockProfile::Msi, ClockProfile::Hsi16, ClockProfile::Pll32];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClockProfile::Msi   => "msi",
+            ClockProfile::Hsi16 => "hsi16",
+            ClockProfile::Pll32 => "pll32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "msi"   => Some(ClockProfile::Msi),
+            "hsi16" => Some(ClockProfile::Hsi16),
+            "pll32" => Some(ClockProfile::Pll32),
+            _       => None,
+        }
+    }
+}
+
+pub trait ClockInterface {
+    fn profile(&self) -> ClockProfile;
+    // Switches SYSCLK and re-derives the time base, returns new SYSCLK in Hz
+    fn set_profile(&mut self, profile: ClockProfile) -> Result<u32, ClockError>;
+    fn sysclk(&self) -> u32;
+}
+
+pub struct Clock {
+    ifc:      Box<dyn ClockInterface + Send + Sync + 'static>,
+    switches: u32,
+}
+
+impl Clock {
+    pub fn new(ifc: impl ClockInterface + Send + Sync + 'static) -> Self {
+        Self { ifc: Box::new(ifc), switches: 0 }
+    }
+
+    pub fn profile(&self) -> ClockProfile {
+        self.ifc.profile()
+    }
+
+    pub fn sysclk(&self) -> u32 {
+        self.ifc.sysclk()
+    }
+
+    pub fn switches(&self) -> u32 {
+        self.switches
+    }
+
+    // Listeners are called after the switch, they must not access the clock object
+    pub fn set_profile(&mut self, profile: ClockProfile) -> Result<u32, ClockError> {
+        if profile == self.ifc.profile() {
+            return Ok(self.ifc.sysclk());
+        }
+
+        let sysclk = self.ifc.set_profile(profile)?;
+        self.switches += 1;
+
+        notify(sysclk);
+
+        Ok(sysclk)
+    }
+}
+
+impl rtrs::object::Object for Clock {}
+
+// Triggered after every clock switch, drivers that can't use a listener may wait on it
+pub static CLOCK_EVENT: Event = Event::new();
+
+// Drivers that derive their timing from SYSCLK (baudrates, prescalers)
+static LISTENERS: RwLock<heapless::Vec<(&'static str, fn(u32)), MAX_LISTENERS>> = RwLock::new(heapless::Vec::new());
+
+// Replaces previous listener with the same name
+pub fn on_change(name: &'static str, listener: fn(u32)) {
+    let mut listeners = LISTENERS.lock_mut();
+
+    match (*listeners).iter_mut().find(|(n, _)| *n == name) {
+        Some(entry) => entry.1 = listener,
+        None => {
+            if (*listeners).push((name, listener)).is_err() {
+                panic!("Too many clock listeners");
+            }
+        }
+    }
+}
+
+pub fn listeners() -> heapless::Vec<(&'static str, fn(u32)), MAX_LISTENERS> {
+    LISTENERS.lock().clone()
+}
+
+fn notify(sysclk: u32) {
+    // Copied, so listeners are free to take other locks
+    for (_, listener) in listeners() {
+        listener(sysclk);
+    }
+
+    CLOCK_EVENT.trigger();
+}
@@ -0,0 +1,201 @@
+use rtrs::task::Event;
+use rtrs::sync::RwLock;
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::services::idle;
+
+pub const EXTI_LINES: usize = 16;
+
+// SX127x DIO0 line (RxDone/TxDone), if the target has it wired
+pub const RADIO_DIO0_NAME: &str = "radio_dio0";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Any,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExtiError {
+    NoLine,
+    Timeout,
+}
+
+// Lines are configured by the target for both edges, the edge is filtered when waiting
+static LINES: RwLock<[Option<&'static str>; EXTI_LINES]> = RwLock::new([None; EXTI_LINES]);
+
+// Set from interrupt, read and cleared by the waiter in a critical section (thumbv6m has no
+// atomic RMW, so a plain load and store could lose an edge in between)
+static RISING: [AtomicBool; EXTI_LINES] = [const { AtomicBool::new(false) }; EXTI_LINES];
+static FALLING: [AtomicBool; EXTI_LINES] = [const { AtomicBool::new(false) }; EXTI_LINES];
+
+// Pin level after the last edge
+static LEVELS: [AtomicBool; EXTI_LINES] = [const { AtomicBool::new(false) }; EXTI_LINES];
+
+static EVENTS: [Event; EXTI_LINES] = [const { Event::new() }; EXTI_LINES];
+
+// Timeout of the waiter on each line, the SysTick hook triggers its event once it passes
+static TIMED: [AtomicBool; EXTI_LINES] = [const { AtomicBool::new(false) }; EXTI_LINES];
+static DEADLINES: [AtomicU32; EXTI_LINES] = [const { AtomicU32::new(0) }; EXTI_LINES];
+
+// Edges of pins a user operates (buttons) keep the MCU awake for a while, the radio's don't
+static USER_FACING: [AtomicBool; EXTI_LINES] = [const { AtomicBool::new(false) }; EXTI_LINES];
+
+// Called by the target after routing pin `name` to EXTI `line`
+pub fn register(name: &'static str, line: u8) {
+    let mut lines = LINES.lock_mut();
+    (*lines)[line as usize] = Some(name);
+}
+
+pub fn line(name: &str) -> Option<u8> {
+    let lines = LINES.lock();
+    (*lines).iter().position(|line| *line == Some(name)).map(|line| line as u8)
+}
+
+pub fn set_user_facing(name: &str) -> Result<(), ExtiError> {
+    let line = line(name).ok_or(ExtiError::NoLine)?;
+    USER_FACING[line as usize].store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// Called from interrupt, `level` is the pin level sampled right after the edge
+pub fn notify(line: u8, level: bool) {
+    let line = line as usize;
+
+    LEVELS[line].store(level, Ordering::SeqCst);
+
+    if level {
+        RISING[line].store(true, Ordering::SeqCst);
+    } else {
+        FALLING[line].store(true, Ordering::SeqCst);
+    }
+
+    EVENTS[line].trigger();
+
+    if USER_FACING[line].load(Ordering::SeqCst) {
+        idle::notify_activity();
+    }
+}
+
+// Called from SysTick (and after STOP), wakes waiters whose timeout has passed
+pub fn on_tick(now: u32) {
+    for line in 0..EXTI_LINES {
+        if TIMED[line].load(Ordering::SeqCst) && reached(now, DEADLINES[line].load(Ordering::SeqCst)) {
+            EVENTS[line].trigger();
+        }
+    }
+}
+
+fn reached(now: u32, deadline: u32) -> bool {
+    now.wrapping_sub(deadline) < u32::MAX / 2
+}
+
+fn take_flag(flag: &AtomicBool) -> bool {
+    let seen = flag.load(Ordering::SeqCst);
+
+    if seen {
+        flag.store(false, Ordering::SeqCst);
+    }
+
+    seen
+}
+
+fn take(line: u8, edge: Edge) -> Option<bool> {
+    let line = line as usize;
+
+    critical_section::with(|_| {
+        let seen = match edge {
+            Edge::Rising  => take_flag(&RISING[line]),
+            Edge::Falling => take_flag(&FALLING[line]),
+            Edge::Any     => take_flag(&RISING[line]) | take_flag(&FALLING[line]),
+        };
+
+        if !seen {
+            return None;
+        }
+
+        match edge {
+            Edge::Rising  => Some(true),
+            Edge::Falling => Some(false),
+            Edge::Any     => Some(LEVELS[line].load(Ordering::SeqCst)),
+        }
+    })
+}
+
+// Waits for an edge that happened after the call, returns pin level after the edge.
+// Sleeps on the line's event, woken by the pin interrupt or by SysTick once the timeout passed
+pub async fn wait_for_edge(pin: &str, edge: Edge, timeout: Option<u32>) -> Result<bool, ExtiError> {
+    wait(pin, edge, None, timeout).await
+}
+
+// Returns at once if the pin is already at `level`, otherwise waits for the edge to it.
+// For lines that stay asserted until serviced, an edge before the call isn't missed
+pub async fn wait_for_level(pin: &str, level: bool, timeout: Option<u32>) -> Result<(), ExtiError> {
+    let edge = if level { Edge::Rising } else { Edge::Falling };
+
+    wait(pin, edge, Some(level), timeout).await.map(|_| ())
+}
+
+async fn wait(pin: &str, edge: Edge, level: Option<bool>, timeout: Option<u32>) -> Result<bool, ExtiError> {
+    let line = line(pin).ok_or(ExtiError::NoLine)?;
+    let start = rtrs::time::global_tick();
+
+    // Name from the registry is 'static, so it can be used as idle deadline name
+    let name = (*LINES.lock())[line as usize].ok_or(ExtiError::NoLine)?;
+
+    let idx = line as usize;
+
+    // Level is read along with clearing the flags, an edge right after sets them again
+    let current = critical_section::with(|_| {
+        RISING[idx].store(false, Ordering::SeqCst);
+        FALLING[idx].store(false, Ordering::SeqCst);
+
+        LEVELS[idx].load(Ordering::SeqCst)
+    });
+
+    if level == Some(current) {
+        return Ok(current);
+    }
+
+    if let Some(timeout) = timeout {
+        DEADLINES[idx].store(start.wrapping_add(timeout), Ordering::SeqCst);
+        TIMED[idx].store(true, Ordering::SeqCst);
+        idle::wake_at(name, start.wrapping_add(timeout));
+    }
+
+    let res = loop {
+        // Cleared before the flags are checked, so an edge in between still triggers it
+        EVENTS[idx].clear();
+
+        if let Some(level) = take(line, edge) {
+            break Ok(level);
+        }
+
+        if timeout.is_some_and(|timeout| rtrs::time::global_tick().wrapping_sub(start) >= timeout) {
+            break Err(ExtiError::Timeout);
+        }
+
+        (&EVENTS[idx]).await;
+    };
+
+    if timeout.is_some() {
+        TIMED[idx].store(false, Ordering::SeqCst);
+        idle::cancel_wake(name);
+    }
+
+    res
+}
+
+pub async fn wait_for_rising(pin: &str, timeout: Option<u32>) -> Result<(), ExtiError> {
+    wait_for_edge(pin, Edge::Rising, timeout).await.map(|_| ())
+}
+
+pub async fn wait_for_falling(pin: &str, timeout: Option<u32>) -> Result<(), ExtiError> {
+    wait_for_edge(pin, Edge::Falling, timeout).await.map(|_| ())
+}
+
+pub async fn wait_for_any_edge(pin: &str, timeout: Option<u32>) -> Result<bool, ExtiError> {
+    wait_for_edge(pin, Edge::Any, timeout).await
+}
@@ -0,0 +1,130 @@
+extern crate alloc;
+use alloc::boxed::Box;
+
+
+use crate::services::battery;
+
+pub const FLASH_OBJECT_NAME: &str = "flash";
+
+// Writes start on a word boundary and cover whole words
+pub const WRITE_SIZE: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlashError {
+    OutOfRange,
+    Alignment,
+    // Supply is too low to erase or write, see battery::brownout_risk
+    Brownout,
+    Timeout,
+    Write,
+    // Target can't boot the staging area
+    Unsupported,
+}
+
+// Staging area for firmware updates in program flash, offsets are relative to its start.
+// Words have to be erased before they're written again
+pub trait FlashInterface {
+    // Start of the area in the memory map
+    fn address(&self) -> u32;
+    fn size(&self) -> usize;
+    fn page_size(&self) -> usize;
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), FlashError>;
+    fn erase_page(&mut self, offset: usize) -> Result<(), FlashError>;
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError>;
+    // Next reset starts the image in the staging area, the running one takes its place there.
+    // May reset right away if the switch needs one to take effect
+    fn boot_staged(&mut self) -> Result<(), FlashError> {
+        Err(FlashError::Unsupported)
+    }
+}
+
+pub struct Flash {
+    ifc:    Box<dyn FlashInterface + Send + Sync + 'static>,
+    erases: u32,
+    writes: u32,
+}
+
+impl Flash {
+    pub fn new(ifc: impl FlashInterface + Send + Sync + 'static) -> Self {
+        Self { ifc: Box::new(ifc), erases: 0, writes: 0 }
+    }
+
+    pub fn address(&self) -> u32 {
+        self.ifc.address()
+    }
+
+    pub fn size(&self) -> usize {
+        self.ifc.size()
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.ifc.page_size()
+    }
+
+    // Page erases and successful writes since boot
+    pub fn stats(&self) -> (u32, u32) {
+        (self.erases, self.writes)
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<(), FlashError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.ifc.size() => Ok(()),
+            _ => Err(FlashError::OutOfRange),
+        }
+    }
+
+    pub fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), FlashError> {
+        self.check(offset, buf.len())?;
+        self.ifc.read(offset, buf)
+    }
+
+    // Page that holds `offset`
+    pub fn erase_page(&mut self, offset: usize) -> Result<(), FlashError> {
+        self.check(offset, 1)?;
+
+        if battery::brownout_risk() {
+            return Err(FlashError::Brownout);
+        }
+
+        let page_size = self.ifc.page_size();
+
+        self.ifc.erase_page(offset - offset % page_size)?;
+        self.erases += 1;
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        self.check(offset, data.len())?;
+
+        if offset % WRITE_SIZE != 0 || data.len() % WRITE_SIZE != 0 {
+            return Err(FlashError::Alignment);
+        }
+
+        // Interrupted write leaves a word that can only be erased with its whole page
+        if battery::brownout_risk() {
+            return Err(FlashError::Brownout);
+        }
+
+        self.ifc.write(offset, data)?;
+        self.writes += 1;
+
+        Ok(())
+    }
+
+    pub fn boot_staged(&mut self) -> Result<(), FlashError> {
+        // Half written option bytes may leave nothing to boot
+        if battery::brownout_risk() {
+            return Err(FlashError::Brownout);
+        }
+
+        self.ifc.boot_staged()
+    }
+}
+
+impl rtrs::object::Object for Flash {}
+
+// Staging area needs room for a second image, not every target has it
+pub fn available() -> bool {
+    crate::object_exists(FLASH_OBJECT_NAME)
+}
@@ -0,0 +1,182 @@
+use rtrs::gpio::{Input, Output};
+use rtrs::object::{Object, STORAGE};
+use rtrs::sync::RwLock;
+use rtrs::{object_with, object_with_mut, println, logger, info, error};
+
+use core::any::Any;
+use core::fmt::Write; // For println!
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::peripherals::exti;
+use crate::services::idle;
+
+logger!("gpio");
+
+const MAX_PINS: usize = 8;
+
+// Pins without an EXTI line are polled
+const WATCH_POLL_MS: u32 = 10;
+// Longest an EXTI wait blocks before checking for a stop
+const WATCH_WAIT_MS: u32 = 200;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PinKind {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GpioError {
+    NotFound,
+    WrongKind,
+    Pin,
+}
+
+#[derive(Copy, Clone)]
+pub struct PinInfo {
+    pub name: &'static str,
+    pub kind: PinKind,
+}
+
+// Last level written through this module, outputs can't be read back
+static LEVELS: RwLock<heapless::Vec<(&'static str, bool), MAX_PINS>> = RwLock::new(heapless::Vec::new());
+
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+// Objects are `Any`, so pins are told apart from the rest of STORAGE by their type
+fn kind(object: &dyn Object) -> Option<PinKind> {
+    let object: &dyn Any = object;
+
+    if object.is::<Input>() {
+        Some(PinKind::Input)
+    } else if object.is::<Output>() {
+        Some(PinKind::Output)
+    } else {
+        None
+    }
+}
+
+// All Input and Output objects in STORAGE
+pub fn pins() -> heapless::Vec<PinInfo, MAX_PINS> {
+    let mut pins = heapless::Vec::new();
+
+    for (name, object) in STORAGE.lock().iter() {
+        if let Some(kind) = kind(object.as_ref()) {
+            if pins.push(PinInfo { name, kind }).is_err() {
+                break;
+            }
+        }
+    }
+
+    pins
+}
+
+pub fn find(name: &str) -> Option<PinInfo> {
+    pins().into_iter().find(|pin| pin.name == name)
+}
+
+fn find_kind(name: &str, kind: PinKind) -> Result<PinInfo, GpioError> {
+    match find(name) {
+        Some(info) if info.kind == kind => Ok(info),
+        Some(_) => Err(GpioError::WrongKind),
+        None => Err(GpioError::NotFound),
+    }
+}
+
+fn level(name: &str) -> bool {
+    let levels = LEVELS.lock();
+    (*levels).iter().find(|(pin, _)| *pin == name).map(|(_, level)| *level).unwrap_or(false)
+}
+
+fn set_level(name: &'static str, level: bool) {
+    let mut levels = LEVELS.lock_mut();
+
+    match (*levels).iter_mut().find(|(pin, _)| *pin == name) {
+        Some(entry) => entry.1 = level,
+        None => {
+            let _ = (*levels).push((name, level));
+        }
+    }
+}
+
+pub fn read(name: &str) -> Result<bool, GpioError> {
+    let info = find(name).ok_or(GpioError::NotFound)?;
+
+    match info.kind {
+        PinKind::Input  => object_with!(info.name, Input, pin, pin.is_high()).map_err(|_| GpioError::Pin),
+        PinKind::Output => Ok(level(info.name)),
+    }
+}
+
+pub fn write(name: &str, level: bool) -> Result<(), GpioError> {
+    let info = find_kind(name, PinKind::Output)?;
+
+    object_with_mut!(info.name, Output, pin, {
+        if level {
+            pin.set_high()
+        } else {
+            pin.set_low()
+        }
+    }).map_err(|_| GpioError::Pin)?;
+
+    set_level(info.name, level);
+
+    Ok(())
+}
+
+pub fn toggle(name: &str) -> Result<bool, GpioError> {
+    let level = !read(name)?;
+    write(name, level)?;
+    Ok(level)
+}
+
+pub fn watching() -> bool {
+    WATCHING.load(Ordering::SeqCst)
+}
+
+// Set before the task is spawned, so a key press right away isn't missed
+pub fn start_watch(name: &str) -> Result<PinInfo, GpioError> {
+    let info = find_kind(name, PinKind::Input)?;
+
+    WATCHING.store(true, Ordering::SeqCst);
+
+    Ok(info)
+}
+
+pub fn stop_watch() {
+    WATCHING.store(false, Ordering::SeqCst);
+}
+
+// Prints edges until stopped. Sleeps on the EXTI line if the pin has one, otherwise polls
+pub async fn watch(pin: PinInfo) {
+    let has_line = exti::line(pin.name).is_some();
+    let mut last = read(pin.name);
+
+    while watching() {
+        let level = if has_line {
+            match exti::wait_for_any_edge(pin.name, Some(WATCH_WAIT_MS)).await {
+                Ok(level) => Ok(level),
+                Err(exti::ExtiError::Timeout) => continue,
+                Err(err) => {
+                    error!("{}: {:?}", pin.name, err);
+                    break;
+                }
+            }
+        } else {
+            idle::sleep(WATCH_POLL_MS).await;
+            read(pin.name)
+        };
+
+        if level != last {
+            match level {
+                Ok(true)  => println!("[{}] rising",  rtrs::time::global_tick()),
+                Ok(false) => println!("[{}] falling", rtrs::time::global_tick()),
+                Err(err)  => println!("[{}] error {:?}", rtrs::time::global_tick(), err),
+            }
+            last = level;
+        }
+    }
+
+    stop_watch();
+    info!("Stopped watching '{}'", pin.name);
+}
@@ -1 +1,10 @@
-pub mod pulse_sensor;
\ No newline at end of file
+pub mod adc;
+pub mod clock;
+pub mod exti;
+pub mod flash;
+pub mod gpio;
+pub mod nvm;
+pub mod pulse_sensor;
+pub mod rtc;
+pub mod spi;
+pub mod tone;
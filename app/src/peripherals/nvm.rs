@@ -0,0 +1,87 @@
+extern crate alloc;
+use alloc::boxed::Box;
+
+
+use crate::services::battery;
+
+pub const NVM_OBJECT_NAME: &str = "nvm";
+
+// Layout of the non-volatile memory. Regions are only appended, so data written by older
+// firmware stays where newer firmware expects it
+pub const RADIO_PROFILES_OFFSET: usize = 0;
+pub const RADIO_PROFILES_SIZE: usize = 128;
+pub const RADIO_KEYS_OFFSET: usize = RADIO_PROFILES_OFFSET + RADIO_PROFILES_SIZE;
+pub const RADIO_KEYS_SIZE: usize = 128;
+pub const OTA_STATE_OFFSET: usize = RADIO_KEYS_OFFSET + RADIO_KEYS_SIZE;
+pub const OTA_STATE_SIZE: usize = 64;
+pub const RADIO_REPLAY_OFFSET: usize = OTA_STATE_OFFSET + OTA_STATE_SIZE;
+pub const RADIO_REPLAY_SIZE: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NvmError {
+    OutOfRange,
+    // Supply is too low to write, see battery::brownout_risk
+    Brownout,
+    Timeout,
+    Write,
+}
+
+pub trait NvmInterface {
+    fn size(&self) -> usize;
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), NvmError>;
+    // Only bytes that differ have to be written, EEPROM cells wear out
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NvmError>;
+}
+
+pub struct Nvm {
+    ifc:    Box<dyn NvmInterface + Send + Sync + 'static>,
+    writes: u32,
+}
+
+impl Nvm {
+    pub fn new(ifc: impl NvmInterface + Send + Sync + 'static) -> Self {
+        Self { ifc: Box::new(ifc), writes: 0 }
+    }
+
+    pub fn size(&self) -> usize {
+        self.ifc.size()
+    }
+
+    // Successful writes since boot
+    pub fn writes(&self) -> u32 {
+        self.writes
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<(), NvmError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.ifc.size() => Ok(()),
+            _ => Err(NvmError::OutOfRange),
+        }
+    }
+
+    pub fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), NvmError> {
+        self.check(offset, buf.len())?;
+        self.ifc.read(offset, buf)
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NvmError> {
+        self.check(offset, data.len())?;
+
+        // Interrupted write leaves a corrupted cell behind
+        if battery::brownout_risk() {
+            return Err(NvmError::Brownout);
+        }
+
+        self.ifc.write(offset, data)?;
+        self.writes += 1;
+
+        Ok(())
+    }
+}
+
+impl rtrs::object::Object for Nvm {}
+
+// Not every target has non-volatile memory for settings
+pub fn available() -> bool {
+    crate::object_exists(NVM_OBJECT_NAME)
+}
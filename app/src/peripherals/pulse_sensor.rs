@@ -2,8 +2,13 @@
 extern crate alloc;
 use alloc::boxed::Box;
 
+use crate::peripherals::adc::AdcError;
+
+// Name of the sensor's ADC channel, the sensor itself belongs to the heart rate sampling
+pub const PULSE_SENSOR_OBJECT_NAME: &str = "pulse_sensor";
+
 pub trait PulseSensorInterface {
-    fn read(&mut self) -> u16;
+    fn read(&mut self) -> Result<u16, AdcError>;
 }
 
 pub struct PulseSensor {
@@ -15,9 +20,7 @@ impl PulseSensor {
         Self { ifc: Box::new(ifc) }
     }
 
-    pub fn read(&mut self) -> u16 {
+    pub fn read(&mut self) -> Result<u16, AdcError> {
         (*self.ifc).read()
     }
 }
-
-impl rtrs::object::Object for PulseSensor {}
@@ -0,0 +1,317 @@
+extern crate alloc;
+use alloc::boxed::Box;
+
+use rtrs::task::Event;
+use rtrs::object_with_mut;
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub const RTC_OBJECT_NAME: &str = "rtc";
+
+// Hardware calendar only stores two year digits
+pub const MIN_YEAR: u16 = 2000;
+pub const MAX_YEAR: u16 = 2099;
+
+const SECONDS_PER_DAY: u32 = 86_400;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RtcError {
+    NotSet,
+    InvalidDate,
+    Parse,
+    Timeout,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year:   u16,
+    pub month:  u8,
+    pub day:    u8,
+    pub hour:   u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn is_leap(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap(year) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Result<Self, RtcError> {
+        let valid = (MIN_YEAR..=MAX_YEAR).contains(&year)
+            && (1..=12).contains(&month)
+            && day >= 1 && day <= days_in_month(year, month)
+            && hour < 24 && minute < 60 && second < 60;
+
+        if !valid {
+            return Err(RtcError::InvalidDate);
+        }
+
+        Ok(Self { year, month, day, hour, minute, second })
+    }
+
+    // "YYYY-MM-DD" and "HH:MM:SS"
+    pub fn parse(date: &str, time: &str) -> Result<Self, RtcError> {
+        fn fields<const N: usize>(s: &str, sep: char) -> Result<[u16; N], RtcError> {
+            let mut out = [0; N];
+            let mut parts = s.split(sep);
+
+            for field in out.iter_mut() {
+                *field = parts.next().and_then(|v| v.parse().ok()).ok_or(RtcError::Parse)?;
+            }
+
+            if parts.next().is_some() {
+                return Err(RtcError::Parse);
+            }
+
+            Ok(out)
+        }
+
+        let [year, month, day] = fields::<3>(date, '-')?;
+        let [hour, minute, second] = fields::<3>(time, ':')?;
+
+        let narrow = |v: u16| u8::try_from(v).map_err(|_| RtcError::InvalidDate);
+
+        Self::new(year, narrow(month)?, narrow(day)?, narrow(hour)?, narrow(minute)?, narrow(second)?)
+    }
+
+    // Days since 1970-01-01 (proleptic Gregorian calendar)
+    fn days(&self) -> u32 {
+        let year = self.year as u32 - if self.month <= 2 { 1 } else { 0 };
+        let month = self.month as u32;
+
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as u32 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+        era * 146_097 + doe - 719_468
+    }
+
+    pub fn to_unix(&self) -> u32 {
+        self.days() * SECONDS_PER_DAY + self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32
+    }
+
+    pub fn from_unix(timestamp: u32) -> Result<Self, RtcError> {
+        let days = timestamp / SECONDS_PER_DAY + 719_468;
+        let secs = timestamp % SECONDS_PER_DAY;
+
+        let era = days / 146_097;
+        let doe = days - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        let year = u16::try_from(year).map_err(|_| RtcError::InvalidDate)?;
+
+        Self::new(year, month as u8, day as u8, (secs / 3600) as u8, (secs / 60 % 60) as u8, (secs % 60) as u8)
+    }
+
+    pub fn add_seconds(&self, seconds: u32) -> Result<Self, RtcError> {
+        Self::from_unix(self.to_unix().checked_add(seconds).ok_or(RtcError::InvalidDate)?)
+    }
+
+    // ISO weekday, 1 is Monday (same as the hardware calendar)
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((self.days() + 3) % 7 + 1) as u8
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+pub trait RtcInterface {
+    fn now(&mut self) -> Result<DateTime, RtcError>;
+    fn set(&mut self, time: &DateTime) -> Result<(), RtcError>;
+    // `None` disables the alarm
+    fn set_alarm(&mut self, at: Option<&DateTime>) -> Result<(), RtcError>;
+    // For backends without an alarm interrupt, called periodically by their target
+    fn poll(&mut self) {}
+}
+
+pub struct Rtc {
+    ifc:   Box<dyn RtcInterface + Send + Sync + 'static>,
+    alarm: Option<DateTime>,
+}
+
+impl Rtc {
+    pub fn new(ifc: impl RtcInterface + Send + Sync + 'static) -> Self {
+        Self { ifc: Box::new(ifc), alarm: None }
+    }
+
+    pub fn now(&mut self) -> Result<DateTime, RtcError> {
+        self.ifc.now()
+    }
+
+    pub fn set(&mut self, time: &DateTime) -> Result<(), RtcError> {
+        self.ifc.set(time)
+    }
+
+    // Alarm wakes the MCU from STOP, `wait_for_alarm` returns once it fires
+    pub fn set_alarm(&mut self, at: Option<DateTime>) -> Result<(), RtcError> {
+        self.ifc.set_alarm(at.as_ref())?;
+        self.alarm = at;
+
+        ALARM_FIRED.store(false, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    pub fn alarm(&self) -> Option<DateTime> {
+        self.alarm
+    }
+
+    pub fn poll(&mut self) {
+        self.ifc.poll();
+    }
+}
+
+impl rtrs::object::Object for Rtc {}
+
+// Backend for hosts, the calendar runs on the system clock (Unix seconds, e.g. from SystemTime).
+// Setting the time only moves an offset, the system clock itself is left alone
+pub struct ClockRtc {
+    clock:  fn() -> u64,
+    offset: i64,
+    alarm:  Option<u32>,
+}
+
+impl ClockRtc {
+    pub fn new(clock: fn() -> u64) -> Self {
+        Self { clock, offset: 0, alarm: None }
+    }
+
+    fn unix(&self) -> i64 {
+        (self.clock)() as i64 + self.offset
+    }
+}
+
+impl RtcInterface for ClockRtc {
+    fn now(&mut self) -> Result<DateTime, RtcError> {
+        DateTime::from_unix(u32::try_from(self.unix()).map_err(|_| RtcError::InvalidDate)?)
+    }
+
+    fn set(&mut self, time: &DateTime) -> Result<(), RtcError> {
+        self.offset = time.to_unix() as i64 - (self.clock)() as i64;
+        Ok(())
+    }
+
+    fn set_alarm(&mut self, at: Option<&DateTime>) -> Result<(), RtcError> {
+        self.alarm = at.map(|at| at.to_unix());
+        Ok(())
+    }
+
+    fn poll(&mut self) {
+        if self.alarm.is_some_and(|at| self.unix() >= at as i64) {
+            self.alarm = None;
+            notify_alarm();
+        }
+    }
+}
+
+static ALARM_FIRED: AtomicBool = AtomicBool::new(false);
+static ALARM_EVENT: Event = Event::new();
+
+// Called by the target from the alarm interrupt
+pub fn notify_alarm() {
+    ALARM_FIRED.store(true, Ordering::SeqCst);
+    ALARM_EVENT.trigger();
+}
+
+pub fn alarm_fired() -> bool {
+    ALARM_FIRED.load(Ordering::SeqCst)
+}
+
+pub async fn wait_for_alarm() {
+    while !ALARM_FIRED.load(Ordering::SeqCst) {
+        (&ALARM_EVENT).await;
+    }
+
+    ALARM_FIRED.store(false, Ordering::SeqCst);
+}
+
+// Lock-free calendar read registered by the target, address of a
+// `fn() -> Result<DateTime, RtcError>` or 0
+static UNLOCKED_READ: AtomicUsize = AtomicUsize::new(0);
+
+pub fn register_unlocked_read(read: fn() -> Result<DateTime, RtcError>) {
+    UNLOCKED_READ.store(read as usize, Ordering::SeqCst);
+}
+
+// For the panic handler, which can't rely on STORAGE: reads the registers without any lock
+pub fn timestamp_unlocked() -> Option<DateTime> {
+    let read = UNLOCKED_READ.load(Ordering::SeqCst);
+
+    if read == 0 {
+        return None;
+    }
+
+    let read: fn() -> Result<DateTime, RtcError> = unsafe { core::mem::transmute(read) };
+
+    read().ok()
+}
+
+// Wall-clock time for log lines, `None` if there is no RTC or it wasn't set
+pub fn timestamp() -> Option<DateTime> {
+    if !crate::object_exists(RTC_OBJECT_NAME) {
+        return None;
+    }
+
+    object_with_mut!(RTC_OBJECT_NAME, Rtc, rtc, rtc.now()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicU64;
+
+    static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+    fn clock() -> u64 {
+        CLOCK.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn clock_rtc_follows_the_system_clock() {
+        // 2024-02-29 12:00:00
+        CLOCK.store(1_709_208_000, Ordering::SeqCst);
+
+        let mut rtc = Rtc::new(ClockRtc::new(clock));
+
+        assert_eq!(rtc.now(), DateTime::new(2024, 2, 29, 12, 0, 0));
+
+        rtc.set(&DateTime::new(2030, 12, 31, 23, 59, 50).unwrap()).unwrap();
+        CLOCK.store(1_709_208_000 + 15, Ordering::SeqCst);
+
+        assert_eq!(rtc.now(), DateTime::new(2031, 1, 1, 0, 0, 5));
+
+        rtc.set_alarm(Some(DateTime::new(2031, 1, 1, 0, 0, 10).unwrap())).unwrap();
+        rtc.poll();
+        assert!(!alarm_fired());
+
+        CLOCK.store(1_709_208_000 + 20, Ordering::SeqCst);
+        rtc.poll();
+        assert!(alarm_fired());
+    }
+}
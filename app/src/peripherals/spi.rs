@@ -0,0 +1,116 @@
+extern crate alloc;
+use alloc::boxed::Box;
+
+use rtrs::object::{Object, STORAGE};
+use rtrs::sync::RwLock;
+
+use core::any::Any;
+
+// MSB of the address byte selects write access (SX127x, most register-based SPI devices)
+pub const REG_WRITE_BIT: u8 = 0x80;
+
+const MAX_ALIASES: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpiError {
+    Bus,
+    Select,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeviceError {
+    NotFound,
+    NotSpi,
+}
+
+// Chips whose driver object owns the name (e.g. "radio") are reached through the SpiDevice of their bus
+static ALIASES: RwLock<heapless::Vec<(&'static str, &'static str), MAX_ALIASES>> = RwLock::new(heapless::Vec::new());
+
+// Replaces previous alias with the same name
+pub fn alias(name: &'static str, device: &'static str) {
+    let mut aliases = ALIASES.lock_mut();
+
+    match (*aliases).iter_mut().find(|(n, _)| *n == name) {
+        Some(entry) => entry.1 = device,
+        None => {
+            if (*aliases).push((name, device)).is_err() {
+                panic!("Too many SPI aliases");
+            }
+        }
+    }
+}
+
+// Name of the SpiDevice object for a device or an alias of one
+pub fn find(name: &str) -> Result<&'static str, DeviceError> {
+    let target = ALIASES.lock().iter().find(|(n, _)| *n == name).map(|(_, device)| *device);
+    let name = target.unwrap_or(name);
+
+    for (key, object) in STORAGE.lock().iter() {
+        if *key == name {
+            let object: &dyn Any = object.as_ref();
+
+            return if object.is::<SpiDevice>() { Ok(key) } else { Err(DeviceError::NotSpi) };
+        }
+    }
+
+    Err(DeviceError::NotFound)
+}
+
+pub trait SpiInterface {
+    fn select(&mut self) -> Result<(), SpiError>;
+    fn deselect(&mut self) -> Result<(), SpiError>;
+    fn transfer(&mut self, byte: u8) -> Result<u8, SpiError>;
+}
+
+pub struct SpiDevice {
+    ifc: Box<dyn SpiInterface + Send + Sync + 'static>,
+}
+
+impl SpiDevice {
+    pub fn new(ifc: impl SpiInterface + Send + Sync + 'static) -> Self {
+        Self { ifc: Box::new(ifc) }
+    }
+
+    // Full-duplex transfer, every byte in `data` is replaced with the byte clocked in
+    pub fn transfer(&mut self, data: &mut [u8]) -> Result<(), SpiError> {
+        self.ifc.select()?;
+
+        let res = data.iter_mut().try_for_each(|byte| {
+            *byte = self.ifc.transfer(*byte)?;
+            Ok(())
+        });
+
+        self.ifc.deselect()?;
+
+        res
+    }
+
+    pub fn read_reg(&mut self, reg: u8) -> Result<u8, SpiError> {
+        let mut buf = [reg & !REG_WRITE_BIT, 0];
+        self.transfer(&mut buf)?;
+        Ok(buf[1])
+    }
+
+    pub fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), SpiError> {
+        let mut buf = [reg | REG_WRITE_BIT, value];
+        self.transfer(&mut buf)
+    }
+
+    // Burst read, relies on the device auto-incrementing the address
+    pub fn read_regs(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), SpiError> {
+        self.ifc.select()?;
+
+        let res = self.ifc.transfer(reg & !REG_WRITE_BIT).and_then(|_| {
+            buf.iter_mut().try_for_each(|byte| {
+                *byte = self.ifc.transfer(0)?;
+                Ok(())
+            })
+        });
+
+        self.ifc.deselect()?;
+
+        res
+    }
+}
+
+impl Object for SpiDevice {}
@@ -0,0 +1,76 @@
+extern crate alloc;
+use alloc::boxed::Box;
+
+pub const TONE_OBJECT_NAME: &str = "tone";
+
+pub trait ToneInterface {
+    fn start(&mut self, freq: u32);
+    fn stop(&mut self);
+}
+
+pub struct Tone {
+    ifc: Box<dyn ToneInterface + Send + Sync + 'static>,
+    freq: Option<u32>,
+}
+
+impl Tone {
+    pub fn new(ifc: impl ToneInterface + Send + Sync + 'static) -> Self {
+        Self { ifc: Box::new(ifc), freq: None }
+    }
+
+    pub fn freq(&self) -> Option<u32> {
+        self.freq
+    }
+}
+
+impl ToneInterface for Tone {
+    fn start(&mut self, freq: u32) {
+        (*self.ifc).start(freq);
+        self.freq = Some(freq);
+    }
+
+    fn stop(&mut self) {
+        (*self.ifc).stop();
+        self.freq = None;
+    }
+}
+
+impl rtrs::object::Object for Tone {}
+
+// Stand-in for real hardware, records every change of the output as (time, freq), 0 means silence
+pub struct ToneRecorder<const N: usize> {
+    now:    u32,
+    events: heapless::Vec<(u32, u32), N>,
+}
+
+impl<const N: usize> ToneRecorder<N> {
+    pub fn new() -> Self {
+        Self { now: 0, events: heapless::Vec::new() }
+    }
+
+    pub fn set_time(&mut self, now: u32) {
+        self.now = now;
+    }
+
+    pub fn events(&self) -> &[(u32, u32)] {
+        &self.events
+    }
+
+    fn record(&mut self, freq: u32) {
+        if self.events.last().is_some_and(|(_, last)| *last == freq) {
+            return;
+        }
+
+        let _ = self.events.push((self.now, freq));
+    }
+}
+
+impl<const N: usize> ToneInterface for ToneRecorder<N> {
+    fn start(&mut self, freq: u32) {
+        self.record(freq);
+    }
+
+    fn stop(&mut self) {
+        self.record(0);
+    }
+}
@@ -0,0 +1,207 @@
+use rtrs::task::Event;
+use rtrs::{object_insert, object_with, object_with_mut, logger, info, warn, error};
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::peripherals::adc::{Adc, AdcError, ADC_OBJECT_NAME};
+use crate::services::idle;
+
+logger!("battery");
+
+pub const BATTERY_OBJECT_NAME: &str = "battery";
+
+pub const DEFAULT_PERIOD_MS: u32 = 10_000;
+
+// CR2032 is mostly flat at ~2.9V and drops quickly below ~2.5V.
+// Brownout reset on L0 is at ~1.8V (BOR level 0), radio TX current pulls VDD down by a few hundred mV
+pub const DEFAULT_LOW_MV: u32 = 2500;
+pub const DEFAULT_BROWNOUT_MV: u32 = 2200;
+
+// Voltage has to rise this much above a threshold before the state is recovered
+const HYSTERESIS_MV: u32 = 50;
+
+// Triggered on every state change, current state is taken with `state()`
+pub static POWER_EVENT: Event = Event::new();
+
+// Kept outside of the object, so it can be checked from anywhere without locking storage
+static STATE: AtomicU8 = AtomicU8::new(PowerState::Unknown as u8);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum PowerState {
+    Unknown,
+    Normal,
+    Low,
+    Brownout,
+}
+
+impl PowerState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => PowerState::Normal,
+            2 => PowerState::Low,
+            3 => PowerState::Brownout,
+            _ => PowerState::Unknown,
+        }
+    }
+}
+
+pub fn state() -> PowerState {
+    PowerState::from_u8(STATE.load(Ordering::SeqCst))
+}
+
+// High current operations (radio TX, flash/EEPROM writes) should be skipped,
+// as they may pull VDD below brownout reset level
+pub fn brownout_risk() -> bool {
+    state() == PowerState::Brownout
+}
+
+// Measures VDD directly, `None` if the target has no ADC
+pub fn measure() -> Option<Result<u32, AdcError>> {
+    if !crate::object_exists(ADC_OBJECT_NAME) {
+        return None;
+    }
+
+    Some(object_with_mut!(ADC_OBJECT_NAME, Adc, adc, adc.vdda_mv()))
+}
+
+pub struct Battery {
+    vdd_mv:      Option<u32>,
+    min_mv:      Option<u32>,
+    max_mv:      Option<u32>,
+    low_mv:      u32,
+    brownout_mv: u32,
+    period_ms:   u32,
+    errors:      u32,
+}
+
+impl Battery {
+    pub fn new() -> Self {
+        Self {
+            vdd_mv:      None,
+            min_mv:      None,
+            max_mv:      None,
+            low_mv:      DEFAULT_LOW_MV,
+            brownout_mv: DEFAULT_BROWNOUT_MV,
+            period_ms:   DEFAULT_PERIOD_MS,
+            errors:      0,
+        }
+    }
+
+    pub fn vdd_mv(&self) -> Option<u32> {
+        self.vdd_mv
+    }
+
+    pub fn min_mv(&self) -> Option<u32> {
+        self.min_mv
+    }
+
+    pub fn max_mv(&self) -> Option<u32> {
+        self.max_mv
+    }
+
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    pub fn reset_min_max(&mut self) {
+        self.min_mv = self.vdd_mv;
+        self.max_mv = self.vdd_mv;
+    }
+
+    pub fn thresholds(&self) -> (u32, u32) {
+        (self.low_mv, self.brownout_mv)
+    }
+
+    pub fn set_thresholds(&mut self, low_mv: u32, brownout_mv: u32) -> Result<(), ()> {
+        if brownout_mv >= low_mv {
+            return Err(());
+        }
+
+        self.low_mv = low_mv;
+        self.brownout_mv = brownout_mv;
+
+        Ok(())
+    }
+
+    pub fn period(&self) -> u32 {
+        self.period_ms
+    }
+
+    pub fn set_period(&mut self, period_ms: u32) {
+        self.period_ms = period_ms.max(100);
+    }
+
+    fn classify(&self, vdd: u32, current: PowerState) -> PowerState {
+        let brownout = match current {
+            PowerState::Brownout => self.brownout_mv + HYSTERESIS_MV,
+            _                    => self.brownout_mv,
+        };
+
+        let low = match current {
+            PowerState::Brownout | PowerState::Low => self.low_mv + HYSTERESIS_MV,
+            _                                      => self.low_mv,
+        };
+
+        if vdd < brownout {
+            PowerState::Brownout
+        } else if vdd < low {
+            PowerState::Low
+        } else {
+            PowerState::Normal
+        }
+    }
+
+    pub fn update(&mut self, measurement: Result<u32, AdcError>) {
+        let vdd = match measurement {
+            Ok(vdd) => vdd,
+            Err(err) => {
+                self.errors += 1;
+                error!("VDD measurement failed: {:?}", err);
+                return;
+            }
+        };
+
+        self.vdd_mv = Some(vdd);
+        self.min_mv = Some(self.min_mv.map_or(vdd, |min| min.min(vdd)));
+        self.max_mv = Some(self.max_mv.map_or(vdd, |max| max.max(vdd)));
+
+        let current = state();
+        let next = self.classify(vdd, current);
+
+        if next == current {
+            return;
+        }
+
+        match next {
+            PowerState::Low      => warn!("Low battery: {} mV", vdd),
+            PowerState::Brownout => warn!("Brownout risk: {} mV, radio TX and flash writes are disabled", vdd),
+            _                    => info!("Supply: {} mV", vdd),
+        }
+
+        STATE.store(next as u8, Ordering::SeqCst);
+        POWER_EVENT.trigger();
+    }
+}
+
+impl rtrs::object::Object for Battery {}
+
+pub(crate) fn init() {
+    object_insert!(BATTERY_OBJECT_NAME, Battery::new());
+}
+
+pub(crate) async fn task() {
+    loop {
+        // ADC is locked separately from the battery object, so the shell can use both in between
+        let Some(measurement) = measure() else {
+            warn!("No ADC, supply voltage is not monitored");
+            return;
+        };
+
+        object_with_mut!(BATTERY_OBJECT_NAME, Battery, battery, battery.update(measurement));
+
+        let period = object_with!(BATTERY_OBJECT_NAME, Battery, battery, battery.period());
+
+        idle::sleep(period).await;
+    }
+}
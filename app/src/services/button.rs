@@ -0,0 +1,279 @@
+use rtrs::task::Event;
+use rtrs::{object_insert, object_with_mut, task_sleep, logger, info, error};
+
+use crate::peripherals::exti;
+use crate::peripherals::gpio::{self, GpioError, PinKind};
+use crate::services::idle::{self, IdleMode};
+
+logger!("button");
+
+pub const BUTTON_SERVICE_OBJECT_NAME: &str = "buttons";
+
+// Registered automatically if the target provides it
+const DEFAULT_BUTTON: &str = "btn";
+
+const MAX_BUTTONS: usize = 4;
+const MAX_BINDINGS: usize = 8;
+const EVENT_QUEUE_SIZE: usize = 8;
+
+const DEBOUNCE_MS: u32 = 20;
+const LONG_PRESS_MS: u32 = 800;
+const DOUBLE_CLICK_MS: u32 = 300;
+
+// Triggered on every emitted event, events themselves are taken with `ButtonService::pop_event`
+pub static BUTTON_EVENT: Event = Event::new();
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ButtonEventKind {
+    Press,
+    Release,
+    LongPress,
+    DoubleClick,
+}
+
+impl ButtonEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ButtonEventKind::Press       => "press",
+            ButtonEventKind::Release     => "release",
+            ButtonEventKind::LongPress   => "long",
+            ButtonEventKind::DoubleClick => "double",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "press"   => Some(ButtonEventKind::Press),
+            "release" => Some(ButtonEventKind::Release),
+            "long"    => Some(ButtonEventKind::LongPress),
+            "double"  => Some(ButtonEventKind::DoubleClick),
+            _         => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ButtonEvent {
+    pub button: &'static str,
+    pub kind:   ButtonEventKind,
+    pub tick:   u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ButtonError {
+    Gpio(GpioError),
+    AlreadyAdded,
+    NotFound,
+    TooMany,
+    CommandTooLong,
+}
+
+impl From<GpioError> for ButtonError {
+    fn from(err: GpioError) -> Self {
+        ButtonError::Gpio(err)
+    }
+}
+
+struct Button {
+    pin:          &'static str,
+    active_high:  bool,
+    // Raw (not debounced) state and when it last changed
+    raw:          bool,
+    raw_since:    u32,
+    pressed:      bool,
+    pressed_at:   u32,
+    long_sent:    bool,
+    last_release: Option<u32>,
+}
+
+impl Button {
+    fn new(pin: &'static str, active_high: bool) -> Self {
+        Self {
+            pin,
+            active_high,
+            raw:          false,
+            raw_since:    0,
+            pressed:      false,
+            pressed_at:   0,
+            long_sent:    false,
+            last_release: None,
+        }
+    }
+
+    fn is_busy(&self, now: u32) -> bool {
+        self.raw || self.pressed || self.last_release.is_some_and(|release| now.wrapping_sub(release) <= DOUBLE_CLICK_MS)
+    }
+
+    fn cycle(&mut self, now: u32, mut emit: impl FnMut(ButtonEventKind)) {
+        let Ok(level) = gpio::read(self.pin) else {
+            return;
+        };
+
+        let raw = level == self.active_high;
+
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since = now;
+        }
+
+        if self.raw != self.pressed && now.wrapping_sub(self.raw_since) >= DEBOUNCE_MS {
+            self.pressed = self.raw;
+
+            if self.pressed {
+                self.pressed_at = now;
+                self.long_sent = false;
+
+                emit(ButtonEventKind::Press);
+
+                if self.last_release.take().is_some_and(|release| now.wrapping_sub(release) <= DOUBLE_CLICK_MS) {
+                    emit(ButtonEventKind::DoubleClick);
+                }
+            } else {
+                emit(ButtonEventKind::Release);
+
+                // Long press doesn't count as the first click of a double click
+                self.last_release = if self.long_sent { None } else { Some(now) };
+            }
+        }
+
+        if self.pressed && !self.long_sent && now.wrapping_sub(self.pressed_at) >= LONG_PRESS_MS {
+            self.long_sent = true;
+            emit(ButtonEventKind::LongPress);
+        }
+    }
+}
+
+pub struct Binding {
+    pub button:  &'static str,
+    pub kind:    ButtonEventKind,
+    pub command: heapless::String<{ crate::cmd::MAX_COMMAND_SIZE }>,
+}
+
+pub struct ButtonService {
+    buttons:  heapless::Vec<Button, MAX_BUTTONS>,
+    bindings: heapless::Vec<Binding, MAX_BINDINGS>,
+    events:   heapless::Deque<ButtonEvent, EVENT_QUEUE_SIZE>,
+    verbose:  bool,
+}
+
+impl ButtonService {
+    pub fn new() -> Self {
+        Self {
+            buttons:  heapless::Vec::new(),
+            bindings: heapless::Vec::new(),
+            events:   heapless::Deque::new(),
+            verbose:  false,
+        }
+    }
+
+    pub fn add(&mut self, pin: &str, active_high: bool) -> Result<(), ButtonError> {
+        let info = gpio::find(pin).ok_or(GpioError::NotFound)?;
+
+        if info.kind != PinKind::Input {
+            return Err(GpioError::WrongKind.into());
+        }
+
+        if self.buttons.iter().any(|btn| btn.pin == info.name) {
+            return Err(ButtonError::AlreadyAdded);
+        }
+
+        // Pins without an EXTI line are only polled
+        let _ = exti::set_user_facing(info.name);
+
+        self.buttons.push(Button::new(info.name, active_high)).map_err(|_| ButtonError::TooMany)
+    }
+
+    pub fn buttons(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        self.buttons.iter().map(|btn| (btn.pin, btn.pressed))
+    }
+
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    // Replaces existing binding for the same button and event
+    pub fn bind(&mut self, button: &str, kind: ButtonEventKind, command: &str) -> Result<(), ButtonError> {
+        let button = self.buttons.iter().find(|btn| btn.pin == button).ok_or(ButtonError::NotFound)?.pin;
+
+        let mut binding = Binding { button, kind, command: heapless::String::new() };
+        binding.command.push_str(command).map_err(|_| ButtonError::CommandTooLong)?;
+
+        self.unbind(button, kind);
+
+        self.bindings.push(binding).map_err(|_| ButtonError::TooMany)
+    }
+
+    pub fn unbind(&mut self, button: &str, kind: ButtonEventKind) {
+        self.bindings.retain(|binding| !(binding.button == button && binding.kind == kind));
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    pub fn pop_event(&mut self) -> Option<ButtonEvent> {
+        self.events.pop_front()
+    }
+
+    fn emit(&mut self, event: ButtonEvent) {
+        if self.verbose {
+            info!("[{}] {} {}", event.tick, event.button, event.kind.name());
+        }
+
+        for binding in self.bindings.iter().filter(|b| b.button == event.button && b.kind == event.kind) {
+            if crate::cmd::schedule(&binding.command).is_err() {
+                error!("Command queue is full, dropped '{}'", binding.command);
+            }
+        }
+
+        // Oldest event is dropped if nobody consumes them
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+
+        let _ = self.events.push_back(event);
+
+        BUTTON_EVENT.trigger();
+    }
+
+    pub fn cycle(&mut self) {
+        let now = rtrs::time::global_tick();
+
+        let mut events: heapless::Vec<ButtonEvent, EVENT_QUEUE_SIZE> = heapless::Vec::new();
+
+        for btn in self.buttons.iter_mut() {
+            let button = btn.pin;
+            btn.cycle(now, |kind| {
+                let _ = events.push(ButtonEvent { button, kind, tick: now });
+            });
+        }
+
+        for event in events {
+            self.emit(event);
+        }
+
+        // Press wakes the MCU through EXTI, but debounce, long press and double click need SysTick
+        let busy = self.buttons.iter().any(|btn| btn.is_busy(now));
+        idle::set_inhibit(BUTTON_SERVICE_OBJECT_NAME, IdleMode::Sleep, busy);
+    }
+}
+
+impl rtrs::object::Object for ButtonService {}
+
+pub(crate) fn init() {
+    let mut service = ButtonService::new();
+
+    if gpio::find(DEFAULT_BUTTON).is_some() {
+        // Pin has a pull-down, the button pulls it high when pressed
+        let _ = service.add(DEFAULT_BUTTON, true);
+    }
+
+    object_insert!(BUTTON_SERVICE_OBJECT_NAME, service);
+}
+
+pub(crate) async fn task() {
+    loop {
+        object_with_mut!(BUTTON_SERVICE_OBJECT_NAME, ButtonService, service, service.cycle());
+        task_sleep!(1);
+    }
+}
@@ -0,0 +1,334 @@
+use rtrs::{object_insert, object_with_mut, task_sleep};
+
+use core::cell::{RefCell, UnsafeCell};
+use core::sync::atomic::{AtomicUsize, AtomicU32, Ordering};
+
+use critical_section::Mutex;
+
+use crate::board::{BoardInterface, Callback, CallbackType};
+use crate::services::hrv::IbiHistory;
+use crate::services::idle::{self, IdleMode};
+use crate::peripherals::adc::AdcError;
+use crate::peripherals::pulse_sensor::PulseSensor;
+
+pub const HEART_RATE_OBJECT_NAME: &str = "heart_rate";
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 500;
+
+const SAMPLE_BUFFER_SIZE: usize = 64;
+const BPM_AVERAGE_BEATS: usize = 8;
+
+// 30..200 BPM
+const MIN_IBI_MS: u32 = 300;
+const MAX_IBI_MS: u32 = 2000;
+const REFRACTORY_MS: u32 = 250;
+const NO_SIGNAL_MS: u32 = 3000;
+
+// Filtered signal is scaled by 256, so this is ~16 ADC counts peak
+const MIN_AMPLITUDE: i32 = 16 << 8;
+
+// Max deviation of an IBI from the average for the signal to be considered good
+const MAX_IBI_DEVIATION_PERCENT: u32 = 25;
+
+// Single producer (sampling interrupt), single consumer (heart rate task) ring buffer.
+// Only loads and stores are used, thumbv6m doesn't have atomic read-modify-write
+struct SampleRing {
+    buf:     UnsafeCell<[u16; SAMPLE_BUFFER_SIZE]>,
+    head:    AtomicUsize,
+    tail:    AtomicUsize,
+    dropped: AtomicU32,
+}
+
+unsafe impl Sync for SampleRing {}
+
+impl SampleRing {
+    const fn new() -> Self {
+        Self {
+            buf:     UnsafeCell::new([0; SAMPLE_BUFFER_SIZE]),
+            head:    AtomicUsize::new(0),
+            tail:    AtomicUsize::new(0),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    fn push(&self, sample: u16) {
+        let head = self.head.load(Ordering::Acquire);
+        let next = (head + 1) % SAMPLE_BUFFER_SIZE;
+
+        if next == self.tail.load(Ordering::Acquire) {
+            self.mark_dropped();
+            return;
+        }
+
+        unsafe { (*self.buf.get())[head] = sample };
+
+        self.head.store(next, Ordering::Release);
+    }
+
+    fn mark_dropped(&self) {
+        self.dropped.store(self.dropped.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+    }
+
+    fn pop(&self) -> Option<u16> {
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let sample = unsafe { (*self.buf.get())[tail] };
+
+        self.tail.store((tail + 1) % SAMPLE_BUFFER_SIZE, Ordering::Release);
+
+        Some(sample)
+    }
+
+    fn clear(&self) {
+        self.tail.store(self.head.load(Ordering::Acquire), Ordering::Release);
+    }
+}
+
+static SAMPLES: SampleRing = SampleRing::new();
+
+// Owned by the sampling interrupt, so it never has to go through STORAGE
+static SENSOR: Mutex<RefCell<Option<PulseSensor>>> = Mutex::new(RefCell::new(None));
+
+// Called by the target once the sensor's ADC channel is set up
+pub fn set_sensor(sensor: PulseSensor) {
+    critical_section::with(|cs| *SENSOR.borrow_ref_mut(cs) = Some(sensor));
+}
+
+pub fn has_sensor() -> bool {
+    critical_section::with(|cs| SENSOR.borrow_ref(cs).is_some())
+}
+
+// Single reading outside of sampling, interrupt can't run in between
+pub fn read_sensor() -> Option<Result<u16, AdcError>> {
+    critical_section::with(|cs| SENSOR.borrow_ref_mut(cs).as_mut().map(|sensor| sensor.read()))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SignalQuality {
+    NoSignal,
+    Poor,
+    Good,
+}
+
+// DC removal + low-pass (band-pass ~0.3..10 Hz at 500 Hz) and adaptive threshold beat detection.
+// Doesn't depend on hardware, time is derived from sample count and rate
+pub struct BeatDetector {
+    sample_us: u64,
+    now_us:    u64,
+    dc:        i32,
+    filtered:  i32,
+    envelope:  i32,
+    above:     bool,
+    last_beat: Option<u64>,
+    ibis:      heapless::HistoryBuffer<u16, BPM_AVERAGE_BEATS>,
+}
+
+impl BeatDetector {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            sample_us: 1_000_000 / rate.max(1) as u64,
+            now_us:    0,
+            dc:        -1,
+            filtered:  0,
+            envelope:  0,
+            above:     false,
+            last_beat: None,
+            ibis:      heapless::HistoryBuffer::new(),
+        }
+    }
+
+    // Returns inter-beat interval in ms, if sample completed a valid beat
+    pub fn process(&mut self, sample: u16) -> Option<u16> {
+        self.now_us += self.sample_us;
+
+        let x = (sample as i32) << 8;
+
+        if self.dc < 0 {
+            self.dc = x;
+        }
+
+        self.dc += (x - self.dc) >> 8;
+        self.filtered += ((x - self.dc) - self.filtered) >> 3;
+
+        // Peak envelope decays by ~1/512 per sample
+        self.envelope = (self.envelope - (self.envelope >> 9)).max(self.filtered);
+
+        let threshold = self.envelope / 2;
+        let above = self.filtered > threshold && self.envelope >= MIN_AMPLITUDE;
+        let rising = above && !self.above;
+
+        self.above = above;
+
+        if !rising {
+            return None;
+        }
+
+        let now_ms = self.now_us / 1000;
+
+        let ibi = match self.last_beat {
+            Some(last) if now_ms - last < REFRACTORY_MS as u64 => return None,
+            Some(last) => now_ms - last,
+            None => {
+                self.last_beat = Some(now_ms);
+                return None;
+            }
+        };
+
+        self.last_beat = Some(now_ms);
+
+        if ibi < MIN_IBI_MS as u64 || ibi > MAX_IBI_MS as u64 {
+            return None;
+        }
+
+        self.ibis.write(ibi as u16);
+
+        Some(ibi as u16)
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_us / 1000
+    }
+
+    pub fn filtered(&self) -> i32 {
+        self.filtered >> 8
+    }
+
+    pub fn last_ibi(&self) -> Option<u16> {
+        self.ibis.recent().copied()
+    }
+
+    pub fn ibis(&self) -> impl Iterator<Item = u16> + '_ {
+        self.ibis.oldest_ordered().copied()
+    }
+
+    fn average_ibi(&self) -> Option<u32> {
+        let count = self.ibis.len() as u32;
+
+        if count == 0 {
+            return None;
+        }
+
+        Some(self.ibis.iter().map(|ibi| *ibi as u32).sum::<u32>() / count)
+    }
+
+    pub fn bpm(&self) -> Option<u16> {
+        if self.quality() == SignalQuality::NoSignal {
+            return None;
+        }
+
+        self.average_ibi().map(|ibi| (60_000 / ibi) as u16)
+    }
+
+    pub fn quality(&self) -> SignalQuality {
+        let recent = self.last_beat.is_some_and(|last| self.now_ms() - last < NO_SIGNAL_MS as u64);
+
+        if self.envelope < MIN_AMPLITUDE || !recent || self.ibis.len() < 2 {
+            return SignalQuality::NoSignal;
+        }
+
+        let average = self.average_ibi().unwrap_or(0);
+        let max_deviation = average * MAX_IBI_DEVIATION_PERCENT / 100;
+
+        if self.ibis.iter().all(|ibi| (*ibi as u32).abs_diff(average) <= max_deviation) {
+            SignalQuality::Good
+        } else {
+            SignalQuality::Poor
+        }
+    }
+}
+
+pub struct HeartRate {
+    detector: BeatDetector,
+    history:  IbiHistory,
+    rate:     u32,
+    running:  bool,
+}
+
+impl HeartRate {
+    pub fn new() -> Self {
+        Self {
+            detector: BeatDetector::new(DEFAULT_SAMPLE_RATE),
+            history:  IbiHistory::new(),
+            rate:     DEFAULT_SAMPLE_RATE,
+            running:  false,
+        }
+    }
+
+    pub fn start(&mut self, rate: u32) {
+        self.detector = BeatDetector::new(rate);
+        self.history.clear();
+        self.rate = rate;
+        self.running = true;
+
+        SAMPLES.clear();
+        BoardInterface::callback(Callback::SetSampleRate(rate));
+
+        // Sampling timer doesn't run in STOP mode
+        idle::inhibit(HEART_RATE_OBJECT_NAME, IdleMode::Sleep);
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+        BoardInterface::callback(Callback::SetSampleRate(0));
+
+        idle::release(HEART_RATE_OBJECT_NAME);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn dropped_samples(&self) -> u32 {
+        SAMPLES.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn detector(&self) -> &BeatDetector {
+        &self.detector
+    }
+
+    pub fn history(&self) -> &IbiHistory {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut IbiHistory {
+        &mut self.history
+    }
+
+    fn process(&mut self) {
+        while let Some(sample) = SAMPLES.pop() {
+            if let Some(ibi) = self.detector.process(sample) {
+                self.history.push(ibi);
+            }
+        }
+    }
+}
+
+impl rtrs::object::Object for HeartRate {}
+
+fn on_sample() {
+    // Failed conversions (or no sensor) are counted as dropped samples
+    match read_sensor() {
+        Some(Ok(sample)) => SAMPLES.push(sample),
+        _ => SAMPLES.mark_dropped(),
+    }
+}
+
+pub(crate) fn init() {
+    object_insert!(HEART_RATE_OBJECT_NAME, HeartRate::new());
+    BoardInterface::register_callback(CallbackType::SampleTimer(on_sample));
+}
+
+pub(crate) async fn task() {
+    loop {
+        object_with_mut!(HEART_RATE_OBJECT_NAME, HeartRate, hr, hr.process());
+        task_sleep!(10);
+    }
+}
@@ -0,0 +1,300 @@
+// Heart-rate variability over a history of inter-beat intervals (IBI).
+// Doesn't depend on hardware, so it can be fed with recorded intervals
+
+pub const HISTORY_SIZE: usize = 64;
+pub const DEFAULT_WINDOW: usize = 32;
+
+// Accepted IBIs used as reference for artifact rejection
+const REFERENCE_SIZE: usize = 5;
+const MAX_DEVIATION_PERCENT: u32 = 20;
+
+// After this many rejections in a row the rhythm has most likely changed, so reference is reset
+const MAX_CONSECUTIVE_REJECTS: u8 = 5;
+
+const NN50_MS: u32 = 50;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Artifact {
+    // Much longer than expected, a beat was most likely not detected
+    Missed,
+    // Much shorter than expected, noise was detected as a beat
+    Extra,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct HrvMetrics {
+    // Number of intervals the metrics were computed from
+    pub count:    u16,
+    pub mean_ibi: u16,
+    pub sdnn:     u16,
+    pub rmssd:    u16,
+    // In tenths of a percent
+    pub pnn50:    u16,
+    pub missed:   u16,
+    pub extra:    u16,
+}
+
+impl HrvMetrics {
+    pub const ENCODED_SIZE: usize = 14;
+
+    // Little-endian u16 fields in declaration order
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let fields = [self.count, self.mean_ibi, self.sdnn, self.rmssd, self.pnn50, self.missed, self.extra];
+
+        let buf = buf.get_mut(..Self::ENCODED_SIZE)?;
+
+        for (chunk, field) in buf.chunks_exact_mut(2).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+
+        Some(Self::ENCODED_SIZE)
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..Self::ENCODED_SIZE)?;
+        let field = |i: usize| u16::from_le_bytes([buf[i * 2], buf[i * 2 + 1]]);
+
+        Some(Self {
+            count:    field(0),
+            mean_ibi: field(1),
+            sdnn:     field(2),
+            rmssd:    field(3),
+            pnn50:    field(4),
+            missed:   field(5),
+            extra:    field(6),
+        })
+    }
+}
+
+fn isqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+
+    let mut x = value;
+    let mut y = (x + 1) / 2;
+
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+
+    x
+}
+
+pub struct IbiHistory {
+    history:   heapless::HistoryBuffer<u16, HISTORY_SIZE>,
+    reference: heapless::HistoryBuffer<u16, REFERENCE_SIZE>,
+    rejects:   u8,
+    missed:    u16,
+    extra:     u16,
+    window:    usize,
+}
+
+impl IbiHistory {
+    pub fn new() -> Self {
+        Self {
+            history:   heapless::HistoryBuffer::new(),
+            reference: heapless::HistoryBuffer::new(),
+            rejects:   0,
+            missed:    0,
+            extra:     0,
+            window:    DEFAULT_WINDOW,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self { window: self.window, ..Self::new() };
+    }
+
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.clamp(2, HISTORY_SIZE);
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    fn reference(&self) -> Option<u32> {
+        if self.reference.len() < 3 {
+            return None;
+        }
+
+        // Median of the last accepted intervals
+        let mut sorted: heapless::Vec<u16, REFERENCE_SIZE> = self.reference.iter().copied().collect();
+        sorted.sort_unstable();
+
+        Some(sorted[sorted.len() / 2] as u32)
+    }
+
+    fn classify(&self, ibi: u32) -> Option<Artifact> {
+        let reference = self.reference()?;
+        let max_deviation = reference * MAX_DEVIATION_PERCENT / 100;
+
+        if ibi > reference + max_deviation {
+            Some(Artifact::Missed)
+        } else if ibi + max_deviation < reference {
+            Some(Artifact::Extra)
+        } else {
+            None
+        }
+    }
+
+    // Returns artifact kind if the interval was rejected
+    pub fn push(&mut self, ibi: u16) -> Option<Artifact> {
+        if let Some(artifact) = self.classify(ibi as u32) {
+            self.rejects += 1;
+
+            if self.rejects < MAX_CONSECUTIVE_REJECTS {
+                match artifact {
+                    Artifact::Missed => self.missed = self.missed.saturating_add(1),
+                    Artifact::Extra  => self.extra = self.extra.saturating_add(1),
+                }
+
+                return Some(artifact);
+            }
+
+            self.reference.clear();
+        }
+
+        self.rejects = 0;
+        self.reference.write(ibi);
+        self.history.write(ibi);
+
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn ibis(&self) -> impl Iterator<Item = u16> + '_ {
+        self.history.oldest_ordered().copied()
+    }
+
+    pub fn metrics(&self) -> Option<HrvMetrics> {
+        let count = self.history.len().min(self.window);
+
+        if count < 2 {
+            return None;
+        }
+
+        let skip = self.history.len() - count;
+        let window = || self.history.oldest_ordered().skip(skip).map(|ibi| *ibi as u64);
+
+        let mean = window().sum::<u64>() / count as u64;
+        let variance = window().map(|ibi| ibi.abs_diff(mean).pow(2)).sum::<u64>() / count as u64;
+
+        let mut diffs = 0u64;
+        let mut squares = 0u64;
+        let mut nn50 = 0u64;
+
+        for (prev, next) in window().zip(window().skip(1)) {
+            let diff = prev.abs_diff(next);
+
+            diffs += 1;
+            squares += diff * diff;
+
+            if diff > NN50_MS as u64 {
+                nn50 += 1;
+            }
+        }
+
+        Some(HrvMetrics {
+            count:    count as u16,
+            mean_ibi: mean as u16,
+            sdnn:     isqrt(variance) as u16,
+            rmssd:    isqrt(squares / diffs) as u16,
+            pnn50:    (nn50 * 1000 / diffs) as u16,
+            missed:   self.missed,
+            extra:    self.extra,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Recorded at rest, with two missed beats and one extra beat
+    const RECORDING: &str = include_str!("../../data/ibi_rest.txt");
+
+    fn recorded(window: usize) -> (IbiHistory, heapless::Vec<(usize, Artifact), 8>) {
+        let mut history = IbiHistory::new();
+        let mut rejected = heapless::Vec::new();
+
+        history.set_window(window);
+
+        for (i, line) in RECORDING.lines().filter(|line| !line.starts_with('#')).enumerate() {
+            if let Some(artifact) = history.push(line.trim().parse().unwrap()) {
+                rejected.push((i, artifact)).unwrap();
+            }
+        }
+
+        (history, rejected)
+    }
+
+    #[test]
+    fn artifacts_rejected() {
+        let (history, rejected) = recorded(HISTORY_SIZE);
+
+        assert_eq!(&rejected[..], &[(20, Artifact::Missed), (41, Artifact::Extra), (42, Artifact::Extra), (60, Artifact::Missed)]);
+        assert_eq!(history.len(), HISTORY_SIZE);
+    }
+
+    #[test]
+    fn recorded_metrics() {
+        let (history, _) = recorded(HISTORY_SIZE);
+
+        assert_eq!(history.metrics(), Some(HrvMetrics {
+            count:    64,
+            mean_ibi: 847,
+            sdnn:     27,
+            rmssd:    34,
+            pnn50:    126,
+            missed:   2,
+            extra:    2,
+        }));
+
+        let (history, _) = recorded(DEFAULT_WINDOW);
+        let hrv = history.metrics().unwrap();
+
+        assert_eq!((hrv.count, hrv.mean_ibi, hrv.sdnn, hrv.rmssd, hrv.pnn50), (32, 848, 26, 32, 64));
+    }
+
+    #[test]
+    fn rhythm_change_resets_reference() {
+        let mut history = IbiHistory::new();
+
+        for _ in 0..5 {
+            assert_eq!(history.push(1000), None);
+        }
+
+        // Heart rate jumps to 100 BPM, after a few rejections the new rhythm is accepted
+        let rejected = (0..8).filter(|_| history.push(600).is_some()).count();
+
+        assert_eq!(rejected, MAX_CONSECUTIVE_REJECTS as usize - 1);
+        assert_eq!(history.push(600), None);
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let (history, _) = recorded(HISTORY_SIZE);
+        let hrv = history.metrics().unwrap();
+
+        let mut buf = [0; HrvMetrics::ENCODED_SIZE];
+
+        assert_eq!(hrv.encode(&mut buf), Some(HrvMetrics::ENCODED_SIZE));
+        assert_eq!(HrvMetrics::decode(&buf), Some(hrv));
+        assert_eq!(hrv.encode(&mut buf[1..]), None);
+    }
+
+    #[test]
+    fn too_few_intervals() {
+        let mut history = IbiHistory::new();
+
+        assert_eq!(history.metrics(), None);
+        history.push(800);
+        assert_eq!(history.metrics(), None);
+    }
+}
@@ -0,0 +1,303 @@
+use rtrs::sync::RwLock;
+use rtrs::time::{TimeProvider, TIME_OBJECT_NAME};
+use rtrs::{object_insert, object_with, object_with_mut, task_sleep, task_yield, logger, trace};
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::board::{BoardInterface, Callback};
+
+logger!("idle");
+
+pub const IDLE_OBJECT_NAME: &str = "idle";
+
+// Shorter STOP isn't worth the wake-up and clock restore time
+const MIN_STOP_MS: u32 = 20;
+
+// Upper bound for a single STOP, in case something forgot to register a deadline
+const MAX_STOP_MS: u32 = 30_000;
+
+// After console input or a button edge, MCU doesn't sleep for a while (e.g. while typing)
+const ACTIVITY_HOLDOFF_MS: u32 = 3000;
+
+const MAX_INHIBITORS: usize = 8;
+const MAX_DEADLINES: usize = 8;
+// One per task (RTRS_SCHED_STORAGE_SIZE)
+const MAX_SLEEPERS: usize = 16;
+
+// Ordered from shallowest to deepest
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IdleMode {
+    // Busy loop, nothing is allowed to be delayed
+    Off,
+    // WFI, woken up by any interrupt (at least every SysTick)
+    Sleep,
+    // STOP mode until the nearest deadline, SysTick is stopped and global tick is compensated
+    Stop,
+}
+
+impl IdleMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IdleMode::Off   => "off",
+            IdleMode::Sleep => "sleep",
+            IdleMode::Stop  => "stop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off"   => Some(IdleMode::Off),
+            "sleep" => Some(IdleMode::Sleep),
+            "stop"  => Some(IdleMode::Stop),
+            _       => None,
+        }
+    }
+}
+
+// Each inhibitor limits the deepest allowed mode while it's held
+static INHIBITORS: RwLock<heapless::Vec<(&'static str, IdleMode), MAX_INHIBITORS>> = RwLock::new(heapless::Vec::new());
+
+// Ticks at which something has to run, STOP never lasts past the nearest one
+static DEADLINES: RwLock<heapless::Vec<(&'static str, u32), MAX_DEADLINES>> = RwLock::new(heapless::Vec::new());
+
+// Wake-up ticks of tasks in `sleep`, by sleep id. Next id is kept along, thumbv6m has no fetch_add
+static SLEEPERS: RwLock<(u32, heapless::Vec<(u32, u32), MAX_SLEEPERS>)> = RwLock::new((0, heapless::Vec::new()));
+
+static ACTIVITY: AtomicBool = AtomicBool::new(false);
+
+// Replaces previous inhibitor with the same name
+pub fn inhibit(name: &'static str, deepest: IdleMode) {
+    let mut inhibitors = INHIBITORS.lock_mut();
+
+    match (*inhibitors).iter_mut().find(|(n, _)| *n == name) {
+        Some(entry) => entry.1 = deepest,
+        None => {
+            if (*inhibitors).push((name, deepest)).is_err() {
+                panic!("Too many idle inhibitors");
+            }
+        }
+    }
+}
+
+pub fn release(name: &str) {
+    let mut inhibitors = INHIBITORS.lock_mut();
+    (*inhibitors).retain(|(n, _)| *n != name);
+}
+
+// Convenience for services that re-evaluate their state every cycle
+pub fn set_inhibit(name: &'static str, deepest: IdleMode, active: bool) {
+    if active {
+        inhibit(name, deepest);
+    } else {
+        release(name);
+    }
+}
+
+pub fn inhibitors() -> heapless::Vec<(&'static str, IdleMode), MAX_INHIBITORS> {
+    INHIBITORS.lock().clone()
+}
+
+// Replaces previous deadline with the same name
+pub fn wake_at(name: &'static str, tick: u32) {
+    let mut deadlines = DEADLINES.lock_mut();
+
+    match (*deadlines).iter_mut().find(|(n, _)| *n == name) {
+        Some(entry) => entry.1 = tick,
+        None => {
+            if (*deadlines).push((name, tick)).is_err() {
+                panic!("Too many idle deadlines");
+            }
+        }
+    }
+}
+
+pub fn cancel_wake(name: &str) {
+    let mut deadlines = DEADLINES.lock_mut();
+    (*deadlines).retain(|(n, _)| *n != name);
+}
+
+pub fn deadlines() -> heapless::Vec<(&'static str, u32), MAX_DEADLINES> {
+    DEADLINES.lock().clone()
+}
+
+// Removed on drop, so a sleep cut short (its task stopped) doesn't hold STOP back
+struct Sleeper(u32);
+
+impl Sleeper {
+    fn new(tick: u32) -> Self {
+        let mut sleepers = SLEEPERS.lock_mut();
+        let id = (*sleepers).0;
+
+        (*sleepers).0 = id.wrapping_add(1);
+
+        if (*sleepers).1.push((id, tick)).is_err() {
+            panic!("Too many sleeping tasks");
+        }
+
+        Self(id)
+    }
+}
+
+impl Drop for Sleeper {
+    fn drop(&mut self) {
+        let mut sleepers = SLEEPERS.lock_mut();
+        (*sleepers).1.retain(|(id, _)| *id != self.0);
+    }
+}
+
+// task_sleep! with the wake-up known to idle, STOP ends in time for it. Loops that only poll for
+// work an interrupt brings (which ends STOP anyway) can use plain task_sleep!
+pub async fn sleep(ms: u32) {
+    let _sleeper = Sleeper::new(rtrs::time::global_tick().wrapping_add(ms));
+
+    task_sleep!(ms);
+}
+
+// Called on console input and from interrupts of user-facing pins (buttons)
+pub fn notify_activity() {
+    ACTIVITY.store(true, Ordering::SeqCst);
+}
+
+// Checked by the target with interrupts disabled right before entering STOP,
+// so activity between the decision and WFI isn't lost
+pub fn pending_activity() -> bool {
+    ACTIVITY.load(Ordering::SeqCst)
+}
+
+enum Action {
+    Sleep,
+    Stop(u32),
+}
+
+pub struct Idle {
+    mode:          IdleMode,
+    last_activity: u32,
+    sleeps:        u32,
+    stops:         u32,
+    stopped_ms:    u32,
+}
+
+impl Idle {
+    pub fn new() -> Self {
+        Self {
+            mode:          IdleMode::Stop,
+            last_activity: 0,
+            sleeps:        0,
+            stops:         0,
+            stopped_ms:    0,
+        }
+    }
+
+    pub fn mode(&self) -> IdleMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: IdleMode) {
+        self.mode = mode;
+    }
+
+    pub fn stats(&self) -> (u32, u32, u32) {
+        (self.sleeps, self.stops, self.stopped_ms)
+    }
+
+    // Deepest mode allowed right now, taking inhibitors into account
+    pub fn allowed(&self) -> IdleMode {
+        INHIBITORS.lock().iter().fold(self.mode, |mode, (_, deepest)| mode.min(*deepest))
+    }
+
+    // Time to the nearest deadline or sleep wake-up, 0 if one is due
+    fn stop_budget(&self, now: u32) -> u32 {
+        let left = |budget: u32, tick: u32| {
+            let left = tick.wrapping_sub(now);
+
+            // Passed deadlines wrap to huge values
+            if left > u32::MAX / 2 { 0 } else { budget.min(left) }
+        };
+
+        let budget = DEADLINES.lock().iter().fold(MAX_STOP_MS, |budget, (_, tick)| left(budget, *tick));
+
+        SLEEPERS.lock().1.iter().fold(budget, |budget, (_, tick)| left(budget, *tick))
+    }
+
+    fn select(&mut self, now: u32) -> Option<Action> {
+        if ACTIVITY.load(Ordering::SeqCst) {
+            ACTIVITY.store(false, Ordering::SeqCst);
+            self.last_activity = now;
+        }
+
+        // Console is polled, so even WFI (up to a SysTick period) could overrun UART while typing
+        if now.wrapping_sub(self.last_activity) < ACTIVITY_HOLDOFF_MS {
+            return None;
+        }
+
+        let budget = self.stop_budget(now);
+
+        // Task that is due runs in this round, even WFI would hold it up until the next SysTick
+        if budget == 0 {
+            return None;
+        }
+
+        match self.allowed() {
+            IdleMode::Off => None,
+            IdleMode::Sleep => Some(Action::Sleep),
+            IdleMode::Stop if budget >= MIN_STOP_MS => Some(Action::Stop(budget)),
+            IdleMode::Stop => Some(Action::Sleep),
+        }
+    }
+
+    fn stopped(&mut self, ms: u32) {
+        self.stops += 1;
+        self.stopped_ms = self.stopped_ms.wrapping_add(ms);
+    }
+}
+
+impl rtrs::object::Object for Idle {}
+
+// SysTick doesn't run in STOP, so time spent there is added to the global tick. TimeProvider only
+// counts by one, but an increment is cheap next to the wake-up from STOP
+fn compensate(ms: u32) {
+    object_with!(TIME_OBJECT_NAME, TimeProvider, time, {
+        for _ in 0..ms {
+            time.increment();
+        }
+    });
+
+    BoardInterface::callback(Callback::Systick);
+}
+
+fn idle() {
+    let now = rtrs::time::global_tick();
+
+    // Board is called outside of the object, so interrupts and other tasks can access it meanwhile
+    match object_with_mut!(IDLE_OBJECT_NAME, Idle, idle, idle.select(now)) {
+        None => {}
+        Some(Action::Sleep) => {
+            BoardInterface::callback(Callback::Sleep);
+            object_with_mut!(IDLE_OBJECT_NAME, Idle, idle, idle.sleeps += 1);
+        }
+        Some(Action::Stop(ms)) => {
+            let mut slept = 0;
+
+            BoardInterface::callback(Callback::Stop(ms, &mut slept));
+
+            // 0 if target has no STOP support or it was aborted by pending activity
+            if slept > 0 {
+                compensate(slept);
+                trace!("Stopped for {} ms (budget {} ms)", slept, ms);
+                object_with_mut!(IDLE_OBJECT_NAME, Idle, idle, idle.stopped(slept));
+            }
+        }
+    }
+}
+
+pub(crate) fn init() {
+    object_insert!(IDLE_OBJECT_NAME, Idle::new());
+}
+
+// Runs once per scheduler round, every other task has had a chance to run in between
+pub(crate) async fn task() {
+    loop {
+        idle();
+        task_yield!();
+    }
+}
@@ -0,0 +1,362 @@
+use rtrs::{object_insert, object_with_mut, task_sleep, logger, error};
+
+use crate::peripherals::tone::{Tone, ToneInterface, TONE_OBJECT_NAME};
+use crate::services::idle::{self, IdleMode};
+
+logger!("melody");
+
+pub const MELODY_PLAYER_OBJECT_NAME: &str = "melody_player";
+
+const MAX_MELODY_SIZE: usize = 128;
+
+// Part of every note that is audible, the rest is silence to separate repeated notes
+const NOTE_ON_PERCENT: u32 = 90;
+
+pub static MELODIES: [(&str, &str); 3] = [
+    ("beep",  "beep:d=16,o=6,b=120:c,p,c"),
+    ("scale", "scale:d=8,o=5,b=120:c,d,e,f,g,a,b,c6"),
+    ("nokia", "nokia:d=4,o=5,b=225:8e6,8d6,f#,g#,8c#6,8b,d,e,8b,8a,c#,e,2a"),
+];
+
+// Octave 4 frequencies (C4..B4) in centihertz
+const OCTAVE4_CHZ: [u32; 12] = [26163, 27718, 29366, 31113, 32963, 34923, 36999, 39200, 41530, 44000, 46616, 49388];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MelodyError {
+    TooLong,
+    Header,
+    // Byte offset of the invalid note in the melody
+    Note(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Note {
+    // 0 is a pause
+    pub freq:     u32,
+    pub duration: u32,
+}
+
+pub fn note_freq(semitone: u32, octave: u32) -> u32 {
+    let octave = octave + semitone / 12;
+    let chz = OCTAVE4_CHZ[(semitone % 12) as usize];
+
+    let chz = if octave >= 4 {
+        chz << (octave - 4)
+    } else {
+        chz >> (4 - octave)
+    };
+
+    (chz + 50) / 100
+}
+
+// RTTTL (Ring Tone Text Transfer Language) parser, e.g. "name:d=4,o=5,b=120:8c,d#,2p,8e6."
+// Doesn't borrow the melody, so it can be stored next to it
+#[derive(Debug, Copy, Clone)]
+pub struct Rtttl {
+    duration: u32,
+    octave:   u32,
+    bpm:      u32,
+    pos:      usize,
+}
+
+impl Rtttl {
+    pub fn parse(melody: &str) -> Result<Self, MelodyError> {
+        let mut sections = melody.splitn(3, ':');
+
+        let (Some(name), Some(defaults), Some(_)) = (sections.next(), sections.next(), sections.next()) else {
+            return Err(MelodyError::Header);
+        };
+
+        let mut rtttl = Self {
+            duration: 4,
+            octave:   6,
+            bpm:      63,
+            pos:      name.len() + defaults.len() + 2,
+        };
+
+        for value in defaults.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            let (key, value) = value.split_once('=').ok_or(MelodyError::Header)?;
+            let value: u32 = value.trim().parse().map_err(|_| MelodyError::Header)?;
+
+            match key.trim() {
+                "d" => rtttl.duration = value,
+                "o" => rtttl.octave = value,
+                "b" => rtttl.bpm = value,
+                _   => return Err(MelodyError::Header),
+            }
+        }
+
+        if !Self::valid_duration(rtttl.duration) || rtttl.octave > 8 || rtttl.bpm == 0 {
+            return Err(MelodyError::Header);
+        }
+
+        Ok(rtttl)
+    }
+
+    fn valid_duration(duration: u32) -> bool {
+        matches!(duration, 1 | 2 | 4 | 8 | 16 | 32)
+    }
+
+    fn parse_note(&self, note: &str) -> Option<Note> {
+        let bytes = note.as_bytes();
+        let mut i = 0;
+
+        let mut duration: u32 = 0;
+        while let Some(digit) = bytes.get(i).filter(|b| b.is_ascii_digit()) {
+            duration = duration.checked_mul(10)?.checked_add((digit - b'0') as u32)?;
+            i += 1;
+        }
+
+        let duration = if duration == 0 { self.duration } else { duration };
+
+        if !Self::valid_duration(duration) {
+            return None;
+        }
+
+        let mut semitone = match bytes.get(i)?.to_ascii_lowercase() {
+            b'c' => Some(0),
+            b'd' => Some(2),
+            b'e' => Some(4),
+            b'f' => Some(5),
+            b'g' => Some(7),
+            b'a' => Some(9),
+            b'b' => Some(11),
+            b'p' => None,
+            _    => return None,
+        };
+        i += 1;
+
+        if bytes.get(i) == Some(&b'#') {
+            semitone = semitone.map(|s| s + 1);
+            i += 1;
+        }
+
+        let mut dotted = false;
+        if bytes.get(i) == Some(&b'.') {
+            dotted = true;
+            i += 1;
+        }
+
+        let mut octave = self.octave;
+        if let Some(digit) = bytes.get(i).filter(|b| b.is_ascii_digit()) {
+            octave = (digit - b'0') as u32;
+            i += 1;
+        }
+
+        if bytes.get(i) == Some(&b'.') {
+            dotted = true;
+            i += 1;
+        }
+
+        if i != bytes.len() || octave > 8 {
+            return None;
+        }
+
+        // Whole note is 4 beats
+        let mut duration = 60_000 * 4 / self.bpm.checked_mul(duration)?;
+
+        if dotted {
+            duration += duration / 2;
+        }
+
+        Some(Note { freq: semitone.map_or(0, |s| note_freq(s, octave)), duration })
+    }
+
+    pub fn next_note(&mut self, melody: &str) -> Option<Result<Note, MelodyError>> {
+        let rest = melody.get(self.pos..)?;
+
+        if rest.trim().is_empty() {
+            return None;
+        }
+
+        let len = rest.find(',').unwrap_or(rest.len());
+        let start = self.pos;
+
+        self.pos += (len + 1).min(rest.len());
+
+        Some(self.parse_note(rest[..len].trim()).ok_or(MelodyError::Note(start)))
+    }
+}
+
+enum State {
+    Idle,
+    Tone {
+        freq:     u32,
+        duration: u32,
+        started:  Option<u32>,
+    },
+    Melody {
+        rtttl:    Rtttl,
+        // Start of the next note, None starts on the next cycle
+        next:     Option<u32>,
+        // End of the audible part of the current note
+        silence:  u32,
+    },
+}
+
+fn reached(now: u32, deadline: u32) -> bool {
+    now.wrapping_sub(deadline) < u32::MAX / 2
+}
+
+pub struct MelodyPlayer {
+    melody: heapless::String<MAX_MELODY_SIZE>,
+    state:  State,
+    // Output must be stopped on the next cycle
+    stop:   bool,
+}
+
+impl MelodyPlayer {
+    pub fn new() -> Self {
+        Self { melody: heapless::String::new(), state: State::Idle, stop: false }
+    }
+
+    pub fn tone(&mut self, freq: u32, duration: u32) {
+        self.state = State::Tone { freq, duration, started: None };
+    }
+
+    pub fn play(&mut self, melody: &str) -> Result<(), MelodyError> {
+        let rtttl = Rtttl::parse(melody)?;
+
+        self.melody.clear();
+        self.melody.push_str(melody).map_err(|_| MelodyError::TooLong)?;
+
+        self.state = State::Melody { rtttl, next: None, silence: 0 };
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.state = State::Idle;
+        self.stop = true;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, State::Idle) && !self.stop
+    }
+
+    pub fn cycle(&mut self, now: u32, out: &mut dyn ToneInterface) {
+        if self.stop {
+            out.stop();
+            self.stop = false;
+        }
+
+        match &mut self.state {
+            State::Idle => {}
+            State::Tone { freq, started: started @ None, .. } => {
+                out.start(*freq);
+                *started = Some(now);
+            }
+            State::Tone { duration, started: Some(started), .. } => {
+                if now.wrapping_sub(*started) >= *duration {
+                    self.stop();
+                }
+            }
+            State::Melody { rtttl, next, silence } => {
+                let start = next.unwrap_or(now);
+
+                if !reached(now, start) {
+                    if reached(now, *silence) {
+                        out.stop();
+                    }
+                    return;
+                }
+
+                match rtttl.next_note(&self.melody) {
+                    Some(Ok(note)) => {
+                        if note.freq == 0 {
+                            out.stop();
+                        } else {
+                            out.start(note.freq);
+                        }
+
+                        *silence = start.wrapping_add(note.duration * NOTE_ON_PERCENT / 100);
+                        *next = Some(start.wrapping_add(note.duration));
+                    }
+                    Some(Err(err)) => {
+                        error!("Invalid melody: {:?}", err);
+                        self.stop();
+                    }
+                    None => {
+                        self.stop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl rtrs::object::Object for MelodyPlayer {}
+
+pub fn find(name: &str) -> Option<&'static str> {
+    MELODIES.iter().find(|(n, _)| *n == name).map(|(_, melody)| *melody)
+}
+
+pub(crate) fn init() {
+    object_insert!(MELODY_PLAYER_OBJECT_NAME, MelodyPlayer::new());
+}
+
+pub(crate) async fn task() {
+    loop {
+        let now = rtrs::time::global_tick();
+
+        object_with_mut!(MELODY_PLAYER_OBJECT_NAME, MelodyPlayer, player, {
+            object_with_mut!(TONE_OBJECT_NAME, Tone, tone, player.cycle(now, &mut *tone));
+
+            // PWM timer is stopped in STOP mode
+            idle::set_inhibit(MELODY_PLAYER_OBJECT_NAME, IdleMode::Sleep, !player.is_idle());
+        });
+
+        task_sleep!(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripherals::tone::ToneRecorder;
+
+    fn rtttl() -> Rtttl {
+        Rtttl::parse("test:d=4,o=5,b=120:c").unwrap()
+    }
+
+    #[test]
+    fn note_durations() {
+        let rtttl = rtttl();
+
+        // Quarter note at 120 bpm is 500 ms
+        assert_eq!(rtttl.parse_note("c"), Some(Note { freq: 523, duration: 500 }));
+        assert_eq!(rtttl.parse_note("8e6"), Some(Note { freq: 1319, duration: 250 }));
+        assert_eq!(rtttl.parse_note("2p."), Some(Note { freq: 0, duration: 1500 }));
+        assert_eq!(rtttl.parse_note("c#4."), Some(Note { freq: 277, duration: 750 }));
+        assert_eq!(rtttl.parse_note("3c"), None);
+        assert_eq!(rtttl.parse_note("c9"), None);
+    }
+
+    #[test]
+    fn overflow_rejects_note() {
+        assert_eq!(rtttl().parse_note("42949672961c"), None);
+
+        let rtttl = Rtttl::parse("test:d=4,o=5,b=4294967295:c").unwrap();
+        assert_eq!(rtttl.parse_note("c"), None);
+    }
+
+    #[test]
+    fn plays_melody() {
+        let mut player = MelodyPlayer::new();
+        let mut recorder = ToneRecorder::<8>::new();
+
+        player.play(find("beep").unwrap()).unwrap();
+
+        let mut now = 0;
+
+        while !player.is_idle() && now < 1000 {
+            recorder.set_time(now);
+            player.cycle(now, &mut recorder);
+            now += 1;
+        }
+
+        // Sixteenth notes are 125 ms, 90 % of each is audible
+        assert_eq!(recorder.events(), &[(0, 1047), (112, 0), (250, 1047), (362, 0)]);
+        assert_eq!(now, 377);
+    }
+}
@@ -0,0 +1,27 @@
+pub mod pattern_player;
+pub mod melody;
+pub mod button;
+pub mod heart_rate;
+pub mod hrv;
+pub mod battery;
+pub mod idle;
+
+use rtrs::task::Task;
+use rtrs::task::sched::Scheduler;
+
+// Inserts service objects and attaches their background tasks
+pub(crate) fn init(sched: &mut Scheduler) {
+    pattern_player::init();
+    melody::init();
+    button::init();
+    heart_rate::init();
+    battery::init();
+    idle::init();
+
+    sched.attach(Task::new(pattern_player::task()));
+    sched.attach(Task::new(melody::task()));
+    sched.attach(Task::new(button::task()));
+    sched.attach(Task::new(heart_rate::task()));
+    sched.attach(Task::new(battery::task()));
+    sched.attach(Task::new(idle::task()));
+}
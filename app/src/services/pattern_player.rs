@@ -0,0 +1,216 @@
+use rtrs::gpio::{Output, PatternExecutionContext};
+use rtrs::{object_insert, object_with_mut, task_yield};
+
+use crate::patterns::{self, NamedPattern, TickBase};
+use crate::services::idle::{self, IdleMode};
+use crate::peripherals::gpio::{self, GpioError, PinKind};
+
+pub const PATTERN_PLAYER_OBJECT_NAME: &str = "pattern_player";
+
+pub const DEFAULT_PRIORITY: u8 = 0;
+
+const MAX_CHANNELS: usize = 4;
+const QUEUE_SIZE: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlayerError {
+    Gpio(GpioError),
+    TooManyChannels,
+    QueueFull,
+}
+
+impl From<GpioError> for PlayerError {
+    fn from(err: GpioError) -> Self {
+        PlayerError::Gpio(err)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Entry {
+    pub pattern:  &'static NamedPattern,
+    pub priority: u8,
+    // Stop after this many ms, None plays until stopped or pre-empted
+    pub duration: Option<u32>,
+}
+
+struct Playing {
+    entry:   Entry,
+    ctx:     PatternExecutionContext<'static>,
+    started: u32,
+}
+
+impl Playing {
+    fn new(entry: Entry, now: u32) -> Self {
+        Self {
+            entry,
+            ctx: PatternExecutionContext::new(entry.pattern.pattern, patterns::tick_provider(entry.pattern.tick)),
+            started: now,
+        }
+    }
+
+    fn expired(&self, now: u32) -> bool {
+        self.entry.duration.is_some_and(|duration| now.wrapping_sub(self.started) >= duration)
+    }
+}
+
+struct Channel {
+    output: &'static str,
+    active: Option<Playing>,
+    // Sorted by priority (highest first), FIFO within the same priority
+    queue:  heapless::Vec<Entry, QUEUE_SIZE>,
+}
+
+impl Channel {
+    fn new(output: &'static str) -> Self {
+        Self { output, active: None, queue: heapless::Vec::new() }
+    }
+
+    fn enqueue(&mut self, entry: Entry, front_of_group: bool) -> Result<(), PlayerError> {
+        let pos = self.queue.iter()
+            .position(|e| if front_of_group { e.priority <= entry.priority } else { e.priority < entry.priority })
+            .unwrap_or(self.queue.len());
+
+        self.queue.insert(pos, entry).map_err(|_| PlayerError::QueueFull)
+    }
+
+    fn start(&mut self, entry: Entry, now: u32) -> Result<(), PlayerError> {
+        if let Some(active) = self.active.as_ref().map(|playing| playing.entry) {
+            // Higher priority timed pattern finishes first, an endless one would never let it play
+            if active.priority > entry.priority && active.duration.is_some() {
+                return self.enqueue(entry, true);
+            }
+
+            // Pre-empted endless pattern resumes (from the start) once the new one finishes, timed
+            // ones are dropped. Queued before anything changes, so a full queue leaves it playing
+            if active.duration.is_none() {
+                self.enqueue(active, true)?;
+            }
+        }
+
+        self.off();
+        self.active = Some(Playing::new(entry, now));
+
+        Ok(())
+    }
+
+    fn queue(&mut self, entry: Entry, now: u32) -> Result<(), PlayerError> {
+        if self.active.is_none() {
+            return self.start(entry, now);
+        }
+
+        self.enqueue(entry, false)
+    }
+
+    fn stop(&mut self) {
+        self.active = None;
+        self.queue.clear();
+        self.off();
+    }
+
+    fn off(&self) {
+        let _ = gpio::write(self.output, false);
+    }
+
+    fn cycle(&mut self, now: u32) {
+        if self.active.as_ref().is_some_and(|playing| playing.expired(now)) {
+            self.active = None;
+            self.off();
+
+            if !self.queue.is_empty() {
+                let entry = self.queue.remove(0);
+                self.active = Some(Playing::new(entry, now));
+            }
+        }
+
+        let output = self.output;
+
+        if let Some(playing) = &mut self.active {
+            object_with_mut!(output, Output, pin, playing.ctx.cycle(&mut pin));
+        }
+    }
+}
+
+pub struct PatternPlayer {
+    channels: heapless::Vec<Channel, MAX_CHANNELS>,
+}
+
+impl PatternPlayer {
+    pub fn new() -> Self {
+        Self { channels: heapless::Vec::new() }
+    }
+
+    fn channel(&mut self, output: &str) -> Result<&mut Channel, PlayerError> {
+        let info = gpio::find(output).ok_or(GpioError::NotFound)?;
+
+        if info.kind != PinKind::Output {
+            return Err(GpioError::WrongKind.into());
+        }
+
+        if let Some(idx) = self.channels.iter().position(|ch| ch.output == info.name) {
+            return Ok(&mut self.channels[idx]);
+        }
+
+        self.channels.push(Channel::new(info.name)).map_err(|_| PlayerError::TooManyChannels)?;
+
+        Ok(self.channels.last_mut().unwrap())
+    }
+
+    // Plays right away, unless a higher priority timed pattern is active - then it's queued
+    pub fn start(&mut self, output: &str, entry: Entry) -> Result<(), PlayerError> {
+        let now = rtrs::time::global_tick();
+        self.channel(output)?.start(entry, now)
+    }
+
+    // Plays after everything of the same or higher priority has finished
+    pub fn queue(&mut self, output: &str, entry: Entry) -> Result<(), PlayerError> {
+        let now = rtrs::time::global_tick();
+        self.channel(output)?.queue(entry, now)
+    }
+
+    pub fn stop(&mut self, output: &str) -> Result<(), PlayerError> {
+        self.channel(output)?.stop();
+        Ok(())
+    }
+
+    pub fn stop_all(&mut self) {
+        self.channels.iter_mut().for_each(|ch| ch.stop());
+    }
+
+    pub fn for_each(&self, mut f: impl FnMut(&'static str, Option<&Entry>, &[Entry])) {
+        for ch in self.channels.iter() {
+            f(ch.output, ch.active.as_ref().map(|playing| &playing.entry), &ch.queue);
+        }
+    }
+
+    pub fn cycle(&mut self) {
+        let now = rtrs::time::global_tick();
+
+        for ch in self.channels.iter_mut() {
+            ch.cycle(now);
+        }
+
+        // Microsecond patterns can't tolerate WFI latency, millisecond ones can't survive STOP
+        let deepest = self.channels.iter()
+            .filter_map(|ch| ch.active.as_ref())
+            .map(|playing| if playing.entry.pattern.tick == TickBase::Micros { IdleMode::Off } else { IdleMode::Sleep })
+            .min();
+
+        match deepest {
+            Some(deepest) => idle::inhibit(PATTERN_PLAYER_OBJECT_NAME, deepest),
+            None => idle::release(PATTERN_PLAYER_OBJECT_NAME),
+        }
+    }
+}
+
+impl rtrs::object::Object for PatternPlayer {}
+
+pub(crate) fn init() {
+    object_insert!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer::new());
+}
+
+pub(crate) async fn task() {
+    loop {
+        object_with_mut!(PATTERN_PLAYER_OBJECT_NAME, PatternPlayer, player, player.cycle());
+        task_yield!();
+    }
+}
@@ -0,0 +1,70 @@
+use rtrs::object_with;
+
+use crate::services::heart_rate::{HeartRate, HEART_RATE_OBJECT_NAME};
+use crate::services::hrv::HrvMetrics;
+use crate::services::battery::{Battery, BATTERY_OBJECT_NAME};
+
+pub const TELEMETRY_VERSION: u8 = 1;
+
+const FLAG_BPM: u8 = 1 << 0;
+const FLAG_HRV: u8 = 1 << 1;
+const FLAG_VDD: u8 = 1 << 2;
+
+// Header (version, flags) + uptime + bpm + hrv + vdd
+pub const MAX_ENCODED_SIZE: usize = 2 + 4 + 2 + HrvMetrics::ENCODED_SIZE + 2;
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Telemetry {
+    pub uptime: u32,
+    pub bpm:    Option<u16>,
+    pub hrv:    Option<HrvMetrics>,
+    pub vdd_mv: Option<u16>,
+}
+
+impl Telemetry {
+    pub fn collect() -> Self {
+        let (bpm, hrv) = object_with!(HEART_RATE_OBJECT_NAME, HeartRate, hr, {
+            (hr.detector().bpm(), hr.history().metrics())
+        });
+
+        // Last periodic measurement, so ADC isn't touched here
+        let vdd_mv = object_with!(BATTERY_OBJECT_NAME, Battery, battery, battery.vdd_mv());
+
+        Self {
+            uptime: rtrs::time::global_tick(),
+            bpm,
+            hrv,
+            vdd_mv: vdd_mv.map(|vdd| vdd as u16),
+        }
+    }
+
+    // Optional fields are only present if their flag is set
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut flags = 0;
+        let mut size = 6;
+
+        buf.get_mut(2..6)?.copy_from_slice(&self.uptime.to_le_bytes());
+
+        if let Some(bpm) = self.bpm {
+            flags |= FLAG_BPM;
+            buf.get_mut(size..size + 2)?.copy_from_slice(&bpm.to_le_bytes());
+            size += 2;
+        }
+
+        if let Some(hrv) = self.hrv {
+            flags |= FLAG_HRV;
+            size += hrv.encode(buf.get_mut(size..)?)?;
+        }
+
+        if let Some(vdd) = self.vdd_mv {
+            flags |= FLAG_VDD;
+            buf.get_mut(size..size + 2)?.copy_from_slice(&vdd.to_le_bytes());
+            size += 2;
+        }
+
+        buf[0] = TELEMETRY_VERSION;
+        buf[1] = flags;
+
+        Some(size)
+    }
+}
@@ -2,10 +2,9 @@ use rtrs::object::Object;
 use rtrs::time::TimeProvider;
 use rtrs::log::console::CONSOLE_OBJECT_NAME;
 use rtrs::task::{Event, ExecutionContext, Task};
-use rtrs::gpio::{Command, Action, Pattern, PatternExecutionContext, Input, Output};
+use rtrs::gpio::Input;
 
 use rtrs::{
-    gpio_pattern,
     object_insert,
     object_remove,
     object_with,
@@ -37,8 +36,6 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 
 use alloc::boxed::Box;
 
-use crate::board::{BoardInterface, Callback};
-
 logger!("test");
 
 async fn task1() {
@@ -263,6 +260,7 @@ pub(crate) fn test_box() {
     println!("Box<TimeProvider>::now {}", (*b4).now());
 }
 
+#[cfg(not(test))]
 pub(crate) fn test_heap() {
     unsafe {
         println!("GLOBAL_HEAP: {:?} buf={:?}", &crate::GLOBAL_HEAP as *const _, crate::GLOBAL_HEAP.buffer().as_ptr());
@@ -435,22 +433,12 @@ pub(crate) fn test_task_sched_cancel() {
 
 // pub(crate) fn test_task_sched_idle() {}
 
-static BEEP: Pattern = gpio_pattern!(
-    Action::Command(Command::On(20)),
-    Action::Command(Command::Off(20)),
-    Action::Repeat(Command::Goto(0), 500),
-    Action::Command(Command::On(250)),
-    Action::Command(Command::Off(250)),
-    Action::Repeat(Command::Goto(3), 500),
-    Action::Command(Command::Goto(0)),
-);
-
 pub(crate) fn test_button() {
-    let mut last_state = object_with!("btn", Input, btn, btn.is_high().unwrap_or(true));
+    use crate::peripherals::tone::{Tone, ToneInterface, TONE_OBJECT_NAME};
 
-    let mut tick: Box<dyn rtrs::time::TickProvider<Tick = u32>> = Box::new(rtrs::time::GlobalTickProvider {});
-    BoardInterface::callback(Callback::MicrosecondTickProvider(&mut tick));
-    let mut ctx = PatternExecutionContext::new(&BEEP, tick);
+    const BEEP_HZ: u32 = 2000;
+
+    let mut last_state = object_with!("btn", Input, btn, btn.is_high().unwrap_or(true));
 
     loop {
         if let Some(_) = object_with_mut!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty, tty, tty.read()) {
@@ -462,13 +450,18 @@ pub(crate) fn test_button() {
         if state != last_state {
             trace!("state {} -> {}", last_state as u8, state as u8);
             last_state = state;
-        }
 
-        if !state {
-            object_with_mut!("buzzer", Output, pin, ctx.cycle(&mut pin));
+            object_with_mut!(TONE_OBJECT_NAME, Tone, tone, {
+                if state {
+                    tone.start(BEEP_HZ);
+                } else {
+                    tone.stop();
+                }
+            });
         }
-
     }
+
+    object_with_mut!(TONE_OBJECT_NAME, Tone, tone, tone.stop());
 }
 
 pub(crate) fn test_pulse_sensor() {
@@ -477,11 +470,409 @@ pub(crate) fn test_pulse_sensor() {
             break;
         }
         
-        let reading = object_with_mut!("pulse_sensor", crate::peripherals::pulse_sensor::PulseSensor, sensor, sensor.read());
-        
-        println!("{}", reading);
+        match crate::services::heart_rate::read_sensor() {
+            Some(Ok(reading)) => println!("{}", reading),
+            Some(Err(err)) => println!("Error: {:?}", err),
+            None => {
+                println!("No pulse sensor");
+                break;
+            }
+        }
         
         rtrs::time::delay_ms(200);
     }
 }
 
+pub(crate) fn test_melody() {
+    use crate::peripherals::tone::ToneRecorder;
+    use crate::services::melody::{MelodyPlayer, MELODIES};
+
+    for (name, melody) in MELODIES.iter() {
+        let mut player = MelodyPlayer::new();
+        let mut recorder = ToneRecorder::<64>::new();
+
+        if let Err(err) = player.play(melody) {
+            error!("{}: {:?}", name, err);
+            continue;
+        }
+
+        // Simulated time, 1ms per cycle, same as the player task
+        let mut now = 0;
+
+        while !player.is_idle() && now < 60_000 {
+            recorder.set_time(now);
+            player.cycle(now, &mut recorder);
+            now += 1;
+        }
+
+        print!("{}: {}ms", name, now);
+
+        for (time, freq) in recorder.events() {
+            print!(" {}:{}", time, freq);
+        }
+
+        println!();
+
+        if !player.is_idle() {
+            error!("{}: didn't finish", name);
+        } else if recorder.events().first().map_or(true, |(time, freq)| *time != 0 || *freq == 0) {
+            error!("{}: doesn't start with a tone", name);
+        } else if recorder.events().last().map_or(true, |(_, freq)| *freq != 0) {
+            error!("{}: output left on", name);
+        }
+    }
+}
+
+async fn exti_waiter(pin: &'static str) {
+    use crate::peripherals::exti::{self, Edge};
+
+    for edge in [Edge::Rising, Edge::Falling, Edge::Any] {
+        info!("Waiting for {:?} edge on '{}' (5s)", edge, pin);
+
+        let start = rtrs::time::global_tick();
+
+        match exti::wait_for_edge(pin, edge, Some(5000)).await {
+            Ok(level) => info!("Edge after {}ms, level {}", rtrs::time::global_tick() - start, level as u8),
+            Err(err)  => warn!("{:?}", err),
+        }
+    }
+}
+
+pub(crate) fn test_exti() {
+    let mut sched = rtrs::task::sched::Scheduler::new();
+
+    sched.attach(Task::new(exti_waiter("btn")));
+
+    sched.run_to_completion();
+}
+
+pub(crate) fn test_hrv() {
+    use crate::services::hrv::{IbiHistory, HrvMetrics, HISTORY_SIZE};
+
+    // Recorded at rest, with a few artifacts injected
+    const RECORDING: &str = include_str!("../data/ibi_rest.txt");
+
+    let mut history = IbiHistory::new();
+    history.set_window(HISTORY_SIZE);
+
+    for (i, line) in RECORDING.lines().filter(|line| !line.starts_with('#')).enumerate() {
+        let Ok(ibi) = line.trim().parse::<u16>() else {
+            error!("Invalid interval at {}: '{}'", i, line);
+            return;
+        };
+
+        if let Some(artifact) = history.push(ibi) {
+            info!("#{} {} ms rejected: {:?}", i, ibi, artifact);
+        }
+    }
+
+    let Some(hrv) = history.metrics() else {
+        error!("Not enough intervals");
+        return;
+    };
+
+    println!("{:?}", hrv);
+
+    // Same values the host tests in hrv.rs expect
+    let expected = HrvMetrics { count: 64, mean_ibi: 847, sdnn: 27, rmssd: 34, pnn50: 126, missed: 2, extra: 2 };
+
+    if hrv != expected {
+        error!("Expected {:?}", expected);
+    }
+
+    let mut buf = [0; HrvMetrics::ENCODED_SIZE];
+
+    match hrv.encode(&mut buf).and_then(|_| HrvMetrics::decode(&buf)) {
+        Some(decoded) if decoded == hrv => info!("Encode/decode OK"),
+        _ => error!("Encode/decode mismatch"),
+    }
+}
+
+pub(crate) fn test_time() {
+    use crate::time::micros;
+
+    // Sparse reads, more than one 16-bit timer overflow in between
+    for gap_ms in [10, 100, 250] {
+        let (tick, us) = (rtrs::time::global_tick(), micros());
+
+        rtrs::time::delay_ms(gap_ms);
+
+        let ticks = rtrs::time::global_tick().wrapping_sub(tick) as u64;
+        let elapsed = micros() - us;
+
+        // SysTick has 1 ms resolution, reads aren't taken at the exact same moment
+        if elapsed + 1000 < ticks * 1000 || elapsed > (ticks + 1) * 1000 {
+            error!("{} ms gap: {} us elapsed, {} ticks", gap_ms, elapsed, ticks);
+        } else {
+            info!("{} ms gap: {} us elapsed, {} ticks", gap_ms, elapsed, ticks);
+        }
+    }
+
+    // Back-to-back reads for a while, must never go backwards
+    let start = rtrs::time::global_tick();
+    let mut last = micros();
+    let mut reads: u32 = 0;
+
+    while rtrs::time::global_tick().wrapping_sub(start) < 200 {
+        let now = micros();
+
+        if now < last {
+            error!("Went backwards after {} reads: {} -> {}", reads, last, now);
+            return;
+        }
+
+        last = now;
+        reads += 1;
+    }
+
+    info!("{} reads, monotonic", reads);
+}
+
+pub(crate) fn test_link() {
+    use core::cell::RefCell;
+    use crate::net::crc::crc16;
+    use crate::net::link::{Link, DeliveryStatus, BROADCAST, DEFAULT_RETRIES};
+    use crate::net::sim::{SimMedium, SimPort};
+
+    const STEP_MS: u32 = 10;
+    const MAX_STEPS: u32 = 1000;
+
+    // Polls both ends until A has a delivery result
+    fn run(a: &mut Link, b: &mut Link, medium: &RefCell<SimMedium>, now: &mut u32) -> Option<DeliveryStatus> {
+        let (mut pa, mut pb) = (SimPort::new(medium, 0), SimPort::new(medium, 1));
+
+        for _ in 0..MAX_STEPS {
+            *now += STEP_MS;
+
+            a.poll(&mut pa, *now);
+            b.poll(&mut pb, *now);
+
+            if let Some(delivery) = a.delivery() {
+                return Some(delivery.status);
+            }
+        }
+
+        None
+    }
+
+    // CRC-16/CCITT-FALSE check value
+    match crc16(b"123456789") {
+        0x29B1 => info!("CRC OK"),
+        crc => {
+            error!("CRC mismatch: {:04x}", crc);
+            return;
+        }
+    }
+
+    let medium = RefCell::new(SimMedium::new());
+    let (mut a, mut b) = (Link::new(1), Link::new(2));
+    let mut now = 0;
+
+    // Every 5th frame lost and every 7th corrupted, hits both data frames and ACKs
+    medium.borrow_mut().set_faults(5, 7);
+
+    const PACKETS: u8 = 10;
+
+    // More packets than B's RX queue holds, each has to come out exactly once and in order despite retransmissions
+    for i in 0..PACKETS {
+        if let Err(err) = a.send_to(2, &[i, i, i]) {
+            error!("send_to failed: {:?}", err);
+            return;
+        }
+
+        match run(&mut a, &mut b, &medium, &mut now) {
+            Some(DeliveryStatus::Acked { .. }) => {}
+            status => {
+                error!("Packet {} not delivered: {:?}", i, status);
+                return;
+            }
+        }
+
+        match b.recv_from() {
+            Some(packet) if packet.src == 1 && packet.payload() == [i, i, i] => {}
+            Some(packet) => {
+                error!("Unexpected packet from {}: {:?}", packet.src, packet.payload());
+                return;
+            }
+            None => {
+                error!("Packet {} missing", i);
+                return;
+            }
+        }
+    }
+
+    // Retransmissions still queued on the medium are recognized as duplicates
+    for _ in 0..10 {
+        now += STEP_MS;
+        a.poll(&mut SimPort::new(&medium, 0), now);
+        b.poll(&mut SimPort::new(&medium, 1), now);
+    }
+
+    if b.recv_from().is_some() {
+        error!("Duplicate delivered");
+        return;
+    }
+
+    info!("lossy: {} frames, A {:?}", medium.borrow().frames(), a.stats());
+    info!("lossy: B {:?}", b.stats());
+
+    // Broadcast isn't acknowledged
+    medium.borrow_mut().set_faults(0, 0);
+    let _ = a.send_to(BROADCAST, b"hello");
+
+    match (run(&mut a, &mut b, &medium, &mut now), b.recv_from()) {
+        (Some(DeliveryStatus::Sent), Some(packet)) if packet.dst == BROADCAST => info!("broadcast OK"),
+        (status, _) => {
+            error!("Broadcast failed: {:?}", status);
+            return;
+        }
+    }
+
+    // Nobody answers, gives up after all retries
+    medium.borrow_mut().set_down(true);
+    let frames = medium.borrow().frames();
+    let _ = a.send_to(2, b"lost");
+
+    match run(&mut a, &mut b, &medium, &mut now) {
+        Some(DeliveryStatus::Failed) if medium.borrow().frames() - frames == DEFAULT_RETRIES as u32 + 1 => {
+            info!("link down OK");
+        }
+        status => error!("Expected failure after {} attempts: {:?}", DEFAULT_RETRIES + 1, status),
+    }
+
+    // Service frames are sent once, without a delivery, and only `recv_service` sees them
+    medium.borrow_mut().set_down(false);
+    let frames = medium.borrow().frames();
+    let _ = a.send_datagram(2, &[crate::net::PROTO_PING_REQUEST, 1]);
+
+    for _ in 0..10 {
+        now += STEP_MS;
+        a.poll(&mut SimPort::new(&medium, 0), now);
+        b.poll(&mut SimPort::new(&medium, 1), now);
+    }
+
+    let sent_once = medium.borrow().frames() - frames == 1 && a.delivery().is_none();
+
+    match (sent_once, b.recv_from(), b.recv_service(crate::net::PROTO_PING_REQUEST)) {
+        (true, None, Some(_)) => info!("datagram OK"),
+        (sent_once, _, service) => error!("Datagram failed: sent once {}, service frame {}", sent_once, service.is_some()),
+    }
+}
+
+pub(crate) fn test_airtime() {
+    use crate::net::airtime::{self, DutyCycle};
+    use crate::net::config::RadioConfig;
+
+    // Semtech LoRa calculator: 10 bytes, preamble 8, CR 4/5, CRC on, 125 kHz
+    let mut config = RadioConfig::new();
+    config.preamble = 8;
+
+    for (sf, expected) in [(7, 41_216), (12, 991_232)] {
+        config.spreading_factor = sf;
+
+        match airtime::time_on_air_us(&config, 10) {
+            toa if toa == expected => info!("SF{}: {} us", sf, toa),
+            toa => {
+                error!("SF{}: {} us, expected {} us", sf, toa, expected);
+                return;
+            }
+        }
+    }
+
+    // 1 % of an hour is 36 s, then 1 ms of airtime takes 100 ms to earn back
+    let mut duty = DutyCycle::new(10);
+    duty.consume(0, 36_000_000);
+
+    match (duty.wait_ms(0, 1000), duty.wait_ms(100, 1000), duty.wait_ms(100, 36_000_001)) {
+        (Some(100), Some(0), None) => info!("duty cycle OK"),
+        waits => error!("Unexpected duty cycle waits: {:?}", waits),
+    }
+}
+
+pub(crate) fn test_ota() {
+    use crate::net::cobs;
+    use crate::net::ota::{ImageHeader, HEADER_SIZE};
+    use crate::net::sha256::{self, Sha256};
+
+    // FIPS 180-2 appendix B.1 and B.2, the second one needs a padding block of its own
+    let vectors: [(&[u8], [u8; 32]); 3] = [
+        (b"abc", [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23,
+            0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+        ]),
+        (b"", [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
+            0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55,
+        ]),
+        (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", [
+            0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e, 0x60, 0x39,
+            0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4, 0x19, 0xdb, 0x06, 0xc1,
+        ]),
+    ];
+
+    for (message, expected) in &vectors {
+        let digest = sha256::digest(message);
+
+        if digest != *expected {
+            error!("SHA-256 of {} bytes mismatch: {:02x?}", message.len(), digest);
+            return;
+        }
+    }
+
+    // Image is hashed block by block, split differently than it's padded
+    let image: [u8; 200] = core::array::from_fn(|i| i as u8);
+    let mut sha = Sha256::new();
+
+    for chunk in image.chunks(37) {
+        sha.update(chunk);
+    }
+
+    let digest = sha.finish();
+
+    if digest[..4] != [0x19, 0x01, 0xda, 0x1c] || digest != sha256::digest(&image) {
+        error!("Streamed SHA-256 mismatch: {:02x?}", digest);
+        return;
+    }
+
+    info!("SHA-256 OK");
+
+    let header = ImageHeader { version: 7, size: image.len() as u32, digest };
+    let mut bytes = header.to_bytes();
+
+    if ImageHeader::parse(&bytes) != Some(header) {
+        error!("Header round trip failed");
+        return;
+    }
+
+    bytes[8] ^= 1;
+
+    if ImageHeader::parse(&bytes).is_some() || ImageHeader::parse(&bytes[..HEADER_SIZE - 1]).is_some() {
+        error!("Corrupted header accepted");
+        return;
+    }
+
+    info!("header OK");
+
+    // Console frames: zeros in the data and a run longer than one COBS group
+    let mut frame = [0x5a; 280];
+    frame[0] = 0;
+    frame[20] = 0;
+    frame[279] = 0;
+
+    let mut encoded = [0; cobs::max_encoded_size(280)];
+    let mut decoded = [0; 280];
+
+    let decoded_size = cobs::encode(&frame, &mut encoded).and_then(|size| {
+        match encoded[..size].contains(&0) {
+            true => None,
+            false => cobs::decode(&encoded[..size], &mut decoded),
+        }
+    });
+
+    if decoded_size != Some(frame.len()) || decoded != frame {
+        error!("COBS round trip failed: {:?}", decoded_size);
+        return;
+    }
+
+    info!("ota OK");
+}
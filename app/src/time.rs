@@ -0,0 +1,25 @@
+use rtrs::time::TickProvider;
+
+use crate::board::{BoardInterface, Callback};
+
+// Monotonic microseconds since boot. Falls back to global tick resolution if the target
+// has no high-resolution timer
+pub fn micros() -> u64 {
+    let mut us = rtrs::time::global_tick() as u64 * 1000;
+
+    BoardInterface::callback(Callback::Micros(&mut us));
+
+    us
+}
+
+// Truncated to 32 bits (wraps every ~71 minutes), users only look at differences
+#[derive(Copy, Clone)]
+pub struct MicrosTickProvider {}
+
+impl TickProvider for MicrosTickProvider {
+    type Tick = u32;
+
+    fn get_tick(&mut self) -> Self::Tick {
+        micros() as u32
+    }
+}
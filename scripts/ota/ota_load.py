@@ -0,0 +1,221 @@
+#!/usr/bin/env python3
+"""Loads a firmware image into the staging area of a node over its console.
+
+Runs `ota load` on the node, then sends the image header and the image in chunks. Requests
+and answers are zero-delimited COBS frames followed by CRC-16/CCITT-FALSE, the same requests
+nodes send each other over the radio (see app/src/net/ota.rs). Every chunk is answered with
+the offset the node wants next, so running the script again resumes an interrupted upload.
+
+Once the node verified the image it can pass it on over the radio (--send) or install it
+itself (--activate).
+
+Usage:
+    ota_load.py /dev/ttyACM0 firmware.bin --version 3
+    ota_load.py /dev/ttyACM0 firmware.bin --version 3 --send 2 --activate
+"""
+
+import argparse
+import hashlib
+import struct
+import sys
+import time
+
+BAUDRATE = 115200
+
+HEADER_MAGIC = 0x5746_5452
+
+PROTO_OTA_REQUEST = 0xF6
+PROTO_OTA_STATUS = 0xF7
+
+MSG_BEGIN = 1
+MSG_DATA = 2
+MSG_FINISH = 3
+
+FINISH_ACTIVATE = 1
+
+# Same as over the radio: link payload minus request header, whole words
+CHUNK_SIZE = 44
+
+STATUSES = [
+    "Receiving", "Verified", "Activating", "HashMismatch", "NoSession",
+    "Busy", "Invalid", "Denied", "Failed", "Unsupported",
+]
+
+TIMEOUT = 2.0
+# Node hashes the whole image before it answers FINISH
+FINISH_TIMEOUT = 30.0
+MAX_ATTEMPTS = 5
+
+# Last line `ota send` logs, whichever way it ends
+SEND_RESULTS = (
+    "Image verified on", "resets to install", "got corrupted", "another image", "can't take",
+    "Access denied", "no staging area", "No answer", "keeps failing", "No verified image",
+    "No key for", "not initialized", "already being sent",
+)
+
+
+def crc16(data):
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021) if crc & 0x8000 else crc << 1
+            crc &= 0xFFFF
+    return crc
+
+
+def cobs_encode(data):
+    out = bytearray([0])
+    code_index = 0
+    for byte in data:
+        if byte:
+            out.append(byte)
+        if not byte or len(out) - code_index == 0xFF:
+            out[code_index] = len(out) - code_index
+            code_index = len(out)
+            out.append(0)
+    out[code_index] = len(out) - code_index
+    return bytes(out)
+
+
+def cobs_decode(data):
+    out = bytearray()
+    i = 0
+    while i < len(data):
+        code = data[i]
+        if code == 0 or i + code > len(data):
+            return None
+        out += data[i + 1:i + code]
+        i += code
+        if code < 0xFF and i < len(data):
+            out.append(0)
+    return bytes(out)
+
+
+def image_header(image, version):
+    header = struct.pack("<III", HEADER_MAGIC, version, len(image)) + hashlib.sha256(image).digest()
+    return header + struct.pack("<H", crc16(header))
+
+
+class Node:
+    def __init__(self, port, baudrate):
+        import serial
+        self.serial = serial.Serial(port, baudrate, timeout=0.1)
+        self.pending = bytearray()
+
+    def command(self, line):
+        self.serial.write(line.encode() + b"\r\n")
+
+    def request(self, payload):
+        body = bytes([PROTO_OTA_REQUEST]) + payload
+        frame = cobs_encode(body + struct.pack("<H", crc16(body)))
+        # Leading delimiter ends whatever the node's parser had so far
+        self.serial.write(b"\0" + frame + b"\0")
+
+    def answer(self, timeout):
+        deadline = time.monotonic() + timeout
+
+        while time.monotonic() < deadline:
+            self.pending += self.serial.read(self.serial.in_waiting or 1)
+
+            while b"\0" in self.pending:
+                chunk, _, rest = self.pending.partition(b"\0")
+                self.pending = bytearray(rest)
+
+                # Log lines and the prompt don't decode or fail the CRC
+                frame = cobs_decode(bytes(chunk)) if chunk else None
+                if frame is None or len(frame) != 8 or frame[0] != PROTO_OTA_STATUS:
+                    continue
+                if crc16(frame[:6]) != struct.unpack("<H", frame[6:])[0] or frame[1] >= len(STATUSES):
+                    continue
+
+                return STATUSES[frame[1]], struct.unpack("<I", frame[2:6])[0]
+
+        return None
+
+    def exchange(self, payload, timeout=TIMEOUT):
+        for _ in range(MAX_ATTEMPTS):
+            self.request(payload)
+            answer = self.answer(timeout)
+            if answer is not None:
+                return answer
+
+        sys.exit("No answer from the node")
+
+    def log(self, seconds, until=()):
+        deadline = time.monotonic() + seconds
+
+        while time.monotonic() < deadline:
+            line = self.serial.readline().decode(errors="replace")
+            if line:
+                print(line, end="")
+            if any(text in line for text in until):
+                return
+
+
+def load(node, image, header, activate):
+    status, offset = node.exchange(bytes([MSG_BEGIN]) + header)
+    if offset:
+        print(f"Resuming at {offset}/{len(image)}")
+
+    reported = -1
+
+    while status in ("Receiving", "Failed") and offset < len(image):
+        chunk = image[offset:offset + CHUNK_SIZE]
+        status, offset = node.exchange(bytes([MSG_DATA]) + struct.pack("<I", offset) + chunk)
+
+        percent = offset * 100 // len(image)
+        if percent // 10 != reported:
+            reported = percent // 10
+            print(f"{percent}%")
+
+    # Also when the node had the image already, FINISH gives the console back
+    if status in ("Receiving", "Failed", "Verified"):
+        flags = FINISH_ACTIVATE if activate else 0
+        status, _ = node.exchange(bytes([MSG_FINISH, flags]), FINISH_TIMEOUT)
+
+    return status
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("port", help="serial port of the node")
+    parser.add_argument("image", help="raw firmware binary")
+    parser.add_argument("--version", type=int, required=True, help="image version for the header")
+    parser.add_argument("--send", type=int, metavar="NODE", help="send the image on to NODE over the radio")
+    parser.add_argument("--activate", action="store_true", help="install the image, on NODE with --send")
+    parser.add_argument("--baudrate", type=int, default=BAUDRATE)
+    args = parser.parse_args()
+
+    with open(args.image, "rb") as f:
+        image = f.read()
+
+    if not image:
+        sys.exit("Image is empty")
+
+    node = Node(args.port, args.baudrate)
+
+    node.command("ota load")
+    node.log(1.0, until=("Waiting for the image", "already running", "No staging area"))
+
+    start = time.monotonic()
+    # Installed right away unless it goes on to another node
+    status = load(node, image, image_header(image, args.version), args.activate and args.send is None)
+
+    if status not in ("Verified", "Activating"):
+        sys.exit(f"Upload failed: {status}")
+
+    print(f"Image v{args.version} ({len(image)} bytes) verified after {time.monotonic() - start:.0f} s")
+
+    if status == "Activating":
+        print("Node resets to install it")
+        node.log(2.0)
+
+    if args.send is not None:
+        node.command(f"ota send {args.send}" + (" activate" if args.activate else ""))
+        # Waits for the sender's final log line, the transfer can take a while
+        node.log(3600, until=SEND_RESULTS)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,127 @@
+#!/usr/bin/env python3
+"""Converts `radio sniff pcap` console output into a pcap file for Wireshark.
+
+The device sends zero-delimited COBS frames, each holding a pcap record (LoRaTap header
+plus the LoRa frame) followed by CRC-16/CCITT-FALSE of the record. Anything else on the
+console (log lines, the prompt) fails the CRC check and is skipped.
+
+Usage:
+    sniff2pcap.py /dev/ttyACM0 capture.pcap      # serial port, needs pyserial
+    sniff2pcap.py console.bin capture.pcap       # raw capture of the console
+    sniff2pcap.py - - < console.bin | wireshark -k -i -
+"""
+
+import argparse
+import struct
+import sys
+
+LINKTYPE_LORATAP = 270
+SNAPLEN = 65535
+BAUDRATE = 115200
+
+PCAP_RECORD_HEADER_SIZE = 16
+CRC_SIZE = 2
+
+
+def crc16(data):
+    crc = 0xFFFF
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x1021) if crc & 0x8000 else crc << 1
+            crc &= 0xFFFF
+    return crc
+
+
+def cobs_decode(data):
+    out = bytearray()
+    i = 0
+    while i < len(data):
+        code = data[i]
+        if code == 0 or i + code > len(data):
+            return None
+        out += data[i + 1:i + code]
+        i += code
+        if code < 0xFF and i < len(data):
+            out.append(0)
+    return bytes(out)
+
+
+def parse_record(chunk):
+    record = cobs_decode(chunk)
+    if record is None or len(record) < PCAP_RECORD_HEADER_SIZE + CRC_SIZE:
+        return None
+
+    body, crc = record[:-CRC_SIZE], struct.unpack("<H", record[-CRC_SIZE:])[0]
+    if crc16(body) != crc:
+        return None
+
+    incl_len = struct.unpack_from("<I", body, 8)[0]
+    if incl_len != len(body) - PCAP_RECORD_HEADER_SIZE:
+        return None
+
+    return body
+
+
+def open_input(path):
+    if path == "-":
+        return sys.stdin.buffer
+
+    if path.startswith("/dev/") or path.upper().startswith("COM"):
+        import serial
+        return serial.Serial(path, BAUDRATE)
+
+    return open(path, "rb")
+
+
+def open_output(path):
+    return sys.stdout.buffer if path == "-" else open(path, "wb")
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("input", help="serial port, raw console capture or - for stdin")
+    parser.add_argument("output", help="pcap file or - for stdout")
+    args = parser.parse_args()
+
+    src = open_input(args.input)
+    dst = open_output(args.output)
+
+    # Global header, microsecond timestamps, little endian
+    dst.write(struct.pack("<IHHiIII", 0xA1B2C3D4, 2, 4, 0, 0, SNAPLEN, LINKTYPE_LORATAP))
+    dst.flush()
+
+    pending = bytearray()
+    records = skipped = 0
+
+    try:
+        while True:
+            data = src.read(src.in_waiting or 1) if hasattr(src, "in_waiting") else src.read(4096)
+            if not data:
+                break
+
+            pending += data
+
+            while b"\0" in pending:
+                chunk, _, rest = pending.partition(b"\0")
+                pending = bytearray(rest)
+
+                if not chunk:
+                    continue
+
+                record = parse_record(bytes(chunk))
+                if record is None:
+                    skipped += 1
+                    continue
+
+                dst.write(record)
+                dst.flush()
+                records += 1
+    except KeyboardInterrupt:
+        pass
+
+    print(f"{records} packets, {skipped} chunks skipped", file=sys.stderr)
+
+
+if __name__ == "__main__":
+    main()
@@ -1,6 +1,7 @@
+/* Bank 2 (upper 96K) is the staging area for firmware updates, see src/flash.rs */
 MEMORY
 {
-    FLASH : ORIGIN = 0x08000000, LENGTH = 192K
+    FLASH : ORIGIN = 0x08000000, LENGTH = 96K
     RAM :   ORIGIN = 0x20000000, LENGTH = 20K
 }
 
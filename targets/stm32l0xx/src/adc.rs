@@ -0,0 +1,233 @@
+use crate::hal::pac::{self, Interrupt};
+use cortex_m::peripheral::NVIC;
+
+use rtrs::sync::RwLock;
+
+use app::peripherals::adc::{AdcCalibration, AdcError, AdcInterface};
+use app::peripherals::pulse_sensor::PulseSensorInterface;
+
+pub const VREFINT_CHANNEL: u8 = 17;
+pub const TEMPERATURE_CHANNEL: u8 = 18;
+
+// Factory calibration values (RM0377 / datasheet)
+const VREFINT_CAL_ADDR: u32 = 0x1FF8_0078;
+const TS_CAL1_ADDR: u32 = 0x1FF8_007A;
+const TS_CAL2_ADDR: u32 = 0x1FF8_007E;
+
+const CR_ADEN: u32 = 1 << 0;
+const CR_ADDIS: u32 = 1 << 1;
+const CR_ADSTART: u32 = 1 << 2;
+const CR_ADVREGEN: u32 = 1 << 28;
+const CR_ADCAL: u32 = 1 << 31;
+
+const ISR_ADRDY: u32 = 1 << 0;
+const ISR_EOC: u32 = 1 << 2;
+const ISR_EOCAL: u32 = 1 << 11;
+
+// Synchronous clock PCLK/2 (8 MHz on HSI16), so HSI doesn't have to stay on for the ADC.
+// Follows clock switches, see `on_clock_change`
+const CFGR2_CKMODE_PCLK_DIV2: u32 = 0b01 << 30;
+const CFGR2_OVSE: u32 = 1 << 0;
+
+const CCR_VREFEN: u32 = 1 << 22;
+const CCR_TSEN: u32 = 1 << 23;
+const CCR_LFMEN: u32 = 1 << 25;
+
+// Below this ADC clock low frequency mode is required
+const LFM_MAX_HZ: u32 = 3_500_000;
+
+const CFGR3_EN_VREFINT: u32 = 1 << 0;
+const CFGR3_ENBUF_VREFINT_ADC: u32 = 1 << 8;
+const CFGR3_ENBUF_SENSOR_ADC: u32 = 1 << 9;
+const CFGR3_VREFINT_RDYF: u32 = 1 << 30;
+
+// 12.5 cycles for external channels, internal ones need at least 10us (160.5 cycles)
+const SMP_FAST: u32 = 0b010;
+const SMP_INTERNAL: u32 = 0b111;
+
+const TIMEOUT_LOOPS: u32 = 100_000;
+
+fn wait_for(mut ready: impl FnMut() -> bool) -> Result<(), AdcError> {
+    for _ in 0..TIMEOUT_LOOPS {
+        if ready() {
+            return Ok(());
+        }
+    }
+
+    Err(AdcError::Timeout)
+}
+
+pub struct AdcDriver {
+    calibration: AdcCalibration,
+}
+
+impl AdcDriver {
+    pub fn new() -> Result<Self, AdcError> {
+        let rcc_reg = unsafe { &*pac::RCC::ptr() };
+        rcc_reg.apb2enr.modify(|_, w| w.adcen().set_bit().syscfgen().set_bit());
+
+        let adc = unsafe { &*pac::ADC::ptr() };
+        let syscfg = unsafe { &*pac::SYSCFG::ptr() };
+
+        unsafe {
+            // Internal reference buffer and temperature sensor buffer for the ADC
+            syscfg.cfgr3.modify(|r, w| {
+                w.bits(r.bits() | CFGR3_EN_VREFINT | CFGR3_ENBUF_VREFINT_ADC | CFGR3_ENBUF_SENSOR_ADC)
+            });
+
+            adc.cfgr2.write(|w| w.bits(CFGR2_CKMODE_PCLK_DIV2));
+            adc.ccr.modify(|r, w| w.bits(r.bits() | CCR_VREFEN | CCR_TSEN));
+            adc.cr.modify(|r, w| w.bits(r.bits() | CR_ADVREGEN));
+        }
+
+        wait_for(|| syscfg.cfgr3.read().bits() & CFGR3_VREFINT_RDYF != 0)?;
+
+        // Calibration must be done with ADC disabled
+        unsafe { adc.cr.modify(|r, w| w.bits(r.bits() | CR_ADCAL)) };
+        wait_for(|| adc.isr.read().bits() & ISR_EOCAL != 0)?;
+        adc.isr.write(|w| unsafe { w.bits(ISR_EOCAL | ISR_ADRDY) });
+
+        unsafe { adc.cr.modify(|r, w| w.bits(r.bits() | CR_ADEN)) };
+        wait_for(|| adc.isr.read().bits() & ISR_ADRDY != 0)?;
+
+        let calibration = unsafe {
+            AdcCalibration {
+                vrefint: core::ptr::read_volatile(VREFINT_CAL_ADDR as *const u16),
+                ts_cal1: core::ptr::read_volatile(TS_CAL1_ADDR as *const u16),
+                ts_cal2: core::ptr::read_volatile(TS_CAL2_ADDR as *const u16),
+            }
+        };
+
+        Ok(Self { calibration })
+    }
+
+    fn read(&mut self, channel: u8) -> Result<u16, AdcError> {
+        if channel > TEMPERATURE_CHANNEL {
+            return Err(AdcError::NoChannel);
+        }
+
+        let adc = unsafe { &*pac::ADC::ptr() };
+
+        let smp = if channel >= VREFINT_CHANNEL { SMP_INTERNAL } else { SMP_FAST };
+
+        unsafe {
+            adc.smpr.write(|w| w.bits(smp));
+            adc.chselr.write(|w| w.bits(1 << channel));
+            adc.cr.modify(|r, w| w.bits(r.bits() | CR_ADSTART));
+        }
+
+        // EOC is cleared by reading DR
+        wait_for(|| adc.isr.read().bits() & ISR_EOC != 0)?;
+
+        Ok(adc.dr.read().bits() as u16)
+    }
+
+    fn set_oversampling(&mut self, ratio: u16) {
+        let adc = unsafe { &*pac::ADC::ptr() };
+
+        // OVSR selects 2^(OVSR+1) samples, OVSS shifts the sum back to 12 bits
+        let bits = match ratio {
+            0 | 1 => 0,
+            ratio => {
+                let shift = ratio.trailing_zeros();
+                CFGR2_OVSE | ((shift - 1) << 2) | (shift << 5)
+            }
+        };
+
+        // CFGR2 can only be written with ADC disabled
+        unsafe {
+            adc.cr.modify(|r, w| w.bits(r.bits() | CR_ADDIS));
+        }
+
+        let _ = wait_for(|| adc.cr.read().bits() & CR_ADEN == 0);
+
+        unsafe {
+            adc.cfgr2.write(|w| w.bits(CFGR2_CKMODE_PCLK_DIV2 | bits));
+            adc.isr.write(|w| w.bits(ISR_ADRDY));
+            adc.cr.modify(|r, w| w.bits(r.bits() | CR_ADEN));
+        }
+
+        let _ = wait_for(|| adc.isr.read().bits() & ISR_ADRDY != 0);
+    }
+
+    // Only allowed with no conversion ongoing, which is always true between reads
+    fn set_clock(&mut self, sysclk: u32) {
+        let adc = unsafe { &*pac::ADC::ptr() };
+
+        unsafe {
+            if sysclk / 2 < LFM_MAX_HZ {
+                adc.ccr.modify(|r, w| w.bits(r.bits() | CCR_LFMEN));
+            } else {
+                adc.ccr.modify(|r, w| w.bits(r.bits() & !CCR_LFMEN));
+            }
+        }
+    }
+}
+
+unsafe impl Sync for AdcDriver {}
+
+static ADC: RwLock<Option<AdcDriver>> = RwLock::new(None);
+
+pub(crate) fn init_adc(driver: AdcDriver) {
+    let mut r = ADC.lock_mut();
+    *r = Some(driver);
+}
+
+// Handle to the shared ADC, used by both `adc` object and the pulse sensor
+pub struct AdcHandle;
+
+impl AdcHandle {
+    // Sampling interrupt (TIM22) is masked, so it can't preempt a conversion started from a task
+    fn with<R>(f: impl FnOnce(&mut AdcDriver) -> R) -> Result<R, AdcError> {
+        let sampling = NVIC::is_enabled(Interrupt::TIM22);
+
+        NVIC::mask(Interrupt::TIM22);
+
+        let res = {
+            let mut r = ADC.lock_mut();
+            (*r).as_mut().map(f).ok_or(AdcError::NoAdc)
+        };
+
+        if sampling {
+            unsafe { NVIC::unmask(Interrupt::TIM22) };
+        }
+
+        res
+    }
+}
+
+impl AdcInterface for AdcHandle {
+    fn read(&mut self, channel: u8) -> Result<u16, AdcError> {
+        Self::with(|adc| adc.read(channel))?
+    }
+
+    fn set_oversampling(&mut self, ratio: u16) {
+        let _ = Self::with(|adc| adc.set_oversampling(ratio));
+    }
+
+    // Zeroed without a driver, which `Adc` reports as a calibration error
+    fn calibration(&self) -> AdcCalibration {
+        Self::with(|adc| adc.calibration).unwrap_or_default()
+    }
+}
+
+// Clock listener, registered once the ADC is initialized
+pub(crate) fn on_clock_change(sysclk: u32) {
+    let _ = AdcHandle::with(|adc| adc.set_clock(sysclk));
+}
+
+pub struct PulseSensorAdc {
+    channel: u8,
+}
+
+impl PulseSensorAdc {
+    pub fn new(channel: u8) -> Self {
+        Self { channel }
+    }
+}
+
+impl PulseSensorInterface for PulseSensorAdc {
+    fn read(&mut self) -> Result<u16, AdcError> {
+        AdcHandle.read(self.channel)
+    }
+}
@@ -0,0 +1,220 @@
+use crate::hal::pac;
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use app::peripherals::clock::{ClockError, ClockInterface, ClockProfile};
+
+const RCC_CR_HSI16ON: u32 = 1 << 0;
+const RCC_CR_HSI16RDYF: u32 = 1 << 2;
+const RCC_CR_MSION: u32 = 1 << 8;
+const RCC_CR_MSIRDY: u32 = 1 << 9;
+const RCC_CR_PLLON: u32 = 1 << 24;
+const RCC_CR_PLLRDY: u32 = 1 << 25;
+
+// MSI range 5 (2.097 MHz, reset default)
+const RCC_ICSCR_MSIRANGE_MASK: u32 = 0b111 << 13;
+const RCC_ICSCR_MSIRANGE_5: u32 = 0b101 << 13;
+
+const RCC_CFGR_SW_MASK: u32 = 0b11;
+const RCC_CFGR_SWS_SHIFT: u32 = 2;
+// AHB, APB1 and APB2 prescalers, all kept at 1
+const RCC_CFGR_PRESCALERS_MASK: u32 = 0x3FF << 4;
+const RCC_CFGR_STOPWUCK: u32 = 1 << 15;
+// PLL from HSI16, x4 / 2
+const RCC_CFGR_PLL_MASK: u32 = (1 << 16) | (0b1111 << 18) | (0b11 << 22);
+const RCC_CFGR_PLL_HSI16_X4_DIV2: u32 = (0b0001 << 18) | (0b01 << 22);
+
+const SW_MSI: u32 = 0b00;
+const SW_HSI16: u32 = 0b01;
+const SW_PLL: u32 = 0b11;
+
+const PWR_CR_VOS_SHIFT: u32 = 11;
+const PWR_CR_VOS_MASK: u32 = 0b11 << PWR_CR_VOS_SHIFT;
+const PWR_CSR_VOSF: u32 = 1 << 4;
+
+// Range 1 is the highest voltage (1.8V), range 3 the lowest (1.2V)
+const VOS_RANGE_1: u32 = 0b01;
+const VOS_RANGE_2: u32 = 0b10;
+const VOS_RANGE_3: u32 = 0b11;
+
+const FLASH_ACR_LATENCY: u32 = 1 << 0;
+
+const MSI_HZ: u32 = 2_097_152;
+const HSI16_HZ: u32 = 16_000_000;
+const PLL_HZ: u32 = 32_000_000;
+
+const READY_LOOPS: u32 = 100_000;
+
+fn wait_for(mut ready: impl FnMut() -> bool) -> Result<(), ClockError> {
+    if (0..READY_LOOPS).any(|_| ready()) { Ok(()) } else { Err(ClockError::Timeout) }
+}
+
+struct Settings {
+    sw:      u32,
+    hz:      u32,
+    vos:     u32,
+    latency: bool,
+}
+
+// Voltage range and flash wait states are the lowest allowed for the frequency (RM0377 3.3.4)
+fn settings(profile: ClockProfile) -> Settings {
+    match profile {
+        ClockProfile::Msi   => Settings { sw: SW_MSI,   hz: MSI_HZ,   vos: VOS_RANGE_3, latency: false },
+        ClockProfile::Hsi16 => Settings { sw: SW_HSI16, hz: HSI16_HZ, vos: VOS_RANGE_2, latency: true  },
+        ClockProfile::Pll32 => Settings { sw: SW_PLL,   hz: PLL_HZ,   vos: VOS_RANGE_1, latency: true  },
+    }
+}
+
+// HAL is frozen with HSI16 in main
+static PROFILE: AtomicU8 = AtomicU8::new(ClockProfile::Hsi16 as u8);
+
+fn profile() -> ClockProfile {
+    match PROFILE.load(Ordering::SeqCst) {
+        0 => ClockProfile::Msi,
+        2 => ClockProfile::Pll32,
+        _ => ClockProfile::Hsi16,
+    }
+}
+
+fn set_voltage(vos: u32) -> Result<(), ClockError> {
+    let pwr = unsafe { &*pac::PWR::ptr() };
+
+    wait_for(|| pwr.csr.read().bits() & PWR_CSR_VOSF == 0)?;
+    unsafe { pwr.cr.modify(|r, w| w.bits((r.bits() & !PWR_CR_VOS_MASK) | (vos << PWR_CR_VOS_SHIFT))) };
+    wait_for(|| pwr.csr.read().bits() & PWR_CSR_VOSF == 0)
+}
+
+fn set_latency(latency: bool) {
+    let flash = unsafe { &*pac::FLASH::ptr() };
+
+    unsafe {
+        if latency {
+            flash.acr.modify(|r, w| w.bits(r.bits() | FLASH_ACR_LATENCY));
+        } else {
+            flash.acr.modify(|r, w| w.bits(r.bits() & !FLASH_ACR_LATENCY));
+        }
+    }
+
+    // New latency has to be in effect before the frequency changes
+    while (flash.acr.read().bits() & FLASH_ACR_LATENCY != 0) != latency {}
+}
+
+// Starts the oscillator (and PLL) of the profile and selects it as SYSCLK
+fn select(sw: u32) -> Result<(), ClockError> {
+    let rcc_reg = unsafe { &*pac::RCC::ptr() };
+
+    match sw {
+        SW_MSI => unsafe {
+            rcc_reg.icscr.modify(|r, w| w.bits((r.bits() & !RCC_ICSCR_MSIRANGE_MASK) | RCC_ICSCR_MSIRANGE_5));
+            rcc_reg.cr.modify(|r, w| w.bits(r.bits() | RCC_CR_MSION));
+            wait_for(|| rcc_reg.cr.read().bits() & RCC_CR_MSIRDY != 0)?;
+        },
+        _ => unsafe {
+            rcc_reg.cr.modify(|r, w| w.bits(r.bits() | RCC_CR_HSI16ON));
+            wait_for(|| rcc_reg.cr.read().bits() & RCC_CR_HSI16RDYF != 0)?;
+        },
+    }
+
+    if sw == SW_PLL {
+        // PLL can only be configured while it's off
+        unsafe {
+            rcc_reg.cr.modify(|r, w| w.bits(r.bits() & !RCC_CR_PLLON));
+            wait_for(|| rcc_reg.cr.read().bits() & RCC_CR_PLLRDY == 0)?;
+
+            rcc_reg.cfgr.modify(|r, w| w.bits((r.bits() & !RCC_CFGR_PLL_MASK) | RCC_CFGR_PLL_HSI16_X4_DIV2));
+            rcc_reg.cr.modify(|r, w| w.bits(r.bits() | RCC_CR_PLLON));
+            wait_for(|| rcc_reg.cr.read().bits() & RCC_CR_PLLRDY != 0)?;
+        }
+    }
+
+    unsafe {
+        rcc_reg.cfgr.modify(|r, w| w.bits(r.bits() & !(RCC_CFGR_SW_MASK | RCC_CFGR_PRESCALERS_MASK) | sw));
+    }
+
+    wait_for(|| (rcc_reg.cfgr.read().bits() >> RCC_CFGR_SWS_SHIFT) & RCC_CFGR_SW_MASK == sw)
+}
+
+fn switch(profile: ClockProfile) -> Result<u32, ClockError> {
+    let rcc_reg = unsafe { &*pac::RCC::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+    let flash = unsafe { &*pac::FLASH::ptr() };
+
+    let next = settings(profile);
+
+    let vos = (pwr.cr.read().bits() & PWR_CR_VOS_MASK) >> PWR_CR_VOS_SHIFT;
+    let latency = flash.acr.read().bits() & FLASH_ACR_LATENCY != 0;
+
+    // Going faster: voltage and wait states first. Lower range number is a higher voltage
+    if next.vos < vos {
+        set_voltage(next.vos)?;
+    }
+
+    if next.latency && !latency {
+        set_latency(true);
+    }
+
+    select(next.sw)?;
+
+    // Going slower: voltage and wait states after
+    if !next.latency && latency {
+        set_latency(false);
+    }
+
+    if next.vos > vos {
+        set_voltage(next.vos)?;
+    }
+
+    unsafe {
+        // HSI16 stays on, it's the USART1 kernel clock (console baudrate doesn't depend on SYSCLK)
+        let unused = match next.sw {
+            SW_MSI   => RCC_CR_PLLON,
+            SW_HSI16 => RCC_CR_PLLON | RCC_CR_MSION,
+            _        => RCC_CR_MSION,
+        };
+
+        rcc_reg.cr.modify(|r, w| w.bits(r.bits() & !unused));
+
+        // Wake up from STOP on MSI in low-power profile, otherwise on HSI16 (PLL is restored by `resume`)
+        if next.sw == SW_MSI {
+            rcc_reg.cfgr.modify(|r, w| w.bits(r.bits() & !RCC_CFGR_STOPWUCK));
+        } else {
+            rcc_reg.cfgr.modify(|r, w| w.bits(r.bits() | RCC_CFGR_STOPWUCK));
+        }
+    }
+
+    PROFILE.store(profile as u8, Ordering::SeqCst);
+
+    Ok(next.hz)
+}
+
+// PLL is stopped in STOP mode, called with interrupts disabled right after wake-up
+pub(crate) fn resume() {
+    if profile() == ClockProfile::Pll32 {
+        let _ = select(SW_PLL);
+    }
+}
+
+pub struct Stm32Clock;
+
+impl ClockInterface for Stm32Clock {
+    fn profile(&self) -> ClockProfile {
+        profile()
+    }
+
+    fn set_profile(&mut self, profile: ClockProfile) -> Result<u32, ClockError> {
+        // Nothing may run while SYSCLK and the timers derived from it disagree
+        let hz = cortex_m::interrupt::free(|_| -> Result<u32, ClockError> {
+            let hz = switch(profile)?;
+            crate::time::reconfigure(hz);
+            Ok(hz)
+        })?;
+
+        crate::time::calibrate_delay();
+
+        Ok(hz)
+    }
+
+    fn sysclk(&self) -> u32 {
+        crate::time::sysclk()
+    }
+}
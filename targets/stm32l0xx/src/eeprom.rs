@@ -0,0 +1,106 @@
+use crate::hal::pac;
+
+use app::peripherals::nvm::{NvmError, NvmInterface};
+
+// Data EEPROM (RM0377 3.3.1), bank 1 only on parts with two banks
+const EEPROM_BASE: usize = 0x0808_0000;
+
+#[cfg(feature = "mcu-stm32l073")]
+const EEPROM_SIZE: usize = 3 * 1024;
+#[cfg(feature = "mcu-stm32l051")]
+const EEPROM_SIZE: usize = 2 * 1024;
+
+const FLASH_PEKEY1: u32 = 0x89AB_CDEF;
+const FLASH_PEKEY2: u32 = 0x0203_0405;
+
+const FLASH_PECR_PELOCK: u32 = 1 << 0;
+
+const FLASH_SR_BSY: u32 = 1 << 0;
+const FLASH_SR_WRPERR: u32 = 1 << 8;
+const FLASH_SR_PGAERR: u32 = 1 << 9;
+const FLASH_SR_SIZERR: u32 = 1 << 10;
+const FLASH_SR_NOTZEROERR: u32 = 1 << 16;
+const FLASH_SR_FWWERR: u32 = 1 << 17;
+
+const FLASH_SR_ERRORS: u32 =
+    FLASH_SR_WRPERR | FLASH_SR_PGAERR | FLASH_SR_SIZERR | FLASH_SR_NOTZEROERR | FLASH_SR_FWWERR;
+
+// Byte write with automatic erase takes up to 2 * 3.2 ms
+const WRITE_LOOPS: u32 = 200_000;
+
+pub(crate) fn flash() -> &'static pac::flash::RegisterBlock {
+    unsafe { &*pac::FLASH::ptr() }
+}
+
+pub(crate) fn wait_ready() -> Result<(), NvmError> {
+    let flash = flash();
+
+    if !(0..WRITE_LOOPS).any(|_| flash.sr.read().bits() & FLASH_SR_BSY == 0) {
+        return Err(NvmError::Timeout);
+    }
+
+    let errors = flash.sr.read().bits() & FLASH_SR_ERRORS;
+
+    if errors != 0 {
+        // Error flags are cleared by writing 1
+        unsafe { flash.sr.write(|w| w.bits(errors)) };
+        return Err(NvmError::Write);
+    }
+
+    Ok(())
+}
+
+// PECR (and with it the data EEPROM and program memory) is locked again after every write
+pub(crate) fn with_unlocked<R>(f: impl FnOnce() -> Result<R, NvmError>) -> Result<R, NvmError> {
+    let flash = flash();
+
+    wait_ready()?;
+
+    if flash.pecr.read().bits() & FLASH_PECR_PELOCK != 0 {
+        unsafe {
+            flash.pekeyr.write(|w| w.bits(FLASH_PEKEY1));
+            flash.pekeyr.write(|w| w.bits(FLASH_PEKEY2));
+        }
+    }
+
+    let res = f();
+
+    unsafe { flash.pecr.modify(|r, w| w.bits(r.bits() | FLASH_PECR_PELOCK)) };
+
+    res
+}
+
+pub struct Stm32Eeprom;
+
+impl NvmInterface for Stm32Eeprom {
+    fn size(&self) -> usize {
+        EEPROM_SIZE
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), NvmError> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((EEPROM_BASE + offset + i) as *const u8) };
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), NvmError> {
+        with_unlocked(|| {
+            for (i, byte) in data.iter().enumerate() {
+                let addr = (EEPROM_BASE + offset + i) as *mut u8;
+
+                if unsafe { core::ptr::read_volatile(addr) } == *byte {
+                    continue;
+                }
+
+                // FIX = 0, so the cell is erased before it's programmed
+                unsafe { core::ptr::write_volatile(addr, *byte) };
+
+                wait_ready()?;
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -36,6 +36,11 @@ unsafe fn stack_trace() {
 unsafe fn HardFault(ef: &cortex_m_rt::ExceptionFrame) -> ! {
     println!("{}{}        HARD FAULT        {}", rtrs::ANSI_COLOR_BG_RED, rtrs::ANSI_TEXT_BOLD, rtrs::ANSI_TEXT_RESET);
 
+    // RTC registers are read directly, storage may be in any state here
+    if let Ok(time) = crate::rtc::read() {
+        println!("Time: {}", time);
+    }
+
     print_regs!(
         {"R0",   ef.r0()},
         {"R1",   ef.r1()},
@@ -72,6 +77,8 @@ fn SysTick() {
         time.increment()
     });
 
+    crate::tone::on_tick();
+
     app::board::BoardInterface::callback(app::board::Callback::Systick)
 }
 
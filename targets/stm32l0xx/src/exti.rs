@@ -0,0 +1,98 @@
+use crate::hal::pac::{self, interrupt, Interrupt};
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Copy, Clone)]
+#[repr(u8)]
+pub enum Port {
+    A = 0,
+    B = 1,
+    C = 2,
+}
+
+// GPIO port routed to each line, needed to sample the level in interrupt
+static LINE_PORTS: [AtomicU8; 16] = [const { AtomicU8::new(0) }; 16];
+
+fn port_idr(port: u8) -> u32 {
+    unsafe {
+        match port {
+            0 => (*pac::GPIOA::ptr()).idr.read().bits(),
+            1 => (*pac::GPIOB::ptr()).idr.read().bits(),
+            _ => (*pac::GPIOC::ptr()).idr.read().bits(),
+        }
+    }
+}
+
+fn line_irq(line: u8) -> Interrupt {
+    match line {
+        0..=1 => Interrupt::EXTI0_1,
+        2..=3 => Interrupt::EXTI2_3,
+        _     => Interrupt::EXTI4_15,
+    }
+}
+
+// Routes pin to its EXTI line with both edges enabled, then registers it by name in the app
+pub(crate) fn setup_line(name: &'static str, port: Port, pin: u8) {
+    let rcc_reg = unsafe { &*pac::RCC::ptr() };
+    rcc_reg.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+
+    let syscfg = unsafe { &*pac::SYSCFG::ptr() };
+    let shift = (pin % 4) * 4;
+    let port_bits = (port as u32) << shift;
+
+    unsafe {
+        match pin / 4 {
+            0 => syscfg.exticr1.modify(|r, w| w.bits((r.bits() & !(0xF << shift)) | port_bits)),
+            1 => syscfg.exticr2.modify(|r, w| w.bits((r.bits() & !(0xF << shift)) | port_bits)),
+            2 => syscfg.exticr3.modify(|r, w| w.bits((r.bits() & !(0xF << shift)) | port_bits)),
+            _ => syscfg.exticr4.modify(|r, w| w.bits((r.bits() & !(0xF << shift)) | port_bits)),
+        }
+    }
+
+    LINE_PORTS[pin as usize].store(port as u8, Ordering::SeqCst);
+
+    let exti = unsafe { &*pac::EXTI::ptr() };
+
+    unsafe {
+        exti.rtsr.modify(|r, w| w.bits(r.bits() | (1 << pin)));
+        exti.ftsr.modify(|r, w| w.bits(r.bits() | (1 << pin)));
+        exti.imr.modify(|r, w| w.bits(r.bits() | (1 << pin)));
+
+        cortex_m::peripheral::NVIC::unmask(line_irq(pin));
+    }
+
+    app::peripherals::exti::register(name, pin);
+}
+
+fn handle_lines(first: u8, last: u8) {
+    let exti = unsafe { &*pac::EXTI::ptr() };
+    let pending = exti.pr.read().bits();
+
+    for line in first..=last {
+        if pending & (1 << line) == 0 {
+            continue;
+        }
+
+        // Write 1 to clear
+        exti.pr.write(|w| unsafe { w.bits(1 << line) });
+
+        let level = port_idr(LINE_PORTS[line as usize].load(Ordering::SeqCst)) & (1 << line) != 0;
+
+        app::peripherals::exti::notify(line, level);
+    }
+}
+
+#[interrupt]
+fn EXTI0_1() {
+    handle_lines(0, 1);
+}
+
+#[interrupt]
+fn EXTI2_3() {
+    handle_lines(2, 3);
+}
+
+#[interrupt]
+fn EXTI4_15() {
+    handle_lines(4, 15);
+}
@@ -0,0 +1,164 @@
+use crate::hal::pac;
+
+use app::peripherals::flash::{FlashError, FlashInterface, WRITE_SIZE};
+use app::peripherals::nvm::NvmError;
+
+use super::eeprom::{flash, wait_ready, with_unlocked};
+
+// Upper bank of the STM32L073 (RM0377 3.3.1), bank 2 unless bank 2 was booted. Flash is
+// read-while-write between banks, so the CPU keeps running while the staging area is erased or
+// written. memory.x keeps the application out of here
+const STAGING_BASE: usize = 0x0801_8000;
+const STAGING_SIZE: usize = 96 * 1024;
+
+const PAGE_SIZE: usize = 128;
+
+const FLASH_PRGKEY1: u32 = 0x8C9D_AEBF;
+const FLASH_PRGKEY2: u32 = 0x1314_1516;
+
+const FLASH_OPTKEY1: u32 = 0xFBEA_D9C8;
+const FLASH_OPTKEY2: u32 = 0x2425_2627;
+
+const FLASH_PECR_PRGLOCK: u32 = 1 << 1;
+const FLASH_PECR_OPTLOCK: u32 = 1 << 2;
+const FLASH_PECR_PROG: u32 = 1 << 3;
+const FLASH_PECR_ERASE: u32 = 1 << 9;
+const FLASH_PECR_OBL_LAUNCH: u32 = 1 << 18;
+
+// User option bytes (RM0377 3.4.1), the value in the low half and its complement in the high half.
+// They're FLASH_OPTR[31:16] once loaded
+const OPTION_USER_ADDR: usize = 0x1FF8_0004;
+const OPTION_USER_BFB2: u16 = 1 << 7;
+
+// Set while bank 2 is mapped at 0x08000000
+const SYSCFG_CFGR1_UFB: u32 = 1 << 3;
+
+fn map_err(err: NvmError) -> FlashError {
+    match err {
+        NvmError::Timeout => FlashError::Timeout,
+        _ => FlashError::Write,
+    }
+}
+
+// Needs PECR unlocked, locked again with it
+fn unlock_program() {
+    let flash = flash();
+
+    if flash.pecr.read().bits() & FLASH_PECR_PRGLOCK != 0 {
+        unsafe {
+            flash.prgkeyr.write(|w| w.bits(FLASH_PRGKEY1));
+            flash.prgkeyr.write(|w| w.bits(FLASH_PRGKEY2));
+        }
+    }
+}
+
+// Needs PECR unlocked, locked again with it
+fn unlock_options() {
+    let flash = flash();
+
+    if flash.pecr.read().bits() & FLASH_PECR_OPTLOCK != 0 {
+        unsafe {
+            flash.optkeyr.write(|w| w.bits(FLASH_OPTKEY1));
+            flash.optkeyr.write(|w| w.bits(FLASH_OPTKEY2));
+        }
+    }
+}
+
+pub struct Stm32Flash;
+
+impl FlashInterface for Stm32Flash {
+    fn address(&self) -> u32 {
+        STAGING_BASE as u32
+    }
+
+    fn size(&self) -> usize {
+        STAGING_SIZE
+    }
+
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), FlashError> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((STAGING_BASE + offset + i) as *const u8) };
+        }
+
+        Ok(())
+    }
+
+    // Erased words read as zero, not 0xFF
+    fn erase_page(&mut self, offset: usize) -> Result<(), FlashError> {
+        with_unlocked(|| {
+            let flash = flash();
+
+            unlock_program();
+
+            unsafe {
+                flash.pecr.modify(|r, w| w.bits(r.bits() | FLASH_PECR_ERASE | FLASH_PECR_PROG));
+                // Any word of the page starts the erase
+                core::ptr::write_volatile((STAGING_BASE + offset) as *mut u32, 0);
+            }
+
+            let res = wait_ready();
+
+            unsafe { flash.pecr.modify(|r, w| w.bits(r.bits() & !(FLASH_PECR_ERASE | FLASH_PECR_PROG))) };
+
+            res
+        })
+        .map_err(map_err)
+    }
+
+    // Word by word, half-page programming would be faster but has to run from RAM
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        with_unlocked(|| {
+            unlock_program();
+
+            for (i, word) in data.chunks_exact(WRITE_SIZE).enumerate() {
+                let addr = (STAGING_BASE + offset + i * WRITE_SIZE) as *mut u32;
+                let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+
+                // Zero is what the erase left there
+                if unsafe { core::ptr::read_volatile(addr) } == value {
+                    continue;
+                }
+
+                unsafe { core::ptr::write_volatile(addr, value) };
+
+                wait_ready()?;
+            }
+
+            Ok(())
+        })
+        .map_err(map_err)
+    }
+
+    // With BFB2 the system bootloader starts bank 2 and maps it at 0x08000000 (UFB), bank 1 moves
+    // to the staging address. So BFB2 is flipped to the bank that isn't running. Option bytes are
+    // only loaded by OBL_LAUNCH (or a power cycle), which resets right away
+    fn boot_staged(&mut self) -> Result<(), FlashError> {
+        let syscfg = unsafe { &*pac::SYSCFG::ptr() };
+        let running_bank2 = syscfg.cfgr1.read().bits() & SYSCFG_CFGR1_UFB != 0;
+
+        with_unlocked(|| {
+            let flash = flash();
+
+            unlock_options();
+
+            let user = (flash.optr.read().bits() >> 16) as u16;
+            let user = if running_bank2 { user & !OPTION_USER_BFB2 } else { user | OPTION_USER_BFB2 };
+
+            unsafe { core::ptr::write_volatile(OPTION_USER_ADDR as *mut u32, (!user as u32) << 16 | user as u32) };
+
+            wait_ready()?;
+
+            unsafe { flash.pecr.modify(|r, w| w.bits(r.bits() | FLASH_PECR_OBL_LAUNCH)) };
+
+            // Not reached, unless the reload didn't happen
+            unsafe { flash.pecr.modify(|r, w| w.bits(r.bits() | FLASH_PECR_OPTLOCK)) };
+
+            Ok(())
+        })
+        .map_err(map_err)
+    }
+}
@@ -1,12 +1,22 @@
 #![no_std]
 #![no_main]
 
+mod adc;
+mod clock;
+mod eeprom;
 mod exc;
+mod exti;
+#[cfg(feature = "mcu-stm32l073")]
+mod flash;
 mod util;
 mod time;
 mod objects;
+mod power;
+mod rtc;
 mod tty;
 mod spi;
+mod sx1278;
+mod tone;
 
 use cortex_m_rt::entry;
 
@@ -14,12 +24,13 @@ use stm32l0xx_hal as hal;
 use hal::prelude::*;
 
 extern crate alloc;
-use alloc::boxed::Box;
 use rtrs::object_with_mut;
 
 pub const GREEN_LED_NAME: &str = "led_green";
 pub const BTN_PIN_NAME: &str = "btn";
-pub const BUZZER_PIN_NAME: &str = "buzzer";
+pub const BUZZER_NAME: &str = "buzzer";
+pub const SPI1_NAME: &str = "spi1";
+pub const RADIO_DIO0_NAME: &str = app::peripherals::exti::RADIO_DIO0_NAME;
 
 #[unsafe(no_mangle)]
 fn rtrs_critical_section_acquire() {
@@ -39,10 +50,10 @@ unsafe fn main() -> ! {
 
     let peripherals = hal::pac::Peripherals::take().unwrap();
     let mut rcc = peripherals.RCC.freeze(hal::rcc::Config::hsi16());
-    let mut adc = peripherals.ADC.constrain(&mut rcc);
 
     time::setup_systick(&mut core_peripherals.SYST, rcc.clocks.sys_clk().0, 1_000);
-    time::setup_tim2();
+    time::setup_tim21(rcc.clocks.sys_clk().0);
+    time::calibrate_delay();
 
     let gpioa = peripherals.GPIOA.split(&mut rcc);
     let gpiob = peripherals.GPIOB.split(&mut rcc);
@@ -51,17 +62,17 @@ unsafe fn main() -> ! {
         peripherals.USART1.usart(
             gpioa.pa9,  // tx
             gpioa.pa10, // rx
-            hal::serial::Config::default().baudrate(115_200.Bd()),
+            hal::serial::Config::default().baudrate(tty::CONSOLE_BAUDRATE.Bd()),
             &mut rcc
         ).unwrap()
     );
     // FIXME: Can't use green led on nucleo, because SPI1_CLK is wired there also
     // objects::init_led(gpioa.pa5.into_push_pull_output());
     objects::init_btn(gpioa.pa14.into_pull_down_input());
-    objects::init_buzz(gpioa.pa15.into_push_pull_output());
+    objects::init_tone(gpioa.pa15);
     objects::init_time();
 
-    objects::init_radio(
+    spi::init_spi1(
         spi::Spi1Bus::new(
             peripherals.SPI1.spi(
                 (
@@ -73,7 +84,7 @@ unsafe fn main() -> ! {
                     polarity: hal::spi::Polarity::IdleLow,
                     phase:    hal::spi::Phase::CaptureOnFirstTransition,
                 },
-                4000000.Hz(),
+                spi::SPI1_MAX_HZ.Hz(),
                 &mut rcc
             ),
             #[cfg(feature = "mcu-stm32l073")]
@@ -83,7 +94,14 @@ unsafe fn main() -> ! {
         )
     );
 
-    objects::init_pulse_sensor(adc, gpioa.pa2.into_analog());
+    objects::init_spi(SPI1_NAME, spi::Spi1Handle);
+    objects::init_radio(spi::Spi1Handle);
+    // SX1278 DIO0 (RxDone/TxDone) is wired to PA8
+    objects::init_radio_dio0(gpioa.pa8.into_pull_down_input());
+
+    if objects::init_adc().is_ok() {
+        objects::init_pulse_sensor(gpioa.pa2.into_analog());
+    }
 
     app::board::BoardInterface::register_callback(
         app::board::CallbackType::TriggerCrash(|| {
@@ -92,18 +110,46 @@ unsafe fn main() -> ! {
     );
 
     app::board::BoardInterface::register_callback(
-        app::board::CallbackType::MicrosecondDelay(|us| {
-            let r = time::SYSCLK.lock();
-            time::delay_us(us, *r, 3);
+        app::board::CallbackType::Reset(|| {
+            cortex_m::peripheral::SCB::sys_reset();
         })
     );
 
     app::board::BoardInterface::register_callback(
-        app::board::CallbackType::MicrosecondTickProvider(|provider| {
-            *provider = Box::new(time::MicrosecondTickProvider::new());
+        app::board::CallbackType::MicrosecondDelay(time::delay_us)
+    );
+
+    app::board::BoardInterface::register_callback(
+        app::board::CallbackType::Micros(time::micros)
+    );
+
+    app::board::BoardInterface::register_callback(
+        app::board::CallbackType::SetSampleRate(|hz| {
+            time::set_sample_rate(hz);
         })
     );
 
+    // LSE startup takes up to a few hundred ms, so it's only waited for once
+    let low_speed_clock = power::start_low_speed_clock();
+
+    power::setup_stop_mode(low_speed_clock);
+
+    rtc::setup_rtc(low_speed_clock);
+    objects::init_rtc();
+
+    objects::init_clock();
+    objects::init_nvm();
+    #[cfg(feature = "mcu-stm32l073")]
+    objects::init_flash();
+
+    app::board::BoardInterface::register_callback(
+        app::board::CallbackType::Sleep(power::sleep)
+    );
+
+    app::board::BoardInterface::register_callback(
+        app::board::CallbackType::Stop(power::stop)
+    );
+
     unsafe { cortex_m::interrupt::enable() };
 
     app::main();
@@ -1,43 +1,41 @@
-use crate::hal::gpio::gpioa::{PA2, PA5, PA14, PA15};
+use crate::hal::gpio::gpioa::{PA2, PA5, PA8, PA14, PA15};
 use crate::hal::gpio::{Analog, Output, Input, PushPull, PullDown};
 use crate::hal::pac::USART1;
 use crate::hal::serial::Serial;
-use crate::hal::adc::{Adc, Ready};
 
-use rtrs::{object_insert, output_pin_wrapper, input_pin_wrapper};
+use rtrs::{object_insert, object_with_mut, output_pin_wrapper, input_pin_wrapper, println};
 use rtrs::time::{TimeProvider, TIME_OBJECT_NAME};
 use rtrs::log::console::CONSOLE_OBJECT_NAME;
 use rtrs_drivers::radio::sx1278::SX1278RadioDriver;
 
 output_pin_wrapper!(LedPin,    PA5<Output<PushPull>>);
-output_pin_wrapper!(BuzzerPin, PA15<Output<PushPull>>);
 input_pin_wrapper!(ButtonPin,  PA14<Input<PullDown>>);
+input_pin_wrapper!(Dio0Pin,    PA8<Input<PullDown>>);
+output_pin_wrapper!(BuzzerPin, super::tone::TonePin);
 
-use app::peripherals::pulse_sensor::{PulseSensorInterface, PulseSensor};
+use app::peripherals::pulse_sensor::{PulseSensor, PULSE_SENSOR_OBJECT_NAME};
+use app::peripherals::adc::{self, Adc, AdcError, ADC_OBJECT_NAME};
+use app::peripherals::clock::{self, Clock, CLOCK_OBJECT_NAME};
+use app::peripherals::spi::{self, SpiDevice};
+use app::net::radio::{RadioSignal, RADIO_SIGNAL_OBJECT_NAME};
+use app::peripherals::tone::{Tone, TONE_OBJECT_NAME};
+use app::peripherals::rtc::{Rtc, RTC_OBJECT_NAME};
+use app::peripherals::nvm::{Nvm, NVM_OBJECT_NAME};
+#[cfg(feature = "mcu-stm32l073")]
+use app::peripherals::flash::{Flash, FLASH_OBJECT_NAME};
 
-struct PulseSensorAdc {
-    adc: Adc<Ready>,
-    pin: PA2<Analog>,
-    _dummy: u8
-}
-
-impl PulseSensorAdc {
-    pub fn new(adc: Adc<Ready>, pin: PA2<Analog>) -> Self {
-        Self { adc, pin, _dummy: 0 }
-    }
-}
+use core::fmt::Write;
 
-impl PulseSensorInterface for PulseSensorAdc {
-    fn read(&mut self) -> u16 {
-        use embedded_hal::adc::OneShot;
-        self.adc.read(&mut self.pin).unwrap()
-    }
-}
+// PA2 is ADC_IN2
+const PULSE_SENSOR_CHANNEL: u8 = 2;
 
-unsafe impl Sync for PulseSensorAdc {}
+// Hardware oversampling ratio for all channels, the result stays 12-bit
+const ADC_OVERSAMPLING: u16 = 16;
 
 pub(crate) fn init_serial(log_serial: Serial<USART1>) {
-    object_insert!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty::new(super::tty::TtyUSART1Backend::new(log_serial)));
+    // Wrapped so output of remote shell commands can be sent back over the radio
+    let backend = app::net::remote::CaptureBackend::new(super::tty::TtyUSART1Backend::new(log_serial));
+    object_insert!(CONSOLE_OBJECT_NAME, rtrs::tty::Tty::new(backend));
 }
 
 pub(crate) fn init_led(green_led: PA5<Output<PushPull>>) {
@@ -46,22 +44,92 @@ pub(crate) fn init_led(green_led: PA5<Output<PushPull>>) {
 
 pub(crate) fn init_btn(btn: PA14<Input<PullDown>>) {
     object_insert!(crate::BTN_PIN_NAME, rtrs::gpio::Input::new(ButtonPin::new(btn)));
+    super::exti::setup_line(crate::BTN_PIN_NAME, super::exti::Port::A, 14);
+}
+
+pub(crate) fn init_radio_dio0(dio0: PA8<Input<PullDown>>) {
+    object_insert!(crate::RADIO_DIO0_NAME, rtrs::gpio::Input::new(Dio0Pin::new(dio0)));
+    super::exti::setup_line(crate::RADIO_DIO0_NAME, super::exti::Port::A, 8);
 }
 
-pub(crate) fn init_buzz(pin: PA15<Output<PushPull>>) {
-    object_insert!(crate::BUZZER_PIN_NAME, rtrs::gpio::Output::new(BuzzerPin::new(pin)));
+// Buzzer pin is driven by TIM2 only, a GPIO object for it would fight the PWM output. Patterns
+// get an output object that plays through the tone instead (the LED shares its pin with SPI1)
+pub(crate) fn init_tone(buzzer: PA15<Analog>) {
+    object_insert!(TONE_OBJECT_NAME, Tone::new(super::tone::Tim2Tone::new(buzzer)));
+    object_insert!(crate::BUZZER_NAME, rtrs::gpio::Output::new(BuzzerPin::new(super::tone::TonePin)));
+}
+
+pub(crate) fn init_rtc() {
+    object_insert!(RTC_OBJECT_NAME, Rtc::new(super::rtc::Stm32Rtc));
+    app::peripherals::rtc::register_unlocked_read(super::rtc::read);
+}
+
+pub(crate) fn init_nvm() {
+    object_insert!(NVM_OBJECT_NAME, Nvm::new(super::eeprom::Stm32Eeprom));
+}
+
+// Staging area for firmware updates, the 64K of the L051 only fit one image
+#[cfg(feature = "mcu-stm32l073")]
+pub(crate) fn init_flash() {
+    object_insert!(FLASH_OBJECT_NAME, Flash::new(super::flash::Stm32Flash));
+}
+
+// Drivers that derive their timing from SYSCLK follow clock switches
+pub(crate) fn init_clock() {
+    clock::on_change("tone", super::tone::on_clock_change);
+    clock::on_change("spi1", super::spi::on_clock_change);
+    clock::on_change("console", super::tty::on_clock_change);
+
+    object_insert!(CLOCK_OBJECT_NAME, Clock::new(super::clock::Stm32Clock));
 }
 
 pub(crate) fn init_time() {
     object_insert!(TIME_OBJECT_NAME, TimeProvider::new());
 }
 
-pub(crate) fn init_radio(bus: super::spi::Spi1Bus) {
+pub(crate) fn init_spi(name: &'static str, handle: super::spi::Spi1Handle) {
+    object_insert!(name, SpiDevice::new(handle));
+}
+
+pub(crate) fn init_radio(bus: super::spi::Spi1Handle) {
     let radio = SX1278RadioDriver::create_radio(bus);
     object_insert!("radio", radio);
+
+    // `spi reg radio ..` goes to the chip on SPI1
+    spi::alias("radio", crate::SPI1_NAME);
+
+    object_insert!(RADIO_SIGNAL_OBJECT_NAME, RadioSignal::new(super::sx1278::Sx1278Signal::new(super::spi::Spi1Handle)));
+}
+
+// Nothing that uses the ADC is registered if it fails
+pub(crate) fn init_adc() -> Result<(), AdcError> {
+    match super::adc::AdcDriver::new() {
+        Ok(driver) => {
+            super::adc::init_adc(driver);
+            clock::on_change(ADC_OBJECT_NAME, super::adc::on_clock_change);
+        }
+        Err(err) => {
+            println!("ADC init failed: {:?}", err);
+            return Err(err);
+        }
+    }
+
+    let mut adc = Adc::new(super::adc::AdcHandle);
+
+    let _ = adc.add_channel(adc::VREFINT_CHANNEL_NAME, super::adc::VREFINT_CHANNEL);
+    let _ = adc.add_channel(adc::TEMPERATURE_CHANNEL_NAME, super::adc::TEMPERATURE_CHANNEL);
+    let _ = adc.set_oversampling(ADC_OVERSAMPLING);
+
+    object_insert!(ADC_OBJECT_NAME, adc);
+
+    Ok(())
 }
 
-pub(crate) fn init_pulse_sensor(adc: Adc<Ready>, pin: PA2<Analog>) {
-    let pulse_sensor = PulseSensor::new(PulseSensorAdc::new(adc, pin));
-    object_insert!("pulse_sensor", pulse_sensor);
+// Pin is only taken to make sure it's in analog mode, conversions go through the shared ADC
+pub(crate) fn init_pulse_sensor(_pin: PA2<Analog>) {
+    object_with_mut!(ADC_OBJECT_NAME, Adc, adc, {
+        let _ = adc.add_channel(PULSE_SENSOR_OBJECT_NAME, PULSE_SENSOR_CHANNEL);
+    });
+
+    app::services::heart_rate::set_sensor(PulseSensor::new(super::adc::PulseSensorAdc::new(PULSE_SENSOR_CHANNEL)));
 }
@@ -0,0 +1,231 @@
+use crate::hal::pac::{self, interrupt, Interrupt};
+
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+// LPTIM1 runs from LSE (or LSI if there is no crystal) divided by 32
+const LPTIM_PRESC_DIV32: u32 = 0b101 << 9;
+pub(crate) const LSE_HZ: u32 = 32_768;
+pub(crate) const LSI_HZ: u32 = 37_000;
+
+// LPTIM1 counter frequency, needed to convert ticks slept back to ms
+static LPTIM_HZ: AtomicU32 = AtomicU32::new(LSE_HZ / 32);
+
+const RCC_CSR_LSEON: u32 = 1 << 8;
+const RCC_CSR_LSERDY: u32 = 1 << 9;
+const RCC_CSR_LSION: u32 = 1 << 0;
+const RCC_CSR_LSIRDY: u32 = 1 << 1;
+const RCC_CR_HSI16KERON: u32 = 1 << 1;
+const RCC_CFGR_STOPWUCK: u32 = 1 << 15;
+const RCC_CCIPR_LPTIM1SEL_LSI: u32 = 0b01 << 18;
+const RCC_CCIPR_LPTIM1SEL_LSE: u32 = 0b11 << 18;
+const RCC_CCIPR_LPTIM1SEL_MASK: u32 = 0b11 << 18;
+const RCC_CCIPR_USART1SEL_HSI16: u32 = 0b10 << 0;
+const RCC_CCIPR_USART1SEL_MASK: u32 = 0b11 << 0;
+
+const PWR_CR_LPSDSR: u32 = 1 << 0;
+const PWR_CR_PDDS: u32 = 1 << 1;
+const PWR_CR_CWUF: u32 = 1 << 2;
+const PWR_CR_DBP: u32 = 1 << 8;
+const PWR_CR_ULP: u32 = 1 << 9;
+const PWR_CR_FWU: u32 = 1 << 10;
+
+const LPTIM_CR_ENABLE: u32 = 1 << 0;
+const LPTIM_CR_SNGSTRT: u32 = 1 << 1;
+const LPTIM_ISR_ARRM: u32 = 1 << 1;
+const LPTIM_ISR_ARROK: u32 = 1 << 4;
+const LPTIM_IER_ARRMIE: u32 = 1 << 1;
+const LPTIM_ICR_ALL: u32 = 0x7F;
+
+const USART_CR1_UE: u32 = 1 << 0;
+const USART_CR1_UESM: u32 = 1 << 1;
+const USART_CR3_WUS_RXNE: u32 = 0b11 << 20;
+const USART_CR3_WUFIE: u32 = 1 << 22;
+const USART_ICR_WUCF: u32 = 1 << 20;
+
+// Direct EXTI lines, only need to be unmasked to wake the core
+const EXTI_LINE_USART1: u32 = 25;
+const EXTI_LINE_LPTIM1: u32 = 29;
+
+const DBGMCU_CR_DBG_STOP: u32 = 1 << 1;
+
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
+
+const SYST_CSR_ENABLE: u32 = 1 << 0;
+
+const STARTUP_LOOPS: u32 = 1_000_000;
+
+fn wait_for(mut ready: impl FnMut() -> bool) -> bool {
+    (0..STARTUP_LOOPS).any(|_| ready())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum LowSpeedClock {
+    Lse,
+    Lsi,
+}
+
+// Starts LSE, falls back to LSI if there is no crystal. Also enables backup domain write access,
+// LSE and RTC live there and keep running across resets
+pub(crate) fn start_low_speed_clock() -> LowSpeedClock {
+    let rcc_reg = unsafe { &*pac::RCC::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+
+    rcc_reg.apb1enr.modify(|_, w| w.pwren().set_bit());
+
+    unsafe {
+        pwr.cr.modify(|r, w| w.bits(r.bits() | PWR_CR_DBP));
+        rcc_reg.csr.modify(|r, w| w.bits(r.bits() | RCC_CSR_LSEON));
+    }
+
+    if wait_for(|| rcc_reg.csr.read().bits() & RCC_CSR_LSERDY != 0) {
+        return LowSpeedClock::Lse;
+    }
+
+    unsafe { rcc_reg.csr.modify(|r, w| w.bits(r.bits() | RCC_CSR_LSION)) };
+    wait_for(|| rcc_reg.csr.read().bits() & RCC_CSR_LSIRDY != 0);
+
+    LowSpeedClock::Lsi
+}
+
+// LPTIM1 as STOP wake-up timer, USART1 wake-up on RX and STOP mode configuration
+pub(crate) fn setup_stop_mode(low_speed_clock: LowSpeedClock) {
+    let rcc_reg = unsafe { &*pac::RCC::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+    let exti = unsafe { &*pac::EXTI::ptr() };
+
+    rcc_reg.apb1enr.modify(|_, w| w.lptim1en().set_bit());
+
+    let lptim_sel = match low_speed_clock {
+        LowSpeedClock::Lse => {
+            LPTIM_HZ.store(LSE_HZ / 32, Ordering::SeqCst);
+            RCC_CCIPR_LPTIM1SEL_LSE
+        }
+        LowSpeedClock::Lsi => {
+            LPTIM_HZ.store(LSI_HZ / 32, Ordering::SeqCst);
+            RCC_CCIPR_LPTIM1SEL_LSI
+        }
+    };
+
+    unsafe {
+        // USART1 is clocked from HSI16 (same 16 MHz as PCLK2, so baudrate doesn't change),
+        // which is kept on in STOP, so RX can wake the core
+        rcc_reg.ccipr.modify(|r, w| {
+            w.bits((r.bits() & !(RCC_CCIPR_LPTIM1SEL_MASK | RCC_CCIPR_USART1SEL_MASK)) | lptim_sel | RCC_CCIPR_USART1SEL_HSI16)
+        });
+        rcc_reg.cr.modify(|r, w| w.bits(r.bits() | RCC_CR_HSI16KERON));
+
+        // Wake up on HSI16, so clocks don't have to be restored
+        rcc_reg.cfgr.modify(|r, w| w.bits(r.bits() | RCC_CFGR_STOPWUCK));
+
+        // Low-power regulator in STOP, internal reference is off and its restart isn't waited for
+        pwr.cr.modify(|r, w| w.bits((r.bits() & !PWR_CR_PDDS) | PWR_CR_LPSDSR | PWR_CR_ULP | PWR_CR_FWU));
+
+        let lptim = &*pac::LPTIM::ptr();
+        lptim.cfgr.write(|w| w.bits(LPTIM_PRESC_DIV32));
+        lptim.ier.write(|w| w.bits(LPTIM_IER_ARRMIE));
+
+        let usart = &*pac::USART1::ptr();
+        usart.cr1.modify(|r, w| w.bits(r.bits() & !USART_CR1_UE));
+        usart.cr3.modify(|r, w| w.bits(r.bits() | USART_CR3_WUS_RXNE | USART_CR3_WUFIE));
+        usart.cr1.modify(|r, w| w.bits(r.bits() | USART_CR1_UESM | USART_CR1_UE));
+
+        exti.imr.modify(|r, w| w.bits(r.bits() | (1 << EXTI_LINE_USART1) | (1 << EXTI_LINE_LPTIM1)));
+
+        // Keep debugger attached in STOP for debug builds
+        #[cfg(debug_assertions)]
+        (*pac::DBGMCU::ptr()).cr.modify(|r, w| w.bits(r.bits() | DBGMCU_CR_DBG_STOP));
+
+        NVIC::unmask(Interrupt::LPTIM1);
+        NVIC::unmask(Interrupt::USART1);
+    }
+}
+
+pub(crate) fn sleep() {
+    cortex_m::asm::wfi();
+}
+
+fn lptim_count(lptim: &pac::lptim::RegisterBlock) -> u32 {
+    // Counter is asynchronous to the bus, two equal reads in a row are required
+    loop {
+        let a = lptim.cnt.read().bits();
+
+        if a == lptim.cnt.read().bits() {
+            return a;
+        }
+    }
+}
+
+// Returns ms actually spent in STOP, 0 if it wasn't entered
+pub(crate) fn stop(ms: u32) -> u32 {
+    let hz = LPTIM_HZ.load(Ordering::SeqCst);
+    let ticks = (ms as u64 * hz as u64 / 1000).clamp(2, u16::MAX as u64) as u32;
+
+    let lptim = unsafe { &*pac::LPTIM::ptr() };
+    let pwr = unsafe { &*pac::PWR::ptr() };
+
+    // WFI wakes on pending interrupts even with PRIMASK set, handlers run after it's cleared
+    cortex_m::interrupt::disable();
+
+    if app::services::idle::pending_activity() {
+        unsafe { cortex_m::interrupt::enable() };
+        return 0;
+    }
+
+    unsafe {
+        lptim.icr.write(|w| w.bits(LPTIM_ICR_ALL));
+        lptim.cr.write(|w| w.bits(LPTIM_CR_ENABLE));
+        lptim.arr.write(|w| w.bits(ticks));
+    }
+
+    wait_for(|| lptim.isr.read().bits() & LPTIM_ISR_ARROK != 0);
+
+    unsafe {
+        lptim.icr.write(|w| w.bits(LPTIM_ICR_ALL));
+        lptim.cr.write(|w| w.bits(LPTIM_CR_ENABLE | LPTIM_CR_SNGSTRT));
+
+        (*SYST::PTR).csr.modify(|v| v & !SYST_CSR_ENABLE);
+
+        pwr.cr.modify(|r, w| w.bits(r.bits() | PWR_CR_CWUF));
+        (*SCB::PTR).scr.modify(|v| v | SCB_SCR_SLEEPDEEP);
+    }
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+
+    let elapsed = if lptim.isr.read().bits() & LPTIM_ISR_ARRM != 0 { ticks } else { lptim_count(lptim) };
+
+    crate::clock::resume();
+
+    unsafe {
+        (*SCB::PTR).scr.modify(|v| v & !SCB_SCR_SLEEPDEEP);
+
+        lptim.cr.write(|w| w.bits(0));
+
+        (*SYST::PTR).cvr.write(0);
+        (*SYST::PTR).csr.modify(|v| v | SYST_CSR_ENABLE);
+
+        cortex_m::interrupt::enable();
+    }
+
+    crate::time::advance(elapsed as u64 * 1_000_000 / hz as u64);
+
+    (elapsed as u64 * 1000 / hz as u64) as u32
+}
+
+#[interrupt]
+fn LPTIM1() {
+    let lptim = unsafe { &*pac::LPTIM::ptr() };
+    lptim.icr.write(|w| unsafe { w.bits(LPTIM_ICR_ALL) });
+}
+
+#[interrupt]
+fn USART1() {
+    let usart = unsafe { &*pac::USART1::ptr() };
+
+    // Received byte stays in RDR, console polls it
+    usart.icr.write(|w| unsafe { w.bits(USART_ICR_WUCF) });
+
+    app::services::idle::notify_activity();
+}
@@ -0,0 +1,203 @@
+use crate::hal::pac::{self, interrupt, Interrupt};
+use crate::power::{self, LowSpeedClock};
+
+use cortex_m::peripheral::NVIC;
+
+use app::peripherals::rtc::{DateTime, RtcError, RtcInterface};
+
+const RCC_CSR_RTCSEL_LSE: u32 = 0b01 << 16;
+const RCC_CSR_RTCSEL_LSI: u32 = 0b10 << 16;
+const RCC_CSR_RTCSEL_MASK: u32 = 0b11 << 16;
+const RCC_CSR_RTCEN: u32 = 1 << 18;
+
+const RTC_ISR_ALRAWF: u32 = 1 << 0;
+const RTC_ISR_INITS: u32 = 1 << 4;
+const RTC_ISR_INITF: u32 = 1 << 6;
+const RTC_ISR_INIT: u32 = 1 << 7;
+const RTC_ISR_ALRAF: u32 = 1 << 8;
+
+const RTC_CR_BYPSHAD: u32 = 1 << 5;
+const RTC_CR_ALRAE: u32 = 1 << 8;
+const RTC_CR_ALRAIE: u32 = 1 << 12;
+
+// Alarm is EXTI line 17 (rising edge)
+const EXTI_LINE_RTC_ALARM: u32 = 17;
+
+const INIT_LOOPS: u32 = 100_000;
+
+fn wait_for(mut ready: impl FnMut() -> bool) -> Result<(), RtcError> {
+    if (0..INIT_LOOPS).any(|_| ready()) { Ok(()) } else { Err(RtcError::Timeout) }
+}
+
+fn bcd(value: u8) -> u32 {
+    ((value / 10) << 4 | (value % 10)) as u32
+}
+
+fn from_bcd(value: u32) -> u8 {
+    ((value >> 4 & 0xF) * 10 + (value & 0xF)) as u8
+}
+
+fn rtc() -> &'static pac::rtc::RegisterBlock {
+    unsafe { &*pac::RTC::ptr() }
+}
+
+fn write_protect(enable: bool) {
+    let rtc = rtc();
+
+    unsafe {
+        if enable {
+            rtc.wpr.write(|w| w.bits(0xFF));
+        } else {
+            rtc.wpr.write(|w| w.bits(0xCA));
+            rtc.wpr.write(|w| w.bits(0x53));
+        }
+    }
+}
+
+// Calendar (TR/DR) is only writable in init mode
+fn with_init<R>(f: impl FnOnce() -> R) -> Result<R, RtcError> {
+    let rtc = rtc();
+
+    write_protect(false);
+
+    unsafe { rtc.isr.modify(|r, w| w.bits(r.bits() | RTC_ISR_INIT)) };
+
+    let res = wait_for(|| rtc.isr.read().bits() & RTC_ISR_INITF != 0).map(|_| f());
+
+    unsafe { rtc.isr.modify(|r, w| w.bits(r.bits() & !RTC_ISR_INIT)) };
+
+    write_protect(true);
+
+    res
+}
+
+// Lock-free, so it can be used from fault handlers
+pub(crate) fn read() -> Result<DateTime, RtcError> {
+    let rtc = rtc();
+
+    if rtc.isr.read().bits() & RTC_ISR_INITS == 0 {
+        return Err(RtcError::NotSet);
+    }
+
+    // Shadow registers are bypassed (they're stale after STOP), so read until two reads match
+    let (tr, dr) = loop {
+        let tr = rtc.tr.read().bits();
+        let dr = rtc.dr.read().bits();
+
+        if tr == rtc.tr.read().bits() && dr == rtc.dr.read().bits() {
+            break (tr, dr);
+        }
+    };
+
+    DateTime::new(
+        2000 + from_bcd(dr >> 16 & 0xFF) as u16,
+        from_bcd(dr >> 8 & 0x1F),
+        from_bcd(dr & 0x3F),
+        from_bcd(tr >> 16 & 0x3F),
+        from_bcd(tr >> 8 & 0x7F),
+        from_bcd(tr & 0x7F),
+    )
+}
+
+pub(crate) fn setup_rtc(low_speed_clock: LowSpeedClock) {
+    let rcc_reg = unsafe { &*pac::RCC::ptr() };
+    let rtc = rtc();
+    let exti = unsafe { &*pac::EXTI::ptr() };
+
+    let (rtcsel, prediv_a, prediv_s) = match low_speed_clock {
+        // 1 Hz calendar clock = RTCCLK / ((PREDIV_A + 1) * (PREDIV_S + 1))
+        LowSpeedClock::Lse => (RCC_CSR_RTCSEL_LSE, 127, power::LSE_HZ / 128 - 1),
+        LowSpeedClock::Lsi => (RCC_CSR_RTCSEL_LSI, 127, power::LSI_HZ / 128 - 1),
+    };
+
+    // Backup domain survives resets, so RTC is only configured once. Changing the clock source
+    // later would need a backup domain reset, which would also lose the time
+    if rcc_reg.csr.read().bits() & RCC_CSR_RTCEN == 0 {
+        unsafe {
+            rcc_reg.csr.modify(|r, w| w.bits((r.bits() & !RCC_CSR_RTCSEL_MASK) | rtcsel | RCC_CSR_RTCEN));
+        }
+
+        let _ = with_init(|| unsafe {
+            rtc.prer.write(|w| w.bits(prediv_a << 16 | prediv_s));
+        });
+    }
+
+    write_protect(false);
+    unsafe { rtc.cr.modify(|r, w| w.bits(r.bits() | RTC_CR_BYPSHAD)) };
+    write_protect(true);
+
+    unsafe {
+        exti.rtsr.modify(|r, w| w.bits(r.bits() | (1 << EXTI_LINE_RTC_ALARM)));
+        exti.imr.modify(|r, w| w.bits(r.bits() | (1 << EXTI_LINE_RTC_ALARM)));
+
+        NVIC::unmask(Interrupt::RTC);
+    }
+}
+
+pub struct Stm32Rtc;
+
+impl RtcInterface for Stm32Rtc {
+    fn now(&mut self) -> Result<DateTime, RtcError> {
+        read()
+    }
+
+    fn set(&mut self, time: &DateTime) -> Result<(), RtcError> {
+        let rtc = rtc();
+
+        let tr = bcd(time.hour) << 16 | bcd(time.minute) << 8 | bcd(time.second);
+        let dr = bcd((time.year - 2000) as u8) << 16 | (time.weekday() as u32) << 13 | bcd(time.month) << 8 | bcd(time.day);
+
+        // 24 hour format (CR.FMT = 0) is the reset default
+        with_init(|| unsafe {
+            rtc.tr.write(|w| w.bits(tr));
+            rtc.dr.write(|w| w.bits(dr));
+        })
+    }
+
+    fn set_alarm(&mut self, at: Option<&DateTime>) -> Result<(), RtcError> {
+        let rtc = rtc();
+
+        write_protect(false);
+
+        unsafe { rtc.cr.modify(|r, w| w.bits(r.bits() & !(RTC_CR_ALRAE | RTC_CR_ALRAIE))) };
+
+        let res = match at {
+            None => Ok(()),
+            Some(at) => wait_for(|| rtc.isr.read().bits() & RTC_ISR_ALRAWF != 0).map(|_| unsafe {
+                // Date, hours, minutes and seconds must match (all MSKx = 0, WDSEL = 0)
+                rtc.alrmar.write(|w| {
+                    w.bits(bcd(at.day) << 24 | bcd(at.hour) << 16 | bcd(at.minute) << 8 | bcd(at.second))
+                });
+
+                rtc.isr.modify(|r, w| w.bits(r.bits() & !RTC_ISR_ALRAF));
+                rtc.cr.modify(|r, w| w.bits(r.bits() | RTC_CR_ALRAE | RTC_CR_ALRAIE));
+            }),
+        };
+
+        write_protect(true);
+
+        res
+    }
+}
+
+#[interrupt]
+fn RTC() {
+    let rtc = rtc();
+    let exti = unsafe { &*pac::EXTI::ptr() };
+
+    if rtc.isr.read().bits() & RTC_ISR_ALRAF != 0 {
+        // Alarm is one-shot, otherwise it would match again next month
+        write_protect(false);
+
+        unsafe {
+            rtc.cr.modify(|r, w| w.bits(r.bits() & !(RTC_CR_ALRAE | RTC_CR_ALRAIE)));
+            rtc.isr.modify(|r, w| w.bits(r.bits() & !RTC_ISR_ALRAF));
+        }
+
+        write_protect(true);
+
+        app::peripherals::rtc::notify_alarm();
+    }
+
+    exti.pr.write(|w| unsafe { w.bits(1 << EXTI_LINE_RTC_ALARM) });
+}
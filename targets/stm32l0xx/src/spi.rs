@@ -9,8 +9,11 @@ use crate::hal::gpio::gpioa::{PA4, PA5, PA6, PA7};
 use crate::hal::gpio::gpiob::{PB6};
 
 use rtrs::bus::Bus;
+use rtrs::sync::RwLock;
 use rtrs::ok;
 
+use app::peripherals::spi::{SpiError, SpiInterface};
+
 use core::fmt::Write;
 use rtrs::println;
 
@@ -32,6 +35,18 @@ impl Spi1Bus {
         Self { spi, cs }
     }
 
+    fn transfer(&mut self, data: u8) -> Result<u8, ()> {
+        let res = block!(self.spi.send(data)).and_then(|_| block!(self.spi.read()));
+
+        match res {
+            Ok(b) => Ok(b),
+            Err(err) => {
+                println!("spi1transfer error {:?}", err);
+                Err(())
+            }
+        }
+    }
+
     fn clear(&mut self) {
         unsafe {
             (*SPI1::ptr()).sr.read();
@@ -81,3 +96,79 @@ impl Bus for Spi1Bus {
 }
 
 unsafe impl Sync for Spi1Bus {}
+
+// SX1278 supports up to 10 MHz, 4 MHz leaves margin for wiring
+pub(crate) const SPI1_MAX_HZ: u32 = 4_000_000;
+
+const SPI_CR1_SPE: u32 = 1 << 6;
+const SPI_CR1_BR_SHIFT: u32 = 3;
+const SPI_CR1_BR_MASK: u32 = 0b111 << SPI_CR1_BR_SHIFT;
+const SPI_SR_BSY: u32 = 1 << 7;
+
+// Clock listener, SPI1 is on APB2 (not divided), so the fastest baudrate
+// that doesn't exceed SPI1_MAX_HZ is selected (PCLK / 2^(BR + 1))
+pub(crate) fn on_clock_change(sysclk: u32) {
+    let spi = unsafe { &*SPI1::ptr() };
+
+    let br = (0..8).find(|br| sysclk >> (br + 1) <= SPI1_MAX_HZ).unwrap_or(7);
+
+    // Transfers are blocking, so bus is idle here unless a byte is still shifting out
+    while spi.sr.read().bits() & SPI_SR_BSY != 0 {}
+
+    unsafe {
+        spi.cr1.modify(|r, w| w.bits(r.bits() & !SPI_CR1_SPE));
+        spi.cr1.modify(|r, w| w.bits((r.bits() & !SPI_CR1_BR_MASK) | (br << SPI_CR1_BR_SHIFT)));
+        spi.cr1.modify(|r, w| w.bits(r.bits() | SPI_CR1_SPE));
+    }
+}
+
+static SPI1_BUS: RwLock<Option<Spi1Bus>> = RwLock::new(None);
+
+pub(crate) fn init_spi1(bus: Spi1Bus) {
+    let mut r = SPI1_BUS.lock_mut();
+    *r = Some(bus);
+}
+
+// Handle to the shared SPI1 bus, so both radio driver and `spi` shell command can use it
+pub struct Spi1Handle;
+
+impl Spi1Handle {
+    fn with<R>(f: impl FnOnce(&mut Spi1Bus) -> R) -> R {
+        let mut r = SPI1_BUS.lock_mut();
+        f((*r).as_mut().expect("SPI1 is not initialized"))
+    }
+}
+
+impl Bus for Spi1Handle {
+    type Error = ();
+
+    fn lock(&mut self) -> Result<(), Self::Error> {
+        Self::with(|bus| bus.lock())
+    }
+
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        Self::with(|bus| bus.unlock())
+    }
+
+    fn send(&mut self, data: u8) -> Result<(), Self::Error> {
+        Self::with(|bus| bus.send(data))
+    }
+
+    fn recv(&mut self) -> Result<u8, Self::Error> {
+        Self::with(|bus| bus.recv())
+    }
+}
+
+impl SpiInterface for Spi1Handle {
+    fn select(&mut self) -> Result<(), SpiError> {
+        Self::with(|bus| bus.lock()).map_err(|_| SpiError::Select)
+    }
+
+    fn deselect(&mut self) -> Result<(), SpiError> {
+        Self::with(|bus| bus.unlock()).map_err(|_| SpiError::Select)
+    }
+
+    fn transfer(&mut self, byte: u8) -> Result<u8, SpiError> {
+        Self::with(|bus| bus.transfer(byte)).map_err(|_| SpiError::Bus)
+    }
+}
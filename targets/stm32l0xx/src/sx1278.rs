@@ -0,0 +1,64 @@
+use app::net::radio::{SignalError, SignalInterface};
+use app::peripherals::spi::SpiDevice;
+
+use crate::spi::Spi1Handle;
+
+// LoRa registers with the last packet's signal quality
+const REG_PKT_SNR_VALUE: u8 = 0x19;
+const REG_PKT_RSSI_VALUE: u8 = 0x1A;
+const REG_FEI_MSB: u8 = 0x28;
+
+// RegIrqFlags, PayloadCrcError is set when a packet fails its CRC
+const REG_IRQ_FLAGS: u8 = 0x12;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 1 << 5;
+
+// Low frequency port (SX1278 has only this one)
+const RSSI_OFFSET_LF: i16 = -164;
+const FXOSC_HZ: i64 = 32_000_000;
+
+// Reads the packet registers next to the driver, on the same bus
+pub struct Sx1278Signal {
+    spi: SpiDevice,
+}
+
+impl Sx1278Signal {
+    pub fn new(bus: Spi1Handle) -> Self {
+        Self { spi: SpiDevice::new(bus) }
+    }
+
+    fn read(&mut self, reg: u8) -> Result<u8, SignalError> {
+        self.spi.read_reg(reg).map_err(|_| SignalError::Bus)
+    }
+}
+
+impl SignalInterface for Sx1278Signal {
+    // SX1276/77/78/79 datasheet 5.5.5, below the noise floor SNR corrects the packet RSSI
+    fn rssi_dbm(&mut self) -> Result<i16, SignalError> {
+        let snr_x4 = self.snr_x4()?;
+        let rssi = self.read(REG_PKT_RSSI_VALUE)? as i16;
+
+        Ok(if snr_x4 < 0 {
+            RSSI_OFFSET_LF + rssi + snr_x4 as i16 / 4
+        } else {
+            RSSI_OFFSET_LF + rssi * 16 / 15
+        })
+    }
+
+    fn snr_x4(&mut self) -> Result<i8, SignalError> {
+        Ok(self.read(REG_PKT_SNR_VALUE)? as i8)
+    }
+
+    // 20-bit two's complement, scaled by 2^24 / Fxtal * BW / 500 kHz (datasheet 4.1.5)
+    fn fei_hz(&mut self, bandwidth_hz: u32) -> Result<i32, SignalError> {
+        let mut fei = [0; 3];
+        self.spi.read_regs(REG_FEI_MSB, &mut fei).map_err(|_| SignalError::Bus)?;
+
+        let raw = ((fei[0] as i32 & 0x0F) << 16 | (fei[1] as i32) << 8 | fei[2] as i32) << 12 >> 12;
+
+        Ok((raw as i64 * (1 << 24) * bandwidth_hz as i64 / (FXOSC_HZ * 500_000)) as i32)
+    }
+
+    fn crc_error(&mut self) -> Result<bool, SignalError> {
+        Ok(self.read(REG_IRQ_FLAGS)? & IRQ_PAYLOAD_CRC_ERROR != 0)
+    }
+}
@@ -1,8 +1,22 @@
 use cortex_m::peripheral::SYST;
 use cortex_m::peripheral::syst::SystClkSource;
-use rtrs::sync::RwLock;
+use cortex_m::interrupt::Mutex;
 
-pub(crate) static SYSCLK: RwLock<u32> = RwLock::new(0);
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::hal::pac::interrupt;
+
+// Atomic instead of a lock, clock switch updates it with interrupts disabled
+static SYSCLK: AtomicU32 = AtomicU32::new(0);
+
+// Rates derived from SYSCLK, re-applied after a clock switch
+static SYSTICK_HZ: AtomicU32 = AtomicU32::new(0);
+static SAMPLE_RATE: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn sysclk() -> u32 {
+    SYSCLK.load(Ordering::SeqCst)
+}
 
 pub(crate) fn setup_systick(syst: &mut SYST, core_freq: u32, hz: u32) {
     syst.set_clock_source(SystClkSource::Core);
@@ -11,74 +25,218 @@ pub(crate) fn setup_systick(syst: &mut SYST, core_freq: u32, hz: u32) {
     syst.enable_counter();
     syst.enable_interrupt();
 
-    let mut r = SYSCLK.lock_mut();
-    *r = core_freq;
+    SYSCLK.store(core_freq, Ordering::SeqCst);
+    SYSTICK_HZ.store(hz, Ordering::SeqCst);
 }
 
-pub(crate) fn setup_tim2() {
-    let rcc_reg = unsafe { &*crate::hal::pac::RCC::ptr() };
-    rcc_reg.apb1enr.modify(|_, w| w.tim2en().set_bit());
+// Called by the clock switch right after SYSCLK has changed, with interrupts disabled.
+// Delay has to be re-calibrated afterwards (needs SysTick running)
+pub(crate) fn reconfigure(core_freq: u32) {
+    let syst = unsafe { &*SYST::PTR };
+
+    SYSCLK.store(core_freq, Ordering::SeqCst);
+
+    // Current millisecond is cut short, tick count itself is kept
+    unsafe {
+        syst.rvr.write(core_freq / SYSTICK_HZ.load(Ordering::SeqCst) - 1);
+        syst.cvr.write(0);
+    }
+
+    setup_tim21(core_freq);
+    set_sample_rate(SAMPLE_RATE.load(Ordering::SeqCst));
+}
 
-    let tim2 = unsafe { &*crate::hal::pac::TIM2::ptr() };
+const MICROS_HZ: u32 = 1_000_000;
 
-    tim2.psc.write(|w| w.psc().bits(16 - 1));
-    tim2.arr.write(|w| w.arr().bits(u16::MAX));
-    tim2.cr1.modify(|_, w| w.cen().set_bit());
+// TIM21 is a free running 16-bit counter, extended to 64 bits by counting overflows
+// (TIM2 is used for buzzer PWM). Counter runs at SYSCLK / PSC, as close to 1 MHz as possible,
+// so ticks are converted to microseconds with the actual timer frequency
+struct MicrosClock {
+    // 16-bit counter overflows since the last reconfiguration
+    overflows: u64,
+    // Microseconds accumulated before the last reconfiguration
+    base_us:   u64,
+    timer_hz:  u32,
 }
 
-#[inline(never)]
-fn delay_cycles(cycles: u32) {
-    unsafe {
-        core::arch::asm!(
-            "1:",
-            "   subs {0}, #1",
-            "   bne 1b",
-            inout(reg) cycles => _,
-            options(nomem, nostack, preserves_flags),
-        );
+static MICROS_CLOCK: Mutex<RefCell<MicrosClock>> =
+    Mutex::new(RefCell::new(MicrosClock { overflows: 0, base_us: 0, timer_hz: MICROS_HZ }));
+
+fn tim21() -> &'static crate::hal::pac::tim21::RegisterBlock {
+    unsafe { &*crate::hal::pac::TIM21::ptr() }
+}
+
+// Must be called with interrupts disabled. Overflow that is pending (interrupt didn't run yet)
+// is accounted for here, so the result is correct at any call rate
+fn timer_ticks(clock: &mut MicrosClock) -> u64 {
+    let tim21 = tim21();
+
+    let mut cnt = tim21.cnt.read().cnt().bits();
+
+    if tim21.sr.read().uif().bit_is_set() {
+        tim21.sr.modify(|_, w| w.uif().clear_bit());
+        clock.overflows += 1;
+
+        // Overflow could happen between the first read and the flag check
+        cnt = tim21.cnt.read().cnt().bits();
     }
+
+    (clock.overflows << 16) | cnt as u64
 }
 
-pub(crate) fn delay_us(us: u32, cpu_hz: u32, cycles_per_loop: u32) {
-    let cycles_needed = (us as u64 * cpu_hz as u64) / 1_000_000u64;
-    let loops = (cycles_needed + (cycles_per_loop as u64 - 1)) / cycles_per_loop as u64;
-    delay_cycles(loops as u32);
+fn ticks_to_us(ticks: u64, hz: u32) -> u64 {
+    let hz = hz as u64;
+    (ticks / hz) * MICROS_HZ as u64 + (ticks % hz) * MICROS_HZ as u64 / hz
 }
 
-#[derive(Copy, Clone)]
-pub struct MicrosecondTickProvider {
-    tick: u32,
-    last: u16,
+pub(crate) fn micros() -> u64 {
+    cortex_m::interrupt::free(|cs| {
+        let mut clock = MICROS_CLOCK.borrow(cs).borrow_mut();
+        let ticks = timer_ticks(&mut clock);
+        clock.base_us + ticks_to_us(ticks, clock.timer_hz)
+    })
 }
 
-impl MicrosecondTickProvider {
-    pub fn new() -> Self {
-        MicrosecondTickProvider {
-            tick: Self::get_tim_cnt() as u32,
-            last: Self::get_tim_cnt(),
+// TIM21 isn't clocked in STOP, time spent there is added separately
+pub(crate) fn advance(us: u64) {
+    cortex_m::interrupt::free(|cs| MICROS_CLOCK.borrow(cs).borrow_mut().base_us += us);
+}
+
+// (Re)derives TIM21 prescaler from SYSCLK, time counted so far is kept
+pub(crate) fn setup_tim21(sysclk: u32) {
+    let rcc_reg = unsafe { &*crate::hal::pac::RCC::ptr() };
+    rcc_reg.apb2enr.modify(|_, w| w.tim21en().set_bit());
+
+    let tim21 = tim21();
+
+    let psc = (sysclk / MICROS_HZ).clamp(1, u16::MAX as u32 + 1);
+
+    cortex_m::interrupt::free(|cs| {
+        let mut clock = MICROS_CLOCK.borrow(cs).borrow_mut();
+
+        if tim21.cr1.read().cen().bit_is_set() {
+            let ticks = timer_ticks(&mut clock);
+            clock.base_us += ticks_to_us(ticks, clock.timer_hz);
         }
+
+        clock.overflows = 0;
+        clock.timer_hz = sysclk / psc;
+
+        tim21.cr1.modify(|_, w| w.cen().clear_bit());
+        tim21.psc.write(|w| w.psc().bits((psc - 1) as u16));
+        tim21.arr.write(|w| w.arr().bits(u16::MAX));
+
+        // Loads the prescaler and resets the counter, update flag is set by this too
+        tim21.egr.write(|w| w.ug().set_bit());
+        tim21.sr.modify(|_, w| w.uif().clear_bit());
+
+        tim21.dier.modify(|_, w| w.uie().set_bit());
+        tim21.cr1.modify(|_, w| w.cen().set_bit());
+    });
+
+    unsafe { cortex_m::peripheral::NVIC::unmask(crate::hal::pac::Interrupt::TIM21) };
+}
+
+#[interrupt]
+fn TIM21() {
+    cortex_m::interrupt::free(|cs| {
+        // Only accounts for the overflow, same as any reader would
+        timer_ticks(&mut MICROS_CLOCK.borrow(cs).borrow_mut());
+    });
+}
+
+fn timer_hz() -> u32 {
+    cortex_m::interrupt::free(|cs| MICROS_CLOCK.borrow(cs).borrow().timer_hz)
+}
+
+// Fixed cost of a delay call (entry, conversion, loop exit), in TIM21 ticks
+static DELAY_OVERHEAD: AtomicU32 = AtomicU32::new(0);
+
+// Busy waits on the raw counter, so it doesn't depend on flash wait states or instruction timing.
+// Counter is sampled far more often than it wraps, unless an interrupt runs for longer than a wrap
+fn delay_ticks(ticks: u64) {
+    let tim21 = tim21();
+
+    let mut last = tim21.cnt.read().cnt().bits();
+    let mut elapsed: u64 = 0;
+
+    while elapsed < ticks {
+        let cnt = tim21.cnt.read().cnt().bits();
+        elapsed += cnt.wrapping_sub(last) as u64;
+        last = cnt;
     }
-    
-    fn get_tim_cnt() -> u16 {
-        let tim2 = unsafe { &*crate::hal::pac::TIM2::ptr() };
-        tim2.cnt.read().cnt().bits()
-    }
 }
 
-impl rtrs::time::TickProvider for MicrosecondTickProvider {
-    type Tick = u32;
+pub(crate) fn delay_us(us: u32) {
+    let ticks = us as u64 * timer_hz() as u64 / MICROS_HZ as u64;
+    delay_ticks(ticks.saturating_sub(DELAY_OVERHEAD.load(Ordering::SeqCst) as u64));
+}
+
+// Has to be shorter than a SysTick period
+const CALIBRATION_US: u32 = 200;
+
+// Times a delay with SysTick (counts SYSCLK cycles) and subtracts the excess from every following
+// delay. Must be repeated after SYSCLK changes, returns measured overhead in SYSCLK cycles
+pub(crate) fn calibrate_delay() -> u32 {
+    let syst = unsafe { &*SYST::PTR };
+
+    let sysclk = sysclk();
+    let reload = syst.rvr.read() + 1;
+
+    DELAY_OVERHEAD.store(0, Ordering::SeqCst);
+
+    // SysTick counts down and wraps at reload
+    let cycles = cortex_m::interrupt::free(|_| {
+        let start = syst.cvr.read();
+        delay_us(CALIBRATION_US);
+        let end = syst.cvr.read();
+
+        (start + reload - end) % reload
+    });
+
+    let expected = (CALIBRATION_US as u64 * sysclk as u64 / MICROS_HZ as u64) as u32;
+    let excess = cycles.saturating_sub(expected);
+
+    DELAY_OVERHEAD.store((excess as u64 * timer_hz() as u64 / sysclk as u64) as u32, Ordering::SeqCst);
+
+    excess
+}
+
+// TIM22 is a periodic sampling timer, runs at 10 kHz and overflows at the requested rate
+pub(crate) fn set_sample_rate(hz: u32) {
+    let rcc_reg = unsafe { &*crate::hal::pac::RCC::ptr() };
+    rcc_reg.apb2enr.modify(|_, w| w.tim22en().set_bit());
+
+    let tim22 = unsafe { &*crate::hal::pac::TIM22::ptr() };
 
-    fn get_tick(&mut self) -> Self::Tick {
-        let tick = Self::get_tim_cnt();
-        
-        let diff = if tick < self.last {
-            u16::MAX - self.last + tick
-        } else {
-            tick - self.last
-        };
+    tim22.cr1.modify(|_, w| w.cen().clear_bit());
 
-        self.tick += diff as u32;
-        self.last = tick;
-        self.tick
+    SAMPLE_RATE.store(hz, Ordering::SeqCst);
+
+    if hz == 0 {
+        cortex_m::peripheral::NVIC::mask(crate::hal::pac::Interrupt::TIM22);
+        return;
     }
+
+    const TIMER_HZ: u32 = 10_000;
+
+    let sysclk = sysclk();
+    let reload = (TIMER_HZ / hz).clamp(1, u16::MAX as u32);
+
+    tim22.psc.write(|w| w.psc().bits((sysclk / TIMER_HZ - 1) as u16));
+    tim22.arr.write(|w| w.arr().bits((reload - 1) as u16));
+    tim22.egr.write(|w| w.ug().set_bit());
+    tim22.sr.modify(|_, w| w.uif().clear_bit());
+    tim22.dier.modify(|_, w| w.uie().set_bit());
+    tim22.cr1.modify(|_, w| w.cen().set_bit());
+
+    unsafe { cortex_m::peripheral::NVIC::unmask(crate::hal::pac::Interrupt::TIM22) };
+}
+
+#[interrupt]
+fn TIM22() {
+    let tim22 = unsafe { &*crate::hal::pac::TIM22::ptr() };
+    tim22.sr.modify(|_, w| w.uif().clear_bit());
+
+    app::board::BoardInterface::callback(app::board::Callback::SampleTimer);
 }
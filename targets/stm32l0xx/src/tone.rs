@@ -0,0 +1,172 @@
+use crate::hal::gpio::gpioa::PA15;
+use crate::hal::gpio::Analog;
+
+use embedded_hal::digital::v2::OutputPin;
+
+use app::peripherals::tone::ToneInterface;
+
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+// Buzzer is on PA15, which is TIM2_CH1 (AF5)
+const BUZZER_PIN: u32 = 15;
+const BUZZER_AF: u32 = 5;
+
+// Timer runs at 1 MHz, so with 16-bit ARR the lowest possible frequency is ~16 Hz
+const TIMER_HZ: u32 = 1_000_000;
+
+// Pin patterns sound at this frequency while they are on
+const PATTERN_HZ: u32 = 2_000;
+
+// Rising edges closer than this are a waveform (like the microsecond BEEP pattern), not on/off
+const WAVEFORM_PERIOD_US: u32 = 20_000;
+
+fn prescaler(sysclk: u32) -> u16 {
+    ((sysclk / TIMER_HZ).max(1) - 1) as u16
+}
+
+// Clock listener, keeps the frequency of a tone that is playing during a clock switch
+pub(crate) fn on_clock_change(sysclk: u32) {
+    let tim2 = unsafe { &*crate::hal::pac::TIM2::ptr() };
+
+    if tim2.cr1.read().cen().bit_is_set() {
+        tim2.psc.write(|w| w.psc().bits(prescaler(sysclk)));
+        tim2.egr.write(|w| w.ug().set_bit());
+    }
+}
+
+pub struct Tim2Tone {
+    _buzzer: PA15<Analog>,
+}
+
+impl Tim2Tone {
+    pub fn new(buzzer: PA15<Analog>) -> Self {
+        let rcc_reg = unsafe { &*crate::hal::pac::RCC::ptr() };
+        rcc_reg.apb1enr.modify(|_, w| w.tim2en().set_bit());
+
+        let tim2 = unsafe { &*crate::hal::pac::TIM2::ptr() };
+
+        // PWM mode 1 with preload on CH1
+        tim2.ccmr1_output().modify(|_, w| unsafe { w.oc1m().bits(0b110).oc1pe().set_bit() });
+        tim2.cr1.modify(|_, w| w.arpe().set_bit());
+
+        Self { _buzzer: buzzer }
+    }
+
+    fn set_pin_mode(alternate: bool) {
+        let gpioa = unsafe { &*crate::hal::pac::GPIOA::ptr() };
+
+        let mode = if alternate { 0b10 } else { 0b01 };
+
+        gpioa.afrh.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0xF << ((BUZZER_PIN - 8) * 4))) | (BUZZER_AF << ((BUZZER_PIN - 8) * 4)))
+        });
+
+        gpioa.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (BUZZER_PIN * 2))) | (mode << (BUZZER_PIN * 2)))
+        });
+
+        if !alternate {
+            gpioa.bsrr.write(|w| unsafe { w.bits(1 << (BUZZER_PIN + 16)) });
+        }
+    }
+
+    fn run(freq: u32) {
+        let tim2 = unsafe { &*crate::hal::pac::TIM2::ptr() };
+
+        let period = (TIMER_HZ / freq.max(1)).clamp(2, u16::MAX as u32 + 1);
+
+        tim2.psc.write(|w| w.psc().bits(prescaler(crate::time::sysclk())));
+        tim2.arr.write(|w| w.arr().bits((period - 1) as u16));
+        tim2.ccr1.write(|w| unsafe { w.bits(period / 2) });
+        tim2.egr.write(|w| w.ug().set_bit());
+
+        tim2.ccer.modify(|_, w| w.cc1e().set_bit());
+        tim2.cr1.modify(|_, w| w.cen().set_bit());
+
+        Self::set_pin_mode(true);
+    }
+
+    fn halt() {
+        let tim2 = unsafe { &*crate::hal::pac::TIM2::ptr() };
+
+        Self::set_pin_mode(false);
+
+        tim2.cr1.modify(|_, w| w.cen().clear_bit());
+        tim2.ccer.modify(|_, w| w.cc1e().clear_bit());
+    }
+}
+
+impl ToneInterface for Tim2Tone {
+    fn start(&mut self, freq: u32) {
+        Self::run(freq);
+    }
+
+    fn stop(&mut self) {
+        Self::halt();
+    }
+}
+
+static LAST_RISE: AtomicU32 = AtomicU32::new(0);
+static WAVEFORM: AtomicBool = AtomicBool::new(false);
+// What the pattern pin plays, 0 is silence
+static PIN_FREQ: AtomicU32 = AtomicU32::new(0);
+
+// Goes to the timer directly, so it can be called from SysTick
+fn pin_play(freq: u32) {
+    let current = PIN_FREQ.load(Ordering::SeqCst);
+
+    // Rate of a software waveform jitters, timer is only restarted on a real change
+    if freq == current || (freq != 0 && current != 0 && freq.abs_diff(current) * 16 <= current) {
+        return;
+    }
+
+    PIN_FREQ.store(freq, Ordering::SeqCst);
+
+    match freq {
+        0 => Tim2Tone::halt(),
+        _ => Tim2Tone::run(freq),
+    }
+}
+
+// Output pin for the pattern player in place of a GPIO buzzer (PA15 belongs to TIM2). On/off
+// patterns beep at PATTERN_HZ, a pin toggled at audio rate sounds at the rate it's toggled at
+pub struct TonePin;
+
+impl OutputPin for TonePin {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        cortex_m::interrupt::free(|_| {
+            let now = crate::time::micros() as u32;
+            let period = now.wrapping_sub(LAST_RISE.swap(now, Ordering::SeqCst));
+            let waveform = period < WAVEFORM_PERIOD_US;
+
+            WAVEFORM.store(waveform, Ordering::SeqCst);
+            pin_play(if waveform { TIMER_HZ / period.max(1) } else { PATTERN_HZ });
+        });
+
+        Ok(())
+    }
+
+    // Low half of a waveform period doesn't silence it, on_tick() does once the edges stop
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        cortex_m::interrupt::free(|_| {
+            if !WAVEFORM.load(Ordering::SeqCst) {
+                pin_play(0);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+// Called from SysTick
+pub(crate) fn on_tick() {
+    let since = (crate::time::micros() as u32).wrapping_sub(LAST_RISE.load(Ordering::SeqCst));
+
+    if WAVEFORM.load(Ordering::SeqCst) && since >= WAVEFORM_PERIOD_US {
+        WAVEFORM.store(false, Ordering::SeqCst);
+        pin_play(0);
+    }
+}
@@ -1,5 +1,4 @@
-use core::fmt::Write;
-use embedded_hal::serial::Read;
+use embedded_hal::serial::{Read, Write};
 
 use crate::hal::pac::USART1;
 use crate::hal::serial::Serial;
@@ -16,12 +15,58 @@ impl TtyUSART1Backend {
 
 impl rtrs::tty::TtyBackend for TtyUSART1Backend {
     fn read(&mut self) -> Option<u8> {
-        self.serial.read().ok()
+        let byte = self.serial.read().ok();
+
+        // Keeps the MCU awake while typing
+        if byte.is_some() {
+            app::services::idle::notify_activity();
+        }
+
+        byte
     }
 
+    // Raw byte, `write_char` would UTF-8 encode everything above 0x7F and break binary output
     fn write(&mut self, byte: u8) {
-        self.serial.write_char(byte as char).unwrap();
+        nb::block!(self.serial.write(byte)).unwrap();
     }
 }
 
 unsafe impl Sync for TtyUSART1Backend {}
+
+pub(crate) const CONSOLE_BAUDRATE: u32 = 115_200;
+
+const RCC_CCIPR_USART1SEL_MASK: u32 = 0b11 << 0;
+const HSI16_HZ: u32 = 16_000_000;
+
+const USART_CR1_UE: u32 = 1 << 0;
+const USART_ISR_TC: u32 = 1 << 6;
+
+// Clock listener. USART1 is normally clocked from HSI16 (see power::setup_stop_mode),
+// so the baudrate only has to be re-derived if it runs from SYSCLK/PCLK2
+pub(crate) fn on_clock_change(sysclk: u32) {
+    let rcc_reg = unsafe { &*crate::hal::pac::RCC::ptr() };
+    let usart = unsafe { &*USART1::ptr() };
+
+    let kernel_hz = match rcc_reg.ccipr.read().bits() & RCC_CCIPR_USART1SEL_MASK {
+        0b10 => HSI16_HZ,
+        0b11 => crate::power::LSE_HZ,
+        // PCLK2 isn't divided, same as SYSCLK
+        _    => sysclk,
+    };
+
+    // Oversampling by 16
+    let brr = (kernel_hz + CONSOLE_BAUDRATE / 2) / CONSOLE_BAUDRATE;
+
+    if usart.brr.read().bits() == brr {
+        return;
+    }
+
+    // Let the last byte go out at the old baudrate
+    while usart.isr.read().bits() & USART_ISR_TC == 0 {}
+
+    unsafe {
+        usart.cr1.modify(|r, w| w.bits(r.bits() & !USART_CR1_UE));
+        usart.brr.write(|w| w.bits(brr));
+        usart.cr1.modify(|r, w| w.bits(r.bits() | USART_CR1_UE));
+    }
+}